@@ -0,0 +1,255 @@
+// x86-64 instructions as buffered by the code generator, so that the
+// peephole pass can rewrite them before they are printed. They print in
+// Intel syntax, or in AT&T syntax through Line::display.
+use crate::ir::Width;
+use crate::options::AsmSyntax;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Reg(&'static str),
+    Imm(i64),
+    /// [base-disp], with a size prefix where no register operand implies one
+    Mem {
+        size: Option<Width>,
+        base: &'static str,
+        disp: usize,
+    },
+    /// Target of a jump or call
+    Label(String),
+    /// Address of a symbol, i.e. "offset name"
+    Offset(String),
+    /// Symbol addressed relative to rip, i.e. "[rip+name]"
+    Rip(String),
+    /// Entry of the GOT holding the address of a symbol
+    Got(String),
+    /// Entry of a jump table, i.e. "[name+index*8]", along with the labels
+    /// the table holds. Position-independent code has the address of the
+    /// table loaded into base instead.
+    Table {
+        name: String,
+        base: Option<&'static str>,
+        index: &'static str,
+        targets: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Label(String),
+    Inst(&'static str, Vec<Operand>),
+}
+
+impl Operand {
+    pub fn mem(size: Option<Width>, base: &'static str, disp: usize) -> Self {
+        Operand::Mem { size, base, disp }
+    }
+}
+
+/// Full register containing the named one, e.g. rax for al
+pub fn reg64(name: &'static str) -> &'static str {
+    match name {
+        "al" | "ax" | "eax" => "rax",
+        "bl" | "bx" | "ebx" => "rbx",
+        "cl" | "cx" | "ecx" => "rcx",
+        "dl" | "dx" | "edx" => "rdx",
+        "sil" | "si" | "esi" => "rsi",
+        "dil" | "di" | "edi" => "rdi",
+        // r8b, r10w, r12d and so on
+        _ => ["r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"]
+            .iter()
+            .find(|full| name.len() == full.len() + 1 && name.starts_with(*full))
+            .unwrap_or(&name),
+    }
+}
+
+/// Name of the 64-bit register full when accessed at width
+pub fn reg_named(full: &'static str, width: Width) -> &'static str {
+    let names: [&'static str; 4] = match full {
+        "rax" => ["al", "ax", "eax", "rax"],
+        "rbx" => ["bl", "bx", "ebx", "rbx"],
+        "rcx" => ["cl", "cx", "ecx", "rcx"],
+        "rdx" => ["dl", "dx", "edx", "rdx"],
+        "rsi" => ["sil", "si", "esi", "rsi"],
+        "rdi" => ["dil", "di", "edi", "rdi"],
+        "r8" => ["r8b", "r8w", "r8d", "r8"],
+        "r9" => ["r9b", "r9w", "r9d", "r9"],
+        "r10" => ["r10b", "r10w", "r10d", "r10"],
+        "r11" => ["r11b", "r11w", "r11d", "r11"],
+        "r12" => ["r12b", "r12w", "r12d", "r12"],
+        "r13" => ["r13b", "r13w", "r13d", "r13"],
+        "r14" => ["r14b", "r14w", "r14d", "r14"],
+        "r15" => ["r15b", "r15w", "r15d", "r15"],
+        _ => panic!("asm: {} has no sub-registers", full),
+    };
+    match width {
+        Width::I8 => names[0],
+        Width::I16 => names[1],
+        Width::I32 => names[2],
+        Width::I64 => names[3],
+    }
+}
+
+/// Width of the named register
+pub fn reg_width(name: &'static str) -> Width {
+    if reg64(name) == name {
+        Width::I64
+    } else if name.starts_with('e') || name.ends_with('d') {
+        Width::I32
+    } else if name.ends_with('l') || name.ends_with('b') {
+        Width::I8
+    } else {
+        Width::I16
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Reg(name) => write!(f, "{}", name),
+            Operand::Imm(val) => write!(f, "{}", val),
+            Operand::Mem { size, base, disp } => {
+                match size {
+                    Some(Width::I8) => write!(f, "byte ptr ")?,
+                    Some(Width::I16) => write!(f, "word ptr ")?,
+                    Some(Width::I32) => write!(f, "dword ptr ")?,
+                    Some(Width::I64) => write!(f, "qword ptr ")?,
+                    None => (),
+                }
+                if disp == 0 {
+                    write!(f, "[{}]", base)
+                } else {
+                    write!(f, "[{}-{}]", base, disp)
+                }
+            }
+            Operand::Label(ref name) => write!(f, "{}", name),
+            Operand::Offset(ref name) => write!(f, "offset {}", name),
+            Operand::Rip(ref name) => write!(f, "[rip+{}]", name),
+            Operand::Got(ref name) => write!(f, "qword ptr [rip+{}@GOTPCREL]", name),
+            Operand::Table {
+                base: Some(base),
+                index,
+                ..
+            } => write!(f, "qword ptr [{}+{}*8]", base, index),
+            Operand::Table {
+                ref name, index, ..
+            } => write!(f, "qword ptr [{}+{}*8]", name, index),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Line::Label(ref name) => write!(f, "{}:", name),
+            Line::Inst(op, ref args) => {
+                write!(f, "  {}", op)?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { " " } else { ", " }, arg)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A line printed in either dialect
+pub struct Display<'a>(&'a Line, AsmSyntax);
+
+impl Line {
+    pub fn display(&self, syntax: AsmSyntax) -> Display<'_> {
+        Display(self, syntax)
+    }
+}
+
+/// Register name as written in the given dialect
+pub fn reg_in(name: &str, syntax: AsmSyntax) -> String {
+    match syntax {
+        AsmSyntax::Intel => name.to_string(),
+        AsmSyntax::Att => format!("%{}", name),
+    }
+}
+
+// Suffix of an AT&T mnemonic for an operand size
+fn suffix(width: Width) -> char {
+    match width {
+        Width::I8 => 'b',
+        Width::I16 => 'w',
+        Width::I32 => 'l',
+        Width::I64 => 'q',
+    }
+}
+
+fn operand_size(opnd: &Operand) -> Option<Width> {
+    match *opnd {
+        Operand::Reg(name) => Some(reg_width(name)),
+        Operand::Mem { size, .. } => size,
+        _ => None,
+    }
+}
+
+impl Operand {
+    fn fmt_att(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Reg(name) => write!(f, "%{}", name),
+            Operand::Imm(val) => write!(f, "${}", val),
+            Operand::Mem { base, disp: 0, .. } => write!(f, "(%{})", base),
+            Operand::Mem { base, disp, .. } => write!(f, "-{}(%{})", disp, base),
+            Operand::Label(ref name) => write!(f, "{}", name),
+            Operand::Offset(ref name) => write!(f, "${}", name),
+            Operand::Rip(ref name) => write!(f, "{}(%rip)", name),
+            Operand::Got(ref name) => write!(f, "{}@GOTPCREL(%rip)", name),
+            Operand::Table {
+                base: Some(base),
+                index,
+                ..
+            } => write!(f, "(%{},%{},8)", base, index),
+            Operand::Table {
+                ref name, index, ..
+            } => write!(f, "{}(,%{},8)", name, index),
+        }
+    }
+}
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (op, args) = match (self.0, self.1) {
+            (Line::Inst(op, args), AsmSyntax::Att) => (*op, args),
+            (line, _) => return write!(f, "{}", line),
+        };
+        // Extensions name both sizes, and without a register to go by
+        // the size of a memory operand goes into the mnemonic
+        let mnemonic = match op {
+            "cqo" => "cqto".to_string(),
+            "movsxd" => "movslq".to_string(),
+            "movsx" | "movzb" => {
+                let from = operand_size(&args[1]).unwrap_or(Width::I8);
+                let to = operand_size(&args[0]).unwrap_or(Width::I64);
+                format!("mov{}{}{}", &op[3..4], suffix(from), suffix(to))
+            }
+            _ => {
+                let has_reg = args.iter().any(|a| matches!(a, Operand::Reg(_)));
+                match args.iter().find_map(|a| match *a {
+                    Operand::Mem { size, .. } => size,
+                    _ => None,
+                }) {
+                    Some(width) if !has_reg && op != "call" && op != "jmp" => {
+                        format!("{}{}", op, suffix(width))
+                    }
+                    _ => op.to_string(),
+                }
+            }
+        };
+        write!(f, "  {}", mnemonic)?;
+        // Indirect targets are starred
+        let indirect = (op == "call" || op == "jmp") && !matches!(args[0], Operand::Label(_));
+        for (i, arg) in args.iter().rev().enumerate() {
+            write!(f, "{}", if i == 0 { " " } else { ", " })?;
+            if indirect {
+                write!(f, "*")?;
+            }
+            arg.fmt_att(f)?;
+        }
+        Ok(())
+    }
+}
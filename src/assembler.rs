@@ -0,0 +1,790 @@
+// Built-in assembler for -c. It reads the text CodeGen writes, the same
+// that -S leaves in tmp.s, and lays it out into an ELF object. Jumps
+// start out short and are made long until every one reaches its label.
+// Under -g it builds the line table from the .loc directives and the
+// call frame information from the .cfi ones, as GNU as would.
+use crate::elf::{self, Object, Place, Reloc, Section, Symbol, Target};
+use crate::encoder::{self, Encoded, Fixup, FixupKind};
+use crate::tokenizer;
+use std::collections::{HashMap, HashSet};
+
+enum Item {
+    Bytes(Vec<u8>, Vec<Fixup>),
+    Jump {
+        cc: Option<u8>,
+        target: String,
+        short: bool,
+    },
+    Align(u64),
+    Label(String),
+}
+
+struct Sec {
+    name: String,
+    kind: u32,  // SHT_*
+    flags: u64, // SHF_*
+    align: u64,
+    entsize: u64,
+    items: Vec<Item>,
+    offsets: Vec<u64>, // Offset of each item once laid out
+    size: u64,
+}
+
+enum Size {
+    Const(u64),
+    Since(String, String), // From a symbol up to a mark
+}
+
+enum Cfi {
+    StartProc,
+    EndProc,
+    Op(Vec<u8>),
+}
+
+#[derive(Default)]
+struct Assembler {
+    sections: Vec<Sec>,
+    current: usize,
+    labels: HashMap<String, (usize, usize)>, // Section and item of each label
+    globals: HashSet<String>,
+    types: HashMap<String, u8>,
+    sizes: Vec<(String, Size)>,
+    commons: Vec<(String, u64, u64)>, // Name, size and alignment
+    file: Option<String>,
+    rows: Vec<(String, u64, u64)>, // Line table: mark, line and column
+    frames: Vec<(String, Cfi)>,    // Call frame information at marks
+    num_marks: usize,
+}
+
+/// Assembles the output of CodeGen into an object
+pub fn assemble(text: &str) -> Result<Object, String> {
+    let mut asm = Assembler::default();
+    asm.switch(".text", None)?;
+    for line in text.lines() {
+        asm.line(line)?;
+    }
+    for i in 0..asm.sections.len() {
+        asm.layout(i)?;
+    }
+    asm.gen_line_table()?;
+    asm.gen_frames()?;
+    asm.object()
+}
+
+fn uleb128(out: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb128(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn int(s: &str) -> Result<i64, String> {
+    encoder::parse_int(s.trim()).ok_or_else(|| format!("Expected a number, found {}", s))
+}
+
+// Contents of a quoted string, with its escapes undone
+fn quoted(s: &str) -> Result<Vec<u8>, String> {
+    let s = s.trim();
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) => Ok(tokenizer::unescape(inner)),
+        None => Err(format!("Expected a string, found {}", s)),
+    }
+}
+
+fn cfa_reg(name: &str) -> Result<u64, String> {
+    match encoder::dwarf_reg(name.trim()) {
+        Some(num) => Ok(num as u64),
+        None => Err(format!("Unknown register {}", name)),
+    }
+}
+
+impl Assembler {
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        if let Some(name) = line.strip_suffix(':') {
+            return self.label(name);
+        }
+        let (head, rest) = match line.split_once(' ') {
+            Some((head, rest)) => (head, rest.trim()),
+            None => (line, ""),
+        };
+        if head.starts_with('.') {
+            return self.directive(head, rest);
+        }
+        let args: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+        let item = match encoder::encode(head, &args)? {
+            Encoded::Bytes(bytes, fixups) => Item::Bytes(bytes, fixups),
+            Encoded::Jump(cc, target) => Item::Jump {
+                cc,
+                target,
+                short: true,
+            },
+        };
+        self.push(item);
+        Ok(())
+    }
+
+    fn push(&mut self, item: Item) {
+        self.sections[self.current].items.push(item);
+    }
+
+    fn label(&mut self, name: &str) -> Result<(), String> {
+        let at = (self.current, self.sections[self.current].items.len());
+        if self.labels.insert(name.to_string(), at).is_some() {
+            return Err(format!("{} is defined twice", name));
+        }
+        self.push(Item::Label(name.to_string()));
+        Ok(())
+    }
+
+    // Label for the current position, kept out of the symbol table
+    fn mark(&mut self) -> String {
+        let name = format!(".Lmark{}", self.num_marks);
+        self.num_marks += 1;
+        self.label(&name).unwrap();
+        name
+    }
+
+    fn switch(&mut self, name: &str, attrs: Option<(&str, &str, u64)>) -> Result<(), String> {
+        if let Some(i) = self.sections.iter().position(|s| s.name == name) {
+            self.current = i;
+            return Ok(());
+        }
+        let (mut kind, mut flags) = match name {
+            ".text" => (elf::SHT_PROGBITS, elf::SHF_ALLOC | elf::SHF_EXECINSTR),
+            ".data" => (elf::SHT_PROGBITS, elf::SHF_ALLOC | elf::SHF_WRITE),
+            ".bss" => (elf::SHT_NOBITS, elf::SHF_ALLOC | elf::SHF_WRITE),
+            ".eh_frame" => (elf::SHT_X86_64_UNWIND, elf::SHF_ALLOC),
+            _ if name.starts_with(".rodata") => (elf::SHT_PROGBITS, elf::SHF_ALLOC),
+            _ => (elf::SHT_PROGBITS, 0),
+        };
+        let mut entsize = 0;
+        if let Some((letters, typ, size)) = attrs {
+            flags = 0;
+            for c in letters.chars() {
+                flags |= match c {
+                    'a' => elf::SHF_ALLOC,
+                    'w' => elf::SHF_WRITE,
+                    'x' => elf::SHF_EXECINSTR,
+                    'M' => elf::SHF_MERGE,
+                    'S' => elf::SHF_STRINGS,
+                    _ => return Err(format!("Unknown section flag {}", c)),
+                };
+            }
+            kind = match typ {
+                "" | "@progbits" => kind,
+                "@nobits" => elf::SHT_NOBITS,
+                _ => return Err(format!("Unknown section type {}", typ)),
+            };
+            entsize = size;
+        }
+        self.sections.push(Sec {
+            name: name.to_string(),
+            kind,
+            flags,
+            align: 1,
+            entsize,
+            items: Vec::new(),
+            offsets: Vec::new(),
+            size: 0,
+        });
+        self.current = self.sections.len() - 1;
+        Ok(())
+    }
+
+    fn directive(&mut self, head: &str, rest: &str) -> Result<(), String> {
+        let args: Vec<&str> = rest.split(',').map(str::trim).collect();
+        match head {
+            ".intel_syntax" => (),
+            ".text" | ".data" | ".bss" => self.switch(head, None)?,
+            ".section" => {
+                let attrs = if args.len() > 1 {
+                    let letters = args[1].trim_matches('"');
+                    let typ = args.get(2).copied().unwrap_or("");
+                    let entsize = match args.get(3) {
+                        Some(size) => int(size)? as u64,
+                        None => 0,
+                    };
+                    Some((letters, typ, entsize))
+                } else {
+                    None
+                };
+                self.switch(args[0], attrs)?;
+            }
+            ".global" | ".globl" => {
+                self.globals.insert(rest.to_string());
+            }
+            ".type" => {
+                let kind = match args.get(1).copied() {
+                    Some("@function") => elf::STT_FUNC,
+                    Some("@object") => elf::STT_OBJECT,
+                    _ => return Err(format!("Unknown symbol type in .type {}", rest)),
+                };
+                self.types.insert(args[0].to_string(), kind);
+            }
+            ".size" => {
+                let size = match args.get(1).and_then(|a| a.strip_prefix(".-")) {
+                    Some(sym) => Size::Since(sym.to_string(), self.mark()),
+                    None => Size::Const(int(args.get(1).unwrap_or(&""))? as u64),
+                };
+                self.sizes.push((args[0].to_string(), size));
+            }
+            ".comm" if args.len() == 3 => {
+                let size = int(args[1])? as u64;
+                let align = int(args[2])? as u64;
+                self.commons.push((args[0].to_string(), size, align));
+            }
+            ".zero" => self.push(Item::Bytes(vec![0; int(rest)? as usize], Vec::new())),
+            ".byte" | ".short" | ".long" | ".quad" => {
+                let width = match head {
+                    ".byte" => 1,
+                    ".short" => 2,
+                    ".long" => 4,
+                    _ => 8,
+                };
+                for arg in args.iter() {
+                    let item = self.datum(width, arg)?;
+                    self.push(item);
+                }
+            }
+            ".string" => {
+                let mut bytes = quoted(rest)?;
+                bytes.push(0);
+                self.push(Item::Bytes(bytes, Vec::new()));
+            }
+            ".uleb128" | ".sleb128" => {
+                let mut bytes = Vec::new();
+                if head == ".uleb128" {
+                    uleb128(&mut bytes, int(rest)? as u64);
+                } else {
+                    sleb128(&mut bytes, int(rest)?);
+                }
+                self.push(Item::Bytes(bytes, Vec::new()));
+            }
+            ".p2align" => {
+                let align = 1 << int(rest)?;
+                let sec = &mut self.sections[self.current];
+                sec.align = sec.align.max(align);
+                self.push(Item::Align(align));
+            }
+            ".file" => match rest.split_once(' ') {
+                Some((_, name)) => {
+                    self.file = Some(String::from_utf8_lossy(&quoted(name)?).into_owned())
+                }
+                None => return Err(format!("Expected a file number in .file {}", rest)),
+            },
+            ".loc" => {
+                let fields: Vec<&str> = rest.split_whitespace().collect();
+                if fields.len() < 3 {
+                    return Err(format!("Expected a line and a column in .loc {}", rest));
+                }
+                let (line, col) = (int(fields[1])? as u64, int(fields[2])? as u64);
+                let mark = self.mark();
+                self.rows.push((mark, line, col));
+            }
+            ".cfi_startproc" | ".cfi_endproc" => {
+                let mark = self.mark();
+                let cfi = if head == ".cfi_startproc" {
+                    Cfi::StartProc
+                } else {
+                    Cfi::EndProc
+                };
+                self.frames.push((mark, cfi));
+            }
+            _ if head.starts_with(".cfi_") => {
+                let mut ops = Vec::new();
+                match head {
+                    ".cfi_def_cfa_offset" => {
+                        ops.push(0x0e);
+                        uleb128(&mut ops, int(rest)? as u64);
+                    }
+                    ".cfi_offset" if args.len() == 2 => {
+                        // Offsets are in units of the data alignment, -8
+                        ops.push(0x80 | cfa_reg(args[0])? as u8);
+                        uleb128(&mut ops, (-int(args[1])? / 8) as u64);
+                    }
+                    ".cfi_def_cfa_register" => {
+                        ops.push(0x0d);
+                        uleb128(&mut ops, cfa_reg(rest)?);
+                    }
+                    ".cfi_def_cfa" if args.len() == 2 => {
+                        ops.push(0x0c);
+                        uleb128(&mut ops, cfa_reg(args[0])?);
+                        uleb128(&mut ops, int(args[1])? as u64);
+                    }
+                    ".cfi_remember_state" => ops.push(0x0a),
+                    ".cfi_restore_state" => ops.push(0x0b),
+                    _ => return Err(format!("Unknown directive {}", head)),
+                }
+                let mark = self.mark();
+                self.frames.push((mark, Cfi::Op(ops)));
+            }
+            _ => return Err(format!("Unknown directive {} {}", head, rest)),
+        }
+        Ok(())
+    }
+
+    // A number, or a symbol plus or minus a number or another label
+    fn datum(&self, width: usize, s: &str) -> Result<Item, String> {
+        if let Some(v) = encoder::parse_int(s) {
+            return Ok(Item::Bytes(v.to_le_bytes()[..width].to_vec(), Vec::new()));
+        }
+        let split = s
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| c == '+' || c == '-');
+        let (sym, rest) = match split {
+            Some((i, _)) => (&s[..i], Some(&s[i..])),
+            None => (s, None),
+        };
+        let mut fixup = Fixup::new(0, width, FixupKind::Abs, sym, 0);
+        match rest {
+            None => (),
+            Some(rest) => match (encoder::parse_int(rest), rest.strip_prefix('-')) {
+                (Some(v), _) => fixup.addend = v,
+                (None, Some(label)) => fixup.minus = Some(label.to_string()),
+                _ => return Err(format!("Cannot make sense of {}", s)),
+            },
+        }
+        Ok(Item::Bytes(vec![0; width], vec![fixup]))
+    }
+
+    // Offsets of the items of a section. Every jump that does not reach
+    // its label with 8 bits is made long, and the offsets worked out
+    // again, until all of them reach.
+    fn layout(&mut self, index: usize) -> Result<(), String> {
+        loop {
+            let sec = &self.sections[index];
+            let mut offsets = Vec::with_capacity(sec.items.len());
+            let mut pos = 0u64;
+            for item in sec.items.iter() {
+                if let Item::Align(align) = *item {
+                    pos = pos.div_ceil(align) * align;
+                }
+                offsets.push(pos);
+                pos += match *item {
+                    Item::Bytes(ref bytes, _) => bytes.len() as u64,
+                    Item::Jump { cc, short, .. } => encoder::jump_len(cc, short) as u64,
+                    Item::Align(_) | Item::Label(_) => 0,
+                };
+            }
+            let mut grown = Vec::new();
+            for (i, item) in sec.items.iter().enumerate() {
+                if let Item::Jump {
+                    cc,
+                    ref target,
+                    short: true,
+                } = *item
+                {
+                    let reaches = match self.labels.get(target) {
+                        Some(&(s, at)) if s == index => {
+                            let end = offsets[i] + encoder::jump_len(cc, true) as u64;
+                            let disp = offsets[at] as i64 - end as i64;
+                            disp == disp as i8 as i64
+                        }
+                        _ => false,
+                    };
+                    if !reaches {
+                        grown.push(i);
+                    }
+                }
+            }
+            let sec = &mut self.sections[index];
+            sec.offsets = offsets;
+            sec.size = pos;
+            if grown.is_empty() {
+                return Ok(());
+            }
+            for i in grown {
+                if let Item::Jump { ref mut short, .. } = sec.items[i] {
+                    *short = false;
+                }
+            }
+        }
+    }
+
+    fn offset(&self, label: &str) -> Result<(usize, u64), String> {
+        match self.labels.get(label) {
+            Some(&(sec, at)) => Ok((sec, self.sections[sec].offsets[at])),
+            None => Err(format!("{} is not defined", label)),
+        }
+    }
+
+    // The line table for .debug_line, as one sequence covering the text
+    fn gen_line_table(&mut self) -> Result<(), String> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let (text, first) = self.offset(&rows[0].0)?;
+        let end = self.sections[text].size;
+
+        let mut header = vec![1, 1, -5i8 as u8, 14, 13];
+        // Operands taken by each standard opcode
+        header.extend_from_slice(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+        // The file goes by its name within its directory
+        let path = self.file.clone().unwrap_or_default();
+        let (dir, name) = match path.rsplit_once('/') {
+            Some((dir, name)) => (Some(dir), name),
+            None => (None, path.as_str()),
+        };
+        if let Some(dir) = dir {
+            header.extend_from_slice(dir.as_bytes());
+            header.push(0);
+        }
+        header.push(0);
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(&[0, dir.is_some() as u8, 0, 0]);
+        header.push(0);
+
+        // DW_LNE_set_address
+        let mut program = vec![0, 9, 2];
+        program.extend_from_slice(&[0; 8]);
+        let (mut addr, mut line, mut col) = (first, 1, 0);
+        for (mark, l, c) in rows.iter() {
+            let (_, pos) = self.offset(mark)?;
+            if *l != line {
+                program.push(0x03); // DW_LNS_advance_line
+                sleb128(&mut program, *l as i64 - line as i64);
+                line = *l;
+            }
+            if *c != col {
+                program.push(0x05); // DW_LNS_set_column
+                uleb128(&mut program, *c);
+                col = *c;
+            }
+            if pos != addr {
+                program.push(0x02); // DW_LNS_advance_pc
+                uleb128(&mut program, pos - addr);
+                addr = pos;
+            }
+            program.push(0x01); // DW_LNS_copy
+        }
+        if end > addr {
+            program.push(0x02);
+            uleb128(&mut program, end - addr);
+        }
+        program.extend_from_slice(&[0, 1, 1]); // DW_LNE_end_sequence
+
+        let mut data = Vec::new();
+        let length = 2 + 4 + header.len() + program.len();
+        data.extend_from_slice(&(length as u32).to_le_bytes());
+        data.extend_from_slice(&3u16.to_le_bytes());
+        data.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        data.extend_from_slice(&header);
+        let at = data.len() + 3;
+        data.extend_from_slice(&program);
+        let fixup = Fixup::new(at, 8, FixupKind::Abs, &rows[0].0, 0);
+
+        let current = self.current;
+        self.switch(".debug_line", None)?;
+        self.push(Item::Bytes(data, vec![fixup]));
+        let index = self.current;
+        self.current = current;
+        self.layout(index)
+    }
+
+    // .eh_frame: one CIE with the state on entry to any function, then an
+    // FDE for each function with how that state changes along it
+    fn gen_frames(&mut self) -> Result<(), String> {
+        if self.frames.is_empty() {
+            return Ok(());
+        }
+        let frames = std::mem::take(&mut self.frames);
+        let mut data = Vec::new();
+        let mut fixups = Vec::new();
+
+        let mut cie = vec![0, 0, 0, 0, 1];
+        cie.extend_from_slice(b"zR\0");
+        cie.extend_from_slice(&[1, 0x78, 16]); // Code and data alignment, return address
+        cie.extend_from_slice(&[1, 0x1b]); // Addresses are pc-relative and 32-bit
+        cie.extend_from_slice(&[0x0c, 7, 8]); // The CFA is rsp+8
+        cie.extend_from_slice(&[0x90, 1]); // The return address is at CFA-8
+        while (cie.len() + 4) % 8 != 0 {
+            cie.push(0);
+        }
+        data.extend_from_slice(&(cie.len() as u32).to_le_bytes());
+        data.extend_from_slice(&cie);
+
+        let mut fdes = Vec::new();
+        let mut start = None;
+        let mut ops = Vec::new();
+        let mut loc = 0;
+        for (mark, cfi) in frames.iter() {
+            let (_, pos) = self.offset(mark)?;
+            match *cfi {
+                Cfi::StartProc => {
+                    start = Some((mark, pos));
+                    ops.clear();
+                    loc = pos;
+                }
+                Cfi::Op(ref bytes) => {
+                    let delta = pos - loc;
+                    if delta > 0 {
+                        if delta < 0x40 {
+                            ops.push(0x40 | delta as u8); // DW_CFA_advance_loc
+                        } else if delta < 0x100 {
+                            ops.push(0x02);
+                            ops.push(delta as u8);
+                        } else if delta < 0x10000 {
+                            ops.push(0x03);
+                            ops.extend_from_slice(&(delta as u16).to_le_bytes());
+                        } else {
+                            ops.push(0x04);
+                            ops.extend_from_slice(&(delta as u32).to_le_bytes());
+                        }
+                    }
+                    ops.extend_from_slice(bytes);
+                    loc = pos;
+                }
+                Cfi::EndProc => {
+                    let (begin, begin_pos) = match start.take() {
+                        Some(start) => start,
+                        None => return Err(".cfi_endproc without .cfi_startproc".to_string()),
+                    };
+                    let mut fde = vec![0; 8];
+                    fde.extend_from_slice(&((pos - begin_pos) as u32).to_le_bytes());
+                    fde.push(0); // No augmentation data
+                    fde.extend_from_slice(&ops);
+                    fdes.push((begin, fde));
+                }
+            }
+        }
+        // Each FDE is padded to 4 bytes, and the last one to 8
+        let count = fdes.len();
+        for (i, (begin, mut fde)) in fdes.into_iter().enumerate() {
+            let align = if i + 1 == count { 8 } else { 4 };
+            while (data.len() + 4 + fde.len()) % align != 0 {
+                fde.push(0);
+            }
+            let at = data.len();
+            fde[..4].copy_from_slice(&(at as u32 + 4).to_le_bytes()); // Back to the CIE
+            fixups.push(Fixup::new(at + 8, 4, FixupKind::Pc32, begin, 0));
+            data.extend_from_slice(&(fde.len() as u32).to_le_bytes());
+            data.extend_from_slice(&fde);
+        }
+
+        let current = self.current;
+        self.switch(".eh_frame", None)?;
+        let sec = &mut self.sections[self.current];
+        sec.align = 8;
+        sec.items.push(Item::Bytes(data, fixups));
+        let index = self.current;
+        self.current = current;
+        self.layout(index)
+    }
+
+    fn object(&self) -> Result<Object, String> {
+        let mut obj = Object::default();
+        let mut symbols: HashMap<&str, usize> = HashMap::new();
+        for (index, sec) in self.sections.iter().enumerate() {
+            for (i, item) in sec.items.iter().enumerate() {
+                match *item {
+                    Item::Label(ref name) if !name.starts_with(".L") => {
+                        symbols.insert(name, obj.symbols.len());
+                        obj.symbols.push(Symbol {
+                            name: name.clone(),
+                            place: Place::Section(index),
+                            value: sec.offsets[i],
+                            size: 0,
+                            kind: self.types.get(name).copied().unwrap_or(elf::STT_NOTYPE),
+                            global: self.globals.contains(name),
+                        });
+                    }
+                    _ => (),
+                }
+            }
+        }
+        for (name, size, align) in self.commons.iter() {
+            symbols.insert(name, obj.symbols.len());
+            obj.symbols.push(Symbol {
+                name: name.clone(),
+                place: Place::Common,
+                value: *align,
+                size: *size,
+                kind: elf::STT_OBJECT,
+                global: true,
+            });
+        }
+        for (name, size) in self.sizes.iter() {
+            let size = match *size {
+                Size::Const(size) => size,
+                Size::Since(ref sym, ref mark) => self.offset(mark)?.1 - self.offset(sym)?.1,
+            };
+            if let Some(&i) = symbols.get(name.as_str()) {
+                obj.symbols[i].size = size;
+            }
+        }
+
+        for (index, sec) in self.sections.iter().enumerate() {
+            let mut data = Vec::new();
+            let mut relocs = Vec::new();
+            for (i, item) in sec.items.iter().enumerate() {
+                let pos = sec.offsets[i];
+                match *item {
+                    Item::Bytes(ref bytes, ref fixups) => {
+                        let mut bytes = bytes.clone();
+                        for fixup in fixups.iter() {
+                            let at = pos + fixup.at as u64;
+                            let val = match self.resolve(index, at, fixup)? {
+                                Some(val) => val,
+                                None => {
+                                    let relative = fixup.kind != FixupKind::Abs
+                                        && fixup.kind != FixupKind::Abs32S;
+                                    let (target, addend) =
+                                        self.target(&fixup.sym, relative, &mut obj, &mut symbols);
+                                    let kind = match (fixup.kind, fixup.width) {
+                                        (FixupKind::Abs, 8) => elf::R_X86_64_64,
+                                        (FixupKind::Abs, 4) => elf::R_X86_64_32,
+                                        (FixupKind::Abs32S, 4) => elf::R_X86_64_32S,
+                                        (FixupKind::Pc32, 4) => elf::R_X86_64_PC32,
+                                        (FixupKind::Plt32, 4) => elf::R_X86_64_PLT32,
+                                        (FixupKind::GotPcRelX, 4) => elf::R_X86_64_REX_GOTPCRELX,
+                                        _ => {
+                                            return Err(format!(
+                                                "Cannot relocate {} bytes for {}",
+                                                fixup.width, fixup.sym
+                                            ))
+                                        }
+                                    };
+                                    relocs.push(Reloc {
+                                        offset: at,
+                                        target,
+                                        kind,
+                                        addend: fixup.addend + addend,
+                                    });
+                                    0
+                                }
+                            };
+                            let field = &mut bytes[fixup.at..fixup.at + fixup.width];
+                            field.copy_from_slice(&val.to_le_bytes()[..fixup.width]);
+                        }
+                        data.extend_from_slice(&bytes);
+                    }
+                    Item::Jump {
+                        cc,
+                        ref target,
+                        short,
+                    } => {
+                        let end = pos + encoder::jump_len(cc, short) as u64;
+                        let disp = match self.labels.get(target) {
+                            Some(&(s, at)) if s == index => sec.offsets[at] as i64 - end as i64,
+                            _ => {
+                                let (target, addend) =
+                                    self.target(target, true, &mut obj, &mut symbols);
+                                relocs.push(Reloc {
+                                    offset: end - 4,
+                                    target,
+                                    kind: elf::R_X86_64_PC32,
+                                    addend: addend - 4,
+                                });
+                                0
+                            }
+                        };
+                        data.extend_from_slice(&encoder::jump(cc, short, disp));
+                    }
+                    Item::Align(_) => data.resize(pos as usize, 0),
+                    Item::Label(_) => (),
+                }
+            }
+            if sec.kind == elf::SHT_NOBITS {
+                data.clear();
+            }
+            obj.sections.push(Section {
+                name: sec.name.clone(),
+                kind: sec.kind,
+                flags: sec.flags,
+                align: sec.align,
+                entsize: sec.entsize,
+                data,
+                size: sec.size,
+                relocs,
+            });
+        }
+        Ok(obj)
+    }
+
+    // Value of a field known without the linker: a difference of labels,
+    // or a relative reference to a local label in the same section
+    fn resolve(&self, index: usize, at: u64, fixup: &Fixup) -> Result<Option<i64>, String> {
+        if let Some(ref minus) = fixup.minus {
+            let (sec, to) = self.offset(&fixup.sym)?;
+            let (other, from) = self.offset(minus)?;
+            if sec != other {
+                return Err(format!(
+                    "{} and {} are in different sections",
+                    fixup.sym, minus
+                ));
+            }
+            return Ok(Some(to as i64 - from as i64 + fixup.addend));
+        }
+        let relative = matches!(fixup.kind, FixupKind::Pc32 | FixupKind::Plt32);
+        match self.labels.get(&fixup.sym) {
+            Some(&(sec, item)) if relative && sec == index && fixup.sym.starts_with(".L") => {
+                let to = self.sections[sec].offsets[item] as i64;
+                Ok(Some(to + fixup.addend - at as i64))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // What a relocation against name refers to, and what to add to it.
+    // Local labels become their section and offset. The linker tells the
+    // strings of a merged section apart by the offset, which a relative
+    // relocation skews, so those refer to the label itself.
+    fn target<'a>(
+        &'a self,
+        name: &'a str,
+        relative: bool,
+        obj: &mut Object,
+        symbols: &mut HashMap<&'a str, usize>,
+    ) -> (Target, i64) {
+        if let Some(&i) = symbols.get(name) {
+            return (Target::Symbol(i), 0);
+        }
+        let (place, value, global) = match self.labels.get(name) {
+            Some(&(sec, item)) => {
+                let value = self.sections[sec].offsets[item];
+                if !relative || self.sections[sec].flags & elf::SHF_MERGE == 0 {
+                    return (Target::Section(sec), value as i64);
+                }
+                (Place::Section(sec), value, false)
+            }
+            None => (Place::Undefined, 0, true),
+        };
+        symbols.insert(name, obj.symbols.len());
+        obj.symbols.push(Symbol {
+            name: name.to_string(),
+            place,
+            value,
+            size: 0,
+            kind: elf::STT_NOTYPE,
+            global,
+        });
+        (Target::Symbol(obj.symbols.len() - 1), 0)
+    }
+}
@@ -0,0 +1,6 @@
+// Language server for editors, speaking LSP over stdin and stdout
+use std::process;
+
+fn main() {
+    process::exit(rcc::lsp::run());
+}
@@ -1,11 +1,17 @@
 // Variables, literals, tags, scopes
-use crate::ctype::{EnumMember, Type};
+use crate::ctype::{EnumMember, StructDef, StructRef, Type};
+use crate::tokenizer::Span;
 use std::collections::VecDeque;
 
-#[derive(Debug)]
+/// A function declaration: name, type and span of the declarator
+pub type Prototype = (String, Type, Span);
+
+#[derive(Debug, Clone)]
 pub struct Env {
     pub literals: VecDeque<String>,
-    pub prototypes: Vec<(String, Type)>,
+    pub prototypes: Vec<Prototype>,
+    pub structs: Vec<StructRef>, // Every struct and union, in order of appearance
+    pub enums: Vec<Type>,        // Every tagged enum at file scope, in order of appearance
     pub scopes: Scopes,
 }
 
@@ -15,6 +21,8 @@ pub struct Var {
     pub ty: Type,
     pub offset: Option<usize>, // None if global
     pub scope: usize,          // 0 if global
+    pub is_extern: bool,       // Declared but defined elsewhere
+    pub span: Span,            // Of the name, in a declaration that defines it if any
 }
 
 #[derive(Debug, Clone)]
@@ -22,15 +30,39 @@ pub struct Tag {
     pub name: String,
     pub ty: Type,
     pub scope: usize, // 0 if global
+    pub span: Span,   // Of the name where it is first declared
 }
 
 #[derive(Debug, Clone)]
 pub struct EnumConst {
     pub member: EnumMember,
     pub scope: usize,
+    pub span: Span,
+}
+
+/// What kind of entity an identifier names
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RefKind {
+    Var,
+    Func,
+    Tag,
+    Const(i32),
 }
 
-#[derive(Debug)]
+/// An identifier in the source together with the declaration it resolves
+/// to in the scopes where it appears. The parser records these for the
+/// language server; a declaration refers to itself.
+#[derive(Debug, Clone)]
+pub struct Ref {
+    pub name: String,
+    pub kind: RefKind,
+    pub ty: Type,
+    pub span: Span,   // Of this identifier
+    pub decl: Span,   // Of the identifier in the declaration
+    pub scope: usize, // Of the declaration; 0 if global
+}
+
+#[derive(Debug, Clone)]
 pub struct Scopes {
     vars: Vec<Var>,
     consts: Vec<EnumConst>,
@@ -44,6 +76,8 @@ impl Env {
         Env {
             literals: VecDeque::new(),
             prototypes: Vec::new(),
+            structs: Vec::new(),
+            enums: Vec::new(),
             scopes: Scopes::new(),
         }
     }
@@ -55,23 +89,94 @@ impl Env {
         pos
     }
 
-    pub fn add_prototype(&mut self, name: String, ty: Type) {
-        self.prototypes.push((name, ty));
+    /// Declares a function. Every declaration of a name has to agree with
+    /// the earlier ones, and what is known from them is merged, e.g. the
+    /// parameters of "int f(int)" are kept by a later "int f()".
+    pub fn add_prototype(&mut self, name: String, ty: Type, span: Span) -> Result<(), String> {
+        if self.scopes.globals().any(|var| var.name == name) {
+            return Err(format!(
+                "'{}' redeclared as a different kind of symbol",
+                name
+            ));
+        }
+        let ty = match self.find_prototype(&name) {
+            Some(prev) => match ty.composite(prev) {
+                Some(ty) => ty,
+                None => return Err(format!("Conflicting types for '{}'", name)),
+            },
+            None => ty,
+        };
+        self.prototypes.push((name, ty, span));
+        Ok(())
     }
 
-    pub fn is_prototype(&mut self, ident: &str) -> bool {
-        if let Some(_) = self.prototypes.iter().find(|(name, _)| name == ident) {
-            true
-        } else {
-            false
+    /// Adds a variable with static storage; see Scopes::add_global.
+    /// Functions and objects at file scope share one name space.
+    pub fn add_global(
+        &mut self,
+        name: String,
+        ty: Type,
+        is_extern: bool,
+        span: Span,
+    ) -> Result<Var, String> {
+        if self.find_prototype(&name).is_some() {
+            return Err(format!(
+                "'{}' redeclared as a different kind of symbol",
+                name
+            ));
         }
+        self.scopes.add_global(name, ty, is_extern, span)
     }
 
-    pub fn get_symbols(self) -> (Vec<Var>, VecDeque<String>) {
+    /// Type of the latest prototype with the name, if any
+    pub fn find_prototype(&self, ident: &str) -> Option<&Type> {
+        self.prototypes
+            .iter()
+            .rev()
+            .find(|(name, _, _)| name == ident)
+            .map(|(_, ty, _)| ty)
+    }
+
+    /// Creates a struct or union that is yet to be completed. Tags that
+    /// are declared again in another scope get a unique name, so that all
+    /// of them can be printed at file scope.
+    pub fn add_struct(&mut self, tag: Option<String>, is_union: bool) -> StructRef {
+        let name = tag.map(|tag| {
+            let taken = self
+                .structs
+                .iter()
+                .any(|def| def.borrow().name.as_deref() == Some(tag.as_str()));
+            if taken {
+                format!("{}__{}", tag, self.structs.len())
+            } else {
+                tag
+            }
+        });
+        let def = StructDef::new(name, is_union);
+        self.structs.push(def.clone());
+        def
+    }
+
+    /// Creates a complete enum. Unlike struct tags, only those at file
+    /// scope are kept, as the constants of the others are local too.
+    pub fn add_enum(&mut self, tag: Option<String>, members: Vec<EnumMember>) -> Type {
+        let ty = Type::new_enum(tag, members);
+        if ty.enum_tag().is_some() && self.scopes.level == 0 {
+            self.enums.push(ty.clone());
+        }
+        ty
+    }
+
+    pub fn get_symbols(self) -> (Vec<Var>, VecDeque<String>, Vec<Prototype>, Vec<StructRef>) {
         if self.scopes.level != 0 {
             panic!("Trying to exit env from non-global level.")
         }
-        (self.scopes.vars, self.literals)
+        (
+            self.scopes.vars,
+            self.literals,
+            self.prototypes,
+            self.structs,
+        )
     }
 }
 
@@ -96,19 +201,19 @@ impl Scopes {
     /// Returns offset only if exiting local context, i.e. level 1 -> 0
     pub fn remove_scope(&mut self) -> Option<usize> {
         // Pop everything in this scope
-        while let Some(ref var) = self.vars.last() {
+        while let Some(var) = self.vars.last() {
             if var.scope != self.level {
                 break;
             }
             self.vars.pop();
         }
-        while let Some(ref tag) = self.tags.last() {
+        while let Some(tag) = self.tags.last() {
             if tag.scope != self.level {
                 break;
             }
             self.tags.pop();
         }
-        while let Some(ref ec) = self.consts.last() {
+        while let Some(ec) = self.consts.last() {
             if ec.scope != self.level {
                 break;
             }
@@ -117,19 +222,23 @@ impl Scopes {
 
         self.level -= 1;
         // Check if we just moved out of local context
-        let retofs = if self.level == 0 {
+
+        if self.level == 0 {
             let tmp = Some(self.offset);
             self.offset = 0;
             tmp
         } else {
             None
-        };
-        retofs
+        }
     }
 
     // Vars
-    pub fn add_var(&mut self, ident_name: String, ty: Type) -> Var {
-        // TODO: Protect against collision with const names
+    /// Adds a local variable, which may not share its name with anything
+    /// else declared in the same scope
+    pub fn add_var(&mut self, ident_name: String, ty: Type, span: Span) -> Result<Var, String> {
+        if self.declared_in_scope(&ident_name) {
+            return Err(format!("Redefinition of '{}'", ident_name));
+        }
         let offset = if self.level > 0 {
             // This is local; perform the computation
             let requested_size = ty.total_size();
@@ -141,12 +250,104 @@ impl Scopes {
 
         let var = Var {
             name: ident_name,
-            ty: ty,
-            offset: offset,
+            ty,
+            offset,
+            scope: self.level,
+            is_extern: false,
+            span,
+        };
+        self.vars.push(var.clone());
+        Ok(var)
+    }
+
+    /// Adds a variable with static storage, i.e. a global or one declared
+    /// "extern" in a block. A global declared before is merged with the
+    /// earlier declaration, which may complete its type or turn it from
+    /// extern into a definition. Declaring one extern in a block has to
+    /// agree with the global and with the block's earlier declarations.
+    pub fn add_global(
+        &mut self,
+        ident_name: String,
+        ty: Type,
+        is_extern: bool,
+        span: Span,
+    ) -> Result<Var, String> {
+        if self.level == 0 {
+            let found = self
+                .vars
+                .iter_mut()
+                .find(|x| x.scope == 0 && x.name == ident_name);
+            if let Some(var) = found {
+                var.ty = match var.ty.composite(&ty) {
+                    Some(ty) => ty,
+                    None => return Err(format!("Conflicting types for '{}'", ident_name)),
+                };
+                if var.is_extern && !is_extern {
+                    var.span = span;
+                }
+                var.is_extern &= is_extern;
+                return Ok(var.clone());
+            }
+            if self
+                .consts
+                .iter()
+                .any(|ec| ec.scope == 0 && ec.member.name == ident_name)
+            {
+                return Err(format!("Redefinition of '{}'", ident_name));
+            }
+        } else {
+            let conflicts = |var: &Var| {
+                var.name == ident_name
+                    && ((var.scope == 0 && !var.ty.is_compatible(&ty))
+                        || (var.scope == self.level
+                            && !(var.is_extern && var.ty.is_compatible(&ty))))
+            };
+            if let Some(var) = self.vars.iter().find(|var| conflicts(var)) {
+                return Err(if var.scope == 0 {
+                    format!("Conflicting types for '{}'", ident_name)
+                } else {
+                    format!("Redefinition of '{}'", ident_name)
+                });
+            }
+            if self
+                .consts
+                .iter()
+                .any(|ec| ec.scope == self.level && ec.member.name == ident_name)
+            {
+                return Err(format!("Redefinition of '{}'", ident_name));
+            }
+        }
+
+        let var = Var {
+            name: ident_name,
+            ty,
+            offset: None,
             scope: self.level,
+            is_extern,
+            span,
         };
         self.vars.push(var.clone());
-        var
+        Ok(var)
+    }
+
+    // Whether a variable or an enum constant has the name in this scope
+    fn declared_in_scope(&self, name: &str) -> bool {
+        self.vars
+            .iter()
+            .any(|var| var.scope == self.level && var.name == name)
+            || self
+                .consts
+                .iter()
+                .any(|ec| ec.scope == self.level && ec.member.name == name)
+    }
+
+    /// Level of the current scope; 0 when global
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = &Var> {
+        self.vars.iter().filter(|x| x.scope == 0)
     }
 
     pub fn find_var(&self, ident_name: &str) -> Option<&Var> {
@@ -155,11 +356,16 @@ impl Scopes {
     }
 
     // Consts
-    pub fn add_const(&mut self, member: EnumMember) {
+    pub fn add_const(&mut self, member: EnumMember, span: Span) -> Result<(), String> {
+        if self.declared_in_scope(&member.name) {
+            return Err(format!("Redefinition of '{}'", member.name));
+        }
         self.consts.push(EnumConst {
-            member: member,
+            member,
             scope: self.level,
+            span,
         });
+        Ok(())
     }
 
     pub fn find_const(&mut self, name: &str) -> Option<&EnumConst> {
@@ -170,7 +376,7 @@ impl Scopes {
     /// Adds a tag with the provided ty
     /// If an identically named tag is already present in the curernt scope,
     /// try to update it with the provided type.
-    pub fn add_tag(&mut self, name: String, ty: Type) {
+    pub fn add_tag(&mut self, name: String, ty: Type, span: Span) {
         if let Some(tag) = self.find_tag(name.as_str()) {
             if tag.scope == self.level {
                 self.update_tag(name.as_str(), ty);
@@ -178,9 +384,10 @@ impl Scopes {
             }
         }
         self.tags.push(Tag {
-            name: name,
-            ty: ty,
+            name,
+            ty,
             scope: self.level,
+            span,
         });
     }
 
@@ -205,4 +412,13 @@ impl Scopes {
     pub fn find_tag(&mut self, name: &str) -> Option<&Tag> {
         self.tags.iter().rev().find(|x| x.name == name)
     }
+
+    /// Finds the tag only if it was declared in the current scope
+    pub fn find_tag_in_scope(&self, name: &str) -> Option<&Tag> {
+        self.tags
+            .iter()
+            .rev()
+            .find(|x| x.name == name)
+            .filter(|x| x.scope == self.level)
+    }
 }
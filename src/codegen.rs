@@ -1,38 +1,77 @@
-use crate::ctype::Type;
-use crate::node::{AssignMode, Node, NodeKind};
-use crate::parser::Program;
-use std::collections::LinkedList;
-use std::fs::File;
+// x86-64 emission from the IR.
+// Below the locals the frame holds the saved callee-saved registers,
+// followed by the spill slots handed out by the register allocator.
+// Instructions of a function are buffered and run through the peephole
+// pass before being written out. Under -g, directives for the debugger are
+// kept apart from the instructions and placed again once they are final.
+use crate::asm::{self, Line, Operand};
+use crate::constexpr::ConstVal;
+use crate::dwarf::{self, DebugInfo};
+use crate::ir::*;
+use crate::options::{AsmSyntax, CodeModel};
+use crate::peephole;
+use crate::regalloc::{self, Allocation, Location};
+use crate::tokenizer;
+use std::collections::HashSet;
 use std::io::Write;
 
-static FUNC_REGS_4: [&str; 6] = ["edi", "esi", "edx", "ecx", "r8d", "r9d"];
 static FUNC_REGS_8: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
 
-static MAGIC: usize = 141421356;
-static LITERAL_HEAD: &str = ".Lstr";
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-enum LevelKind {
-    SWITCH,
-    WHILE,
-    DOWHILE,
-    FOR,
-}
+// Switches with fewer cases compare against each in turn, as do the
+// leaves of a binary search
+const SWITCH_CHAIN_MAX: usize = 4;
+// Largest ratio of table entries to cases for a jump table
+const SWITCH_TABLE_SPREAD: i64 = 3;
 
 pub struct CodeGen<'a> {
-    f: &'a mut File,
-    prog: Program,
-    cond_label: usize, // Next cond label to be issued
-    conds: LinkedList<(usize, LevelKind)>,
+    f: &'a mut dyn Write,
+    module: Module,
+    peephole: bool,                   // Whether to run the peephole pass
+    code_model: CodeModel,            // How addresses of symbols are taken
+    syntax: AsmSyntax,                // Dialect the instructions are written in
+    defined: HashSet<String>,         // Functions and globals defined in the module
+    buf: Vec<Line>,                   // Instructions of the current function
+    func_index: usize,                // Index of the function being emitted, for labels
+    num_labels: usize,                // Labels made up so far in the current function
+    frame_size: usize,                // Bytes used by locals of the current function
+    alloc: Allocation,                // Register allocation of the current function
+    debug: Option<DebugInfo>,         // What to describe to debuggers under -g
+    directives: Vec<(usize, String)>, // Debugger directives and the index in buf they precede
 }
 
 impl<'a> CodeGen<'a> {
-    pub fn new(f: &'a mut File, prog: Program) -> Self {
+    pub fn new(
+        f: &'a mut dyn Write,
+        module: Module,
+        peephole: bool,
+        code_model: CodeModel,
+        syntax: AsmSyntax,
+        debug: Option<DebugInfo>,
+    ) -> Self {
+        let defined = module
+            .functions
+            .iter()
+            .map(|func| func.name.clone())
+            .chain(module.globals.iter().map(|gvar| gvar.name.clone()))
+            .collect();
         CodeGen {
-            f: f,
-            prog: prog,
-            cond_label: 0,
-            conds: LinkedList::new(),
+            f,
+            module,
+            peephole,
+            code_model,
+            syntax,
+            defined,
+            buf: Vec::new(),
+            func_index: 0,
+            num_labels: 0,
+            frame_size: 0,
+            alloc: Allocation {
+                locs: Vec::new(),
+                num_spills: 0,
+                callee_saved: Vec::new(),
+            },
+            debug,
+            directives: Vec::new(),
         }
     }
 
@@ -40,714 +79,699 @@ impl<'a> CodeGen<'a> {
         self.gen_preamble();
         self.gen_data();
         self.gen_text();
+        if let Some(ref debug) = self.debug {
+            debug.gen_sections(self.f);
+        }
+        // The stack need not be executable
+        gen_line!(self.f, "\n.section .note.GNU-stack,\"\",@progbits\n");
     }
 
-    fn issue_level(&mut self) -> usize {
-        let issued = self.cond_label;
-        self.cond_label += 1;
-        issued
-    }
-
-    fn push_level(&mut self, kind: LevelKind) -> usize {
-        let current = self.issue_level();
-        self.conds.push_back((current, kind));
-        current
-    }
-
-    fn pop_level(&mut self) {
-        self.conds.pop_back();
+    fn gen_preamble(&mut self) {
+        if self.syntax == AsmSyntax::Intel {
+            gen_line!(self.f, ".intel_syntax noprefix\n");
+        }
+        if let Some(ref debug) = self.debug {
+            debug.gen_file(self.f);
+        }
     }
 
-    fn get_current_level(&self) -> (usize, LevelKind) {
-        self.conds.back().unwrap().clone()
+    // Tentative definitions become common symbols, so that the ones in
+    // other translation units are merged with them by the linker. The
+    // rest go by whether they may change and hold anything but zeros.
+    fn gen_data(&mut self) {
+        let globals = std::mem::take(&mut self.module.globals);
+        if globals.iter().any(|gvar| gvar.tentative) {
+            gen_line!(self.f, "\n");
+        }
+        for gvar in globals.iter().filter(|gvar| gvar.tentative) {
+            gen_line!(
+                self.f,
+                ".comm {}, {}, {}\n",
+                gvar.name,
+                gvar.size,
+                common_align(gvar.size)
+            );
+        }
+        let (rodata, rest): (Vec<&Global>, Vec<&Global>) = globals
+            .iter()
+            .filter(|gvar| !gvar.tentative)
+            .partition(|gvar| gvar.readonly);
+        let (bss, data): (Vec<&Global>, Vec<&Global>) =
+            rest.into_iter().partition(|gvar| gvar.is_zero());
+        let (relro, rodata): (Vec<&Global>, Vec<&Global>) = rodata.into_iter().partition(|gvar| {
+            gvar.init
+                .iter()
+                .any(|datum| matches!(datum.val, ConstVal::Addr(..)))
+        });
+        self.gen_section(".data", &data, false);
+        self.gen_section(self.readonly_section(false), &rodata, false);
+        self.gen_section(self.readonly_section(true), &relro, false);
+        self.gen_section(".bss", &bss, true);
+        self.gen_literals();
+        gen_line!(self.f, "\n");
     }
 
-    fn gen_data(&mut self) {
-        gen_line!(self.f, ".data\n");
-
-        let mut variter = self.prog.globals.iter();
-        loop {
-            if let Some(ref gvar) = variter.next() {
-                gen_line!(self.f, "{}:\n", gvar.name);
-                gen_line!(self.f, "  .zero {}\n", gvar.ty.total_size());
-            } else {
-                break;
+    fn gen_section(&mut self, section: &str, globals: &[&Global], zero: bool) {
+        if globals.is_empty() {
+            return;
+        }
+        gen_line!(self.f, "\n{}\n", section);
+        for gvar in globals.iter() {
+            gen_line!(self.f, ".global {}\n", gvar.name);
+            gen_line!(self.f, ".type {}, @object\n", gvar.name);
+            gen_line!(self.f, ".size {}, {}\n", gvar.name, gvar.size);
+            let align = common_align(gvar.size);
+            gen_line!(self.f, ".p2align {}\n", align.trailing_zeros());
+            gen_line!(self.f, "{}:\n", gvar.name);
+            let mut pos = 0;
+            for datum in gvar.init.iter().filter(|_| !zero) {
+                if datum.offset > pos {
+                    gen_line!(self.f, "  .zero {}\n", datum.offset - pos);
+                }
+                let directive = match datum.width {
+                    Width::I8 => ".byte",
+                    Width::I16 => ".short",
+                    Width::I32 => ".long",
+                    Width::I64 => ".quad",
+                };
+                match datum.val {
+                    ConstVal::Int(v) => gen_line!(self.f, "  {} {}\n", directive, v),
+                    ConstVal::Addr(ref sym, 0) => gen_line!(self.f, "  {} {}\n", directive, sym),
+                    ConstVal::Addr(ref sym, ofs) => {
+                        gen_line!(self.f, "  {} {}{:+}\n", directive, sym, ofs)
+                    }
+                }
+                pos = datum.offset + datum.width.size();
+            }
+            if gvar.size > pos {
+                gen_line!(self.f, "  .zero {}\n", gvar.size - pos);
             }
         }
+    }
 
-        let mut literal_count = 0;
-        loop {
-            if let Some(literal) = self.prog.literals.pop_front() {
-                gen_line!(self.f, "{}{}:\n", LITERAL_HEAD, literal_count);
+    // Literals are read-only. Identical ones share their bytes, and the
+    // linker merges them with those of other objects. A string holding a
+    // NUL would be cut there, so such ones are kept apart.
+    fn gen_literals(&mut self) {
+        let literals = std::mem::take(&mut self.module.literals);
+        let (plain, merged): (Vec<_>, Vec<_>) = literals
+            .iter()
+            .enumerate()
+            .partition(|(_, literal)| tokenizer::unescape(literal).contains(&0));
+        for (section, group) in [
+            (".section .rodata.str1.1,\"aMS\",@progbits,1", merged),
+            (".section .rodata", plain),
+        ] {
+            if group.is_empty() {
+                continue;
+            }
+            gen_line!(self.f, "\n{}\n", section);
+            for (i, &(_, literal)) in group.iter().enumerate() {
+                if group[..i].iter().any(|&(_, prev)| prev == literal) {
+                    continue;
+                }
+                for &(pos, _) in group.iter().filter(|&&(_, same)| same == literal) {
+                    gen_line!(self.f, "{}{}:\n", LITERAL_HEAD, pos);
+                }
                 gen_line!(self.f, "  .string \"{}\"\n", literal);
-                literal_count += 1;
-            } else {
-                break;
             }
         }
-
-        gen_line!(self.f, "\n");
     }
 
     fn gen_text(&mut self) {
         gen_line!(self.f, ".text\n");
-        loop {
-            if let Some(node) = self.prog.nodes.pop_front() {
-                self.gen(node);
-            } else {
-                break;
-            }
+        if self.debug.is_some() {
+            gen_line!(self.f, "{}:\n", dwarf::TEXT_BEGIN);
+        }
+        let functions = std::mem::take(&mut self.module.functions);
+        for (index, func) in functions.iter().enumerate() {
+            self.func_index = index;
+            self.gen_function(func);
+        }
+        if self.debug.is_some() {
+            gen_line!(self.f, "{}:\n", dwarf::TEXT_END);
         }
     }
 
-    fn gen_preamble(&mut self) {
-        gen_line!(self.f, ".intel_syntax noprefix\n");
-        gen_line!(self.f, ".global main\n\n");
+    fn label(&self, block: BlockId) -> String {
+        format!(".LBB{}_{}", self.func_index, block.0)
     }
 
-    fn gen_push_magic(&mut self) {
-        gen_line!(self.f, "  push {}\n", MAGIC);
+    // Label for a spot within a block, or for a jump table
+    fn new_label(&mut self, kind: &str) -> String {
+        self.num_labels += 1;
+        format!(".L{}{}_{}", kind, self.func_index, self.num_labels - 1)
     }
 
-    fn gen_lval(&mut self, node: Node) {
-        use NodeKind::*;
+    fn emit(&mut self, op: &'static str, args: Vec<Operand>) {
+        self.buf.push(Line::Inst(op, args));
+    }
 
-        match node.kind {
-            NDLVAR { offset } => {
-                gen_line!(self.f, "  mov rax, rbp\n");
-                gen_line!(self.f, "  sub rax, {}\n", offset);
-                gen_line!(self.f, "  push rax\n");
-            }
-            NDGVAR { name } | NDPROTOTY { name } => {
-                gen_line!(self.f, "  push offset {}\n", name);
-            }
-            NDSTR { pos } => {
-                gen_line!(self.f, "  push offset {}{}\n", LITERAL_HEAD, pos);
-            }
-            NDDEREF { node: operand } => {
-                self.gen(*operand);
-            }
-            NDMEMBER {
-                node: varnode,
-                offset: relative_offset,
-                ..
-            } => {
-                self.gen_lval(*varnode);
-                gen_line!(self.f, "  pop rax\n");
-                gen_line!(self.f, "  add rax, {}\n", relative_offset.unwrap());
-                gen_line!(self.f, "  push rax\n");
-            }
-            _ => {
-                panic!("Unexpected node: got {:?}", node.kind);
-            }
+    // Places a directive before the next instruction, under -g only
+    fn directive(&mut self, text: String) {
+        if self.debug.is_some() {
+            self.directives.push((self.buf.len(), text));
         }
     }
 
-    fn gen_load(&mut self, size: usize) {
-        gen_line!(self.f, "  pop rax\n");
+    fn save_offset(&self, index: usize) -> usize {
+        self.frame_size + 8 * (index + 1)
+    }
 
-        match size {
-            1 => {
-                gen_line!(self.f, "  movsx rax, byte ptr [rax]\n");
-            }
-            2 => {
-                gen_line!(self.f, "  movsx rax, word ptr [rax]\n");
-            }
-            4 => {
-                gen_line!(self.f, "  movsxd rax, dword ptr [rax]\n");
-            }
-            8 => {
-                gen_line!(self.f, "  mov rax, [rax]\n");
-            }
-            _ => {
-                panic!("Codegen: Load requested on an invalid size.");
+    // Operand naming the 64-bit home of reg
+    fn opnd(&self, reg: VReg) -> Operand {
+        match self.alloc.locs[reg.0] {
+            Location::Reg(r) => Operand::Reg(r.name(Width::I64)),
+            Location::Spill(slot) => {
+                let saved = self.alloc.callee_saved.len();
+                Operand::mem(Some(Width::I64), "rbp", self.save_offset(saved + slot))
             }
         }
-        gen_line!(self.f, "  push rax\n");
     }
 
-    fn gen_store(&mut self, size: usize) {
-        gen_line!(self.f, "  pop rdi\n");
-        gen_line!(self.f, "  pop rax\n");
-
-        let src = match size {
-            1 => "dil",
-            2 => "di",
-            4 => "edi",
-            8 => "rdi",
-            _ => {
-                panic!("Codegen: Store requested on an invalid size.");
-            }
-        };
+    // Register to compute reg into: its own, or scratch if it was spilled
+    fn dst_reg(&self, reg: VReg, scratch: &'static str) -> Operand {
+        match self.alloc.locs[reg.0] {
+            Location::Reg(r) => Operand::Reg(r.name(Width::I64)),
+            Location::Spill(_) => Operand::Reg(scratch),
+        }
+    }
 
-        gen_line!(self.f, "  mov [rax], {}\n", src);
-        gen_line!(self.f, "  push rdi\n");
+    // Writes back a value computed by dst_reg into a spill slot
+    fn finish(&mut self, reg: VReg, computed: Operand) {
+        let home = self.opnd(reg);
+        self.mov(home, computed);
     }
 
-    fn gen_blockstmts(&mut self, mut blockstmts: LinkedList<Node>) {
-        // Let empty block evaluate to 0
-        if blockstmts.len() == 0 {
-            gen_line!(self.f, "  push 0\n");
-            return;
-        }
-        while let Some(stmt) = blockstmts.pop_front() {
-            self.gen(stmt);
-            if blockstmts.len() != 0 {
-                gen_line!(self.f, "  pop rax\n");
+    // reg as a register operand, reloading it into scratch if spilled
+    fn in_reg(&mut self, reg: VReg, scratch: &'static str) -> Operand {
+        match self.alloc.locs[reg.0] {
+            Location::Reg(r) => Operand::Reg(r.name(Width::I64)),
+            Location::Spill(_) => {
+                let home = self.opnd(reg);
+                self.mov(Operand::Reg(scratch), home);
+                Operand::Reg(scratch)
             }
         }
     }
 
-    // Set the last result to rax, restore the rbp and return
-    fn gen_return(&mut self) {
-        gen_line!(self.f, "  pop rax\n");
-        gen_line!(self.f, "  mov rsp, rbp\n");
-        gen_line!(self.f, "  pop rbp\n");
-        gen_line!(self.f, "  pop r12\n");
-        gen_line!(self.f, "  ret\n");
+    // Base register of a memory operand; spilled addresses go through scratch
+    fn base_reg(&mut self, reg: VReg, scratch: &'static str) -> &'static str {
+        match self.in_reg(reg, scratch) {
+            Operand::Reg(name) => name,
+            _ => unreachable!(),
+        }
     }
 
-    // For NDLOGAND and NDLOGOR
-    fn gen_logical(&mut self, lhs: Node, rhs: Node, is_and: bool) {
-        // Only evaluate the rhs if the lhs evaluates to 1
-        // as per C89 6.3.13 and 6.3.14
-        let my_label = self.issue_level();
-        let instr = if is_and { "je" } else { "jne" };
-
-        self.gen(lhs);
-        gen_line!(self.f, "  pop rax\n");
-        gen_line!(self.f, "  cmp rax, 0\n");
-        gen_line!(self.f, "  setne al\n");
-        gen_line!(self.f, "  movzb rax, al\n");
-        gen_line!(self.f, "  {} .Lend{}\n", instr, my_label);
-
-        self.gen(rhs);
-        gen_line!(self.f, "  pop rax\n");
-        gen_line!(self.f, "  cmp rax, 0\n");
-        gen_line!(self.f, "  setne al\n");
-        gen_line!(self.f, "  movzb rax, al\n");
-
-        gen_line!(self.f, ".Lend{}:\n", my_label);
-        gen_line!(self.f, "  push rax\n");
+    fn mov(&mut self, dst: Operand, src: Operand) {
+        if dst != src {
+            self.emit("mov", vec![dst, src]);
+        }
     }
 
-    // Entry point into codegen
-    pub fn gen(&mut self, mut node: Node) {
-        use NodeKind::*;
+    fn gen_function(&mut self, func: &Function) {
+        self.frame_size = func.frame_size;
+        self.num_labels = 0;
+        self.alloc = regalloc::allocate(func);
+        // Keep rsp 16-byte aligned so that calls need no adjustment
+        let slots = self.alloc.callee_saved.len() + self.alloc.num_spills;
+        let total = (func.frame_size + 8 * slots).div_ceil(16) * 16;
+
+        gen_line!(self.f, ".global {}\n", func.name);
+        gen_line!(self.f, ".type {}, @function\n", func.name);
+        self.buf.push(Line::Label(func.name.clone()));
+        // The canonical frame address is where rsp was before the call
+        self.directive(".cfi_startproc".to_string());
+        if let Some(line) = self.debug.as_ref().and_then(|d| d.func_line(&func.name)) {
+            self.directive(format!(".loc 1 {} 0", line));
+        }
+        self.emit("push", vec![Operand::Reg("rbp")]);
+        self.directive(".cfi_def_cfa_offset 16".to_string());
+        let rbp = asm::reg_in("rbp", self.syntax);
+        self.directive(format!(".cfi_offset {}, -16", rbp));
+        self.emit("mov", vec![Operand::Reg("rbp"), Operand::Reg("rsp")]);
+        self.directive(format!(".cfi_def_cfa_register {}", rbp));
+        if total > 0 {
+            self.emit("sub", vec![Operand::Reg("rsp"), Operand::Imm(total as i64)]);
+        }
+        for i in 0..self.alloc.callee_saved.len() {
+            let reg = self.alloc.callee_saved[i].name(Width::I64);
+            let slot = Operand::mem(None, "rbp", self.save_offset(i));
+            self.emit("mov", vec![slot, Operand::Reg(reg)]);
+        }
+
+        for (id, block) in func.blocks.iter().enumerate() {
+            let label = self.label(BlockId(id));
+            self.buf.push(Line::Label(label));
+            for inst in block.insts.iter() {
+                self.gen_inst(inst);
+            }
+            self.gen_terminator(&block.term, BlockId(id + 1));
+        }
 
-        match node.kind {
-            NDINT { val } => {
-                gen_line!(self.f, "  push {}\n", val);
+        let mut lines = std::mem::take(&mut self.buf);
+        let origins = if self.peephole {
+            peephole::optimize(&mut lines)
+        } else {
+            (0..lines.len()).collect()
+        };
+        // A directive goes before the first line left from those it preceded
+        let mut directives = std::mem::take(&mut self.directives).into_iter().peekable();
+        for (line, origin) in lines.iter().zip(origins) {
+            while let Some((_, text)) = directives.next_if(|(at, _)| *at <= origin) {
+                gen_line!(self.f, "  {}\n", text);
             }
-            NDSTR { .. } => {
-                self.gen_lval(node);
+            gen_line!(self.f, "{}\n", line.display(self.syntax));
+        }
+        for (_, text) in directives {
+            gen_line!(self.f, "  {}\n", text);
+        }
+        if self.debug.is_some() {
+            gen_line!(self.f, "  .cfi_endproc\n");
+            gen_line!(self.f, "{}:\n", dwarf::func_end(&func.name));
+        }
+        gen_line!(self.f, ".size {}, .-{}\n", func.name, func.name);
+
+        for line in lines.iter() {
+            if let Line::Inst("jmp", args) = line {
+                if let Operand::Table {
+                    ref name,
+                    ref targets,
+                    ..
+                } = args[0]
+                {
+                    let section = self.readonly_section(true);
+                    gen_line!(self.f, "{}\n", section);
+                    gen_line!(self.f, "  .p2align 3\n");
+                    gen_line!(self.f, "{}:\n", name);
+                    for target in targets.iter() {
+                        gen_line!(self.f, "  .quad {}\n", target);
+                    }
+                    gen_line!(self.f, ".text\n");
+                }
             }
-            NDLVAR { .. } | NDGVAR { .. } | NDMEMBER { .. } => {
-                if node.ty.as_ref().unwrap().is_array() {
-                    self.gen_lval(node);
+        }
+    }
+
+    fn gen_inst(&mut self, inst: &Inst) {
+        use Inst::*;
+        use Operand::{Imm, Reg};
+
+        match *inst {
+            Const { dst, val } => {
+                let home = self.opnd(dst);
+                // Only registers take 64-bit immediates
+                let in_reg = matches!(self.alloc.locs[dst.0], Location::Reg(_));
+                if in_reg || val == val as i32 as i64 {
+                    self.emit("mov", vec![home, Imm(val)]);
                 } else {
-                    let size = node.ty.as_ref().unwrap().size();
-                    self.gen_lval(node);
-                    self.gen_load(size);
+                    self.emit("mov", vec![Reg("rax"), Imm(val)]);
+                    self.finish(dst, Reg("rax"));
                 }
             }
-            NDPROTOTY { .. } => {
-                self.gen_lval(node);
+            Copy { dst, src } => {
+                let d = self.dst_reg(dst, "rax");
+                let s = self.opnd(src);
+                self.mov(d.clone(), s);
+                self.finish(dst, d);
+            }
+            Bin { dst, op, lhs, rhs } => self.gen_bin(dst, op, lhs, rhs),
+            Un {
+                dst,
+                op: UnOp::Not,
+                src,
+            } => {
+                let d = self.dst_reg(dst, "rax");
+                let s = self.opnd(src);
+                self.mov(d.clone(), s);
+                self.emit("not", vec![d.clone()]);
+                self.finish(dst, d);
+            }
+            Un {
+                dst,
+                op: UnOp::Sext(width),
+                src,
+            } => {
+                // Go through rax for a sub-register that exists for every source
+                let s = self.opnd(src);
+                self.mov(Reg("rax"), s);
+                let d = self.dst_reg(dst, "rax");
+                match width {
+                    Width::I8 => self.emit("movsx", vec![d.clone(), Reg("al")]),
+                    Width::I16 => self.emit("movsx", vec![d.clone(), Reg("ax")]),
+                    Width::I32 => self.emit("movsxd", vec![d.clone(), Reg("eax")]),
+                    Width::I64 => self.mov(d.clone(), Reg("rax")),
+                }
+                self.finish(dst, d);
             }
-            NDASSIGN {
+            Cmp {
+                dst,
+                cond,
                 lhs,
                 rhs,
-                scale_lhs,
-                eval_pre,
-                assign_mode,
-                ..
             } => {
-                use AssignMode::*;
-                if assign_mode == DEFAULT {
-                    self.gen_lval(*lhs);
-                    self.gen(*rhs);
-                    self.gen_store(node.ty.unwrap().size());
-                    return;
-                }
-
-                // Needs to be done on a register
-                let size = lhs.as_ref().ty.as_ref().unwrap().size();
-                self.gen_lval(*lhs);
-                // Duplicate the address for later store
-                gen_line!(self.f, "  pop rax\n");
-                gen_line!(self.f, "  push rax\n");
-                gen_line!(self.f, "  push rax\n");
-                self.gen_load(size);
-                self.gen(*rhs);
-
-                if let Some(to_scale) = scale_lhs {
-                    if to_scale {
-                        if assign_mode == MUL || assign_mode == DIV {
-                            panic!("Scaling should not be allowed for this node.");
+                let l = self.in_reg(lhs, "rax");
+                let r = self.opnd(rhs);
+                self.emit("cmp", vec![l, r]);
+                let set = match cond {
+                    Cond::Eq => "sete",
+                    Cond::Ne => "setne",
+                    Cond::Lt => "setl",
+                    Cond::Le => "setle",
+                    Cond::Ult => "setb",
+                    Cond::Ule => "setbe",
+                };
+                self.emit(set, vec![Reg("al")]);
+                self.emit("movzb", vec![Reg("rax"), Reg("al")]);
+                self.finish(dst, Reg("rax"));
+            }
+            Load { dst, width, addr } => {
+                let a = self.base_reg(addr, "rax");
+                let d = self.dst_reg(dst, "rax");
+                let op = match width {
+                    Width::I8 | Width::I16 => "movsx",
+                    Width::I32 => "movsxd",
+                    Width::I64 => "mov",
+                };
+                let size = if width == Width::I64 {
+                    None
+                } else {
+                    Some(width)
+                };
+                self.emit(op, vec![d.clone(), Operand::mem(size, a, 0)]);
+                self.finish(dst, d);
+            }
+            Store { width, addr, val } => {
+                let a = self.base_reg(addr, "rax");
+                let v = match self.alloc.locs[val.0] {
+                    Location::Reg(r) => r.name(width),
+                    Location::Spill(_) => {
+                        let home = self.opnd(val);
+                        self.mov(Reg("rdx"), home);
+                        match width {
+                            Width::I8 => "dl",
+                            Width::I16 => "dx",
+                            Width::I32 => "edx",
+                            Width::I64 => "rdx",
                         }
-                        gen_line!(self.f, "  pop rax\n");
-                        gen_line!(
-                            self.f,
-                            "  imul rax, {}\n",
-                            node.ty.as_ref().unwrap().base_size()
-                        );
-                        gen_line!(self.f, "  push rax\n");
-                    }
-                }
-
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-                if !eval_pre {
-                    // This is post incr/decr
-                    gen_line!(self.f, "  mov r12, rax\n");
-                }
-                match assign_mode {
-                    ADD => {
-                        gen_line!(self.f, "  add rax, rdi\n");
-                    }
-                    SUB => {
-                        gen_line!(self.f, "  sub rax, rdi\n");
-                    }
-                    MUL => {
-                        gen_line!(self.f, "  imul rax, rdi\n");
-                    }
-                    DIV => {
-                        gen_line!(self.f, "  cqo\n");
-                        gen_line!(self.f, "  idiv rdi\n");
-                    }
-                    MOD => {
-                        gen_line!(self.f, "  cqo\n");
-                        gen_line!(self.f, "  idiv rdi\n");
-                        gen_line!(self.f, "  mov rax, rdx\n");
-                    }
-                    AND => {
-                        gen_line!(self.f, "  and rax, rdi\n");
-                    }
-                    OR => {
-                        gen_line!(self.f, "  or rax, rdi\n");
-                    }
-                    XOR => {
-                        gen_line!(self.f, "  xor rax, rdi\n");
-                    }
-                    SHL => {
-                        // TODO: Not pretty moving around vals on registers:(
-                        gen_line!(self.f, "  mov rcx, rdi\n");
-                        gen_line!(self.f, "  shl rax, cl\n");
-                    }
-                    SHR => {
-                        gen_line!(self.f, "  mov rcx, rdi\n");
-                        gen_line!(self.f, "  shr rax, cl\n");
                     }
-                    DEFAULT => panic!("Default assignment shouldn't reach here."),
-                }
-                gen_line!(self.f, "  push rax\n");
-
-                self.gen_store(node.ty.unwrap().size());
-                if !eval_pre {
-                    gen_line!(self.f, "  pop rax\n");
-                    gen_line!(self.f, "  push r12\n");
+                };
+                self.emit("mov", vec![Operand::mem(None, a, 0), Reg(v)]);
+            }
+            FrameAddr { dst, offset } => {
+                let d = self.dst_reg(dst, "rax");
+                self.emit("lea", vec![d.clone(), Operand::mem(None, "rbp", offset)]);
+                self.finish(dst, d);
+            }
+            GlobalAddr { dst, ref name } => {
+                let d = self.dst_reg(dst, "rax");
+                let (op, addr) = self.symbol_addr(name);
+                self.emit(op, vec![d.clone(), addr]);
+                self.finish(dst, d);
+            }
+            Loc { line, col } => self.directive(format!(".loc 1 {} {}", line, col)),
+            Param { dst, index } => {
+                if index >= FUNC_REGS_8.len() {
+                    panic!("Codegen: Only up to 6 parameters are supported.");
                 }
+                let home = self.opnd(dst);
+                self.mov(home, Reg(FUNC_REGS_8[index]));
             }
-            NDRETURN { node: operand } => {
-                self.gen(*operand);
-                self.gen_return();
-            }
-            NDIF {
-                cond,
-                ifnode,
-                elsenode,
+            Call {
+                dst,
+                ref callee,
+                ref args,
             } => {
-                let my_label = self.issue_level();
-                self.gen(*cond);
-                gen_line!(self.f, "  pop rax\n");
-                gen_line!(self.f, "  cmp rax, 0\n");
-                gen_line!(self.f, "  je .Lelse{}\n", my_label);
-                if let Some(ifnode) = ifnode {
-                    self.gen(*ifnode);
-                    gen_line!(self.f, "  pop r15\n");
-                }
-                gen_line!(self.f, "  jmp .Lend{}\n", my_label);
-                gen_line!(self.f, ".Lelse{}:\n", my_label);
-                if let Some(elsenode) = elsenode {
-                    self.gen(*elsenode);
-                    gen_line!(self.f, "  pop r15\n");
+                if args.len() > FUNC_REGS_8.len() {
+                    panic!("Codegen: Only up to 6 arguments are supported.");
                 }
-                gen_line!(self.f, ".Lend{}:\n", my_label);
-                self.gen_push_magic();
-            }
-            NDCASE { stmt, pos, .. } => {
-                let (label, kind) = self.get_current_level();
-                if kind != LevelKind::SWITCH {
-                    panic!("Kind can't be anything other than switch");
-                }
-                gen_line!(self.f, ".Lcase{}of{}:\n", pos.unwrap(), label);
-                self.gen(*stmt.unwrap());
-            }
-            NDDEFAULT { stmt } => {
-                let (label, kind) = self.get_current_level();
-                if kind != LevelKind::SWITCH {
-                    panic!("Kind can't be anything other than switch");
+                // Argument registers are never allocated, so these
+                // moves cannot overwrite each other's sources
+                for (reg, arg) in FUNC_REGS_8.iter().zip(args.iter()) {
+                    let src = self.opnd(*arg);
+                    self.mov(Reg(reg), src);
                 }
-                gen_line!(self.f, ".Ldefault{}:\n", label);
-                if let Some(stmt) = stmt {
-                    self.gen(*stmt);
-                } else {
-                    self.gen_push_magic();
+                // No vector registers are used by variadic callees.
+                // The stack is aligned by the prologue, so nothing else
+                // needs adjusting around the call.
+                self.emit("mov", vec![Reg("eax"), Imm(0)]);
+                match *callee {
+                    Callee::Direct(ref name) => {
+                        let target =
+                            if self.code_model == CodeModel::Absolute || self.binds_locally(name) {
+                                name.clone()
+                            } else {
+                                format!("{}@PLT", name)
+                            };
+                        self.emit("call", vec![Operand::Label(target)])
+                    }
+                    Callee::Indirect(target) => {
+                        let target = self.opnd(target);
+                        self.emit("call", vec![target]);
+                    }
                 }
+                self.finish(dst, Reg("rax"));
             }
-            NDSWITCH {
-                ctrl,
-                stmt,
-                mut cases,
-                has_default,
-            } => {
-                let my_label = self.push_level(LevelKind::SWITCH);
-                self.gen(*ctrl);
-                gen_line!(self.f, "  pop rax\n");
-
-                let mut counter = 0;
-                while let Some(val) = cases.pop_front() {
-                    gen_line!(self.f, "  cmp rax, {}\n", val);
-                    gen_line!(self.f, "  je .Lcase{}of{}\n", counter, my_label);
-                    counter += 1;
-                }
-                let loc = if has_default { ".Ldefault" } else { ".Lend" };
-                gen_line!(self.f, "  jmp {}{}\n", loc, my_label);
+        }
+    }
 
-                if let Some(stmt) = stmt {
-                    self.gen(*stmt);
-                    gen_line!(self.f, "  pop r15\n");
-                }
-                gen_line!(self.f, ".Lend{}:\n", my_label);
-                self.gen_push_magic();
-            }
-            NDBREAK => {
-                let (label, _) = self.get_current_level();
-                gen_line!(self.f, "  jmp .Lend{}\n", label);
-            }
-            NDCONTINUE => {
-                let (label, kind) = self.get_current_level();
-                let loc = match kind {
-                    LevelKind::FOR => ".Lstep",
-                    LevelKind::DOWHILE => ".Lcond",
-                    LevelKind::WHILE => ".Lbegin",
-                    _ => panic!("Shouldn't reach here."),
+    fn gen_bin(&mut self, dst: VReg, op: BinOp, lhs: VReg, rhs: VReg) {
+        use Operand::Reg;
+
+        match op {
+            BinOp::Div | BinOp::Mod => {
+                let l = self.opnd(lhs);
+                self.mov(Reg("rax"), l);
+                let r = self.opnd(rhs);
+                self.emit("cqo", vec![]);
+                self.emit("idiv", vec![r]);
+                let result = if op == BinOp::Div { "rax" } else { "rdx" };
+                self.finish(dst, Reg(result));
+            }
+            BinOp::Shl | BinOp::Shr | BinOp::Sar => {
+                let r = self.opnd(rhs);
+                self.mov(Reg("rcx"), r);
+                let d = self.dst_reg(dst, "rax");
+                let l = self.opnd(lhs);
+                self.mov(d.clone(), l);
+                let instr = match op {
+                    BinOp::Shl => "shl",
+                    BinOp::Shr => "shr",
+                    _ => "sar",
                 };
-                gen_line!(self.f, "  jmp {}{}\n", loc, label);
-            }
-            NDWHILE { cond, repnode } => {
-                let my_label = self.push_level(LevelKind::WHILE);
-                gen_line!(self.f, ".Lbegin{}:\n", my_label);
-                self.gen(*cond);
-                gen_line!(self.f, "  pop rax\n");
-                gen_line!(self.f, "  cmp rax, 0\n");
-                gen_line!(self.f, "  je .Lend{}\n", my_label);
-                self.gen(*repnode.unwrap());
-                gen_line!(self.f, "  pop r15\n"); // Pop unneeded stuff
-                gen_line!(self.f, "  jmp .Lbegin{}\n", my_label);
-                gen_line!(self.f, ".Lend{}:\n", my_label);
-                self.gen_push_magic();
-                self.pop_level();
-            }
-            NDDOWHILE { cond, repnode } => {
-                let my_label = self.push_level(LevelKind::DOWHILE);
-                gen_line!(self.f, ".Lbegin{}:\n", my_label);
-                self.gen(*repnode.unwrap());
-                gen_line!(self.f, "  pop r15\n");
-                gen_line!(self.f, ".Lcond{}:\n", my_label);
-                self.gen(*cond);
-                gen_line!(self.f, "  pop rax\n");
-                gen_line!(self.f, "  cmp rax, 0\n");
-                gen_line!(self.f, "  jne .Lbegin{}\n", my_label);
-                gen_line!(self.f, ".Lend{}:\n", my_label);
-                self.gen_push_magic();
-                self.pop_level();
+                self.emit(instr, vec![d.clone(), Reg("cl")]);
+                self.finish(dst, d);
             }
-            NDFOR {
-                init,
-                cond,
-                step,
-                repnode,
-            } => {
-                let my_label = self.push_level(LevelKind::FOR);
-                if let Some(init) = init {
-                    self.gen(*init);
-                    gen_line!(self.f, "  pop r15\n");
-                }
-                gen_line!(self.f, ".Lbegin{}:\n", my_label);
-                if let Some(cond) = cond {
-                    self.gen(*cond);
-                } else {
-                    // Infinite loop: push 1 to make sure the cmp always succeeds
-                    gen_line!(self.f, "push 1\n");
-                }
-                gen_line!(self.f, "  pop rax\n");
-                gen_line!(self.f, "  cmp rax, 0\n");
-                gen_line!(self.f, "  je .Lend{}\n", my_label);
-                if let Some(repnode) = repnode {
-                    self.gen(*repnode);
-                    gen_line!(self.f, "  pop r15\n"); // Throw away garbage
-                }
-                gen_line!(self.f, ".Lstep{}:\n", my_label);
-                if let Some(step) = step {
-                    self.gen(*step);
-                    gen_line!(self.f, "  pop r15\n"); // Throw away garbage
+            _ => {
+                let r = self.opnd(rhs);
+                let mut d = self.dst_reg(dst, "rax");
+                if d == r {
+                    // Computing into d would clobber rhs first
+                    d = Reg("rax");
                 }
-                gen_line!(self.f, "  jmp .Lbegin{}\n", my_label);
-                gen_line!(self.f, ".Lend{}:\n", my_label);
-                self.gen_push_magic();
-                self.pop_level();
-            }
-            NDBLOCK { stmts } => {
-                self.gen_blockstmts(stmts);
+                let l = self.opnd(lhs);
+                self.mov(d.clone(), l);
+                let instr = match op {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "imul",
+                    BinOp::And => "and",
+                    BinOp::Or => "or",
+                    BinOp::Xor => "xor",
+                    _ => unreachable!(),
+                };
+                self.emit(instr, vec![d.clone(), r]);
+                self.finish(dst, d);
             }
-            NDCALL { prototy, mut args } => {
-                self.cond_label += 2; // Consume 2
-                let num_args = args.len();
+        }
+    }
 
-                self.gen(*prototy);
-                gen_line!(self.f, "  pop r15\n");
+    fn switch_compare(&mut self, v: &Operand, case: i64) {
+        use Operand::{Imm, Reg};
 
-                // Push args to the designated registers
-                for i in 0..num_args {
-                    self.gen(args.pop_front().unwrap());
-                    gen_line!(self.f, "  pop {}\n", FUNC_REGS_8[i]);
-                }
+        if case == case as i32 as i64 {
+            self.emit("cmp", vec![v.clone(), Imm(case)]);
+        } else {
+            self.emit("mov", vec![Reg("rdx"), Imm(case)]);
+            self.emit("cmp", vec![v.clone(), Reg("rdx")]);
+        }
+    }
 
-                // Align RSP to multiple of 16
-                gen_line!(self.f, "  mov r12, 0x10\n");
-                gen_line!(self.f, "  mov r13, rsp\n");
-                gen_line!(self.f, "  and r13, 0xf\n");
-                gen_line!(self.f, "  sub r12, r13\n"); // Need to sub rsp this much
-                                                       // TODO: Skip alignment if its already a multiple of 16
-                gen_line!(self.f, "  sub rsp, r12\n");
-                gen_line!(self.f, "  call r15\n");
-                // Rewind the alignment
-                gen_line!(self.f, "  add rsp, r12\n");
-
-                // Finally, store result returned from the call:
-                gen_line!(self.f, "  push rax\n");
+    // cases is sorted. Halves the range with every comparison until few
+    // enough cases are left to compare against one by one. next is the
+    // block laid out after the code, if any.
+    fn switch_search(
+        &mut self,
+        v: &Operand,
+        cases: &[(i64, BlockId)],
+        default: BlockId,
+        next: Option<BlockId>,
+    ) {
+        if cases.len() < SWITCH_CHAIN_MAX {
+            for &(case, to) in cases.iter() {
+                self.switch_compare(v, case);
+                self.jump("je", to);
+            }
+            if Some(default) != next {
+                self.jump("jmp", default);
             }
-            NDFUNCDEF {
+            return;
+        }
+        let mid = cases.len() / 2;
+        let (case, to) = cases[mid];
+        let upper = self.new_label("SW");
+        self.switch_compare(v, case);
+        self.jump("je", to);
+        self.emit("jg", vec![Operand::Label(upper.clone())]);
+        self.switch_search(v, &cases[..mid], default, None);
+        self.buf.push(Line::Label(upper));
+        self.switch_search(v, &cases[mid + 1..], default, next);
+    }
+
+    // cases is sorted and dense enough that a table indexed by the value
+    // minus the smallest case is worth its size
+    fn switch_table(&mut self, v: &Operand, cases: &[(i64, BlockId)], default: BlockId) {
+        use Operand::{Imm, Reg};
+
+        let lo = cases[0].0;
+        let spread = cases[cases.len() - 1].0 - lo + 1;
+        let mut targets = vec![self.label(default); spread as usize];
+        for &(case, to) in cases.iter() {
+            targets[(case - lo) as usize] = self.label(to);
+        }
+
+        self.mov(Reg("rdx"), v.clone());
+        if lo != 0 {
+            self.emit("sub", vec![Reg("rdx"), Imm(lo)]);
+        }
+        // Values below the smallest case wrap around to large ones
+        self.emit("cmp", vec![Reg("rdx"), Imm(spread - 1)]);
+        self.jump("ja", default);
+        let name = self.new_label("JT");
+        let base = if self.code_model == CodeModel::Absolute {
+            None
+        } else {
+            self.emit("lea", vec![Reg("rax"), Operand::Rip(name.clone())]);
+            Some("rax")
+        };
+        self.emit(
+            "jmp",
+            vec![Operand::Table {
                 name,
-                mut argvars,
-                stmts,
-                lvars_offset,
-            } => {
-                gen_line!(self.f, "{}:\n", name);
-
-                // Save callee-saved regs that are used by me:
-                gen_line!(self.f, "  push r12\n");
-                gen_line!(self.f, "  push rbp\n");
-                // Make sure to create enough space for variables
-                gen_line!(self.f, "  mov rbp, rsp\n");
-                gen_line!(self.f, "  sub rsp, {}\n", lvars_offset);
-
-                // Get the arguments from the correspoinding registers
-                let num_args = argvars.len();
-                for i in 0..num_args {
-                    let lvar = argvars.pop_front().unwrap();
-                    gen_line!(self.f, "  mov rax, rbp\n");
-                    gen_line!(self.f, "  sub rax, {}\n", lvar.offset.unwrap());
-                    let regs = match lvar.ty.size() {
-                        4 => FUNC_REGS_4,
-                        8 => FUNC_REGS_8,
-                        _ => panic!("Codegen: Invalid size for lvar!"),
-                    };
-                    gen_line!(self.f, "  mov [rax], {}\n", regs[i]);
-                }
+                base,
+                index: "rdx",
+                targets,
+            }],
+        );
+    }
 
-                self.gen_blockstmts(stmts);
-                self.gen_return();
-            }
-            NDDECL { mut inits } => {
-                loop {
-                    if let Some(init) = inits.pop_front() {
-                        self.gen(init);
-                        gen_line!(self.f, "  pop rax\n");
-                    } else {
-                        break;
-                    }
-                }
-                self.gen_push_magic();
-            }
-            NDADDR { node: operand } => {
-                self.gen_lval(*operand);
-            }
-            NDDEREF { node: operand } => {
-                let ty = node.ty.unwrap();
-                self.gen(*operand);
+    // How the address of a symbol is taken: an absolute address, one
+    // relative to rip or one loaded from the GOT
+    fn symbol_addr(&self, name: &str) -> (&'static str, Operand) {
+        if self.code_model == CodeModel::Absolute {
+            ("mov", Operand::Offset(name.to_string()))
+        } else if self.binds_locally(name) {
+            ("lea", Operand::Rip(name.to_string()))
+        } else {
+            ("mov", Operand::Got(name.to_string()))
+        }
+    }
 
-                if !ty.is_array() {
-                    self.gen_load(ty.size());
-                }
-            }
-            NDBITNOT { node: operand } => {
-                self.gen(*operand);
-                gen_line!(self.f, "  pop rax\n");
-                gen_line!(self.f, "  not rax\n");
-                gen_line!(self.f, "  push rax\n");
-            }
-            NDLOGAND { lhs, rhs } => {
-                self.gen_logical(*lhs, *rhs, true);
-            }
-            NDLOGOR { lhs, rhs } => {
-                self.gen_logical(*lhs, *rhs, false);
-            }
-            NDSHL { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rcx\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  shl rax, cl\n");
-                gen_line!(self.f, "  push rax\n");
-            }
-            NDSHR { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rcx\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  shr rax, cl\n");
-                gen_line!(self.f, "  push rax\n");
-            }
-            _ => {
-                // Must be a primitive node
-                self.gen_primitive(node);
-            }
+    // Whether a reference to the symbol surely resolves to a definition in
+    // this module. Under -fPIC a global symbol may be preempted by one that
+    // is loaded earlier, so only local labels do.
+    fn binds_locally(&self, name: &str) -> bool {
+        name.starts_with(".L") || (self.code_model == CodeModel::Pie && self.defined.contains(name))
+    }
+
+    // Read-only data holding addresses needs to be relocated at load time
+    // when the code is position-independent
+    fn readonly_section(&self, has_addrs: bool) -> &'static str {
+        if has_addrs && self.code_model != CodeModel::Absolute {
+            ".section .data.rel.ro,\"aw\""
+        } else {
+            ".section .rodata"
         }
     }
 
-    // Generates code for primitive ops
-    // TODO CLean up repetition
-    fn gen_primitive(&mut self, node: Node) {
-        use NodeKind::*;
+    fn jump(&mut self, op: &'static str, to: BlockId) {
+        let label = self.label(to);
+        self.emit(op, vec![Operand::Label(label)]);
+    }
 
-        match node.kind {
-            NDADD {
-                lhs,
-                rhs,
-                scale_lhs,
-            } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                let ty = node.ty.unwrap();
-                if ty.is_ptr_like() {
-                    let reg_to_scale = if scale_lhs.unwrap() { "rdi" } else { "rax" };
-                    gen_line!(self.f, "  imul {}, {}\n", reg_to_scale, ty.base_size());
+    // next is the block laid out right after this one
+    fn gen_terminator(&mut self, term: &Terminator, next: BlockId) {
+        use Operand::{Imm, Reg};
+
+        match *term {
+            Terminator::Jump(to) => {
+                if to != next {
+                    self.jump("jmp", to);
                 }
-                gen_line!(self.f, "  add rax, rdi\n");
             }
-            NDSUB { lhs, rhs, .. } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                let ty = node.ty.unwrap();
-                if ty.is_ptr_like() {
-                    gen_line!(self.f, "  imul rdi, {}\n", ty.base_size());
+            Terminator::Branch { cond, then, els } => {
+                let c = self.opnd(cond);
+                self.emit("cmp", vec![c, Imm(0)]);
+                if then == next {
+                    self.jump("je", els);
+                } else {
+                    self.jump("jne", then);
+                    if els != next {
+                        self.jump("jmp", els);
+                    }
                 }
-                gen_line!(self.f, "  sub rax, rdi\n");
-            }
-            NDMUL { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  imul rax, rdi\n");
-            }
-            NDDIV { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  cqo\n");
-                gen_line!(self.f, "  idiv rdi\n");
-            }
-            NDMOD { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  cqo\n");
-                gen_line!(self.f, "  idiv rdi\n");
-                gen_line!(self.f, "  mov rax, rdx\n");
-            }
-            NDEQ { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  cmp rax, rdi\n");
-                gen_line!(self.f, "  sete al\n");
-                gen_line!(self.f, "  movzb rax, al\n");
-            }
-            NDNEQ { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  cmp rax, rdi\n");
-                gen_line!(self.f, "  setne al\n");
-                gen_line!(self.f, "  movzb rax, al\n");
-            }
-            NDLEQ { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  cmp rax, rdi\n");
-                gen_line!(self.f, "  setle al\n");
-                gen_line!(self.f, "  movzb rax, al\n");
-            }
-            NDLT { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  cmp rax, rdi\n");
-                gen_line!(self.f, "  setl al\n");
-                gen_line!(self.f, "  movzb rax, al\n");
-            }
-            NDBITAND { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  and rax, rdi\n");
             }
-            NDBITXOR { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  xor rax, rdi\n");
-            }
-            NDBITOR { lhs, rhs } => {
-                self.gen(*lhs);
-                self.gen(*rhs);
-                gen_line!(self.f, "  pop rdi\n");
-                gen_line!(self.f, "  pop rax\n");
-
-                gen_line!(self.f, "  or rax, rdi\n");
+            Terminator::Switch {
+                val,
+                ref cases,
+                default,
+            } => {
+                let v = self.in_reg(val, "rax");
+                let mut cases = cases.clone();
+                cases.sort_unstable();
+                let spread = match (cases.first(), cases.last()) {
+                    (Some(lo), Some(hi)) => hi.0 - lo.0 + 1,
+                    _ => 0,
+                };
+                if cases.len() >= SWITCH_CHAIN_MAX
+                    && spread <= SWITCH_TABLE_SPREAD * cases.len() as i64
+                {
+                    self.switch_table(&v, &cases, default);
+                } else {
+                    self.switch_search(&v, &cases, default, Some(next));
+                }
             }
-            _ => panic!("Oops, found a strange node kind."),
+            Terminator::Ret(val) => {
+                let v = self.opnd(val);
+                self.mov(Reg("rax"), v);
+                for i in 0..self.alloc.callee_saved.len() {
+                    let reg = self.alloc.callee_saved[i].name(Width::I64);
+                    let slot = Operand::mem(None, "rbp", self.save_offset(i));
+                    self.emit("mov", vec![Reg(reg), slot]);
+                }
+                // Code may follow the return, where the frame is still set up
+                self.directive(".cfi_remember_state".to_string());
+                self.emit("mov", vec![Reg("rsp"), Reg("rbp")]);
+                self.emit("pop", vec![Reg("rbp")]);
+                self.directive(format!(
+                    ".cfi_def_cfa {}, 8",
+                    asm::reg_in("rsp", self.syntax)
+                ));
+                self.emit("ret", vec![]);
+                self.directive(".cfi_restore_state".to_string());
+            }
+            Terminator::Unterminated => panic!("Codegen: Unterminated block"),
         }
-
-        gen_line!(self.f, "  push rax\n");
     }
 }
+
+// Alignment of a global, in a section or as a common symbol: the largest
+// power of two up to 16 that divides the size, as objects are not padded
+// to the alignment of their members
+fn common_align(size: usize) -> usize {
+    [16, 8, 4, 2]
+        .iter()
+        .copied()
+        .find(|align| size.is_multiple_of(*align))
+        .unwrap_or(1)
+}
@@ -0,0 +1,371 @@
+// Compile-time evaluation of constant expressions
+// Arithmetic follows what the generated code does at run time: 64-bit
+// wrapping operations with ">>" being a logical shift.
+use crate::ctype::Type;
+use crate::ir::LITERAL_HEAD;
+use crate::node::{Node, NodeKind};
+use crate::parser::Program;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstVal {
+    Int(i64),
+    Addr(String, i64), // Symbol plus a byte offset
+}
+
+impl ConstVal {
+    fn int(&self) -> Option<i64> {
+        match *self {
+            ConstVal::Int(v) => Some(v),
+            ConstVal::Addr(..) => None,
+        }
+    }
+}
+
+/// Value of node if it is an integer constant expression
+pub fn eval_int(node: &Node) -> Option<i64> {
+    eval(node)?.int()
+}
+
+/// Value of node if it is an integer or address constant
+pub fn eval(node: &Node) -> Option<ConstVal> {
+    use ConstVal::*;
+    use NodeKind::*;
+
+    match node.kind {
+        NDINT { val } => Some(Int(val as i64)),
+        // Reading an object is never constant, but arrays decay into the
+        // address of their first element
+        NDGVAR { .. } | NDMEMBER { .. } | NDDEREF { .. } => match node.ty {
+            Some(ref ty) if ty.is_array() => eval_addr(node).map(|(sym, ofs)| Addr(sym, ofs)),
+            _ => None,
+        },
+        NDSTR { pos } => Some(Addr(format!("{}{}", LITERAL_HEAD, pos), 0)),
+        NDPROTOTY { ref name } => Some(Addr(name.clone(), 0)),
+        NDADDR { ref node } => eval_addr(node).map(|(sym, ofs)| Addr(sym, ofs)),
+        NDCAST { node: ref operand } => {
+            let val = eval(operand)?;
+            let ty = node.ty.as_ref().unwrap();
+            match val {
+                Int(v) if ty.is_integral() => Some(Int(truncate(v, ty))),
+                Addr(..) if ty.is_integral() && ty.size() < 8 => None,
+                other => Some(other),
+            }
+        }
+        NDADD {
+            ref lhs,
+            ref rhs,
+            scale_lhs,
+        } => {
+            let (mut l, mut r) = (eval(lhs)?, eval(rhs)?);
+            let ty = node.ty.as_ref().unwrap();
+            if ty.is_ptr_like() {
+                if scale_lhs.unwrap() {
+                    r = Int(r.int()?.wrapping_mul(ty.base_size() as i64));
+                } else {
+                    l = Int(l.int()?.wrapping_mul(ty.base_size() as i64));
+                }
+            }
+            match (l, r) {
+                (Int(l), Int(r)) => Some(Int(l.wrapping_add(r))),
+                (Addr(sym, ofs), Int(v)) | (Int(v), Addr(sym, ofs)) => {
+                    Some(Addr(sym, ofs.wrapping_add(v)))
+                }
+                _ => None,
+            }
+        }
+        NDSUB {
+            ref lhs, ref rhs, ..
+        } => {
+            let lty = lhs.ty.as_ref().unwrap();
+            if lty.is_ptr_like() && rhs.ty.as_ref().unwrap().is_ptr_like() {
+                let diff = match (eval(lhs)?, eval(rhs)?) {
+                    (Int(l), Int(r)) => l.wrapping_sub(r),
+                    (Addr(a, l), Addr(b, r)) if a == b => l.wrapping_sub(r),
+                    _ => return None,
+                };
+                return diff.checked_div(lty.base_size() as i64).map(Int);
+            }
+            let (l, mut r) = (eval(lhs)?, eval(rhs)?.int()?);
+            let ty = node.ty.as_ref().unwrap();
+            if ty.is_ptr_like() {
+                r = r.wrapping_mul(ty.base_size() as i64);
+            }
+            match l {
+                Int(l) => Some(Int(l.wrapping_sub(r))),
+                Addr(sym, ofs) => Some(Addr(sym, ofs.wrapping_sub(r))),
+            }
+        }
+        NDMUL { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some(l.wrapping_mul(r))),
+        NDDIV { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| l.checked_div(r)),
+        NDMOD { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| l.checked_rem(r)),
+        NDBITAND { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some(l & r)),
+        NDBITXOR { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some(l ^ r)),
+        NDBITOR { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some(l | r)),
+        NDSHL { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some(l << (r & 63))),
+        NDSHR { ref lhs, ref rhs } => {
+            binary(lhs, rhs, |l, r| Some(((l as u64) >> (r & 63)) as i64))
+        }
+        NDEQ { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some((l == r) as i64)),
+        NDNEQ { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some((l != r) as i64)),
+        // Addresses order as unsigned values
+        NDLT { ref lhs, ref rhs } if is_ptr(lhs) || is_ptr(rhs) => {
+            binary(lhs, rhs, |l, r| Some(((l as u64) < r as u64) as i64))
+        }
+        NDLEQ { ref lhs, ref rhs } if is_ptr(lhs) || is_ptr(rhs) => {
+            binary(lhs, rhs, |l, r| Some((l as u64 <= r as u64) as i64))
+        }
+        NDLT { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some((l < r) as i64)),
+        NDLEQ { ref lhs, ref rhs } => binary(lhs, rhs, |l, r| Some((l <= r) as i64)),
+        NDBITNOT { ref node } => Some(Int(!eval_int(node)?)),
+        // The right operand is not needed once the left one decides
+        NDLOGAND { ref lhs, ref rhs } => match eval_int(lhs)? {
+            0 => Some(Int(0)),
+            _ => Some(Int((eval_int(rhs)? != 0) as i64)),
+        },
+        NDLOGOR { ref lhs, ref rhs } => match eval_int(lhs)? {
+            0 => Some(Int((eval_int(rhs)? != 0) as i64)),
+            _ => Some(Int(1)),
+        },
+        NDCOND {
+            ref cond,
+            ref then,
+            ref els,
+        } => match eval_int(cond)? {
+            0 => eval(els),
+            _ => eval(then),
+        },
+        _ => None,
+    }
+}
+
+fn binary<F>(lhs: &Node, rhs: &Node, op: F) -> Option<ConstVal>
+where
+    F: Fn(i64, i64) -> Option<i64>,
+{
+    op(eval_int(lhs)?, eval_int(rhs)?).map(ConstVal::Int)
+}
+
+fn is_ptr(node: &Node) -> bool {
+    node.ty.as_ref().unwrap().is_ptr_like()
+}
+
+// Address of an lvalue with static storage
+fn eval_addr(node: &Node) -> Option<(String, i64)> {
+    use NodeKind::*;
+
+    match node.kind {
+        NDGVAR { ref name } | NDPROTOTY { ref name } => Some((name.clone(), 0)),
+        NDSTR { pos } => Some((format!("{}{}", LITERAL_HEAD, pos), 0)),
+        NDMEMBER {
+            ref node,
+            offset,
+            bits: None,
+            ..
+        } => {
+            let (sym, ofs) = eval_addr(node)?;
+            Some((sym, ofs + offset? as i64))
+        }
+        NDDEREF { ref node } => match eval(node)? {
+            ConstVal::Addr(sym, ofs) => Some((sym, ofs)),
+            ConstVal::Int(_) => None,
+        },
+        _ => None,
+    }
+}
+
+/// Keeps the low bytes of v that fit in ty, sign-extended
+pub fn truncate(v: i64, ty: &Type) -> i64 {
+    match ty.size() {
+        1 => v as i8 as i64,
+        2 => v as i16 as i64,
+        4 => v as i32 as i64,
+        _ => v,
+    }
+}
+
+/// Replaces every constant integer subexpression with its value
+pub fn fold_program(prog: &mut Program) {
+    for node in prog.nodes.iter_mut() {
+        fold(node);
+    }
+}
+
+fn fold(node: &mut Node) {
+    use NodeKind::*;
+
+    match node.kind {
+        NDINT { .. } => return,
+        NDADD {
+            ref mut lhs,
+            ref mut rhs,
+            ..
+        }
+        | NDSUB {
+            ref mut lhs,
+            ref mut rhs,
+            ..
+        }
+        | NDMUL {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDDIV {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDMOD {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDEQ {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDNEQ {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDLEQ {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDLT {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDASSIGN {
+            ref mut lhs,
+            ref mut rhs,
+            ..
+        }
+        | NDBITAND {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDBITXOR {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDBITOR {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDLOGAND {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDLOGOR {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDSHL {
+            ref mut lhs,
+            ref mut rhs,
+        }
+        | NDSHR {
+            ref mut lhs,
+            ref mut rhs,
+        } => {
+            fold(lhs);
+            fold(rhs);
+        }
+        NDBITNOT { ref mut node }
+        | NDADDR { ref mut node }
+        | NDDEREF { ref mut node }
+        | NDRETURN { ref mut node }
+        | NDMEMBER { ref mut node, .. }
+        | NDCAST { ref mut node } => fold(node),
+        NDCOND {
+            ref mut cond,
+            ref mut then,
+            ref mut els,
+        } => {
+            fold(cond);
+            fold(then);
+            fold(els);
+        }
+        NDIF {
+            ref mut cond,
+            ref mut ifnode,
+            ref mut elsenode,
+        } => {
+            fold(cond);
+            fold_opt(ifnode);
+            fold_opt(elsenode);
+        }
+        NDSWITCH {
+            ref mut ctrl,
+            ref mut stmt,
+            ..
+        } => {
+            fold(ctrl);
+            fold_opt(stmt);
+        }
+        NDWHILE {
+            ref mut cond,
+            ref mut repnode,
+        }
+        | NDDOWHILE {
+            ref mut cond,
+            ref mut repnode,
+        } => {
+            fold(cond);
+            fold_opt(repnode);
+        }
+        NDFOR {
+            ref mut init,
+            ref mut cond,
+            ref mut step,
+            ref mut repnode,
+        } => {
+            fold_opt(init);
+            fold_opt(cond);
+            fold_opt(step);
+            fold_opt(repnode);
+        }
+        NDBLOCK { ref mut stmts }
+        | NDFUNCDEF { ref mut stmts, .. }
+        | NDDECL {
+            inits: ref mut stmts,
+            ..
+        } => {
+            for stmt in stmts.iter_mut() {
+                fold(stmt);
+            }
+        }
+        NDCASE { ref mut stmt, .. } | NDDEFAULT { ref mut stmt } => fold_opt(stmt),
+        NDCALL {
+            ref mut prototy,
+            ref mut args,
+        } => {
+            fold(prototy);
+            for arg in args.iter_mut() {
+                fold(arg);
+            }
+        }
+        NDSTR { .. } | NDBREAK | NDCONTINUE | NDLVAR { .. } | NDGVAR { .. } | NDPROTOTY { .. } => {
+            return
+        }
+    }
+
+    // Pointers keep their node so that the arithmetic on them stays scaled
+    if let Some(ref ty) = node.ty {
+        if !ty.is_integral() {
+            return;
+        }
+    }
+    if let Some(val) = eval_int(node) {
+        if val == val as i32 as i64 {
+            node.kind = NDINT { val: val as i32 };
+            if node.ty.is_none() {
+                node.ty = Some(Type::new_base("int"));
+            }
+        }
+    }
+}
+
+fn fold_opt(node: &mut Option<Box<Node>>) {
+    if let Some(node) = node {
+        fold(node);
+    }
+}
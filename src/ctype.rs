@@ -1,4 +1,7 @@
 // Type
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
 use std::slice::Iter;
 
 const INIT: usize = 0;
@@ -61,25 +64,79 @@ enum TypeKind {
         ptr_to: Box<Type>,
     },
     STRUCT {
-        size: usize,
-        members: Vec<StructMember>,
+        def: StructRef,
     },
     ENUM {
+        tag: Option<String>,
         members: Vec<EnumMember>,
     },
     FUNCTION {
+        ret: Box<Type>,
         args: Vec<(String, Type)>,
+        variadic: bool, // Whether the parameter list ends with ", ..."
     },
     INCOMPLETE {
         kind: IncompleteKind,
     },
 }
 
+/// A struct or union type is shared by every use of its tag, so that
+/// completing it later in the scope is seen through the earlier uses
+pub type StructRef = Rc<RefCell<StructDef>>;
+
+pub struct StructDef {
+    pub name: Option<String>, // Tag made unique within the program; None if untagged
+    pub is_union: bool,       // Every member starts at offset 0
+    body: Option<(usize, Vec<StructMember>)>, // Size and members once complete
+}
+
+impl StructDef {
+    pub fn new(name: Option<String>, is_union: bool) -> StructRef {
+        Rc::new(RefCell::new(StructDef {
+            name,
+            is_union,
+            body: None,
+        }))
+    }
+
+    pub fn complete(&mut self, size: usize, members: Vec<StructMember>) {
+        self.body = Some((size, members));
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.body.is_some()
+    }
+
+    /// How the type is referred to, e.g. "struct node"
+    pub fn spelling(&self) -> String {
+        let keyword = if self.is_union { "union" } else { "struct" };
+        match self.name {
+            Some(ref name) => format!("{} {}", keyword, name),
+            None => keyword.to_string(),
+        }
+    }
+
+    pub fn members(&self) -> &[StructMember] {
+        match self.body {
+            Some((_, ref members)) => members,
+            None => &[],
+        }
+    }
+}
+
+// Members may point back at the struct itself
+impl fmt::Debug for StructDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.spelling())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StructMember {
     pub name: String,
     pub ty: Type,
     pub offset: usize,
+    pub bits: Option<(usize, usize)>, // Bit offset and width within the unit at offset
 }
 
 #[derive(Debug, Clone)]
@@ -89,23 +146,17 @@ pub struct EnumMember {
 }
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub enum IncompleteKind {
-    VOID,   // TODO: Rethink this...?
-    ARRAY,  // Unknow size
-    STRUCT, // Unknown content
-    ENUM,   // Unknown content
-}
-
-impl PartialEq for Type {
-    fn eq(&self, other: &Self) -> bool {
-        self == other
-    }
+    VOID,                        // TODO: Rethink this...?
+    ARRAY { ptr_to: Box<Type> }, // Unknown size
+    ENUM,                        // Unknown content
 }
 
 impl Type {
     fn new_from_kind(kind: TypeKind) -> Self {
         Type {
-            kind: kind,
+            kind,
             is_const: false,
             is_volatile: false,
         }
@@ -143,63 +194,57 @@ impl Type {
 
     pub fn new_array(basety: Self, num_elems: usize) -> Self {
         let kind = TypeKind::ARRAY {
-            num_elems: num_elems,
+            num_elems,
             ptr_to: Box::new(basety),
         };
         Self::new_from_kind(kind)
     }
 
-    pub fn new_enum(members: Vec<EnumMember>) -> Self {
-        let kind = TypeKind::ENUM { members: members };
+    pub fn new_enum(tag: Option<String>, members: Vec<EnumMember>) -> Self {
+        let kind = TypeKind::ENUM { tag, members };
         Self::new_from_kind(kind)
     }
 
-    pub fn new_struct(size: usize, members: Vec<StructMember>) -> Self {
-        let kind = TypeKind::STRUCT {
-            size: size,
-            members: members,
-        };
-        Self::new_from_kind(kind)
+    pub fn new_struct(def: StructRef) -> Self {
+        Self::new_from_kind(TypeKind::STRUCT { def })
     }
 
-    pub fn new_function(args: Vec<(String, Type)>) -> Self {
-        let kind = TypeKind::FUNCTION { args: args };
-        println!("{:?}", kind);
+    pub fn new_function(ret: Self, args: Vec<(String, Type)>, variadic: bool) -> Self {
+        let kind = TypeKind::FUNCTION {
+            ret: Box::new(ret),
+            args,
+            variadic,
+        };
         Self::new_from_kind(kind)
     }
 
     pub fn new_incomplete(kind: IncompleteKind) -> Self {
-        let tykind = TypeKind::INCOMPLETE { kind: kind };
+        let tykind = TypeKind::INCOMPLETE { kind };
         Self::new_from_kind(tykind)
     }
 
+    /// An array whose size is given by its initializer or a later declaration
+    pub fn new_incomplete_array(basety: Self) -> Self {
+        Self::new_incomplete(IncompleteKind::ARRAY {
+            ptr_to: Box::new(basety),
+        })
+    }
+
     pub fn clone_base(&self) -> Self {
         use TypeKind::*;
 
         match self.kind {
-            PTR { ref ptr_to } | ARRAY { ref ptr_to, .. } => *(ptr_to.clone()),
+            PTR { ref ptr_to }
+            | ARRAY { ref ptr_to, .. }
+            | INCOMPLETE {
+                kind: IncompleteKind::ARRAY { ref ptr_to },
+            } => *(ptr_to.clone()),
+            // A function designator decays into a pointer to itself
+            FUNCTION { .. } => self.clone(),
             _ => panic!("Trying to clone the base of terminal types."),
         }
     }
 
-    /// Used to access array's base type
-    pub fn base_as_ref(&self) -> &Self {
-        use TypeKind::ARRAY;
-        match self.kind {
-            ARRAY { ref ptr_to, .. } => &ptr_to,
-            _ => panic!("Trying to access the base of non-array type"),
-        }
-    }
-
-    /// Used to access array's terminal type
-    pub fn terminal_as_ref(&self) -> &Self {
-        use TypeKind::ARRAY;
-        match self.kind {
-            ARRAY { ref ptr_to, .. } => ptr_to.terminal_as_ref(),
-            _ => &self,
-        }
-    }
-
     pub fn new_ptr_to(&self) -> Self {
         let kind = TypeKind::PTR {
             ptr_to: Box::new(self.clone()),
@@ -207,77 +252,133 @@ impl Type {
         Self::new_from_kind(kind)
     }
 
+    /// The type of a value of this type, in which an array or a function
+    /// designator has decayed into a pointer
+    pub fn decay(&self) -> Self {
+        if self.is_function() {
+            self.new_ptr_to()
+        } else if self.is_array() {
+            self.clone_base().new_ptr_to()
+        } else {
+            self.clone()
+        }
+    }
+
     /// Convenience functions for checking type
     pub fn is_void(&self) -> bool {
         use TypeKind::VOID;
-        match self.kind {
-            VOID => true,
-            _ => false,
-        }
+        matches!(self.kind, VOID)
     }
 
+    /// Whether this is an array, possibly of unknown size
     pub fn is_array(&self) -> bool {
-        use TypeKind::ARRAY;
-        match self.kind {
-            ARRAY { .. } => true,
-            _ => false,
-        }
+        use TypeKind::{ARRAY, INCOMPLETE};
+        matches!(
+            self.kind,
+            ARRAY { .. }
+                | INCOMPLETE {
+                    kind: IncompleteKind::ARRAY { .. }
+                }
+        )
     }
 
+    /// Whether this is a struct or a union
     pub fn is_struct(&self) -> bool {
-        use TypeKind::{INCOMPLETE, STRUCT};
+        use TypeKind::STRUCT;
+        matches!(self.kind, STRUCT { .. })
+    }
+
+    pub fn is_union(&self) -> bool {
+        use TypeKind::STRUCT;
+        matches!(self.kind, STRUCT { ref def } if def.borrow().is_union)
+    }
+
+    /// Whether this is a struct or union too large or oddly sized for a
+    /// register, whose value is then handled through its address
+    pub fn in_memory(&self) -> bool {
+        self.is_struct() && !matches!(self.size(), 1 | 2 | 4 | 8)
+    }
+
+    /// The definition shared by every use of a struct or union tag
+    pub fn struct_def(&self) -> Option<&StructRef> {
+        use TypeKind::STRUCT;
         match self.kind {
-            STRUCT { .. } => true,
-            INCOMPLETE { ref kind } => match kind {
-                IncompleteKind::STRUCT { .. } => true,
-                _ => false,
-            },
-            _ => false,
+            STRUCT { ref def } => Some(def),
+            _ => None,
         }
     }
 
     pub fn is_enum(&self) -> bool {
         use TypeKind::{ENUM, INCOMPLETE};
+        matches!(
+            self.kind,
+            ENUM { .. }
+                | INCOMPLETE {
+                    kind: IncompleteKind::ENUM
+                }
+        )
+    }
+
+    /// The members of an enum type; none while it is incomplete
+    pub fn enum_members(&self) -> &[EnumMember] {
+        use TypeKind::ENUM;
         match self.kind {
-            ENUM { .. } => true,
-            INCOMPLETE { ref kind } => match kind {
-                IncompleteKind::ENUM { .. } => true,
-                _ => false,
-            },
-            _ => false,
+            ENUM { ref members, .. } => members,
+            _ => &[],
+        }
+    }
+
+    /// The tag of a complete enum type, if it has one
+    pub fn enum_tag(&self) -> Option<&str> {
+        use TypeKind::ENUM;
+        match self.kind {
+            ENUM { ref tag, .. } => tag.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Number of elements of an array; None if unknown or not an array
+    pub fn array_len(&self) -> Option<usize> {
+        use TypeKind::ARRAY;
+        match self.kind {
+            ARRAY { num_elems, .. } => Some(num_elems),
+            _ => None,
         }
     }
 
     pub fn is_function(&self) -> bool {
         use TypeKind::FUNCTION;
+        matches!(self.kind, FUNCTION { .. })
+    }
+
+    /// Function type of a function or a pointer to one, i.e. what a call
+    /// can go through
+    pub fn callee_function(&self) -> Option<&Self> {
+        use TypeKind::*;
         match self.kind {
-            FUNCTION { .. } => true,
-            _ => false,
+            FUNCTION { .. } => Some(self),
+            PTR { ref ptr_to } if ptr_to.is_function() => Some(ptr_to),
+            _ => None,
         }
     }
 
     pub fn is_incomplete(&self) -> bool {
-        use TypeKind::INCOMPLETE;
+        use TypeKind::{INCOMPLETE, STRUCT};
         match self.kind {
             INCOMPLETE { .. } => true,
+            STRUCT { ref def } => !def.borrow().is_complete(),
             _ => false,
         }
     }
 
     pub fn is_ptr_like(&self) -> bool {
-        use TypeKind::*;
-        match self.kind {
-            PTR { .. } | ARRAY { .. } => true,
-            _ => false,
-        }
+        use TypeKind::PTR;
+        matches!(self.kind, PTR { .. }) || self.is_array()
     }
 
     pub fn is_integral(&self) -> bool {
         use TypeKind::*;
-        match self.kind {
-            CHAR | SHORT | INT | LONG | ENUM { .. } => true,
-            _ => false,
-        }
+        matches!(self.kind, CHAR | SHORT | INT | LONG | ENUM { .. })
     }
 
     pub fn is_scalar(&self) -> bool {
@@ -285,12 +386,129 @@ impl Type {
         if self.is_integral() {
             return true;
         }
-        match self.kind {
-            PTR { .. } => true,
+        matches!(self.kind, PTR { .. })
+    }
+
+    pub fn is_void_ptr(&self) -> bool {
+        use TypeKind::PTR;
+        matches!(self.kind, PTR { ref ptr_to } if ptr_to.is_void())
+    }
+
+    /// Whether the two types name the same type, as needed for pointer
+    /// assignments. Qualifiers and parameter names do not matter, and an
+    /// enum is taken as its underlying int.
+    pub fn is_compatible(&self, other: &Type) -> bool {
+        use TypeKind::*;
+        match (&self.kind, &other.kind) {
+            (PTR { ptr_to: a }, PTR { ptr_to: b }) => a.is_compatible(b),
+            (
+                ARRAY {
+                    num_elems: n,
+                    ptr_to: a,
+                },
+                ARRAY {
+                    num_elems: m,
+                    ptr_to: b,
+                },
+            ) => n == m && a.is_compatible(b),
+            // An array of unknown size goes with any size
+            (
+                ARRAY { ptr_to: a, .. }
+                | INCOMPLETE {
+                    kind: IncompleteKind::ARRAY { ptr_to: a },
+                },
+                ARRAY { ptr_to: b, .. }
+                | INCOMPLETE {
+                    kind: IncompleteKind::ARRAY { ptr_to: b },
+                },
+            ) => a.is_compatible(b),
+            (
+                FUNCTION {
+                    ret: r1,
+                    args: a1,
+                    variadic: v1,
+                },
+                FUNCTION {
+                    ret: r2,
+                    args: a2,
+                    variadic: v2,
+                },
+            ) => {
+                // An empty list leaves the parameters unspecified, which
+                // goes with any list that default promotions do not alter
+                let unspecified_ok = |args: &[(String, Type)], variadic: bool| {
+                    !variadic && args.iter().all(|(_, ty)| !matches!(ty.kind, CHAR | SHORT))
+                };
+                r1.is_compatible(r2)
+                    && match (a1.is_empty(), a2.is_empty()) {
+                        (true, true) => true,
+                        (true, false) => unspecified_ok(a2, *v2),
+                        (false, true) => unspecified_ok(a1, *v1),
+                        (false, false) => {
+                            v1 == v2
+                                && a1.len() == a2.len()
+                                && a1.iter().zip(a2).all(|((_, x), (_, y))| x.is_compatible(y))
+                        }
+                    }
+            }
+            // A tag names one type; untagged ones are alike if laid out alike
+            (STRUCT { def: d1 }, STRUCT { def: d2 }) => {
+                if Rc::ptr_eq(d1, d2) {
+                    return true;
+                }
+                let (d1, d2) = (d1.borrow(), d2.borrow());
+                let (m1, m2) = (d1.members(), d2.members());
+                d1.name.is_none()
+                    && d2.name.is_none()
+                    && d1.is_union == d2.is_union
+                    && m1.len() == m2.len()
+                    && m1.iter().zip(m2).all(|(x, y)| {
+                        x.name == y.name && x.offset == y.offset && x.ty.is_compatible(&y.ty)
+                    })
+            }
+            // The tag may have been completed since
+            (INCOMPLETE { .. }, _) | (_, INCOMPLETE { .. }) => self.is_enum() && other.is_enum(),
+            (INT | ENUM { .. }, INT | ENUM { .. }) => true,
+            (VOID, VOID) | (CHAR, CHAR) | (SHORT, SHORT) | (LONG, LONG) => true,
             _ => false,
         }
     }
 
+    /// Returns the type that two compatible declarations amount to, i.e.
+    /// the one with the array size or the parameter list if only one has
+    /// it. Parameters left unnamed take the names of the other's. None is
+    /// returned if the types are not compatible.
+    pub fn composite(&self, other: &Type) -> Option<Type> {
+        use TypeKind::FUNCTION;
+        if !self.is_compatible(other) {
+            return None;
+        }
+        if self.is_incomplete() || (self.is_function() && self.func_params().is_none()) {
+            return Some(other.clone());
+        }
+        match (&self.kind, &other.kind) {
+            (
+                FUNCTION {
+                    ret,
+                    args,
+                    variadic,
+                },
+                FUNCTION { args: named, .. },
+            ) if args.len() == named.len() => {
+                let args = args
+                    .iter()
+                    .zip(named)
+                    .map(|((name, ty), (other_name, _))| {
+                        let name = if name.is_empty() { other_name } else { name };
+                        (name.clone(), ty.clone())
+                    })
+                    .collect();
+                Some(Type::new_function((**ret).clone(), args, *variadic))
+            }
+            _ => Some(self.clone()),
+        }
+    }
+
     /// Obtaining size
     pub fn size(&self) -> usize {
         use TypeKind::*;
@@ -301,7 +519,10 @@ impl Type {
             INT => 4,
             LONG | PTR { .. } => 8,
             ARRAY { .. } => self.base_size(),
-            STRUCT { size, .. } => size.clone(),
+            STRUCT { ref def } => match def.borrow().body {
+                Some((size, _)) => size,
+                None => panic!("Requesting size of an incomplete type."),
+            },
             ENUM { .. } => 4,
             FUNCTION { .. } => panic!("not implemented"),
             INCOMPLETE { .. } => panic!("Requesting size of an incomplete type."),
@@ -321,67 +542,268 @@ impl Type {
     pub fn base_size(&self) -> usize {
         use TypeKind::*;
         match self.kind {
-            PTR { ref ptr_to } | ARRAY { ref ptr_to, .. } => ptr_to.total_size(),
+            // Stepping a void * goes byte by byte as in GNU C
+            PTR { ref ptr_to } if ptr_to.is_void() => 1,
+            PTR { ref ptr_to }
+            | ARRAY { ref ptr_to, .. }
+            | INCOMPLETE {
+                kind: IncompleteKind::ARRAY { ref ptr_to },
+            } => ptr_to.total_size(),
             _ => panic!("Requesting a base size for a terminal type."),
         }
     }
 
-    /// Used only for arrays
-    pub fn terminal_size(&self) -> usize {
-        use TypeKind::*;
-        match self.kind {
-            ARRAY { ref ptr_to, .. } => {
-                if ptr_to.is_array() {
-                    ptr_to.terminal_size()
-                } else {
-                    ptr_to.size()
-                }
-            }
-            _ => self.size(),
-        }
-    }
-
-    pub fn num_elems(&self) -> usize {
-        use TypeKind::ARRAY;
-        match self.kind {
-            ARRAY { num_elems, .. } => num_elems,
-            _ => panic!("Requesting the # of elements from a non-array type."),
+    /// Type qualifier setter
+    /// Whether an object of this type may not be modified at all, i.e. it
+    /// or the elements of the array are const-qualified
+    pub fn is_readonly(&self) -> bool {
+        if self.is_array() {
+            self.clone_base().is_readonly()
+        } else {
+            self.is_const
         }
     }
 
-    /// Type qualifier setter
     pub fn set_type_qual(&mut self, is_const: bool, is_volatile: bool) {
         self.is_const = is_const;
         self.is_volatile = is_volatile;
     }
 
-    /// Returns the relative offset and type of a member of struct
+    /// Returns the member of struct with the given name
     /// None is returned if no such member exists
-    pub fn get_member_offset(&self, name: &str) -> Option<(usize, Self)> {
-        use TypeKind::{INCOMPLETE, STRUCT};
-        if !self.is_struct() {
-            panic!("Requesting a member offset from a non-struct type.")
+    pub fn get_member(&self, name: &str) -> Option<StructMember> {
+        use TypeKind::STRUCT;
+        match self.kind {
+            STRUCT { ref def } => {
+                let def = def.borrow();
+                if !def.is_complete() {
+                    panic!("Requesting a member of an incomplete type.")
+                }
+                def.members().iter().find(|m| m.name == name).cloned()
+            }
+            _ => panic!("Requesting a member offset from a non-struct type."),
         }
+    }
+
+    /// Returns what an initializer list for this aggregate goes through, in
+    /// order: the elements of an array, the named members of a struct or
+    /// the first member of a union. Array elements come without a name.
+    pub fn subobjects(&self) -> Vec<StructMember> {
+        use TypeKind::{ARRAY, STRUCT};
         match self.kind {
-            STRUCT { ref members, .. } => {
-                let maybe_found = members.iter().find(|m| m.name == name);
-                if let Some(found) = maybe_found {
-                    Some((found.offset, found.ty.clone()))
+            ARRAY {
+                num_elems,
+                ref ptr_to,
+            } => (0..num_elems)
+                .map(|i| StructMember {
+                    name: String::new(),
+                    ty: (**ptr_to).clone(),
+                    offset: i * ptr_to.total_size(),
+                    bits: None,
+                })
+                .collect(),
+            STRUCT { ref def } => {
+                let def = def.borrow();
+                let named = def.members().iter().filter(|m| !m.name.is_empty()).cloned();
+                if def.is_union {
+                    named.take(1).collect()
                 } else {
-                    None
+                    named.collect()
                 }
             }
-            INCOMPLETE { ref kind } => panic!("Not implemented yet."),
-            _ => panic!("Unreacheable."),
+            _ => panic!("Requesting the subobjects of a non-aggregate type."),
         }
     }
 
     /// Returns an iterator over arguments of a function type.
-    pub fn iter_func_args(&self) -> Iter<(String, Type)> {
+    pub fn iter_func_args(&self) -> Iter<'_, (String, Type)> {
         use TypeKind::FUNCTION;
         match self.kind {
-            FUNCTION { ref args } => args.iter(),
+            FUNCTION { ref args, .. } => args.iter(),
             _ => panic!("Requesting an argument iterator from non-function type."),
         }
     }
+
+    /// Returns the declared parameters of a function type and whether more
+    /// may follow. None is returned if the parameters are unspecified, as in
+    /// "int f()", and "(void)" gives no parameters.
+    pub fn func_params(&self) -> Option<(&[(String, Type)], bool)> {
+        use TypeKind::FUNCTION;
+        match self.kind {
+            FUNCTION { ref args, .. } if args.is_empty() => None,
+            FUNCTION { ref args, .. } if args.len() == 1 && args[0].1.is_void() => {
+                Some((&[], false))
+            }
+            FUNCTION {
+                ref args, variadic, ..
+            } => Some((args, variadic)),
+            _ => panic!("Requesting the parameters of non-function type."),
+        }
+    }
+
+    /// Returns the return type of a function type.
+    pub fn func_ret(&self) -> &Self {
+        use TypeKind::FUNCTION;
+        match self.kind {
+            FUNCTION { ref ret, .. } => ret,
+            _ => panic!("Requesting the return type of non-function type."),
+        }
+    }
+
+    /// Renders a C declaration of name with this type, e.g. "int (*p)[3]".
+    /// Untagged struct and enum types are spelled out with their bodies so
+    /// that the result can be parsed again.
+    pub fn declare(&self, name: &str) -> String {
+        let (base, declarator) = self.render(name.to_string(), true);
+        join_decl(base, &declarator)
+    }
+
+    /// Splits declare() into the specifiers and the declarator so that
+    /// several declarators can share one set of specifiers.
+    pub fn split_declare(&self, name: &str) -> (String, String) {
+        self.render(name.to_string(), true)
+    }
+
+    fn qual_prefix(&self) -> String {
+        let mut q = String::new();
+        if self.is_const {
+            q.push_str("const ");
+        }
+        if self.is_volatile {
+            q.push_str("volatile ");
+        }
+        q
+    }
+
+    // Builds the declarator inside-out: inner is what has been wrapped so far
+    fn render(&self, inner: String, full: bool) -> (String, String) {
+        use TypeKind::*;
+
+        let base = match self.kind {
+            PTR { ref ptr_to } => {
+                let inner = format!("*{}{}", self.qual_prefix(), inner);
+                return ptr_to.render(inner, full);
+            }
+            ARRAY {
+                num_elems,
+                ref ptr_to,
+            } => {
+                let inner = if inner.starts_with('*') {
+                    format!("({})", inner)
+                } else {
+                    inner
+                };
+                return ptr_to.render(format!("{}[{}]", inner, num_elems), full);
+            }
+            INCOMPLETE {
+                kind: IncompleteKind::ARRAY { ref ptr_to },
+            } => {
+                let inner = if inner.starts_with('*') {
+                    format!("({})", inner)
+                } else {
+                    inner
+                };
+                return ptr_to.render(format!("{}[]", inner), full);
+            }
+            FUNCTION {
+                ref ret,
+                ref args,
+                variadic,
+            } => {
+                let inner = if inner.starts_with('*') {
+                    format!("({})", inner)
+                } else {
+                    inner
+                };
+                let mut params: Vec<String> = args
+                    .iter()
+                    .map(|(name, ty)| {
+                        let (base, declarator) = ty.render(name.clone(), full);
+                        join_decl(base, &declarator)
+                    })
+                    .collect();
+                if variadic {
+                    params.push("...".to_string());
+                }
+                return ret.render(format!("{}({})", inner, params.join(", ")), full);
+            }
+            VOID => "void".to_string(),
+            CHAR => "char".to_string(),
+            SHORT => "short".to_string(),
+            INT => "int".to_string(),
+            LONG => "long".to_string(),
+            STRUCT { ref def } => {
+                let def = def.borrow();
+                // Tagged ones are defined on their own, see define_struct()
+                if full && def.name.is_none() {
+                    define_struct(&def)
+                } else {
+                    def.spelling()
+                }
+            }
+            ENUM { ref tag, .. } => match tag {
+                // Tagged ones are defined on their own, see define_enum()
+                Some(tag) => format!("enum {}", tag),
+                None if full => define_enum(self),
+                None => "enum".to_string(),
+            },
+            INCOMPLETE { ref kind } => match kind {
+                IncompleteKind::ENUM => "enum __incomplete".to_string(),
+                _ => "void".to_string(),
+            },
+        };
+
+        (self.qual_prefix() + &base, inner)
+    }
+}
+
+/// Renders the definition of a complete enum, e.g.
+/// "enum color { RED = 0, GREEN = 1 }"
+pub fn define_enum(ty: &Type) -> String {
+    let body: Vec<String> = ty
+        .enum_members()
+        .iter()
+        .map(|m| format!("{} = {}", m.name, m.val))
+        .collect();
+    match ty.enum_tag() {
+        Some(tag) => format!("enum {} {{ {} }}", tag, body.join(", ")),
+        None => format!("enum {{ {} }}", body.join(", ")),
+    }
+}
+
+/// Renders the definition of a complete struct or union, e.g.
+/// "struct node { int val; struct node *next; }"
+pub fn define_struct(def: &StructDef) -> String {
+    format!("{} {}", def.spelling(), struct_body(def))
+}
+
+fn struct_body(def: &StructDef) -> String {
+    let mut body = "{".to_string();
+    for m in def.members().iter() {
+        let (base, declarator) = m.ty.render(m.name.clone(), true);
+        let mut decl = join_decl(base, &declarator);
+        if let Some((_, width)) = m.bits {
+            decl.push_str(&format!(" : {}", width));
+        }
+        body.push_str(&format!(" {};", decl));
+    }
+    body.push_str(" }");
+    body
+}
+
+fn join_decl(mut base: String, declarator: &str) -> String {
+    if !declarator.is_empty() {
+        if !declarator.starts_with('[') {
+            base.push(' ');
+        }
+        base.push_str(declarator);
+    }
+    base
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (base, declarator) = self.render(String::new(), false);
+        write!(f, "{}", join_decl(base, &declarator))
+    }
 }
@@ -0,0 +1,324 @@
+// Debug dumps of tokens and the AST
+use crate::node::{Node, NodeKind};
+use crate::parser::Program;
+use crate::tokenizer::{Span, TokenIter, TokenKind};
+use std::fmt::Write;
+
+fn fmt_span(span: &Span) -> String {
+    format!(
+        "{}:{}-{}:{}",
+        span.lo.line, span.lo.col, span.hi.line, span.hi.col
+    )
+}
+
+/// One token per line: span, kind and its string or value
+pub fn dump_tokens(iter: &TokenIter) -> String {
+    let mut out = String::new();
+    for t in iter.iter() {
+        let text = match t.kind {
+            TokenKind::TKNUM => t.val.to_string(),
+            TokenKind::TKSTR => format!("\"{}\"", t.string.as_ref().unwrap()),
+            TokenKind::TKEOF => String::new(),
+            _ => t.string.clone().unwrap(),
+        };
+        writeln!(
+            out,
+            "{:<12} {:<10} {}",
+            fmt_span(&t.span),
+            format!("{:?}", t.kind),
+            text
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Indented tree of the whole program.
+/// Spans are left out when with_spans is false so that two parses of
+/// equivalent sources can be compared textually.
+pub fn dump_program(prog: &Program, with_spans: bool) -> String {
+    let mut d = AstDumper {
+        out: String::new(),
+        with_spans,
+    };
+
+    for gvar in prog.globals.iter() {
+        if gvar.is_extern {
+            writeln!(d.out, "global {}: {} extern", gvar.name, gvar.ty).unwrap();
+        } else {
+            writeln!(d.out, "global {}: {}", gvar.name, gvar.ty).unwrap();
+        }
+        if let Some(init) = prog.global_inits.iter().find(|i| i.name == gvar.name) {
+            for init in init.vals.iter() {
+                let label = match init.bits {
+                    Some((bit, _)) => format!("+{}:{}", init.offset, bit),
+                    None => format!("+{}", init.offset),
+                };
+                d.node(1, Some(&label), &init.val);
+            }
+        }
+    }
+    // Redeclarations are recorded every time; show each function once
+    let mut seen: Vec<&str> = Vec::new();
+    for (name, ty, _) in prog.prototypes.iter() {
+        if !seen.contains(&name.as_str()) {
+            seen.push(name);
+            writeln!(d.out, "prototype {}: {}", name, ty).unwrap();
+        }
+    }
+    for (pos, literal) in prog.literals.iter().enumerate() {
+        writeln!(d.out, "literal {}: \"{}\"", pos, literal).unwrap();
+    }
+    for node in prog.nodes.iter() {
+        d.node(0, None, node);
+    }
+    d.out
+}
+
+struct AstDumper {
+    out: String,
+    with_spans: bool,
+}
+
+impl AstDumper {
+    fn line(&mut self, depth: usize, label: Option<&str>, node: &Node, head: String) {
+        let mut s = "  ".repeat(depth);
+        if let Some(label) = label {
+            s.push_str(label);
+            s.push_str(": ");
+        }
+        s.push_str(&head);
+        if let Some(ref ty) = node.ty {
+            write!(s, " <{}>", ty).unwrap();
+        }
+        if self.with_spans {
+            if let Some(ref span) = node.span {
+                write!(s, " @{}", fmt_span(span)).unwrap();
+            }
+        }
+        writeln!(self.out, "{}", s).unwrap();
+    }
+
+    fn opt(&mut self, depth: usize, label: &str, node: &Option<Box<Node>>) {
+        if let Some(node) = node {
+            self.node(depth, Some(label), node);
+        }
+    }
+
+    fn node(&mut self, depth: usize, label: Option<&str>, node: &Node) {
+        use NodeKind::*;
+
+        let d = depth + 1;
+        match node.kind {
+            NDINT { val } => self.line(depth, label, node, format!("NDINT {}", val)),
+            NDSTR { pos } => self.line(depth, label, node, format!("NDSTR {}", pos)),
+            NDADD {
+                ref lhs, ref rhs, ..
+            }
+            | NDSUB {
+                ref lhs, ref rhs, ..
+            }
+            | NDMUL { ref lhs, ref rhs }
+            | NDDIV { ref lhs, ref rhs }
+            | NDMOD { ref lhs, ref rhs }
+            | NDEQ { ref lhs, ref rhs }
+            | NDNEQ { ref lhs, ref rhs }
+            | NDLEQ { ref lhs, ref rhs }
+            | NDLT { ref lhs, ref rhs }
+            | NDBITAND { ref lhs, ref rhs }
+            | NDBITXOR { ref lhs, ref rhs }
+            | NDBITOR { ref lhs, ref rhs }
+            | NDLOGAND { ref lhs, ref rhs }
+            | NDLOGOR { ref lhs, ref rhs }
+            | NDSHL { ref lhs, ref rhs }
+            | NDSHR { ref lhs, ref rhs } => {
+                self.line(depth, label, node, node.kind.name().to_string());
+                self.node(d, None, lhs);
+                self.node(d, None, rhs);
+            }
+            NDASSIGN {
+                ref lhs,
+                ref rhs,
+                eval_pre,
+                assign_mode,
+                is_init,
+                ..
+            } => {
+                let mut head = format!("NDASSIGN {:?}", assign_mode);
+                if !eval_pre {
+                    head.push_str(" post");
+                }
+                if is_init {
+                    head.push_str(" init");
+                }
+                self.line(depth, label, node, head);
+                self.node(d, None, lhs);
+                self.node(d, None, rhs);
+            }
+            NDBITNOT { node: ref operand }
+            | NDADDR { node: ref operand }
+            | NDDEREF { node: ref operand }
+            | NDRETURN { node: ref operand }
+            | NDCAST { node: ref operand } => {
+                self.line(depth, label, node, node.kind.name().to_string());
+                self.node(d, None, operand);
+            }
+            NDMEMBER {
+                node: ref operand,
+                ref name,
+                offset,
+                bits,
+            } => {
+                let mut head = match offset {
+                    Some(ofs) => format!("NDMEMBER {} +{}", name, ofs),
+                    None => format!("NDMEMBER {}", name),
+                };
+                if let Some((bit, width)) = bits {
+                    write!(head, " bits {}:{}", bit, width).unwrap();
+                }
+                self.line(depth, label, node, head);
+                self.node(d, None, operand);
+            }
+            NDCOND {
+                ref cond,
+                ref then,
+                ref els,
+            } => {
+                self.line(depth, label, node, "NDCOND".to_string());
+                self.node(d, Some("cond"), cond);
+                self.node(d, Some("then"), then);
+                self.node(d, Some("else"), els);
+            }
+            NDBREAK | NDCONTINUE => self.line(depth, label, node, node.kind.name().to_string()),
+            NDIF {
+                ref cond,
+                ref ifnode,
+                ref elsenode,
+            } => {
+                self.line(depth, label, node, "NDIF".to_string());
+                self.node(d, Some("cond"), cond);
+                self.opt(d, "then", ifnode);
+                self.opt(d, "else", elsenode);
+            }
+            NDSWITCH {
+                ref ctrl,
+                ref stmt,
+                ref cases,
+                has_default,
+            } => {
+                let cases: Vec<String> = cases.iter().map(|c| c.to_string()).collect();
+                let mut head = format!("NDSWITCH cases=[{}]", cases.join(", "));
+                if has_default {
+                    head.push_str(" default");
+                }
+                self.line(depth, label, node, head);
+                self.node(d, Some("ctrl"), ctrl);
+                self.opt(d, "body", stmt);
+            }
+            NDWHILE {
+                ref cond,
+                ref repnode,
+            }
+            | NDDOWHILE {
+                ref cond,
+                ref repnode,
+            } => {
+                self.line(depth, label, node, node.kind.name().to_string());
+                self.node(d, Some("cond"), cond);
+                self.opt(d, "body", repnode);
+            }
+            NDFOR {
+                ref init,
+                ref cond,
+                ref step,
+                ref repnode,
+            } => {
+                self.line(depth, label, node, "NDFOR".to_string());
+                self.opt(d, "init", init);
+                self.opt(d, "cond", cond);
+                self.opt(d, "step", step);
+                self.opt(d, "body", repnode);
+            }
+            NDBLOCK { ref stmts } => {
+                self.line(depth, label, node, "NDBLOCK".to_string());
+                for stmt in stmts.iter() {
+                    self.node(d, None, stmt);
+                }
+            }
+            NDCASE { ref stmt, val, .. } => {
+                self.line(depth, label, node, format!("NDCASE {}", val));
+                self.opt(d, "stmt", stmt);
+            }
+            NDDEFAULT { ref stmt } => {
+                self.line(depth, label, node, "NDDEFAULT".to_string());
+                self.opt(d, "stmt", stmt);
+            }
+            NDCALL {
+                ref prototy,
+                ref args,
+            } => {
+                self.line(depth, label, node, "NDCALL".to_string());
+                self.node(d, Some("callee"), prototy);
+                for arg in args.iter() {
+                    self.node(d, Some("arg"), arg);
+                }
+            }
+            NDFUNCDEF {
+                ref name,
+                ref argvars,
+                ref stmts,
+                lvars_offset,
+            } => {
+                self.line(
+                    depth,
+                    label,
+                    node,
+                    format!("NDFUNCDEF {} frame={}", name, lvars_offset),
+                );
+                for var in argvars.iter() {
+                    writeln!(
+                        self.out,
+                        "{}param {}: {} -{}",
+                        "  ".repeat(d),
+                        var.name,
+                        var.ty,
+                        var.offset.unwrap()
+                    )
+                    .unwrap();
+                }
+                for stmt in stmts.iter() {
+                    self.node(d, None, stmt);
+                }
+            }
+            NDDECL {
+                ref vars,
+                ref inits,
+            } => {
+                self.line(depth, label, node, "NDDECL".to_string());
+                for var in vars.iter() {
+                    let place = match var.offset {
+                        Some(offset) => format!("-{}", offset),
+                        None => "extern".to_string(),
+                    };
+                    writeln!(
+                        self.out,
+                        "{}var {}: {} {}",
+                        "  ".repeat(d),
+                        var.name,
+                        var.ty,
+                        place
+                    )
+                    .unwrap();
+                }
+                for init in inits.iter() {
+                    self.node(d, None, init);
+                }
+            }
+            NDLVAR { ref name, offset } => {
+                self.line(depth, label, node, format!("NDLVAR {} -{}", name, offset))
+            }
+            NDGVAR { ref name } => self.line(depth, label, node, format!("NDGVAR {}", name)),
+            NDPROTOTY { ref name } => self.line(depth, label, node, format!("NDPROTOTY {}", name)),
+        }
+    }
+}
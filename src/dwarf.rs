@@ -0,0 +1,608 @@
+// DWARF debug information for -g.
+// The line table is left to the assembler, which builds .debug_line from
+// the .loc directives codegen places. This module writes .debug_info and
+// .debug_abbrev: every entry is built in memory first and the abbreviations
+// are made up from the shapes of attributes that occur.
+use crate::cenv::Var;
+use crate::ctype::Type;
+use crate::node::{Node, NodeKind};
+use crate::parser::Program;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+pub static TEXT_BEGIN: &str = ".Ltext0";
+pub static TEXT_END: &str = ".Letext0";
+static INFO_BEGIN: &str = ".Ldebug_info0";
+static ABBREV_BEGIN: &str = ".Ldebug_abbrev0";
+static LINE_BEGIN: &str = ".Ldebug_line0";
+
+// Tags
+const DW_TAG_ARRAY_TYPE: u64 = 0x01;
+const DW_TAG_ENUMERATION_TYPE: u64 = 0x04;
+const DW_TAG_FORMAL_PARAMETER: u64 = 0x05;
+const DW_TAG_MEMBER: u64 = 0x0d;
+const DW_TAG_POINTER_TYPE: u64 = 0x0f;
+const DW_TAG_COMPILE_UNIT: u64 = 0x11;
+const DW_TAG_STRUCTURE_TYPE: u64 = 0x13;
+const DW_TAG_SUBROUTINE_TYPE: u64 = 0x15;
+const DW_TAG_UNION_TYPE: u64 = 0x17;
+const DW_TAG_UNSPECIFIED_PARAMETERS: u64 = 0x18;
+const DW_TAG_SUBRANGE_TYPE: u64 = 0x21;
+const DW_TAG_BASE_TYPE: u64 = 0x24;
+const DW_TAG_CONST_TYPE: u64 = 0x26;
+const DW_TAG_ENUMERATOR: u64 = 0x28;
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+const DW_TAG_VARIABLE: u64 = 0x34;
+const DW_TAG_VOLATILE_TYPE: u64 = 0x35;
+
+// Attributes
+const DW_AT_LOCATION: u64 = 0x02;
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_BYTE_SIZE: u64 = 0x0b;
+const DW_AT_BIT_SIZE: u64 = 0x0d;
+const DW_AT_STMT_LIST: u64 = 0x10;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_LANGUAGE: u64 = 0x13;
+const DW_AT_COMP_DIR: u64 = 0x1b;
+const DW_AT_CONST_VALUE: u64 = 0x1c;
+const DW_AT_PRODUCER: u64 = 0x25;
+const DW_AT_PROTOTYPED: u64 = 0x27;
+const DW_AT_UPPER_BOUND: u64 = 0x2f;
+const DW_AT_DATA_MEMBER_LOCATION: u64 = 0x38;
+const DW_AT_DECL_FILE: u64 = 0x3a;
+const DW_AT_DECL_LINE: u64 = 0x3b;
+const DW_AT_DECLARATION: u64 = 0x3c;
+const DW_AT_ENCODING: u64 = 0x3e;
+const DW_AT_EXTERNAL: u64 = 0x3f;
+const DW_AT_TYPE: u64 = 0x49;
+const DW_AT_DATA_BIT_OFFSET: u64 = 0x6b;
+
+// Forms
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_SDATA: u64 = 0x0d;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_REF4: u64 = 0x13;
+const DW_FORM_SEC_OFFSET: u64 = 0x17;
+const DW_FORM_EXPRLOC: u64 = 0x18;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+
+const DW_ATE_SIGNED: u8 = 0x05;
+const DW_ATE_SIGNED_CHAR: u8 = 0x06;
+const DW_LANG_C99: u8 = 0x0c;
+const DW_OP_ADDR: u8 = 0x03;
+const DW_OP_BREG6: u8 = 0x76; // rbp plus an offset
+
+enum Value {
+    Str(String),            // Inline string
+    Data1(u8),              // One byte
+    Udata(usize),           // ULEB128
+    Sdata(i64),             // SLEB128
+    Flag,                   // Present without a value
+    Ref(String),            // Label of another entry in the unit
+    Addr(String),           // Address of a symbol
+    Length(String, String), // End label minus start label
+    Offset(String),         // Label within another section
+    Frame(usize),           // Expression for rbp - offset
+    Symbol(String),         // Expression for the address of a symbol
+}
+
+impl Value {
+    fn form(&self) -> u64 {
+        match self {
+            Value::Str(_) => DW_FORM_STRING,
+            Value::Data1(_) => DW_FORM_DATA1,
+            Value::Udata(_) => DW_FORM_UDATA,
+            Value::Sdata(_) => DW_FORM_SDATA,
+            Value::Flag => DW_FORM_FLAG_PRESENT,
+            Value::Ref(_) => DW_FORM_REF4,
+            Value::Addr(_) => DW_FORM_ADDR,
+            Value::Length(..) => DW_FORM_DATA8,
+            Value::Offset(_) => DW_FORM_SEC_OFFSET,
+            Value::Frame(_) | Value::Symbol(_) => DW_FORM_EXPRLOC,
+        }
+    }
+}
+
+/// A debugging information entry
+struct Die {
+    label: Option<String>, // For references from other entries
+    tag: u64,
+    attrs: Vec<(u64, Value)>,
+    children: Option<Vec<Die>>, // None if the entry cannot have any
+}
+
+impl Die {
+    fn new(tag: u64) -> Self {
+        Die {
+            label: None,
+            tag,
+            attrs: Vec::new(),
+            children: None,
+        }
+    }
+
+    fn attr(mut self, at: u64, val: Value) -> Self {
+        self.attrs.push((at, val));
+        self
+    }
+
+    fn opt_attr(self, at: u64, val: Option<Value>) -> Self {
+        match val {
+            Some(val) => self.attr(at, val),
+            None => self,
+        }
+    }
+
+    // An entry without children is marked as such in its abbreviation
+    fn children(mut self, children: Vec<Die>) -> Self {
+        self.children = Some(children).filter(|children| !children.is_empty());
+        self
+    }
+}
+
+// Tag, whether children follow and the attributes with their forms
+type Abbrev = (u64, bool, Vec<(u64, u64)>);
+
+struct FuncInfo {
+    name: String,
+    line: usize,
+    ty: Type,
+    params: Vec<Var>,
+    locals: Vec<Var>,
+}
+
+/// What the debug information describes, gathered from the program
+pub struct DebugInfo {
+    file: String,
+    functions: Vec<FuncInfo>,
+    globals: Vec<Var>,
+}
+
+/// Label placed right after the code of a function
+pub fn func_end(name: &str) -> String {
+    format!(".Lfunc_end_{}", name)
+}
+
+impl DebugInfo {
+    pub fn new(prog: &Program, file: &str) -> Self {
+        let mut functions = Vec::new();
+        for node in prog.nodes.iter() {
+            if let NodeKind::NDFUNCDEF {
+                ref name,
+                ref argvars,
+                ref stmts,
+                ..
+            } = node.kind
+            {
+                // The prototype given by the definition itself
+                let span = node.span.unwrap();
+                let (_, ty, decl) = prog
+                    .prototypes
+                    .iter()
+                    .find(|(proto, _, s)| proto == name && span.lo <= s.lo && s.hi <= span.hi)
+                    .unwrap();
+                let mut locals = Vec::new();
+                for stmt in stmts.iter() {
+                    collect_locals(stmt, &mut locals);
+                }
+                functions.push(FuncInfo {
+                    name: name.clone(),
+                    line: decl.lo.line,
+                    ty: ty.clone(),
+                    params: argvars.iter().cloned().collect(),
+                    locals,
+                });
+            }
+        }
+        let globals = prog
+            .globals
+            .iter()
+            .filter(|gvar| !gvar.is_extern)
+            .cloned()
+            .collect();
+        DebugInfo {
+            file: file.to_string(),
+            functions,
+            globals,
+        }
+    }
+
+    /// Line on which the named function is defined
+    pub fn func_line(&self, name: &str) -> Option<usize> {
+        self.functions
+            .iter()
+            .find(|func| func.name == name)
+            .map(|func| func.line)
+    }
+
+    /// Names the source for the line table
+    pub fn gen_file(&self, f: &mut dyn Write) {
+        gen_line!(f, ".file 1 \"{}\"\n", escape(&self.file));
+    }
+
+    pub fn gen_sections(&self, f: &mut dyn Write) {
+        let mut types = TypeTable::default();
+        let mut children = Vec::new();
+        for gvar in self.globals.iter() {
+            children.push(
+                Die::new(DW_TAG_VARIABLE)
+                    .attr(DW_AT_NAME, Value::Str(gvar.name.clone()))
+                    .opt_attr(DW_AT_TYPE, types.reference(&gvar.ty))
+                    .attr(DW_AT_EXTERNAL, Value::Flag)
+                    .attr(DW_AT_LOCATION, Value::Symbol(gvar.name.clone())),
+            );
+        }
+        for func in self.functions.iter() {
+            children.push(self.subprogram(func, &mut types));
+        }
+        children.append(&mut types.finish());
+
+        let dir = std::env::current_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+        let unit = Die::new(DW_TAG_COMPILE_UNIT)
+            .attr(DW_AT_PRODUCER, Value::Str("yarcc".to_string()))
+            .attr(DW_AT_LANGUAGE, Value::Data1(DW_LANG_C99))
+            .attr(DW_AT_NAME, Value::Str(self.file.clone()))
+            .attr(DW_AT_COMP_DIR, Value::Str(dir))
+            .attr(DW_AT_LOW_PC, Value::Addr(TEXT_BEGIN.to_string()))
+            .attr(
+                DW_AT_HIGH_PC,
+                Value::Length(TEXT_END.to_string(), TEXT_BEGIN.to_string()),
+            )
+            .attr(DW_AT_STMT_LIST, Value::Offset(LINE_BEGIN.to_string()))
+            .children(children);
+
+        let mut abbrevs: Vec<Abbrev> = Vec::new();
+        gen_line!(f, "\n.section .debug_info,\"\",@progbits\n");
+        gen_line!(f, "{}:\n", INFO_BEGIN);
+        gen_line!(f, "  .long .Ldebug_info_end0-.Ldebug_info_start0\n");
+        gen_line!(f, ".Ldebug_info_start0:\n");
+        gen_line!(f, "  .short 4\n");
+        gen_line!(f, "  .long {}\n", ABBREV_BEGIN);
+        gen_line!(f, "  .byte 8\n");
+        gen_die(f, &unit, &mut abbrevs);
+        gen_line!(f, ".Ldebug_info_end0:\n");
+
+        gen_line!(f, "\n.section .debug_abbrev,\"\",@progbits\n");
+        gen_line!(f, "{}:\n", ABBREV_BEGIN);
+        for (code, (tag, has_children, attrs)) in abbrevs.iter().enumerate() {
+            gen_line!(f, "  .uleb128 {}\n", code + 1);
+            gen_line!(f, "  .uleb128 {:#x}\n", tag);
+            gen_line!(f, "  .byte {}\n", *has_children as u8);
+            for (at, form) in attrs.iter() {
+                gen_line!(f, "  .uleb128 {:#x}\n", at);
+                gen_line!(f, "  .uleb128 {:#x}\n", form);
+            }
+            gen_line!(f, "  .byte 0\n");
+            gen_line!(f, "  .byte 0\n");
+        }
+        gen_line!(f, "  .byte 0\n");
+
+        // The assembler fills in the line table after this label
+        gen_line!(f, "\n.section .debug_line,\"\",@progbits\n");
+        gen_line!(f, "{}:\n", LINE_BEGIN);
+    }
+
+    fn subprogram(&self, func: &FuncInfo, types: &mut TypeTable) -> Die {
+        let mut children = Vec::new();
+        for (tag, vars) in [
+            (DW_TAG_FORMAL_PARAMETER, &func.params),
+            (DW_TAG_VARIABLE, &func.locals),
+        ] {
+            for var in vars.iter() {
+                children.push(
+                    Die::new(tag)
+                        .attr(DW_AT_NAME, Value::Str(var.name.clone()))
+                        .opt_attr(DW_AT_TYPE, types.reference(&var.ty))
+                        .attr(DW_AT_LOCATION, Value::Frame(var.offset.unwrap())),
+                );
+            }
+        }
+        Die::new(DW_TAG_SUBPROGRAM)
+            .attr(DW_AT_NAME, Value::Str(func.name.clone()))
+            .attr(DW_AT_DECL_FILE, Value::Data1(1))
+            .attr(DW_AT_DECL_LINE, Value::Udata(func.line))
+            .opt_attr(DW_AT_PROTOTYPED, func.ty.func_params().map(|_| Value::Flag))
+            .opt_attr(DW_AT_TYPE, types.reference(func.ty.func_ret()))
+            .attr(DW_AT_EXTERNAL, Value::Flag)
+            .attr(DW_AT_LOW_PC, Value::Addr(func.name.clone()))
+            .attr(
+                DW_AT_HIGH_PC,
+                Value::Length(func_end(&func.name), func.name.clone()),
+            )
+            .children(children)
+    }
+}
+
+// Objects with a place in the frame, in the order they are declared
+fn collect_locals(node: &Node, locals: &mut Vec<Var>) {
+    use NodeKind::*;
+
+    let opt = |stmt: &Option<Box<Node>>, locals: &mut Vec<Var>| {
+        if let Some(stmt) = stmt {
+            collect_locals(stmt, locals);
+        }
+    };
+    match node.kind {
+        NDDECL { ref vars, .. } => {
+            locals.extend(vars.iter().filter(|var| var.offset.is_some()).cloned());
+        }
+        NDBLOCK { ref stmts } => {
+            for stmt in stmts.iter() {
+                collect_locals(stmt, locals);
+            }
+        }
+        NDIF {
+            ref ifnode,
+            ref elsenode,
+            ..
+        } => {
+            opt(ifnode, locals);
+            opt(elsenode, locals);
+        }
+        NDSWITCH { ref stmt, .. } | NDCASE { ref stmt, .. } | NDDEFAULT { ref stmt } => {
+            opt(stmt, locals)
+        }
+        NDWHILE { ref repnode, .. } | NDDOWHILE { ref repnode, .. } | NDFOR { ref repnode, .. } => {
+            opt(repnode, locals)
+        }
+        _ => (),
+    }
+}
+
+/// Entries for the types referred to, each made once
+#[derive(Default)]
+struct TypeTable {
+    dies: Vec<Option<Die>>, // None while the entry is being built
+    labels: HashMap<String, String>,
+}
+
+impl TypeTable {
+    /// Label of the entry for ty; None for void, which has no entry
+    fn reference(&mut self, ty: &Type) -> Option<Value> {
+        if ty.is_void() {
+            return None;
+        }
+        let key = type_key(ty);
+        if let Some(label) = self.labels.get(&key) {
+            return Some(Value::Ref(label.clone()));
+        }
+        // Reserve the entry first so that a struct can point at itself
+        let label = format!(".Ldebug_type{}", self.dies.len());
+        let index = self.dies.len();
+        self.dies.push(None);
+        self.labels.insert(key, label.clone());
+        let mut die = self.build(ty);
+        die.label = Some(label.clone());
+        self.dies[index] = Some(die);
+        Some(Value::Ref(label))
+    }
+
+    fn build(&mut self, ty: &Type) -> Die {
+        if ty.is_const || ty.is_volatile {
+            let mut unqualified = ty.clone();
+            let tag = if ty.is_const {
+                unqualified.set_type_qual(false, ty.is_volatile);
+                DW_TAG_CONST_TYPE
+            } else {
+                unqualified.set_type_qual(false, false);
+                DW_TAG_VOLATILE_TYPE
+            };
+            return Die::new(tag).opt_attr(DW_AT_TYPE, self.reference(&unqualified));
+        }
+        if ty.is_array() {
+            let subrange = Die::new(DW_TAG_SUBRANGE_TYPE).opt_attr(
+                DW_AT_UPPER_BOUND,
+                ty.array_len()
+                    .filter(|len| *len > 0)
+                    .map(|len| Value::Udata(len - 1)),
+            );
+            return Die::new(DW_TAG_ARRAY_TYPE)
+                .opt_attr(DW_AT_TYPE, self.reference(&ty.clone_base()))
+                .children(vec![subrange]);
+        }
+        if ty.is_ptr_like() {
+            return Die::new(DW_TAG_POINTER_TYPE)
+                .attr(DW_AT_BYTE_SIZE, Value::Data1(8))
+                .opt_attr(DW_AT_TYPE, self.reference(&ty.clone_base()));
+        }
+        if ty.is_function() {
+            let mut params: Vec<Die> = Vec::new();
+            let variadic = match ty.func_params() {
+                Some((args, variadic)) => {
+                    for (_, arg) in args.iter() {
+                        let param = Die::new(DW_TAG_FORMAL_PARAMETER);
+                        params.push(param.opt_attr(DW_AT_TYPE, self.reference(arg)));
+                    }
+                    variadic
+                }
+                None => true,
+            };
+            if variadic {
+                params.push(Die::new(DW_TAG_UNSPECIFIED_PARAMETERS));
+            }
+            return Die::new(DW_TAG_SUBROUTINE_TYPE)
+                .opt_attr(DW_AT_PROTOTYPED, ty.func_params().map(|_| Value::Flag))
+                .opt_attr(DW_AT_TYPE, self.reference(ty.func_ret()))
+                .children(params);
+        }
+        if let Some(def) = ty.struct_def() {
+            let def = def.borrow();
+            let tag = if def.is_union {
+                DW_TAG_UNION_TYPE
+            } else {
+                DW_TAG_STRUCTURE_TYPE
+            };
+            let die = Die::new(tag).opt_attr(DW_AT_NAME, def.name.clone().map(Value::Str));
+            if !def.is_complete() {
+                return die.attr(DW_AT_DECLARATION, Value::Flag);
+            }
+            let mut members = Vec::new();
+            for m in def.members().iter() {
+                let name = Some(m.name.clone())
+                    .filter(|name| !name.is_empty())
+                    .map(Value::Str);
+                let member = Die::new(DW_TAG_MEMBER)
+                    .opt_attr(DW_AT_NAME, name)
+                    .opt_attr(DW_AT_TYPE, self.reference(&m.ty));
+                members.push(match m.bits {
+                    Some((bit, width)) => member
+                        .attr(DW_AT_BIT_SIZE, Value::Udata(width))
+                        .attr(DW_AT_DATA_BIT_OFFSET, Value::Udata(m.offset * 8 + bit)),
+                    None => member.attr(DW_AT_DATA_MEMBER_LOCATION, Value::Udata(m.offset)),
+                });
+            }
+            return die
+                .attr(DW_AT_BYTE_SIZE, Value::Udata(ty.size()))
+                .children(members);
+        }
+        if ty.is_enum() {
+            let name = ty.enum_tag().map(|tag| Value::Str(tag.to_string()));
+            let die = Die::new(DW_TAG_ENUMERATION_TYPE).opt_attr(DW_AT_NAME, name);
+            if ty.is_incomplete() {
+                return die.attr(DW_AT_DECLARATION, Value::Flag);
+            }
+            let enumerators = ty
+                .enum_members()
+                .iter()
+                .map(|m| {
+                    Die::new(DW_TAG_ENUMERATOR)
+                        .attr(DW_AT_NAME, Value::Str(m.name.clone()))
+                        .attr(DW_AT_CONST_VALUE, Value::Sdata(m.val as i64))
+                })
+                .collect();
+            return die
+                .attr(DW_AT_BYTE_SIZE, Value::Data1(4))
+                .opt_attr(DW_AT_TYPE, self.reference(&Type::new_base("int")))
+                .children(enumerators);
+        }
+        let encoding = if ty.size() == 1 {
+            DW_ATE_SIGNED_CHAR
+        } else {
+            DW_ATE_SIGNED
+        };
+        Die::new(DW_TAG_BASE_TYPE)
+            .attr(DW_AT_NAME, Value::Str(ty.to_string()))
+            .attr(DW_AT_ENCODING, Value::Data1(encoding))
+            .attr(DW_AT_BYTE_SIZE, Value::Data1(ty.size() as u8))
+    }
+
+    fn finish(self) -> Vec<Die> {
+        self.dies.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+// Identifies a type for sharing its entry. Structs go by their definition
+// since untagged ones look alike.
+fn type_key(ty: &Type) -> String {
+    let mut key = String::new();
+    if ty.is_const {
+        key.push_str("const ");
+    }
+    if ty.is_volatile {
+        key.push_str("volatile ");
+    }
+    if ty.is_array() {
+        let len = ty.array_len().map_or(String::new(), |len| len.to_string());
+        key.push_str(&format!("[{}]{}", len, type_key(&ty.clone_base())));
+    } else if ty.is_ptr_like() {
+        key.push_str(&format!("*{}", type_key(&ty.clone_base())));
+    } else if ty.is_function() {
+        let params: Vec<String> = ty.iter_func_args().map(|(_, arg)| type_key(arg)).collect();
+        key.push_str(&format!(
+            "({}){}",
+            params.join(","),
+            type_key(ty.func_ret())
+        ));
+    } else if let Some(def) = ty.struct_def() {
+        key.push_str(&format!("struct {:p}", Rc::as_ptr(def)));
+    } else if ty.is_enum() {
+        let members: Vec<String> = ty
+            .enum_members()
+            .iter()
+            .map(|m| format!("{}={}", m.name, m.val))
+            .collect();
+        let tag = ty.enum_tag().unwrap_or_default();
+        key.push_str(&format!("enum {}{{{}}}", tag, members.join(",")));
+    } else {
+        key.push_str(&ty.to_string());
+    }
+    key
+}
+
+fn gen_die(f: &mut dyn Write, die: &Die, abbrevs: &mut Vec<Abbrev>) {
+    let abbrev: Abbrev = (
+        die.tag,
+        die.children.is_some(),
+        die.attrs
+            .iter()
+            .map(|(at, val)| (*at, val.form()))
+            .collect(),
+    );
+    let code = match abbrevs.iter().position(|a| *a == abbrev) {
+        Some(pos) => pos + 1,
+        None => {
+            abbrevs.push(abbrev);
+            abbrevs.len()
+        }
+    };
+    if let Some(ref label) = die.label {
+        gen_line!(f, "{}:\n", label);
+    }
+    gen_line!(f, "  .uleb128 {}\n", code);
+    for (_, val) in die.attrs.iter() {
+        match *val {
+            Value::Str(ref s) => gen_line!(f, "  .string \"{}\"\n", escape(s)),
+            Value::Data1(v) => gen_line!(f, "  .byte {}\n", v),
+            Value::Udata(v) => gen_line!(f, "  .uleb128 {}\n", v),
+            Value::Sdata(v) => gen_line!(f, "  .sleb128 {}\n", v),
+            Value::Flag => (),
+            Value::Ref(ref label) => gen_line!(f, "  .long {}-{}\n", label, INFO_BEGIN),
+            Value::Addr(ref sym) => gen_line!(f, "  .quad {}\n", sym),
+            Value::Length(ref end, ref begin) => gen_line!(f, "  .quad {}-{}\n", end, begin),
+            Value::Offset(ref label) => gen_line!(f, "  .long {}\n", label),
+            Value::Frame(offset) => {
+                let mut expr = vec![DW_OP_BREG6];
+                expr.extend(sleb128(-(offset as i64)));
+                let bytes: Vec<String> = expr.iter().map(|b| format!("{:#x}", b)).collect();
+                gen_line!(f, "  .uleb128 {}\n", expr.len());
+                gen_line!(f, "  .byte {}\n", bytes.join(", "));
+            }
+            Value::Symbol(ref sym) => {
+                gen_line!(f, "  .uleb128 9\n");
+                gen_line!(f, "  .byte {:#x}\n", DW_OP_ADDR);
+                gen_line!(f, "  .quad {}\n", sym);
+            }
+        }
+    }
+    if let Some(ref children) = die.children {
+        for child in children.iter() {
+            gen_die(f, child, abbrevs);
+        }
+        gen_line!(f, "  .byte 0\n");
+    }
+}
+
+fn sleb128(mut val: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        let done = (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,316 @@
+// ELF64 relocatable objects for x86-64, as written by -c.
+// Sections are laid out in the order given, followed by the relocations
+// of each, the symbol table and the string tables. Every section gets a
+// section symbol so that relocations can refer to places within it.
+use std::io::{self, Write};
+
+pub const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_X86_64_UNWIND: u32 = 0x7000_0001;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_MERGE: u64 = 0x10;
+pub const SHF_STRINGS: u64 = 0x20;
+const SHF_INFO_LINK: u64 = 0x40;
+
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+
+const SHN_COMMON: u16 = 0xfff2;
+
+pub const R_X86_64_64: u32 = 1;
+pub const R_X86_64_PC32: u32 = 2;
+pub const R_X86_64_PLT32: u32 = 4;
+pub const R_X86_64_32: u32 = 10;
+pub const R_X86_64_32S: u32 = 11;
+pub const R_X86_64_REX_GOTPCRELX: u32 = 42;
+
+pub struct Section {
+    pub name: String,
+    pub kind: u32,  // SHT_*
+    pub flags: u64, // SHF_*
+    pub align: u64,
+    pub entsize: u64,
+    pub data: Vec<u8>, // Contents, empty for SHT_NOBITS
+    pub size: u64,     // Size in memory of SHT_NOBITS
+    pub relocs: Vec<Reloc>,
+}
+
+/// What a relocation is computed from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Section(usize), // Index into Object::sections
+    Symbol(usize),  // Index into Object::symbols
+}
+
+pub struct Reloc {
+    pub offset: u64,
+    pub target: Target,
+    pub kind: u32, // R_X86_64_*
+    pub addend: i64,
+}
+
+/// Where a symbol lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Place {
+    Undefined,
+    Common,
+    Section(usize),
+}
+
+pub struct Symbol {
+    pub name: String,
+    pub place: Place,
+    pub value: u64, // Offset in the section, or alignment of a common symbol
+    pub size: u64,
+    pub kind: u8, // STT_*
+    pub global: bool,
+}
+
+#[derive(Default)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+// Names joined by NULs, with the empty name first
+struct StrTab(Vec<u8>);
+
+impl StrTab {
+    fn new() -> Self {
+        StrTab(vec![0])
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        if name.is_empty() {
+            return 0;
+        }
+        let pos = self.0.len() as u32;
+        self.0.extend_from_slice(name.as_bytes());
+        self.0.push(0);
+        pos
+    }
+}
+
+struct Header {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl Object {
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        let mut shstrtab = StrTab::new();
+        let mut strtab = StrTab::new();
+
+        // Local symbols come first: the null one, sections, then the rest
+        let mut symtab = vec![0u8; 24];
+        let mut symbol_index = vec![0; self.symbols.len()];
+        for i in 0..self.sections.len() {
+            put_symbol(&mut symtab, 0, STT_SECTION, STB_LOCAL, i as u16 + 1, 0, 0);
+        }
+        let mut count = 1 + self.sections.len();
+        for pass in [false, true] {
+            for (i, sym) in self.symbols.iter().enumerate() {
+                if sym.global != pass {
+                    continue;
+                }
+                let shndx = match sym.place {
+                    Place::Undefined => 0,
+                    Place::Common => SHN_COMMON,
+                    Place::Section(s) => s as u16 + 1,
+                };
+                let bind = if sym.global { STB_GLOBAL } else { STB_LOCAL };
+                let name = strtab.add(&sym.name);
+                put_symbol(
+                    &mut symtab,
+                    name,
+                    sym.kind,
+                    bind,
+                    shndx,
+                    sym.value,
+                    sym.size,
+                );
+                symbol_index[i] = count;
+                count += 1;
+            }
+        }
+        let first_global =
+            1 + self.sections.len() + self.symbols.iter().filter(|s| !s.global).count();
+
+        let mut headers = Vec::new();
+        let mut contents: Vec<Vec<u8>> = Vec::new();
+        let mut offset = 64u64;
+        let mut add = |header: Header, data: Vec<u8>, headers: &mut Vec<Header>| {
+            let align = header.align.max(1);
+            offset = offset.div_ceil(align) * align;
+            let size = if header.kind == SHT_NOBITS {
+                header.size
+            } else {
+                data.len() as u64
+            };
+            headers.push(Header {
+                offset,
+                size,
+                ..header
+            });
+            if header.kind != SHT_NOBITS {
+                offset += data.len() as u64;
+            }
+            contents.push(data);
+        };
+
+        for section in self.sections.iter() {
+            let header = Header {
+                name: shstrtab.add(&section.name),
+                kind: section.kind,
+                flags: section.flags,
+                offset: 0,
+                size: section.size,
+                link: 0,
+                info: 0,
+                align: section.align,
+                entsize: section.entsize,
+            };
+            add(header, section.data.clone(), &mut headers);
+        }
+        let symtab_index = 1
+            + self.sections.len()
+            + self
+                .sections
+                .iter()
+                .filter(|s| !s.relocs.is_empty())
+                .count();
+        for (i, section) in self.sections.iter().enumerate() {
+            if section.relocs.is_empty() {
+                continue;
+            }
+            let mut data = Vec::new();
+            for reloc in section.relocs.iter() {
+                let sym = match reloc.target {
+                    Target::Section(s) => s + 1,
+                    Target::Symbol(s) => symbol_index[s],
+                };
+                data.extend_from_slice(&reloc.offset.to_le_bytes());
+                data.extend_from_slice(&(((sym as u64) << 32) | reloc.kind as u64).to_le_bytes());
+                data.extend_from_slice(&reloc.addend.to_le_bytes());
+            }
+            let header = Header {
+                name: shstrtab.add(&format!(".rela{}", section.name)),
+                kind: SHT_RELA,
+                flags: SHF_INFO_LINK,
+                offset: 0,
+                size: 0,
+                link: symtab_index as u32,
+                info: i as u32 + 1,
+                align: 8,
+                entsize: 24,
+            };
+            add(header, data, &mut headers);
+        }
+        let header = Header {
+            name: shstrtab.add(".symtab"),
+            kind: SHT_SYMTAB,
+            flags: 0,
+            offset: 0,
+            size: 0,
+            link: symtab_index as u32 + 1,
+            info: first_global as u32,
+            align: 8,
+            entsize: 24,
+        };
+        add(header, symtab, &mut headers);
+        let header = Header {
+            name: shstrtab.add(".strtab"),
+            kind: SHT_STRTAB,
+            flags: 0,
+            offset: 0,
+            size: 0,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        };
+        add(header, strtab.0, &mut headers);
+        let header = Header {
+            name: shstrtab.add(".shstrtab"),
+            kind: SHT_STRTAB,
+            flags: 0,
+            offset: 0,
+            size: 0,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        };
+        // The name of .shstrtab is already in it
+        let names = shstrtab.0.clone();
+        add(header, names, &mut headers);
+        let shoff = offset.div_ceil(8) * 8;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"\x7fELF");
+        out.extend_from_slice(&[2, 1, 1, 0]); // 64-bit, little endian, version 1, System V
+        out.extend_from_slice(&[0; 8]);
+        out.extend_from_slice(&1u16.to_le_bytes()); // ET_REL
+        out.extend_from_slice(&62u16.to_le_bytes()); // EM_X86_64
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes()); // No entry point
+        out.extend_from_slice(&0u64.to_le_bytes()); // No program headers
+        out.extend_from_slice(&shoff.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&64u16.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&64u16.to_le_bytes());
+        out.extend_from_slice(&(headers.len() as u16 + 1).to_le_bytes());
+        out.extend_from_slice(&(headers.len() as u16).to_le_bytes()); // .shstrtab is last
+
+        for (header, data) in headers.iter().zip(contents.iter()) {
+            if header.kind != SHT_NOBITS {
+                out.resize(header.offset as usize, 0);
+                out.extend_from_slice(data);
+            }
+        }
+        out.resize(shoff as usize, 0);
+        out.extend_from_slice(&[0; 64]);
+        for h in headers.iter() {
+            out.extend_from_slice(&h.name.to_le_bytes());
+            out.extend_from_slice(&h.kind.to_le_bytes());
+            out.extend_from_slice(&h.flags.to_le_bytes());
+            out.extend_from_slice(&0u64.to_le_bytes()); // Not loaded anywhere yet
+            out.extend_from_slice(&h.offset.to_le_bytes());
+            out.extend_from_slice(&h.size.to_le_bytes());
+            out.extend_from_slice(&h.link.to_le_bytes());
+            out.extend_from_slice(&h.info.to_le_bytes());
+            out.extend_from_slice(&h.align.max(1).to_le_bytes());
+            out.extend_from_slice(&h.entsize.to_le_bytes());
+        }
+        w.write_all(&out)
+    }
+}
+
+fn put_symbol(out: &mut Vec<u8>, name: u32, kind: u8, bind: u8, shndx: u16, value: u64, size: u64) {
+    out.extend_from_slice(&name.to_le_bytes());
+    out.push((bind << 4) | kind);
+    out.push(0);
+    out.extend_from_slice(&shndx.to_le_bytes());
+    out.extend_from_slice(&value.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+}
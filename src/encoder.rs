@@ -0,0 +1,583 @@
+// Machine code for the x86-64 instructions CodeGen writes, in the Intel
+// syntax it writes them in. Where there is a choice of encodings the one
+// GNU as picks is used: immediates take 8 bits when they fit, and moves
+// between registers go from the reg field to the r/m one. Jumps to labels
+// are left to the assembler, which knows how far they go.
+
+/// How a field is filled in once the symbol it names is placed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FixupKind {
+    Abs,       // Address of the symbol, 64 or 32 bits
+    Abs32S,    // Address sign-extended from 32 bits
+    Pc32,      // Relative to the field itself
+    Plt32,     // As Pc32, through the PLT if the symbol is elsewhere
+    GotPcRelX, // As Pc32, of the GOT entry holding the address
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixup {
+    pub at: usize,    // Offset of the field in the instruction or datum
+    pub width: usize, // Bytes of the field
+    pub kind: FixupKind,
+    pub sym: String,
+    pub addend: i64,
+    pub minus: Option<String>, // Label subtracted from sym, in the same section
+}
+
+impl Fixup {
+    pub fn new(at: usize, width: usize, kind: FixupKind, sym: &str, addend: i64) -> Self {
+        Fixup {
+            at,
+            width,
+            kind,
+            sym: sym.to_string(),
+            addend,
+            minus: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Encoded {
+    Bytes(Vec<u8>, Vec<Fixup>),
+    /// Jump to a label, conditional on a condition code if any
+    Jump(Option<u8>, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Reg {
+    num: u8,
+    size: usize, // Bytes
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Mem {
+    size: Option<usize>,
+    base: Option<u8>,
+    index: Option<(u8, u8)>, // Register and scale
+    rip: bool,
+    disp: i64,
+    sym: Option<String>,
+    got: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Opnd {
+    Reg(Reg),
+    Imm(i64),
+    Mem(Mem),
+    Label(String),
+    Offset(String),
+}
+
+static REGS: [[&str; 16]; 4] = [
+    [
+        "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+        "r13b", "r14b", "r15b",
+    ],
+    [
+        "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w",
+        "r13w", "r14w", "r15w",
+    ],
+    [
+        "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d",
+        "r12d", "r13d", "r14d", "r15d",
+    ],
+    [
+        "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12",
+        "r13", "r14", "r15",
+    ],
+];
+
+/// Number of the named 64-bit register as DWARF counts them
+pub fn dwarf_reg(name: &str) -> Option<u8> {
+    let num = REGS[3].iter().position(|&r| r == name)? as u8;
+    Some(match num {
+        1 => 2, // rcx
+        2 => 1, // rdx
+        3 => 3,
+        4 => 7, // rsp
+        5 => 6, // rbp
+        6 => 4, // rsi
+        7 => 5, // rdi
+        n => n,
+    })
+}
+
+fn reg(name: &str) -> Option<Reg> {
+    REGS.iter().enumerate().find_map(|(i, names)| {
+        names.iter().position(|&r| r == name).map(|num| Reg {
+            num: num as u8,
+            size: 1 << i,
+        })
+    })
+}
+
+/// Condition code of a jcc or setcc suffix
+fn cond(cc: &str) -> Option<u8> {
+    Some(match cc {
+        "e" => 0x4,
+        "ne" => 0x5,
+        "l" => 0xc,
+        "ge" => 0xd,
+        "le" => 0xe,
+        "g" => 0xf,
+        "b" => 0x2,
+        "ae" => 0x3,
+        "be" => 0x6,
+        "a" => 0x7,
+        _ => return None,
+    })
+}
+
+pub fn parse_int(s: &str) -> Option<i64> {
+    let (neg, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let val = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as i64,
+        None => digits.parse::<u64>().ok()? as i64,
+    };
+    Some(if neg { val.wrapping_neg() } else { val })
+}
+
+fn parse_mem(size: Option<usize>, s: &str) -> Option<Mem> {
+    let inner = s.strip_prefix('[')?.strip_suffix(']')?;
+    let mut mem = Mem {
+        size,
+        base: None,
+        index: None,
+        rip: false,
+        disp: 0,
+        sym: None,
+        got: false,
+    };
+    // Split into terms, each keeping the sign in front of it
+    let mut terms = Vec::new();
+    let mut start = 0;
+    for (i, c) in inner.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            terms.push(&inner[start..i]);
+            start = i;
+        }
+    }
+    terms.push(&inner[start..]);
+    for term in terms {
+        let (neg, term) = match term.as_bytes()[0] {
+            b'+' => (false, &term[1..]),
+            b'-' => (true, &term[1..]),
+            _ => (false, term),
+        };
+        if let Some(v) = parse_int(term) {
+            mem.disp += if neg { -v } else { v };
+        } else if neg {
+            return None;
+        } else if term == "rip" {
+            mem.rip = true;
+        } else if let Some((r, scale)) = term.split_once('*') {
+            let r = reg(r)?;
+            mem.index = Some((r.num, parse_int(scale)? as u8));
+        } else if let Some(r) = reg(term) {
+            mem.base = Some(r.num);
+        } else if let Some(name) = term.strip_suffix("@GOTPCREL") {
+            mem.sym = Some(name.to_string());
+            mem.got = true;
+        } else {
+            mem.sym = Some(term.to_string());
+        }
+    }
+    Some(mem)
+}
+
+fn parse_operand(s: &str) -> Option<Opnd> {
+    let s = s.trim();
+    for (prefix, size) in [
+        ("byte ptr ", 1),
+        ("word ptr ", 2),
+        ("dword ptr ", 4),
+        ("qword ptr ", 8),
+    ] {
+        if let Some(rest) = s.strip_prefix(prefix) {
+            return parse_mem(Some(size), rest).map(Opnd::Mem);
+        }
+    }
+    if s.starts_with('[') {
+        return parse_mem(None, s).map(Opnd::Mem);
+    }
+    if let Some(name) = s.strip_prefix("offset ") {
+        return Some(Opnd::Offset(name.to_string()));
+    }
+    if let Some(r) = reg(s) {
+        return Some(Opnd::Reg(r));
+    }
+    if let Some(v) = parse_int(s) {
+        return Some(Opnd::Imm(v));
+    }
+    Some(Opnd::Label(s.to_string()))
+}
+
+fn fits_i8(v: i64) -> bool {
+    v == v as i8 as i64
+}
+
+fn fits_i32(v: i64) -> bool {
+    v == v as i32 as i64
+}
+
+// An instruction as it is put together
+struct Builder {
+    bytes: Vec<u8>,
+    fixups: Vec<Fixup>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Builder {
+            bytes: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    fn imm(&mut self, val: i64, size: usize) {
+        self.bytes.extend_from_slice(&val.to_le_bytes()[..size]);
+    }
+
+    // Prefixes, opcode and ModRM with whatever follows it for an
+    // instruction of the given operand size. field is the reg field,
+    // either a register or an opcode extension. A byte register numbered
+    // from 4 up needs a REX prefix to not mean ah and the like.
+    fn modrm(&mut self, size: usize, opcode: &[u8], field: u8, byte_reg: bool, rm: &Opnd) {
+        if size == 2 {
+            self.bytes.push(0x66);
+        }
+        let mut rex = 0x40;
+        if size == 8 {
+            rex |= 0x08;
+        }
+        if field >= 8 {
+            rex |= 0x04;
+        }
+        let mut force = byte_reg && (4..8).contains(&field);
+        match rm {
+            Opnd::Reg(r) => {
+                if r.num >= 8 {
+                    rex |= 0x01;
+                }
+                force |= r.size == 1 && (4..8).contains(&r.num);
+            }
+            Opnd::Mem(m) => {
+                if m.base.is_some_and(|b| b >= 8) {
+                    rex |= 0x01;
+                }
+                if m.index.is_some_and(|(i, _)| i >= 8) {
+                    rex |= 0x02;
+                }
+            }
+            _ => unreachable!(),
+        }
+        if rex != 0x40 || force {
+            self.bytes.push(rex);
+        }
+        self.bytes.extend_from_slice(opcode);
+        let field = (field & 7) << 3;
+        let m = match rm {
+            Opnd::Reg(r) => {
+                self.bytes.push(0xc0 | field | (r.num & 7));
+                return;
+            }
+            Opnd::Mem(m) => m,
+            _ => unreachable!(),
+        };
+        if m.rip {
+            self.bytes.push(field | 0x05);
+            let sym = m.sym.as_deref().unwrap_or("");
+            let kind = if m.got {
+                FixupKind::GotPcRelX
+            } else {
+                FixupKind::Pc32
+            };
+            let at = self.bytes.len();
+            self.fixups.push(Fixup::new(at, 4, kind, sym, m.disp));
+            self.imm(0, 4);
+            return;
+        }
+        let scale = |s: u8| match s {
+            1 => 0,
+            2 => 1,
+            4 => 2,
+            _ => 3,
+        };
+        let base = match m.base {
+            Some(b) => b,
+            None => {
+                // Absolute address, e.g. of a jump table
+                let (index, s) = m.index.unwrap_or((4, 1));
+                self.bytes.push(field | 0x04);
+                self.bytes.push((scale(s) << 6) | ((index & 7) << 3) | 0x05);
+                let at = self.bytes.len();
+                match m.sym {
+                    Some(ref sym) => {
+                        self.fixups
+                            .push(Fixup::new(at, 4, FixupKind::Abs32S, sym, m.disp));
+                        self.imm(0, 4);
+                    }
+                    None => self.imm(m.disp, 4),
+                }
+                return;
+            }
+        };
+        // rbp and r13 with mod 00 mean no base, so they take a zero disp8
+        let mode = if m.disp == 0 && base & 7 != 5 {
+            0x00
+        } else if fits_i8(m.disp) {
+            0x40
+        } else {
+            0x80
+        };
+        match m.index {
+            Some((index, s)) => {
+                self.bytes.push(mode | field | 0x04);
+                self.bytes
+                    .push((scale(s) << 6) | ((index & 7) << 3) | (base & 7));
+            }
+            // rsp and r12 as r/m mean a SIB byte follows
+            None if base & 7 == 4 => {
+                self.bytes.push(mode | field | 0x04);
+                self.bytes.push(0x24);
+            }
+            None => self.bytes.push(mode | field | (base & 7)),
+        }
+        match mode {
+            0x40 => self.imm(m.disp, 1),
+            0x80 => self.imm(m.disp, 4),
+            _ => (),
+        }
+    }
+
+    fn finish(mut self) -> Encoded {
+        // Relative fields count from the end of the instruction
+        let len = self.bytes.len() as i64;
+        for fixup in self.fixups.iter_mut() {
+            if matches!(
+                fixup.kind,
+                FixupKind::Pc32 | FixupKind::Plt32 | FixupKind::GotPcRelX
+            ) {
+                fixup.addend -= len - fixup.at as i64;
+            }
+        }
+        Encoded::Bytes(self.bytes, self.fixups)
+    }
+}
+
+fn size_of(op: &Opnd) -> Option<usize> {
+    match op {
+        Opnd::Reg(r) => Some(r.size),
+        Opnd::Mem(m) => m.size,
+        _ => None,
+    }
+}
+
+// Operand size of a two-operand instruction
+fn op_size(dst: &Opnd, src: &Opnd) -> Option<usize> {
+    size_of(dst).or_else(|| size_of(src))
+}
+
+/// Encodes an instruction given its mnemonic and operands as written
+pub fn encode(op: &str, args: &[&str]) -> Result<Encoded, String> {
+    let opnds: Option<Vec<Opnd>> = args.iter().map(|a| parse_operand(a)).collect();
+    let opnds = opnds.ok_or_else(|| format!("Bad operand in {} {}", op, args.join(", ")))?;
+    let mut b = Builder::new();
+    let bad = || format!("Cannot encode {} {}", op, args.join(", "));
+
+    match (op, opnds.as_slice()) {
+        ("ret", []) => b.bytes.push(0xc3),
+        ("cqo", []) => b.bytes.extend_from_slice(&[0x48, 0x99]),
+        ("push", [Opnd::Reg(r)]) | ("pop", [Opnd::Reg(r)]) if r.size == 8 => {
+            if r.num >= 8 {
+                b.bytes.push(0x41);
+            }
+            let base = if op == "push" { 0x50 } else { 0x58 };
+            b.bytes.push(base + (r.num & 7));
+        }
+        ("mov", [Opnd::Reg(d), Opnd::Imm(v)]) => match d.size {
+            8 if fits_i32(*v) => {
+                b.modrm(8, &[0xc7], 0, false, &opnds[0]);
+                b.imm(*v, 4);
+            }
+            size => {
+                let rex = if size == 8 { 0x48 } else { 0x40 };
+                let rex = rex | if d.num >= 8 { 0x01 } else { 0 };
+                if size == 2 {
+                    b.bytes.push(0x66);
+                }
+                if rex != 0x40 || (size == 1 && (4..8).contains(&d.num)) {
+                    b.bytes.push(rex);
+                }
+                let opcode = if size == 1 { 0xb0 } else { 0xb8 };
+                b.bytes.push(opcode + (d.num & 7));
+                b.imm(*v, size);
+            }
+        },
+        ("mov", [dst @ Opnd::Mem(_), Opnd::Imm(v)]) => {
+            let size = size_of(dst).ok_or_else(bad)?;
+            let opcode = if size == 1 { 0xc6 } else { 0xc7 };
+            b.modrm(size, &[opcode], 0, false, dst);
+            b.imm(*v, size.min(4));
+        }
+        ("mov", [dst @ Opnd::Reg(d), Opnd::Offset(sym)]) if d.size == 8 => {
+            b.modrm(8, &[0xc7], 0, false, dst);
+            let at = b.bytes.len();
+            b.fixups.push(Fixup::new(at, 4, FixupKind::Abs32S, sym, 0));
+            b.imm(0, 4);
+        }
+        ("mov", [dst, Opnd::Reg(s)]) => {
+            let opcode = if s.size == 1 { 0x88 } else { 0x89 };
+            b.modrm(s.size, &[opcode], s.num, s.size == 1, dst);
+        }
+        ("mov", [Opnd::Reg(d), src @ Opnd::Mem(_)]) => {
+            let opcode = if d.size == 1 { 0x8a } else { 0x8b };
+            b.modrm(d.size, &[opcode], d.num, d.size == 1, src);
+        }
+        ("lea", [Opnd::Reg(d), src @ Opnd::Mem(_)]) => b.modrm(d.size, &[0x8d], d.num, false, src),
+        ("movsx", [Opnd::Reg(d), src]) | ("movzb", [Opnd::Reg(d), src]) => {
+            let opcode = match (op, size_of(src)) {
+                ("movsx", Some(1)) => 0xbe,
+                ("movsx", Some(2)) => 0xbf,
+                ("movzb", Some(1)) => 0xb6,
+                _ => return Err(bad()),
+            };
+            b.modrm(d.size, &[0x0f, opcode], d.num, false, src);
+        }
+        ("movsxd", [Opnd::Reg(d), src]) if d.size == 8 => b.modrm(8, &[0x63], d.num, false, src),
+        ("add", [dst, src])
+        | ("or", [dst, src])
+        | ("and", [dst, src])
+        | ("sub", [dst, src])
+        | ("xor", [dst, src])
+        | ("cmp", [dst, src]) => {
+            let ext: u8 = match op {
+                "add" => 0,
+                "or" => 1,
+                "and" => 4,
+                "sub" => 5,
+                "xor" => 6,
+                _ => 7,
+            };
+            let size = op_size(dst, src).ok_or_else(bad)?;
+            let wide = (size != 1) as u8;
+            match (dst, src) {
+                (_, Opnd::Imm(v)) if size == 1 => {
+                    b.modrm(1, &[0x80], ext, false, dst);
+                    b.imm(*v, 1);
+                }
+                (_, Opnd::Imm(v)) if fits_i8(*v) => {
+                    b.modrm(size, &[0x83], ext, false, dst);
+                    b.imm(*v, 1);
+                }
+                // The accumulator has a form of its own
+                (Opnd::Reg(Reg { num: 0, .. }), Opnd::Imm(v)) => {
+                    if size == 2 {
+                        b.bytes.push(0x66);
+                    } else if size == 8 {
+                        b.bytes.push(0x48);
+                    }
+                    b.bytes.push(ext * 8 + 5);
+                    b.imm(*v, size.min(4));
+                }
+                (_, Opnd::Imm(v)) => {
+                    b.modrm(size, &[0x81], ext, false, dst);
+                    b.imm(*v, size.min(4));
+                }
+                (_, Opnd::Reg(s)) => b.modrm(size, &[ext * 8 + wide], s.num, s.size == 1, dst),
+                (Opnd::Reg(d), Opnd::Mem(_)) => {
+                    b.modrm(size, &[ext * 8 + 2 + wide], d.num, d.size == 1, src)
+                }
+                _ => return Err(bad()),
+            }
+        }
+        ("imul", [Opnd::Reg(d), Opnd::Imm(v)]) => {
+            if fits_i8(*v) {
+                b.modrm(d.size, &[0x6b], d.num, false, &opnds[0]);
+                b.imm(*v, 1);
+            } else {
+                b.modrm(d.size, &[0x69], d.num, false, &opnds[0]);
+                b.imm(*v, d.size.min(4));
+            }
+        }
+        ("imul", [Opnd::Reg(d), src]) => b.modrm(d.size, &[0x0f, 0xaf], d.num, false, src),
+        ("not", [dst]) | ("idiv", [dst]) => {
+            let size = size_of(dst).ok_or_else(bad)?;
+            let ext = if op == "not" { 2 } else { 7 };
+            let opcode = if size == 1 { 0xf6 } else { 0xf7 };
+            b.modrm(size, &[opcode], ext, false, dst);
+        }
+        ("shl", [dst, src]) | ("shr", [dst, src]) | ("sar", [dst, src]) => {
+            let size = size_of(dst).ok_or_else(bad)?;
+            let ext = match op {
+                "shl" => 4,
+                "shr" => 5,
+                _ => 7,
+            };
+            let wide = (size != 1) as u8;
+            match src {
+                Opnd::Reg(Reg { num: 1, size: 1 }) => {
+                    b.modrm(size, &[0xd2 + wide], ext, false, dst)
+                }
+                Opnd::Imm(1) => b.modrm(size, &[0xd0 + wide], ext, false, dst),
+                Opnd::Imm(v) => {
+                    b.modrm(size, &[0xc0 + wide], ext, false, dst);
+                    b.imm(*v, 1);
+                }
+                _ => return Err(bad()),
+            }
+        }
+        ("call", [Opnd::Label(target)]) => {
+            let sym = target.strip_suffix("@PLT").unwrap_or(target);
+            b.bytes.push(0xe8);
+            b.fixups.push(Fixup::new(1, 4, FixupKind::Plt32, sym, 0));
+            b.imm(0, 4);
+        }
+        ("call", [target @ Opnd::Reg(_)])
+        | ("call", [target @ Opnd::Mem(_)])
+        | ("jmp", [target @ Opnd::Reg(_)])
+        | ("jmp", [target @ Opnd::Mem(_)]) => {
+            // Targets are 64 bits wide without a REX.W prefix
+            let ext = if op == "call" { 2 } else { 4 };
+            b.modrm(4, &[0xff], ext, false, target);
+        }
+        ("jmp", [Opnd::Label(target)]) => return Ok(Encoded::Jump(None, target.clone())),
+        (jcc, [Opnd::Label(target)]) if jcc.starts_with('j') => {
+            let cc = cond(&jcc[1..]).ok_or_else(bad)?;
+            return Ok(Encoded::Jump(Some(cc), target.clone()));
+        }
+        (setcc, [dst]) if setcc.starts_with("set") && size_of(dst) == Some(1) => {
+            let cc = cond(&setcc[3..]).ok_or_else(bad)?;
+            b.modrm(1, &[0x0f, 0x90 + cc], 0, false, dst);
+        }
+        _ => return Err(bad()),
+    }
+    Ok(b.finish())
+}
+
+/// Bytes of a jump: short ones take a displacement of 8 bits, others 32
+pub fn jump(cc: Option<u8>, short: bool, disp: i64) -> Vec<u8> {
+    let mut bytes = match (cc, short) {
+        (None, true) => vec![0xeb],
+        (None, false) => vec![0xe9],
+        (Some(cc), true) => vec![0x70 + cc],
+        (Some(cc), false) => vec![0x0f, 0x80 + cc],
+    };
+    let size = if short { 1 } else { 4 };
+    bytes.extend_from_slice(&disp.to_le_bytes()[..size]);
+    bytes
+}
+
+/// Length of a jump, as jump encodes it
+pub fn jump_len(cc: Option<u8>, short: bool) -> usize {
+    match (cc, short) {
+        (_, true) => 2,
+        (None, false) => 5,
+        (Some(_), false) => 6,
+    }
+}
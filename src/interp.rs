@@ -0,0 +1,1573 @@
+// Interpreter for --run, executing the checked AST without going through
+// the assembler. Values are 64-bit like the registers of the generated code
+// and every operation follows the instruction lower would emit for it, so a
+// program exits the same way whether it is run here or compiled.
+//
+// Memory is a list of allocations: one per global, literal, call frame and
+// malloc'd block. An address holds the number of the allocation in its
+// upper half and the byte offset into it in the lower, so pointer
+// arithmetic works as usual while every access can be checked.
+//
+// With --check, operations whose behaviour C leaves undefined stop the
+// program instead of doing what the machine would: signed overflow, shifts
+// by the width or more, pointer arithmetic leaving its object and reads of
+// uninitialized locals or malloc'd memory. The error then shows the values
+// that went into the expression and the calls it was reached through.
+// Locals then get an allocation each rather than sharing their frame's, so
+// indexing past one is caught instead of landing in its neighbour.
+use crate::cenv::Var;
+use crate::constexpr::{self, ConstVal};
+use crate::ctype::Type;
+use crate::ir::{BinOp, Cond, UnOp, Width, LITERAL_HEAD};
+use crate::lower::{bit_field, compound_op};
+use crate::node::{AssignMode, Node, NodeKind};
+use crate::opt::{eval_bin, eval_cmp, eval_un};
+use crate::parser::{InitVal, Program};
+use crate::tokenizer::{unescape, Span};
+use std::collections::{HashMap, LinkedList};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+const OFFSET_BITS: u32 = 32;
+const MAX_DEPTH: usize = 10000; // Calls deep before giving up as a stack overflow
+const MAX_TRACE: usize = 12; // Values shown for an error
+const MAX_CALLS: usize = 8; // Calls shown for an error
+const MAX_SHOWN: usize = 32; // Elements of an array the REPL shows
+const MAX_STRING: usize = 200; // Characters of a string the REPL shows
+
+#[derive(Debug, Clone, PartialEq)]
+enum Region {
+    Global(String),
+    Literal,
+    Frame(String),         // Locals of a call to the function
+    Local(String, String), // Under --check, a variable of the function by itself
+    Heap,
+    Function(String), // Only there to give the function an address
+}
+
+struct Alloc {
+    region: Region,
+    bytes: Vec<u8>,
+    init: Vec<bool>, // Which bytes were written to, if not all are known to be
+    live: bool,      // Cleared when a frame returns or a block is freed
+    readonly: bool,
+}
+
+/// Why evaluation stopped early
+pub enum Stop {
+    Exit(i32),
+    Error(String),
+}
+
+// How a statement finished
+enum Flow {
+    Normal(Option<i64>), // With the value of an expression statement
+    Break,
+    Continue,
+    Return(i64),
+}
+
+// A call in progress
+struct Frame<'a> {
+    func: &'a str,
+    site: Option<Span>,                // Of the call, None for main
+    base: i64,                         // End of the frame, where rbp would point
+    locals: HashMap<usize, i64>,       // Under --check, by offset
+    values: HashMap<*const Node, i64>, // Under --check, of the current statement
+}
+
+// Label a switch jumps to, looked for through its body
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Seek {
+    Case(usize),
+    Default,
+}
+
+/// Runs main of prog, returning its exit status. With checked set,
+/// undefined behaviour is an error; source is only needed to show where.
+pub fn run(prog: &Program, name: &str, source: &str, checked: bool) -> Result<i32, String> {
+    Interp::new(prog, source, checked).run(name)
+}
+
+/// Interpreter state kept from one input of --repl to the next, along with
+/// the inputs themselves, which the functions it calls are part of
+pub struct Session {
+    progs: Vec<Program>,
+    symbols: HashMap<String, i64>,
+    allocs: Vec<Alloc>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            progs: Vec::new(),
+            symbols: HashMap::new(),
+            allocs: Vec::new(),
+        }
+    }
+
+    /// Adds the functions, globals and literals of an input
+    pub fn load(&mut self, prog: Program) {
+        self.resume(|interp| interp.add_data(&prog));
+        self.progs.push(prog);
+    }
+
+    /// Calls a function without parameters, giving what it returns or the
+    /// value of its last statement
+    pub fn call(&mut self, name: &str) -> Result<i64, Stop> {
+        self.resume(|interp| {
+            let func = interp.funcs[name];
+            let result = interp.call_func(func, Vec::new(), None);
+            let _ = interp.out.flush();
+            result
+        })
+    }
+
+    /// A value of the type as the REPL shows it. Arrays, structs and
+    /// unions are given by their address.
+    pub fn show(&mut self, val: i64, ty: &Type) -> String {
+        self.resume(|interp| interp.show(val, ty))
+    }
+
+    // Runs f on an interpreter over the memory and inputs so far. Calls in
+    // progress are not kept, so an error leaves none of them behind.
+    fn resume<T>(&mut self, f: impl FnOnce(&mut Interp) -> T) -> T {
+        let mut interp = Interp::empty("", false);
+        interp.symbols = std::mem::take(&mut self.symbols);
+        interp.allocs = std::mem::take(&mut self.allocs);
+        for prog in self.progs.iter() {
+            interp.add_functions(prog);
+        }
+        let result = f(&mut interp);
+        self.symbols = interp.symbols;
+        self.allocs = interp.allocs;
+        result
+    }
+}
+
+struct Interp<'a> {
+    funcs: HashMap<&'a str, &'a Node>,
+    symbols: HashMap<String, i64>, // Addresses of globals, literals and functions
+    allocs: Vec<Alloc>,
+    frames: Vec<Frame<'a>>,
+    seek: Option<Seek>,
+    span: Option<Span>,     // Of the innermost statement or expression
+    node: Option<&'a Node>, // Innermost expression being evaluated
+    checked: bool,          // --check
+    lines: Vec<&'a str>,    // Of the source, to quote expressions
+    out: io::BufWriter<io::Stdout>,
+}
+
+impl<'a> Interp<'a> {
+    fn new(prog: &'a Program, source: &'a str, checked: bool) -> Self {
+        let mut interp = Interp::empty(source, checked);
+        interp.add_program(prog);
+        interp
+    }
+
+    fn empty(source: &'a str, checked: bool) -> Self {
+        Interp {
+            funcs: HashMap::new(),
+            symbols: HashMap::new(),
+            allocs: Vec::new(),
+            frames: Vec::new(),
+            seek: None,
+            span: None,
+            node: None,
+            checked,
+            lines: source.lines().collect(),
+            out: io::BufWriter::new(io::stdout()),
+        }
+    }
+
+    // Adds what prog defines that is not known yet
+    fn add_program(&mut self, prog: &'a Program) {
+        self.add_functions(prog);
+        self.add_data(prog);
+    }
+
+    fn add_functions(&mut self, prog: &'a Program) {
+        for node in prog.nodes.iter() {
+            if let NodeKind::NDFUNCDEF { ref name, .. } = node.kind {
+                self.funcs.insert(name.as_str(), node);
+            }
+        }
+    }
+
+    // Literals, globals and their initial values
+    fn add_data(&mut self, prog: &Program) {
+        // Equal literals end up merged by the linker, so they share one here too
+        let mut merged: HashMap<Vec<u8>, i64> = HashMap::new();
+        for (pos, literal) in prog.literals.iter().enumerate() {
+            let name = format!("{}{}", LITERAL_HEAD, pos);
+            if self.symbols.contains_key(&name) {
+                continue;
+            }
+            let mut bytes = unescape(literal);
+            bytes.push(0);
+            let addr = match merged.get(&bytes) {
+                Some(&addr) => addr,
+                None => {
+                    let addr = self.alloc(Region::Literal, bytes.clone(), true);
+                    merged.insert(bytes, addr);
+                    addr
+                }
+            };
+            self.symbols.insert(name, addr);
+        }
+        for gvar in prog.globals.iter().filter(|gvar| !gvar.is_extern) {
+            if self.symbols.contains_key(&gvar.name) {
+                continue;
+            }
+            let bytes = vec![0; gvar.ty.total_size()];
+            let region = Region::Global(gvar.name.clone());
+            let addr = self.alloc(region, bytes, gvar.ty.is_readonly());
+            self.symbols.insert(gvar.name.clone(), addr);
+        }
+        for init in prog.global_inits.iter() {
+            self.global_init(&init.name, &init.vals);
+        }
+    }
+
+    // Fills in a global the way lower lays out its data
+    fn global_init(&mut self, name: &str, vals: &[InitVal]) {
+        let base = self.symbols[name];
+        for init in vals.iter() {
+            let addr = base + init.offset as i64;
+            let size = init.ty.size();
+            let val = match constexpr::eval(&init.val).unwrap() {
+                ConstVal::Int(v) => constexpr::truncate(v, &init.ty),
+                ConstVal::Addr(sym, ofs) => self.symbol(&sym).unwrap_or(0) + ofs,
+            };
+            let val = match init.bits {
+                Some((bit, width)) => {
+                    let mask = u64::MAX >> (64 - width);
+                    let unit = self.raw(addr, size);
+                    unit | ((val as u64 & mask) << bit) as i64
+                }
+                None => val,
+            };
+            let id = (addr >> OFFSET_BITS) as usize - 1;
+            let ofs = (addr & 0xffff_ffff) as usize;
+            self.allocs[id].bytes[ofs..ofs + size].copy_from_slice(&val.to_le_bytes()[..size]);
+        }
+    }
+
+    fn raw(&self, addr: i64, size: usize) -> i64 {
+        let id = (addr >> OFFSET_BITS) as usize - 1;
+        let ofs = (addr & 0xffff_ffff) as usize;
+        let mut buf = [0; 8];
+        buf[..size].copy_from_slice(&self.allocs[id].bytes[ofs..ofs + size]);
+        i64::from_le_bytes(buf)
+    }
+
+    fn run(mut self, name: &str) -> Result<i32, String> {
+        let result = match self.funcs.get("main").copied() {
+            Some(main) => {
+                // argv holds the program name and a null pointer
+                let mut arg = name.as_bytes().to_vec();
+                arg.push(0);
+                let arg = self.alloc(Region::Literal, arg, false);
+                let mut argv = arg.to_le_bytes().to_vec();
+                argv.extend_from_slice(&[0; 8]);
+                let argv = self.alloc(Region::Global("argv".to_string()), argv, false);
+                self.call_func(main, vec![1, argv], None)
+            }
+            None => Err(Stop::Error("main is not defined".to_string())),
+        };
+        let _ = self.out.flush();
+        match result {
+            Ok(code) => Ok(code as i32),
+            Err(Stop::Exit(code)) => Ok(code),
+            Err(Stop::Error(msg)) => Err(msg),
+        }
+    }
+
+    fn error<T>(&self, msg: String) -> Result<T, Stop> {
+        let span = self.node.and_then(|node| node.span).or(self.span);
+        let mut msg = match span {
+            Some(span) => format!("{}:{}: {}", span.lo.line, span.lo.col, msg),
+            None => msg,
+        };
+        if self.checked {
+            msg.push_str(&self.trace());
+        }
+        Err(Stop::Error(msg))
+    }
+
+    // The expression being evaluated, the values of its parts and the
+    // calls leading to it, innermost first
+    fn trace(&self) -> String {
+        let mut out = String::new();
+        if let Some(node) = self.node {
+            if let Some(text) = self.quote(node) {
+                write!(out, "\n  in `{}`", text).unwrap();
+            }
+            let mut parts = Vec::new();
+            operands(node, &mut parts);
+            let values = &self.frames.last().unwrap().values;
+            let shown = parts
+                .iter()
+                .filter_map(|part| Some((part, values.get(&(*part as *const Node))?)))
+                .filter_map(|(part, val)| Some((self.quote(part)?, part, *val)))
+                .take(MAX_TRACE);
+            for (mut text, part, val) in shown {
+                // The address a[i] reads from has the same span as a[i]
+                if subscript_of(node, part) || parts.iter().any(|p| subscript_of(p, part)) {
+                    text.insert(0, '&');
+                }
+                let span = part.span.unwrap();
+                let val = match part.ty {
+                    Some(ref ty) if ty.is_ptr_like() || ty.is_function() => self.describe(val),
+                    _ => val.to_string(),
+                };
+                write!(
+                    out,
+                    "\n    {}:{}: {} = {}",
+                    span.lo.line, span.lo.col, text, val
+                )
+                .unwrap();
+            }
+        }
+        for frame in self.frames.iter().rev().take(MAX_CALLS) {
+            let func = frame.func;
+            match frame.site {
+                Some(span) => write!(
+                    out,
+                    "\n  in {} called at {}:{}",
+                    func, span.lo.line, span.lo.col
+                ),
+                None => write!(out, "\n  in {}", func),
+            }
+            .unwrap();
+        }
+        if self.frames.len() > MAX_CALLS {
+            write!(out, "\n  ... {} calls more", self.frames.len() - MAX_CALLS).unwrap();
+        }
+        out
+    }
+
+    // Source text of a node, on one line
+    fn quote(&self, node: &Node) -> Option<String> {
+        let span = node.span?;
+        let mut text = String::new();
+        for line in span.lo.line..=span.hi.line {
+            let src = self.lines.get(line - 1)?;
+            let lo = if line == span.lo.line {
+                span.lo.col - 1
+            } else {
+                0
+            };
+            let hi = if line == span.hi.line {
+                span.hi.col - 1
+            } else {
+                src.len()
+            };
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(src.get(lo..hi)?.trim());
+        }
+        Some(text)
+    }
+
+    // Where an address points to, in words
+    fn describe(&self, addr: i64) -> String {
+        let id = (addr >> OFFSET_BITS) as usize;
+        let ofs = addr & 0xffff_ffff;
+        let alloc = match id.checked_sub(1).and_then(|id| self.allocs.get(id)) {
+            Some(alloc) if addr != 0 => alloc,
+            _ if addr == 0 => return "null".to_string(),
+            _ => return format!("{:#x}", addr),
+        };
+        let object = match alloc.region {
+            Region::Global(ref name) => name.clone(),
+            Region::Literal => "a string literal".to_string(),
+            Region::Frame(ref func) if alloc.live => format!("the frame of {}", func),
+            Region::Frame(ref func) => format!("the frame of {}, which returned", func),
+            Region::Local(ref func, ref var) if alloc.live => format!("{} of {}", var, func),
+            Region::Local(ref func, ref var) => format!("{} of {}, which returned", var, func),
+            Region::Heap if alloc.live => "a malloc'd block".to_string(),
+            Region::Heap => "a freed block".to_string(),
+            Region::Function(ref func) => return func.clone(),
+        };
+        format!("offset {} into {}", ofs, object)
+    }
+
+    fn show(&self, val: i64, ty: &Type) -> String {
+        if ty.is_array() {
+            let elem = ty.clone_base();
+            let len = ty.array_len().unwrap();
+            let mut items: Vec<String> = (0..len.min(MAX_SHOWN))
+                .map(|i| self.show_at(val + (i * elem.total_size()) as i64, &elem, None))
+                .collect();
+            if len > MAX_SHOWN {
+                items.push("...".to_string());
+            }
+            return format!("{{{}}}", items.join(", "));
+        }
+        if let Some(def) = ty.struct_def() {
+            let items: Vec<String> = def
+                .borrow()
+                .members()
+                .iter()
+                .filter(|m| !m.name.is_empty())
+                .map(|m| {
+                    let text = self.show_at(val + m.offset as i64, &m.ty, m.bits);
+                    format!("{} = {}", m.name, text)
+                })
+                .collect();
+            return format!("{{{}}}", items.join(", "));
+        }
+        if ty.is_enum() {
+            if let Some(member) = ty.enum_members().iter().find(|m| m.val as i64 == val) {
+                return member.name.clone();
+            }
+        }
+        // A function is shown by its name, as is a pointer to one
+        if ty.is_function() {
+            return self.describe(val);
+        }
+        if ty.is_ptr_like() {
+            let pointee = ty.clone_base();
+            let sized = !pointee.is_void() && !pointee.is_function();
+            if val != 0 && sized && pointee.size() == 1 {
+                if let Ok(bytes) = self.string(val) {
+                    return quote_bytes(&bytes);
+                }
+            }
+            return self.describe(val);
+        }
+        match ty.size() {
+            1 if (0x20..0x7f).contains(&val) => format!("{} '{}'", val, val as u8 as char),
+            _ => val.to_string(),
+        }
+    }
+
+    // What is stored at addr, a bit-field if bits are given
+    fn show_at(&self, addr: i64, ty: &Type, bits: Option<(usize, usize)>) -> String {
+        if ty.is_array() || ty.is_struct() || ty.is_union() {
+            return self.show(addr, ty);
+        }
+        let val = match self.load(addr, ty.size()) {
+            Ok(val) => val,
+            Err(_) => return "?".to_string(),
+        };
+        let val = match bits {
+            Some((offset, width)) => {
+                let top = eval_bin(BinOp::Shl, val, (64 - offset - width) as i64).unwrap();
+                eval_bin(BinOp::Sar, top, (64 - width) as i64).unwrap()
+            }
+            None => val,
+        };
+        self.show(val, ty)
+    }
+
+    fn alloc(&mut self, region: Region, bytes: Vec<u8>, readonly: bool) -> i64 {
+        self.allocs.push(Alloc {
+            region,
+            bytes,
+            init: Vec::new(),
+            live: true,
+            readonly,
+        });
+        (self.allocs.len() as i64) << OFFSET_BITS
+    }
+
+    // Memory whose contents are indeterminate until written
+    fn alloc_uninit(&mut self, region: Region, size: usize) -> i64 {
+        let addr = self.alloc(region, vec![0; size], false);
+        if self.checked {
+            self.allocs.last_mut().unwrap().init = vec![false; size];
+        }
+        addr
+    }
+
+    // Address of a global, literal or function; functions get theirs when first asked
+    fn symbol(&mut self, name: &str) -> Option<i64> {
+        if let Some(&addr) = self.symbols.get(name) {
+            return Some(addr);
+        }
+        if !self.funcs.contains_key(name) && !is_builtin(name) {
+            return None;
+        }
+        let addr = self.alloc(Region::Function(name.to_string()), vec![0], true);
+        self.symbols.insert(name.to_string(), addr);
+        Some(addr)
+    }
+
+    // Allocation and offset addr points into, if it is still there
+    fn object(&self, addr: i64) -> Result<(usize, usize), Stop> {
+        if addr == 0 {
+            return self.error("Null pointer dereference".to_string());
+        }
+        let id = (addr >> OFFSET_BITS) as usize;
+        let ofs = (addr & 0xffff_ffff) as usize;
+        let alloc = match id.checked_sub(1).and_then(|id| self.allocs.get(id)) {
+            Some(alloc) => alloc,
+            None => return self.error(format!("Invalid address {:#x}", addr)),
+        };
+        if !alloc.live {
+            return match alloc.region {
+                Region::Frame(ref func) => {
+                    self.error(format!("Access to a local of {} after it returned", func))
+                }
+                Region::Local(ref func, ref var) => self.error(format!(
+                    "Access to {}, a local of {}, after it returned",
+                    var, func
+                )),
+                _ => self.error("Access to freed memory".to_string()),
+            };
+        }
+        Ok((id - 1, ofs))
+    }
+
+    // Allocation and offset of size bytes at addr, if they may be accessed
+    fn locate(&self, addr: i64, size: usize, write: bool) -> Result<(usize, usize), Stop> {
+        let (id, ofs) = self.object(addr)?;
+        let alloc = &self.allocs[id];
+        if let Region::Function(ref name) = alloc.region {
+            return self.error(format!("Access to the code of {}", name));
+        }
+        if ofs + size > alloc.bytes.len() {
+            return self.error(format!(
+                "Access of {} bytes at offset {} into an object of {} bytes",
+                size,
+                ofs,
+                alloc.bytes.len()
+            ));
+        }
+        if write && alloc.readonly {
+            return self.error("Write to read-only memory".to_string());
+        }
+        Ok((id, ofs))
+    }
+
+    // Reads an integer, sign-extending it as the load instructions do
+    fn load(&self, addr: i64, size: usize) -> Result<i64, Stop> {
+        let width = self.width(size)?;
+        let (id, ofs) = self.locate(addr, size, false)?;
+        let mut buf = [0; 8];
+        buf[..size].copy_from_slice(&self.allocs[id].bytes[ofs..ofs + size]);
+        let val = i64::from_le_bytes(buf);
+        Ok(eval_un(UnOp::Sext(width), val))
+    }
+
+    fn store(&mut self, addr: i64, size: usize, val: i64) -> Result<(), Stop> {
+        self.width(size)?;
+        let (id, ofs) = self.locate(addr, size, true)?;
+        let alloc = &mut self.allocs[id];
+        alloc.bytes[ofs..ofs + size].copy_from_slice(&val.to_le_bytes()[..size]);
+        if !alloc.init.is_empty() {
+            alloc.init[ofs..ofs + size].fill(true);
+        }
+        Ok(())
+    }
+
+    // Copies an aggregate along with which of its bytes are initialized
+    fn copy(&mut self, dst: i64, src: i64, size: usize) -> Result<(), Stop> {
+        let (sid, sofs) = self.locate(src, size, false)?;
+        let bytes = self.allocs[sid].bytes[sofs..sofs + size].to_vec();
+        let init = if self.allocs[sid].init.is_empty() {
+            vec![true; size]
+        } else {
+            self.allocs[sid].init[sofs..sofs + size].to_vec()
+        };
+        let (did, dofs) = self.locate(dst, size, true)?;
+        let alloc = &mut self.allocs[did];
+        alloc.bytes[dofs..dofs + size].copy_from_slice(&bytes);
+        if !alloc.init.is_empty() {
+            alloc.init[dofs..dofs + size].copy_from_slice(&init);
+        }
+        Ok(())
+    }
+
+    // Under --check, reading what was never written is an error
+    fn check_init(&self, addr: i64, size: usize) -> Result<(), Stop> {
+        if !self.checked {
+            return Ok(());
+        }
+        let (id, ofs) = self.locate(addr, size, false)?;
+        let init = &self.allocs[id].init;
+        if init.is_empty() || init[ofs..ofs + size].iter().all(|b| *b) {
+            return Ok(());
+        }
+        match self.allocs[id].region {
+            Region::Local(_, ref name) => {
+                self.error(format!("Read of the uninitialized local {}", name))
+            }
+            _ => self.error("Read of uninitialized memory".to_string()),
+        }
+    }
+
+    // Under --check, pointer arithmetic may only move within an object or
+    // just past its end
+    fn check_offset(&self, ptr: i64, delta: i64) -> Result<(), Stop> {
+        if !self.checked || delta == 0 {
+            return Ok(());
+        }
+        if ptr == 0 {
+            return self.error("Arithmetic on a null pointer".to_string());
+        }
+        let (id, ofs) = self.object(ptr)?;
+        let len = self.allocs[id].bytes.len() as i64;
+        let to = ofs as i64 + delta;
+        if to < 0 || to > len {
+            return self.error(format!(
+                "Pointer arithmetic moves from offset {} to {}, outside an object of {} bytes",
+                ofs, to, len
+            ));
+        }
+        Ok(())
+    }
+
+    // Under --check, the distance is only defined within one object
+    fn check_distance(&self, l: i64, r: i64) -> Result<(), Stop> {
+        if !self.checked {
+            return Ok(());
+        }
+        let (lid, _) = self.object(l)?;
+        let (rid, _) = self.object(r)?;
+        if lid != rid {
+            return self.error("Subtraction of pointers into different objects".to_string());
+        }
+        Ok(())
+    }
+
+    fn width(&self, size: usize) -> Result<Width, Stop> {
+        match size {
+            1 | 2 | 4 | 8 => Ok(Width::from_size(size)),
+            _ => self.error(format!("Cannot hold a {} byte object in a register", size)),
+        }
+    }
+
+    // Bytes of the NUL-terminated string at addr
+    fn string(&self, addr: i64) -> Result<Vec<u8>, Stop> {
+        let mut bytes = Vec::new();
+        loop {
+            let (id, ofs) = self.locate(addr + bytes.len() as i64, 1, false)?;
+            match self.allocs[id].bytes[ofs] {
+                0 => return Ok(bytes),
+                b => bytes.push(b),
+            }
+        }
+    }
+
+    // Address of the local at offset in the current frame
+    fn local(&mut self, offset: usize, name: &str, ty: &Type) -> i64 {
+        let frame = self.frames.last().unwrap();
+        if !self.checked {
+            return frame.base - offset as i64;
+        }
+        if let Some(&addr) = frame.locals.get(&offset) {
+            return addr;
+        }
+        // Initializers name elements and members by their own offset
+        for (&start, &addr) in frame.locals.iter() {
+            if start > offset
+                && start - offset < self.allocs[(addr >> OFFSET_BITS) as usize - 1].bytes.len()
+            {
+                return addr + (start - offset) as i64;
+            }
+        }
+        let region = Region::Local(frame.func.to_string(), name.to_string());
+        let addr = self.alloc_uninit(region, ty.total_size());
+        self.frames.last_mut().unwrap().locals.insert(offset, addr);
+        addr
+    }
+
+    // Under --check a declaration starts a new lifetime for its locals
+    fn declare(&mut self, vars: &LinkedList<Var>) {
+        if !self.checked {
+            return;
+        }
+        for var in vars.iter().filter(|var| !var.is_extern) {
+            let offset = match var.offset {
+                Some(offset) => offset,
+                None => continue,
+            };
+            let size = var.ty.total_size();
+            let frame = self.frames.last_mut().unwrap();
+            if let Some(addr) = frame.locals.remove(&offset) {
+                let alloc = &mut self.allocs[(addr >> OFFSET_BITS) as usize - 1];
+                if alloc.bytes.len() == size {
+                    alloc.init = vec![false; size];
+                    self.frames.last_mut().unwrap().locals.insert(offset, addr);
+                    continue;
+                }
+                // The slot held a variable of a block that has ended
+                alloc.live = false;
+            }
+            self.local(offset, &var.name, &var.ty);
+        }
+    }
+
+    fn call_func(
+        &mut self,
+        func: &'a Node,
+        args: Vec<i64>,
+        site: Option<Span>,
+    ) -> Result<i64, Stop> {
+        if let NodeKind::NDFUNCDEF {
+            ref name,
+            ref argvars,
+            ref stmts,
+            lvars_offset,
+        } = func.kind
+        {
+            if self.frames.len() == MAX_DEPTH {
+                return self.error(format!("Stack overflow calling {}", name));
+            }
+            let base = if self.checked {
+                0
+            } else {
+                self.alloc_uninit(Region::Frame(name.clone()), lvars_offset) + lvars_offset as i64
+            };
+            self.frames.push(Frame {
+                func: name,
+                site,
+                base,
+                locals: HashMap::new(),
+                values: HashMap::new(),
+            });
+
+            for (var, val) in argvars.iter().zip(args) {
+                let addr = self.local(var.offset.unwrap(), &var.name, &var.ty);
+                self.store(addr, var.ty.size(), val)?;
+            }
+            // Falling off the end returns the value of the last statement
+            let ret = match self.stmts(stmts.iter())? {
+                Flow::Return(val) => val,
+                Flow::Normal(val) => val.unwrap_or(0),
+                Flow::Break | Flow::Continue => 0,
+            };
+
+            // Whatever points into the frame dangles from now on
+            let frame = self.frames.pop().unwrap();
+            let dead = if self.checked {
+                frame.locals.values().cloned().collect()
+            } else {
+                vec![frame.base - lvars_offset as i64]
+            };
+            for addr in dead {
+                let alloc = &mut self.allocs[(addr >> OFFSET_BITS) as usize - 1];
+                alloc.live = false;
+                alloc.bytes = Vec::new();
+                alloc.init = Vec::new();
+            }
+            Ok(ret)
+        } else {
+            panic!("interp: Expected a function definition");
+        }
+    }
+
+    fn call(&mut self, name: &str, args: Vec<i64>) -> Result<i64, Stop> {
+        if let Some(&func) = self.funcs.get(name) {
+            let (span, node) = (self.span, self.node);
+            let ret = self.call_func(func, args, node.and_then(|node| node.span))?;
+            self.span = span;
+            self.node = node;
+            return Ok(ret);
+        }
+        match name {
+            "putchar" => {
+                let c = arg(&args, 0) as u8;
+                self.write(&[c]);
+                Ok(c as i64)
+            }
+            "puts" => {
+                let mut s = self.string(arg(&args, 0))?;
+                s.push(b'\n');
+                self.write(&s);
+                Ok(s.len() as i64)
+            }
+            "printf" => {
+                let fmt = self.string(arg(&args, 0))?;
+                let s = self.format(&fmt, &args[1.min(args.len())..])?;
+                self.write(&s);
+                Ok(s.len() as i64)
+            }
+            "malloc" => Ok(self.malloc(arg(&args, 0), false)),
+            "calloc" => Ok(self.malloc(arg(&args, 0).wrapping_mul(arg(&args, 1)), true)),
+            "free" => {
+                let addr = arg(&args, 0);
+                if addr == 0 {
+                    return Ok(0);
+                }
+                let (id, ofs) = self.locate(addr, 0, false)?;
+                if self.allocs[id].region != Region::Heap || ofs != 0 {
+                    return self.error("free of a pointer malloc did not return".to_string());
+                }
+                self.allocs[id].live = false;
+                self.allocs[id].bytes = Vec::new();
+                Ok(0)
+            }
+            "exit" => Err(Stop::Exit(arg(&args, 0) as i32)),
+            _ => self.error(format!("Call to undefined function {}", name)),
+        }
+    }
+
+    fn malloc(&mut self, size: i64, zeroed: bool) -> i64 {
+        if !(0..1 << OFFSET_BITS).contains(&size) {
+            return 0;
+        }
+        if zeroed {
+            self.alloc(Region::Heap, vec![0; size as usize], false)
+        } else {
+            self.alloc_uninit(Region::Heap, size as usize)
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let _ = self.out.write_all(bytes);
+    }
+
+    // Output of printf for fmt and the values passed after it
+    fn format(&self, fmt: &[u8], args: &[i64]) -> Result<Vec<u8>, Stop> {
+        let mut out = Vec::new();
+        let mut args = args.iter().cloned();
+        let mut next = || args.next().unwrap_or(0);
+        let mut i = 0;
+        while i < fmt.len() {
+            if fmt[i] != b'%' {
+                out.push(fmt[i]);
+                i += 1;
+                continue;
+            }
+            i += 1;
+            let mut spec = Spec::default();
+            while let Some(&flag @ (b'-' | b'0' | b'+' | b' ' | b'#')) = fmt.get(i) {
+                match flag {
+                    b'-' => spec.left = true,
+                    b'0' => spec.zero = true,
+                    b'+' => spec.plus = true,
+                    b' ' => spec.space = true,
+                    _ => spec.alt = true,
+                }
+                i += 1;
+            }
+            if fmt.get(i) == Some(&b'*') {
+                let w = next() as i32;
+                spec.left |= w < 0;
+                spec.width = w.unsigned_abs() as usize;
+                i += 1;
+            }
+            while let Some(&d @ b'0'..=b'9') = fmt.get(i) {
+                spec.width = spec.width * 10 + (d - b'0') as usize;
+                i += 1;
+            }
+            if fmt.get(i) == Some(&b'.') {
+                i += 1;
+                let mut prec = 0;
+                if fmt.get(i) == Some(&b'*') {
+                    prec = (next() as i32).max(0) as usize;
+                    i += 1;
+                }
+                while let Some(&d @ b'0'..=b'9') = fmt.get(i) {
+                    prec = prec * 10 + (d - b'0') as usize;
+                    i += 1;
+                }
+                spec.prec = Some(prec);
+            }
+            // Length modifiers pick how many bytes of the argument count
+            let mut size = 4;
+            while let Some(&m @ (b'h' | b'l' | b'z' | b'j' | b't')) = fmt.get(i) {
+                size = match (m, size) {
+                    (b'h', 4) => 2,
+                    (b'h', _) => 1,
+                    _ => 8,
+                };
+                i += 1;
+            }
+            let conv = match fmt.get(i) {
+                Some(&c) => c,
+                None => break,
+            };
+            i += 1;
+            let text = match conv {
+                b'd' | b'i' => {
+                    let v = eval_un(UnOp::Sext(Width::from_size(size)), next());
+                    let sign = if v < 0 {
+                        "-"
+                    } else if spec.plus {
+                        "+"
+                    } else if spec.space {
+                        " "
+                    } else {
+                        ""
+                    };
+                    spec.number(sign, v.unsigned_abs().to_string())
+                }
+                b'u' | b'x' | b'X' | b'o' => {
+                    let v = next() as u64 & (u64::MAX >> (64 - 8 * size));
+                    let (digits, prefix) = match conv {
+                        b'u' => (v.to_string(), ""),
+                        b'x' => (format!("{:x}", v), "0x"),
+                        b'X' => (format!("{:X}", v), "0X"),
+                        _ => (format!("{:o}", v), "0"),
+                    };
+                    let prefix = if spec.alt && v != 0 { prefix } else { "" };
+                    spec.number(prefix, digits)
+                }
+                b'p' => match next() {
+                    0 => spec.pad(b"(nil)".to_vec()),
+                    v => spec.number("0x", format!("{:x}", v)),
+                },
+                b'c' => spec.pad(vec![next() as u8]),
+                b's' => {
+                    let mut s = self.string(next())?;
+                    if let Some(prec) = spec.prec {
+                        s.truncate(prec);
+                    }
+                    spec.pad(s)
+                }
+                b'%' => vec![b'%'],
+                c => return self.error(format!("Unsupported printf conversion %{}", c as char)),
+            };
+            out.extend(text);
+        }
+        Ok(out)
+    }
+
+    // Runs a statement list, returning the value of the last statement
+    fn stmts<I: Iterator<Item = &'a Node>>(&mut self, stmts: I) -> Result<Flow, Stop> {
+        let mut last = None;
+        for stmt in stmts {
+            match self.stmt(stmt)? {
+                Flow::Normal(val) => last = val,
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal(last))
+    }
+
+    fn opt_stmt(&mut self, stmt: &'a Option<Box<Node>>) -> Result<Flow, Stop> {
+        match stmt {
+            Some(stmt) => self.stmt(stmt),
+            None => Ok(Flow::Normal(None)),
+        }
+    }
+
+    // Runs a loop body. Some(flow) leaves the loop with it.
+    fn loop_body(&mut self, body: &'a Option<Box<Node>>) -> Result<Option<Flow>, Stop> {
+        let flow = self.opt_stmt(body)?;
+        if self.seek.is_some() {
+            // The label is not in here, so the loop is skipped as a whole
+            return Ok(Some(Flow::Normal(None)));
+        }
+        Ok(match flow {
+            Flow::Break => Some(Flow::Normal(None)),
+            Flow::Return(val) => Some(Flow::Return(val)),
+            Flow::Normal(_) | Flow::Continue => None,
+        })
+    }
+
+    // While a switch seeks its label, statements are walked into without
+    // running them until the label is found; from there on they run as usual.
+    fn stmt(&mut self, node: &'a Node) -> Result<Flow, Stop> {
+        use NodeKind::*;
+
+        if node.span.is_some() {
+            self.span = node.span;
+        }
+        if self.checked {
+            self.frames.last_mut().unwrap().values.clear();
+        }
+        let seeking = self.seek.is_some();
+        match node.kind {
+            NDRETURN { node: ref operand } if !seeking => Ok(Flow::Return(self.expr(operand)?)),
+            NDIF {
+                ref cond,
+                ref ifnode,
+                ref elsenode,
+            } => {
+                let flow = if seeking {
+                    match self.opt_stmt(ifnode)? {
+                        _ if self.seek.is_some() => self.opt_stmt(elsenode)?,
+                        flow => flow,
+                    }
+                } else if self.expr(cond)? != 0 {
+                    self.opt_stmt(ifnode)?
+                } else {
+                    self.opt_stmt(elsenode)?
+                };
+                Ok(match flow {
+                    Flow::Normal(_) => Flow::Normal(None),
+                    flow => flow,
+                })
+            }
+            // Labels within belong to the switch itself
+            NDSWITCH {
+                ref ctrl,
+                ref stmt,
+                ref cases,
+                has_default,
+            } if !seeking => {
+                let val = self.expr(ctrl)?;
+                let target = match cases.iter().position(|c| *c as i64 == val) {
+                    Some(pos) => Seek::Case(pos),
+                    None if has_default => Seek::Default,
+                    None => return Ok(Flow::Normal(None)),
+                };
+                self.seek = Some(target);
+                let flow = self.opt_stmt(stmt)?;
+                self.seek = None;
+                Ok(match flow {
+                    Flow::Return(val) => Flow::Return(val),
+                    Flow::Continue => Flow::Continue,
+                    Flow::Normal(_) | Flow::Break => Flow::Normal(None),
+                })
+            }
+            NDCASE { ref stmt, pos, .. } => {
+                if pos.is_some() && self.seek == pos.map(Seek::Case) {
+                    self.seek = None;
+                }
+                self.opt_stmt(stmt)
+            }
+            NDDEFAULT { ref stmt } => {
+                if self.seek == Some(Seek::Default) {
+                    self.seek = None;
+                }
+                self.opt_stmt(stmt)
+            }
+            NDBREAK if !seeking => Ok(Flow::Break),
+            NDCONTINUE if !seeking => Ok(Flow::Continue),
+            NDWHILE {
+                ref cond,
+                ref repnode,
+            } => loop {
+                if self.seek.is_none() && self.expr(cond)? == 0 {
+                    return Ok(Flow::Normal(None));
+                }
+                if let Some(flow) = self.loop_body(repnode)? {
+                    return Ok(flow);
+                }
+            },
+            NDDOWHILE {
+                ref cond,
+                ref repnode,
+            } => loop {
+                if let Some(flow) = self.loop_body(repnode)? {
+                    return Ok(flow);
+                }
+                if self.expr(cond)? == 0 {
+                    return Ok(Flow::Normal(None));
+                }
+            },
+            NDFOR {
+                ref init,
+                ref cond,
+                ref step,
+                ref repnode,
+            } => {
+                if let (Some(init), false) = (init, seeking) {
+                    self.expr(init)?;
+                }
+                loop {
+                    if let (Some(cond), None) = (cond, self.seek) {
+                        if self.expr(cond)? == 0 {
+                            return Ok(Flow::Normal(None));
+                        }
+                    }
+                    if let Some(flow) = self.loop_body(repnode)? {
+                        return Ok(flow);
+                    }
+                    if let Some(step) = step {
+                        self.expr(step)?;
+                    }
+                }
+            }
+            NDBLOCK { ref stmts } => self.stmts(stmts.iter()),
+            _ if seeking => Ok(Flow::Normal(None)),
+            NDDECL {
+                ref vars,
+                ref inits,
+            } => {
+                self.declare(vars);
+                for init in inits.iter() {
+                    self.expr(init)?;
+                }
+                Ok(Flow::Normal(None))
+            }
+            NDFUNCDEF { .. } => panic!("interp: Nested function definition"),
+            _ => Ok(Flow::Normal(Some(self.expr(node)?))),
+        }
+    }
+
+    // Address of an lvalue
+    fn addr(&mut self, node: &'a Node) -> Result<i64, Stop> {
+        use NodeKind::*;
+
+        match node.kind {
+            NDLVAR { offset, ref name } => Ok(self.local(offset, name, node.ty.as_ref().unwrap())),
+            NDGVAR { ref name } | NDPROTOTY { ref name } => match self.symbol(name) {
+                Some(addr) => Ok(addr),
+                None => self.error(format!("Undefined symbol {}", name)),
+            },
+            NDSTR { pos } => Ok(self.symbols[&format!("{}{}", LITERAL_HEAD, pos)]),
+            NDDEREF { node: ref operand } => self.expr(operand),
+            NDMEMBER {
+                node: ref varnode,
+                offset,
+                ..
+            } => Ok(self.addr(varnode)? + offset.unwrap() as i64),
+            _ => panic!("interp: Not an lvalue: {}", node.kind.name()),
+        }
+    }
+
+    // Reads an lvalue of type ty at addr; bit-fields are extracted from their unit.
+    // Only scalars need to be initialized, as copying a struct is fine
+    // whatever it holds.
+    fn load_lvalue(&self, lvalue: &Node, ty: &Type, addr: i64) -> Result<i64, Stop> {
+        if !ty.is_struct() {
+            self.check_init(addr, ty.size())?;
+        }
+        let unit = self.load(addr, ty.size())?;
+        Ok(match bit_field(lvalue) {
+            Some((offset, width)) => {
+                let top = eval_bin(BinOp::Shl, unit, (64 - offset - width) as i64).unwrap();
+                eval_bin(BinOp::Sar, top, (64 - width) as i64).unwrap()
+            }
+            None => unit,
+        })
+    }
+
+    // Writes val to an lvalue, leaving the bits around a bit-field untouched
+    fn store_lvalue(&mut self, lvalue: &Node, ty: &Type, addr: i64, val: i64) -> Result<(), Stop> {
+        let val = match bit_field(lvalue) {
+            Some((offset, bits)) => {
+                let mask = (u64::MAX >> (64 - bits)) << offset;
+                let unit = self.load(addr, ty.size())?;
+                (unit & !mask as i64) | ((val << offset) & mask as i64)
+            }
+            None => val,
+        };
+        self.store(addr, ty.size(), val)
+    }
+
+    fn bin(&self, op: BinOp, l: i64, r: i64) -> Result<i64, Stop> {
+        match eval_bin(op, l, r) {
+            Some(val) => Ok(val),
+            None if r == 0 => self.error("Division by zero".to_string()),
+            None => self.error(format!("Overflow dividing {} by {}", l, r)),
+        }
+    }
+
+    // Integer arithmetic in type ty, which under --check has to give the
+    // same result as it would with unlimited bits
+    fn arith(&self, op: BinOp, ty: &Type, l: i64, r: i64) -> Result<i64, Stop> {
+        if self.checked && ty.is_integral() {
+            // Operands are promoted to int at least
+            let bits = 8 * ty.size().max(4) as u32;
+            let name = if bits == 32 { "int" } else { "long" };
+            if let BinOp::Shl | BinOp::Shr = op {
+                if r < 0 || r >= bits as i64 {
+                    return self.error(format!("Shift by {} bits of a {}-bit {}", r, bits, name));
+                }
+            }
+            let wide = |v: i64| (v << (64 - bits) >> (64 - bits)) as i128;
+            let (x, y) = (wide(l), wide(r));
+            let exact = match op {
+                BinOp::Add => Some((x + y, "+")),
+                BinOp::Sub => Some((x - y, "-")),
+                BinOp::Mul => Some((x * y, "*")),
+                BinOp::Div if y != 0 => Some((x / y, "/")),
+                BinOp::Mod if y != 0 => Some((x / y, "%")),
+                BinOp::Shl if x < 0 => {
+                    return self.error(format!("Left shift of the negative value {}", x));
+                }
+                BinOp::Shl => Some((x << r, "<<")),
+                _ => None,
+            };
+            if let Some((v, sym)) = exact {
+                let max = (1i128 << (bits - 1)) - 1;
+                if v < -max - 1 || v > max {
+                    return self.error(format!(
+                        "Signed overflow: {} {} {} does not fit in {}",
+                        x,
+                        sym,
+                        if op == BinOp::Shl { r as i128 } else { y },
+                        name
+                    ));
+                }
+            }
+        }
+        self.bin(op, l, r)
+    }
+
+    // Value of an expression; under --check it is kept to explain errors
+    fn expr(&mut self, node: &'a Node) -> Result<i64, Stop> {
+        let outer = self.node;
+        self.node = Some(node);
+        let val = self.eval(node)?;
+        self.node = outer;
+        if self.checked {
+            self.frames.last_mut().unwrap().values.insert(node, val);
+        }
+        Ok(val)
+    }
+
+    fn eval(&mut self, node: &'a Node) -> Result<i64, Stop> {
+        use NodeKind::*;
+
+        if node.span.is_some() {
+            self.span = node.span;
+        }
+        match node.kind {
+            NDINT { val } => Ok(val as i64),
+            NDSTR { .. } | NDPROTOTY { .. } => self.addr(node),
+            NDLVAR { .. } | NDGVAR { .. } | NDMEMBER { .. } | NDDEREF { .. } => {
+                let ty = node.ty.as_ref().unwrap();
+                let addr = self.addr(node)?;
+                // Arrays and functions decay into their address, and
+                // aggregates that fit no register are copied from it
+                if ty.is_array() || ty.is_function() || ty.in_memory() {
+                    Ok(addr)
+                } else {
+                    self.load_lvalue(node, ty, addr)
+                }
+            }
+            NDADDR { node: ref operand } => self.addr(operand),
+            NDASSIGN {
+                ref lhs,
+                ref rhs,
+                scale_lhs,
+                eval_pre,
+                assign_mode,
+                ..
+            } => {
+                let ty = node.ty.as_ref().unwrap();
+                let addr = self.addr(lhs)?;
+                if assign_mode == AssignMode::DEFAULT && ty.in_memory() {
+                    let src = self.expr(rhs)?;
+                    self.copy(addr, src, ty.size())?;
+                    return Ok(addr);
+                }
+                // A struct read straight from an object keeps track of
+                // which of its bytes are initialized
+                if assign_mode == AssignMode::DEFAULT && ty.is_struct() {
+                    if let NDLVAR { .. } | NDGVAR { .. } | NDMEMBER { .. } | NDDEREF { .. } =
+                        rhs.kind
+                    {
+                        let src = self.addr(rhs)?;
+                        self.copy(addr, src, ty.size())?;
+                        return self.load(addr, ty.size());
+                    }
+                }
+                if assign_mode == AssignMode::DEFAULT {
+                    let val = self.expr(rhs)?;
+                    self.store_lvalue(lhs, ty, addr, val)?;
+                    return Ok(val);
+                }
+
+                let old = self.load_lvalue(lhs, ty, addr)?;
+                let mut val = self.expr(rhs)?;
+                let op = compound_op(assign_mode);
+                let new = if scale_lhs == Some(true) {
+                    val = val.wrapping_mul(ty.base_size() as i64);
+                    let delta = if op == BinOp::Sub {
+                        val.wrapping_neg()
+                    } else {
+                        val
+                    };
+                    self.check_offset(old, delta)?;
+                    self.bin(op, old, val)?
+                } else {
+                    self.arith(op, common_type(op, lhs, rhs), old, val)?
+                };
+                self.store_lvalue(lhs, ty, addr, new)?;
+                Ok(if eval_pre { new } else { old })
+            }
+            NDADD {
+                ref lhs,
+                ref rhs,
+                scale_lhs,
+            } => {
+                let mut l = self.expr(lhs)?;
+                let mut r = self.expr(rhs)?;
+                let ty = node.ty.as_ref().unwrap();
+                if !ty.is_ptr_like() {
+                    return self.arith(BinOp::Add, ty, l, r);
+                }
+                if scale_lhs.unwrap() {
+                    r = r.wrapping_mul(ty.base_size() as i64);
+                    self.check_offset(l, r)?;
+                } else {
+                    l = l.wrapping_mul(ty.base_size() as i64);
+                    self.check_offset(r, l)?;
+                }
+                Ok(l.wrapping_add(r))
+            }
+            NDSUB {
+                ref lhs, ref rhs, ..
+            } => {
+                let l = self.expr(lhs)?;
+                let mut r = self.expr(rhs)?;
+                let ty = node.ty.as_ref().unwrap();
+                if ty.is_ptr_like() {
+                    r = r.wrapping_mul(ty.base_size() as i64);
+                    self.check_offset(l, r.wrapping_neg())?;
+                }
+                // The distance between two pointers counts elements
+                let lty = lhs.ty.as_ref().unwrap();
+                if lty.is_ptr_like() && rhs.ty.as_ref().unwrap().is_ptr_like() {
+                    self.check_distance(l, r)?;
+                    self.bin(BinOp::Div, l.wrapping_sub(r), lty.base_size() as i64)
+                } else if ty.is_ptr_like() {
+                    Ok(l.wrapping_sub(r))
+                } else {
+                    self.arith(BinOp::Sub, ty, l, r)
+                }
+            }
+            NDMUL { ref lhs, ref rhs } => self.binary(BinOp::Mul, node, lhs, rhs),
+            NDDIV { ref lhs, ref rhs } => self.binary(BinOp::Div, node, lhs, rhs),
+            NDMOD { ref lhs, ref rhs } => self.binary(BinOp::Mod, node, lhs, rhs),
+            NDBITAND { ref lhs, ref rhs } => self.binary(BinOp::And, node, lhs, rhs),
+            NDBITXOR { ref lhs, ref rhs } => self.binary(BinOp::Xor, node, lhs, rhs),
+            NDBITOR { ref lhs, ref rhs } => self.binary(BinOp::Or, node, lhs, rhs),
+            NDSHL { ref lhs, ref rhs } => self.binary(BinOp::Shl, node, lhs, rhs),
+            NDSHR { ref lhs, ref rhs } => self.binary(BinOp::Shr, node, lhs, rhs),
+            NDEQ { ref lhs, ref rhs } => self.compare(Cond::Eq, lhs, rhs),
+            NDNEQ { ref lhs, ref rhs } => self.compare(Cond::Ne, lhs, rhs),
+            NDLT { ref lhs, ref rhs } => self.compare(Cond::Lt, lhs, rhs),
+            NDLEQ { ref lhs, ref rhs } => self.compare(Cond::Le, lhs, rhs),
+            NDBITNOT { node: ref operand } => Ok(eval_un(UnOp::Not, self.expr(operand)?)),
+            NDCAST { node: ref operand } => {
+                let val = self.expr(operand)?;
+                let ty = node.ty.as_ref().unwrap();
+                let from = operand.ty.as_ref().unwrap();
+                // The same extension lower makes, see there
+                let size = if from.is_integral() && from.size() < ty.size() {
+                    from.size()
+                } else {
+                    ty.size()
+                };
+                if ty.is_integral() && size < 8 {
+                    Ok(eval_un(UnOp::Sext(Width::from_size(size)), val))
+                } else {
+                    Ok(val)
+                }
+            }
+            NDCOND {
+                ref cond,
+                ref then,
+                ref els,
+            } => {
+                if self.expr(cond)? != 0 {
+                    self.expr(then)
+                } else {
+                    self.expr(els)
+                }
+            }
+            NDLOGAND { ref lhs, ref rhs } => {
+                Ok((self.expr(lhs)? != 0 && self.expr(rhs)? != 0) as i64)
+            }
+            NDLOGOR { ref lhs, ref rhs } => {
+                Ok((self.expr(lhs)? != 0 || self.expr(rhs)? != 0) as i64)
+            }
+            NDCALL {
+                ref prototy,
+                ref args,
+            } => {
+                let callee = match prototy.kind {
+                    NDPROTOTY { ref name } => name.clone(),
+                    _ => {
+                        let addr = self.expr(prototy)?;
+                        self.function_at(addr)?
+                    }
+                };
+                let mut vals = Vec::new();
+                for arg in args.iter() {
+                    vals.push(self.expr(arg)?);
+                }
+                self.call(&callee, vals)
+            }
+            _ => panic!("interp: {} is not an expression", node.kind.name()),
+        }
+    }
+
+    // Name of the function a pointer points to
+    fn function_at(&self, addr: i64) -> Result<String, Stop> {
+        let id = (addr >> OFFSET_BITS) as usize;
+        match id.checked_sub(1).and_then(|id| self.allocs.get(id)) {
+            Some(Alloc {
+                region: Region::Function(name),
+                ..
+            }) if addr & 0xffff_ffff == 0 => Ok(name.clone()),
+            _ => self.error(format!("Call through {:#x}, which is not a function", addr)),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: BinOp,
+        node: &Node,
+        lhs: &'a Node,
+        rhs: &'a Node,
+    ) -> Result<i64, Stop> {
+        let l = self.expr(lhs)?;
+        let r = self.expr(rhs)?;
+        self.arith(op, node.ty.as_ref().unwrap(), l, r)
+    }
+
+    // Addresses order as unsigned values
+    fn compare(&mut self, cond: Cond, lhs: &'a Node, rhs: &'a Node) -> Result<i64, Stop> {
+        let is_ptr = |node: &Node| node.ty.as_ref().unwrap().is_ptr_like();
+        let cond = match cond {
+            Cond::Lt if is_ptr(lhs) || is_ptr(rhs) => Cond::Ult,
+            Cond::Le if is_ptr(lhs) || is_ptr(rhs) => Cond::Ule,
+            _ => cond,
+        };
+        let l = self.expr(lhs)?;
+        let r = self.expr(rhs)?;
+        Ok(eval_cmp(cond, l, r))
+    }
+}
+
+// Functions of the C library the interpreter provides itself
+fn is_builtin(name: &str) -> bool {
+    matches!(
+        name,
+        "putchar" | "puts" | "printf" | "malloc" | "calloc" | "free" | "exit"
+    )
+}
+
+// Arguments missing from a call read as zero
+fn arg(args: &[i64], index: usize) -> i64 {
+    args.get(index).cloned().unwrap_or(0)
+}
+
+// Flags, width and precision of a printf conversion
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    prec: Option<usize>,
+}
+
+impl Spec {
+    // Pads digits to the precision, then the whole to the width
+    fn number(&self, prefix: &str, digits: String) -> Vec<u8> {
+        let mut digits = digits.into_bytes();
+        if let Some(prec) = self.prec {
+            if prec == 0 && digits == b"0" {
+                digits.clear();
+            }
+            while digits.len() < prec {
+                digits.insert(0, b'0');
+            }
+        }
+        let mut out = prefix.as_bytes().to_vec();
+        let len = out.len() + digits.len();
+        if self.zero && !self.left && self.prec.is_none() && len < self.width {
+            out.resize(out.len() + self.width - len, b'0');
+        }
+        out.extend(digits);
+        self.pad(out)
+    }
+
+    fn pad(&self, text: Vec<u8>) -> Vec<u8> {
+        if text.len() >= self.width {
+            return text;
+        }
+        let fill = vec![b' '; self.width - text.len()];
+        if self.left {
+            [text, fill].concat()
+        } else {
+            [fill, text].concat()
+        }
+    }
+}
+
+// A compound assignment computes in the type of its operands after the
+// usual arithmetic conversions, not in that of the lhs
+fn common_type<'n>(op: BinOp, lhs: &'n Node, rhs: &'n Node) -> &'n Type {
+    let (l, r) = (lhs.ty.as_ref().unwrap(), rhs.ty.as_ref().unwrap());
+    if l.size() >= r.size() || matches!(op, BinOp::Shl | BinOp::Shr) {
+        l
+    } else {
+        r
+    }
+}
+
+// A string as C would write it, cut short if long
+fn quote_bytes(bytes: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for &b in bytes.iter().take(MAX_STRING) {
+        match b {
+            b'\'' => text.push('\''),
+            _ => text.extend(std::ascii::escape_default(b).map(char::from)),
+        }
+    }
+    text.push('"');
+    if bytes.len() > MAX_STRING {
+        text.push_str("...");
+    }
+    text
+}
+
+// Subexpressions of node in evaluation order, looking through the
+// conversions sema added and leaving out constants
+// Whether node is the sum a subscript expression reads through
+fn subscript_of(deref: &Node, node: &Node) -> bool {
+    match deref.kind {
+        NodeKind::NDDEREF { node: ref operand } => {
+            std::ptr::eq(&**operand, node) && operand.span == deref.span
+        }
+        _ => false,
+    }
+}
+
+fn operands<'a>(node: &'a Node, out: &mut Vec<&'a Node>) {
+    use NodeKind::*;
+
+    let children: Vec<&Node> = match node.kind {
+        NDADD {
+            ref lhs, ref rhs, ..
+        }
+        | NDSUB {
+            ref lhs, ref rhs, ..
+        }
+        | NDASSIGN {
+            ref lhs, ref rhs, ..
+        }
+        | NDMUL { ref lhs, ref rhs }
+        | NDDIV { ref lhs, ref rhs }
+        | NDMOD { ref lhs, ref rhs }
+        | NDEQ { ref lhs, ref rhs }
+        | NDNEQ { ref lhs, ref rhs }
+        | NDLEQ { ref lhs, ref rhs }
+        | NDLT { ref lhs, ref rhs }
+        | NDBITAND { ref lhs, ref rhs }
+        | NDBITXOR { ref lhs, ref rhs }
+        | NDBITOR { ref lhs, ref rhs }
+        | NDLOGAND { ref lhs, ref rhs }
+        | NDLOGOR { ref lhs, ref rhs }
+        | NDSHL { ref lhs, ref rhs }
+        | NDSHR { ref lhs, ref rhs } => vec![lhs, rhs],
+        NDBITNOT { ref node }
+        | NDADDR { ref node }
+        | NDDEREF { ref node }
+        | NDMEMBER { ref node, .. }
+        | NDCAST { ref node } => vec![node],
+        NDCOND {
+            ref cond,
+            ref then,
+            ref els,
+        } => vec![cond, then, els],
+        NDCALL { ref args, .. } => args.iter().collect(),
+        _ => Vec::new(),
+    };
+    for child in children {
+        match child.kind {
+            NDINT { .. } | NDSTR { .. } | NDPROTOTY { .. } => continue,
+            NDCAST { .. } => (),
+            _ => out.push(child),
+        }
+        operands(child, out);
+    }
+}
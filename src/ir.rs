@@ -0,0 +1,791 @@
+// Intermediate representation between the AST and the backend.
+// Functions are made of basic blocks holding typed instructions over an
+// unbounded set of virtual registers. Virtual registers are not SSA:
+// a register may be written from several blocks, e.g. the result of "&&".
+use crate::constexpr::ConstVal;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Width of a value in memory. Registers always hold 64-bit values and
+/// loads sign-extend to that width.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Width {
+    I8,
+    I16,
+    I32,
+    I64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VReg(pub usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div, // Signed
+    Mod, // Signed
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr, // Logical, see parser::shift
+    Sar, // Arithmetic, for casts and bit-fields
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    Not,         // Bitwise
+    Sext(Width), // Sign-extends the low bits of the given width
+}
+
+/// Comparisons yielding 0 or 1, signed unless prefixed with U
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Ult,
+    Ule,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Direct(String),
+    Indirect(VReg),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Const {
+        dst: VReg,
+        val: i64,
+    },
+    Copy {
+        dst: VReg,
+        src: VReg,
+    },
+    Bin {
+        dst: VReg,
+        op: BinOp,
+        lhs: VReg,
+        rhs: VReg,
+    },
+    Un {
+        dst: VReg,
+        op: UnOp,
+        src: VReg,
+    },
+    Cmp {
+        dst: VReg,
+        cond: Cond,
+        lhs: VReg,
+        rhs: VReg,
+    },
+    Load {
+        dst: VReg,
+        width: Width,
+        addr: VReg,
+    },
+    Store {
+        width: Width,
+        addr: VReg,
+        val: VReg,
+    },
+    /// Address of a local, i.e. rbp - offset
+    FrameAddr {
+        dst: VReg,
+        offset: usize,
+    },
+    /// Address of a global, function or literal
+    GlobalAddr {
+        dst: VReg,
+        name: String,
+    },
+    /// The index-th argument; only valid at the top of the entry block
+    Param {
+        dst: VReg,
+        index: usize,
+    },
+    Call {
+        dst: VReg,
+        callee: Callee,
+        args: Vec<VReg>,
+    },
+    /// Source position of what follows, only emitted under -g
+    Loc {
+        line: usize,
+        col: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch {
+        cond: VReg,
+        then: BlockId,
+        els: BlockId,
+    },
+    /// Multi-way branch on val; the first matching case wins
+    Switch {
+        val: VReg,
+        cases: Vec<(i64, BlockId)>,
+        default: BlockId,
+    },
+    Ret(VReg),
+    /// Placeholder while a block is being built
+    Unterminated,
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub num_params: usize,
+    pub frame_size: usize,  // Bytes used by locals below rbp
+    pub blocks: Vec<Block>, // blocks[0] is the entry
+    pub num_vregs: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub name: String,
+    pub size: usize,
+    pub init: Vec<Datum>, // Sorted by offset; the gaps are zero
+    pub tentative: bool,  // Defined without an initializer
+    pub readonly: bool,   // Of a const-qualified type
+}
+
+impl Global {
+    /// Whether every byte of the initial value is zero
+    pub fn is_zero(&self) -> bool {
+        self.init.iter().all(|datum| datum.val == ConstVal::Int(0))
+    }
+}
+
+/// Initial value of a scalar within a global
+#[derive(Debug, Clone)]
+pub struct Datum {
+    pub offset: usize,
+    pub width: Width,
+    pub val: ConstVal,
+}
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub globals: Vec<Global>,
+    pub literals: Vec<String>,
+    pub functions: Vec<Function>,
+}
+
+pub static LITERAL_HEAD: &str = ".Lstr";
+
+impl Width {
+    pub fn from_size(size: usize) -> Self {
+        match size {
+            1 => Width::I8,
+            2 => Width::I16,
+            4 => Width::I32,
+            8 => Width::I64,
+            _ => panic!("ir: No width for size {}", size),
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            Width::I8 => 1,
+            Width::I16 => 2,
+            Width::I32 => 4,
+            Width::I64 => 8,
+        }
+    }
+}
+
+impl Inst {
+    /// Register written by this instruction
+    pub fn def(&self) -> Option<VReg> {
+        use Inst::*;
+        match *self {
+            Const { dst, .. }
+            | Copy { dst, .. }
+            | Bin { dst, .. }
+            | Un { dst, .. }
+            | Cmp { dst, .. }
+            | Load { dst, .. }
+            | FrameAddr { dst, .. }
+            | GlobalAddr { dst, .. }
+            | Param { dst, .. }
+            | Call { dst, .. } => Some(dst),
+            Store { .. } | Loc { .. } => None,
+        }
+    }
+
+    /// Registers read by this instruction
+    pub fn uses(&self) -> Vec<VReg> {
+        use Inst::*;
+        match *self {
+            Const { .. } | FrameAddr { .. } | GlobalAddr { .. } | Param { .. } | Loc { .. } => {
+                Vec::new()
+            }
+            Un { src, .. } | Copy { src, .. } => vec![src],
+            Bin { lhs, rhs, .. } | Cmp { lhs, rhs, .. } => vec![lhs, rhs],
+            Load { addr, .. } => vec![addr],
+            Store { addr, val, .. } => vec![addr, val],
+            Call {
+                ref callee,
+                ref args,
+                ..
+            } => {
+                let mut uses = args.clone();
+                if let Callee::Indirect(r) = callee {
+                    uses.push(*r);
+                }
+                uses
+            }
+        }
+    }
+
+    /// Registers read by this instruction, for rewriting them in place
+    pub fn uses_mut(&mut self) -> Vec<&mut VReg> {
+        use Inst::*;
+        match *self {
+            Const { .. } | FrameAddr { .. } | GlobalAddr { .. } | Param { .. } | Loc { .. } => {
+                Vec::new()
+            }
+            Un { ref mut src, .. } | Copy { ref mut src, .. } => vec![src],
+            Bin {
+                ref mut lhs,
+                ref mut rhs,
+                ..
+            }
+            | Cmp {
+                ref mut lhs,
+                ref mut rhs,
+                ..
+            } => vec![lhs, rhs],
+            Load { ref mut addr, .. } => vec![addr],
+            Store {
+                ref mut addr,
+                ref mut val,
+                ..
+            } => vec![addr, val],
+            Call {
+                ref mut callee,
+                ref mut args,
+                ..
+            } => {
+                let mut uses: Vec<&mut VReg> = args.iter_mut().collect();
+                if let Callee::Indirect(r) = callee {
+                    uses.push(r);
+                }
+                uses
+            }
+        }
+    }
+
+    /// Whether removing the instruction is unobservable once its result is unused.
+    /// Loads are kept since volatile accesses are not marked in the IR, and
+    /// source positions since they have no result at all.
+    pub fn is_pure(&self) -> bool {
+        !matches!(
+            self,
+            Inst::Store { .. } | Inst::Call { .. } | Inst::Load { .. } | Inst::Loc { .. }
+        )
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        use Terminator::*;
+        match *self {
+            Jump(to) => vec![to],
+            Branch { then, els, .. } => vec![then, els],
+            Switch {
+                ref cases, default, ..
+            } => {
+                let mut succs: Vec<BlockId> = cases.iter().map(|(_, b)| *b).collect();
+                succs.push(default);
+                succs
+            }
+            Ret(_) | Unterminated => Vec::new(),
+        }
+    }
+
+    pub fn uses(&self) -> Vec<VReg> {
+        use Terminator::*;
+        match *self {
+            Branch { cond, .. } => vec![cond],
+            Switch { val, .. } => vec![val],
+            Ret(val) => vec![val],
+            Jump(_) | Unterminated => Vec::new(),
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut VReg> {
+        use Terminator::*;
+        match *self {
+            Branch { ref mut cond, .. } => vec![cond],
+            Switch { ref mut val, .. } => vec![val],
+            Ret(ref mut val) => vec![val],
+            Jump(_) | Unterminated => Vec::new(),
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        use Terminator::*;
+        match *self {
+            Jump(ref mut to) => vec![to],
+            Branch {
+                ref mut then,
+                ref mut els,
+                ..
+            } => vec![then, els],
+            Switch {
+                ref mut cases,
+                ref mut default,
+                ..
+            } => {
+                let mut succs: Vec<&mut BlockId> = cases.iter_mut().map(|(_, b)| b).collect();
+                succs.push(default);
+                succs
+            }
+            Ret(_) | Unterminated => Vec::new(),
+        }
+    }
+}
+
+impl Function {
+    pub fn new_vreg(&mut self) -> VReg {
+        self.num_vregs += 1;
+        VReg(self.num_vregs - 1)
+    }
+
+    /// Predecessors of every block, indexed by block id
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            for succ in block.term.successors() {
+                if !preds[succ.0].contains(&BlockId(id)) {
+                    preds[succ.0].push(BlockId(id));
+                }
+            }
+        }
+        preds
+    }
+
+    /// Drops blocks the entry cannot reach and renumbers the rest,
+    /// keeping their relative order
+    pub fn remove_unreachable_blocks(&mut self) {
+        let mut reachable = vec![false; self.blocks.len()];
+        for id in self.reverse_postorder() {
+            reachable[id.0] = true;
+        }
+        let mut renumber = vec![None; self.blocks.len()];
+        let mut next = 0;
+        for (id, r) in reachable.iter().enumerate() {
+            if *r {
+                renumber[id] = Some(BlockId(next));
+                next += 1;
+            }
+        }
+
+        let blocks = std::mem::take(&mut self.blocks);
+        for (id, mut block) in blocks.into_iter().enumerate() {
+            if !reachable[id] {
+                continue;
+            }
+            for b in block.term.successors_mut() {
+                *b = renumber[b.0].unwrap();
+            }
+            self.blocks.push(block);
+        }
+    }
+
+    /// Immediate dominator of every reachable block; None for the entry
+    /// and for unreachable blocks. Cooper, Harvey and Kennedy's iteration.
+    pub fn immediate_dominators(&self) -> Vec<Option<BlockId>> {
+        let order = self.reverse_postorder();
+        let mut rpo_index = vec![usize::MAX; self.blocks.len()];
+        for (i, id) in order.iter().enumerate() {
+            rpo_index[id.0] = i;
+        }
+        let preds = self.predecessors();
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[0] = Some(BlockId(0));
+
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while rpo_index[a.0] > rpo_index[b.0] {
+                    a = idom[a.0].unwrap();
+                }
+                while rpo_index[b.0] > rpo_index[a.0] {
+                    b = idom[b.0].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in order.iter().skip(1) {
+                let mut new_idom: Option<BlockId> = None;
+                for &p in preds[id.0].iter() {
+                    if idom[p.0].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => p,
+                        Some(cur) => intersect(&idom, p, cur),
+                    });
+                }
+                if new_idom != idom[id.0] {
+                    idom[id.0] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom[0] = None;
+        idom
+    }
+
+    /// Blocks reachable from the entry in reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        // Iterative DFS; the bool marks that the children are done
+        let mut stack = vec![(BlockId(0), false)];
+        while let Some((id, done)) = stack.pop() {
+            if done {
+                order.push(id);
+                continue;
+            }
+            if visited[id.0] {
+                continue;
+            }
+            visited[id.0] = true;
+            stack.push((id, true));
+            for succ in self.blocks[id.0].term.successors().into_iter().rev() {
+                if !visited[succ.0] {
+                    stack.push((succ, false));
+                }
+            }
+        }
+        order.reverse();
+        order
+    }
+}
+
+/// Checks the structural invariants of the module.
+/// Returns every violation found rather than stopping at the first.
+pub fn verify(module: &Module) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    let names: HashSet<&str> = module.functions.iter().map(|f| f.name.as_str()).collect();
+    if names.len() != module.functions.len() {
+        errors.push("Duplicate function definitions".to_string());
+    }
+    for global in module.globals.iter() {
+        verify_global(global, &mut errors);
+    }
+    for func in module.functions.iter() {
+        verify_function(func, &mut errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn verify_global(global: &Global, errors: &mut Vec<String>) {
+    let mut end = 0;
+    for datum in global.init.iter() {
+        if datum.offset < end {
+            errors.push(format!(
+                "{}: data at +{} overlaps",
+                global.name, datum.offset
+            ));
+        }
+        end = datum.offset + datum.width.size();
+        if end > global.size {
+            errors.push(format!(
+                "{}: data at +{} overflows",
+                global.name, datum.offset
+            ));
+        }
+        if let ConstVal::Addr(..) = datum.val {
+            if datum.width != Width::I64 {
+                errors.push(format!(
+                    "{}: address at +{} is not 8 bytes wide",
+                    global.name, datum.offset
+                ));
+            }
+        }
+    }
+}
+
+fn verify_function(func: &Function, errors: &mut Vec<String>) {
+    let mut err = |msg: String| errors.push(format!("{}: {}", func.name, msg));
+
+    if func.blocks.is_empty() {
+        err("No entry block".to_string());
+        return;
+    }
+
+    for (id, block) in func.blocks.iter().enumerate() {
+        if block.term == Terminator::Unterminated {
+            err(format!("bb{} has no terminator", id));
+        }
+        for succ in block.term.successors() {
+            if succ.0 >= func.blocks.len() {
+                err(format!("bb{} jumps to missing bb{}", id, succ.0));
+            }
+        }
+        for (pos, inst) in block.insts.iter().enumerate() {
+            let regs = inst.uses().into_iter().chain(inst.def());
+            for r in regs {
+                if r.0 >= func.num_vregs {
+                    err(format!("bb{}: v{} out of range", id, r.0));
+                }
+            }
+            if let Inst::Param { index, .. } = *inst {
+                let in_prologue = func.blocks[0].insts[..pos.min(func.blocks[0].insts.len())]
+                    .iter()
+                    .all(|i| matches!(i, Inst::Param { .. }));
+                if id != 0 || !in_prologue {
+                    err(format!(
+                        "bb{}: param {} is not at the top of the entry",
+                        id, index
+                    ));
+                }
+                if index >= func.num_params {
+                    err(format!("bb{}: param {} out of range", id, index));
+                }
+            }
+        }
+    }
+    if !all_targets_exist(func) {
+        return;
+    }
+
+    // Every register must be written on all paths before being read.
+    // Forward dataflow over "definitely defined" sets.
+    let order = func.reverse_postorder();
+    let preds = func.predecessors();
+    let all: HashSet<VReg> = (0..func.num_vregs).map(VReg).collect();
+    let mut defined_out: Vec<HashSet<VReg>> = vec![all.clone(); func.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in order.iter() {
+            let mut defined = entry_set(id, &preds, &defined_out, &order);
+            for inst in func.blocks[id.0].insts.iter() {
+                if let Some(d) = inst.def() {
+                    defined.insert(d);
+                }
+            }
+            if defined != defined_out[id.0] {
+                defined_out[id.0] = defined;
+                changed = true;
+            }
+        }
+    }
+    for &id in order.iter() {
+        let mut defined = entry_set(id, &preds, &defined_out, &order);
+        let block = &func.blocks[id.0];
+        for inst in block.insts.iter() {
+            for u in inst.uses() {
+                if !defined.contains(&u) {
+                    err(format!("bb{}: v{} may be used before it is set", id.0, u.0));
+                }
+            }
+            if let Some(d) = inst.def() {
+                defined.insert(d);
+            }
+        }
+        for u in block.term.uses() {
+            if !defined.contains(&u) {
+                err(format!("bb{}: v{} may be used before it is set", id.0, u.0));
+            }
+        }
+    }
+}
+
+// Registers defined on entry to id: the intersection over reachable predecessors
+fn entry_set(
+    id: BlockId,
+    preds: &[Vec<BlockId>],
+    defined_out: &[HashSet<VReg>],
+    reachable: &[BlockId],
+) -> HashSet<VReg> {
+    if id.0 == 0 {
+        return HashSet::new();
+    }
+    let mut set: Option<HashSet<VReg>> = None;
+    for p in preds[id.0].iter().filter(|p| reachable.contains(p)) {
+        set = Some(match set {
+            None => defined_out[p.0].clone(),
+            Some(s) => s.intersection(&defined_out[p.0]).cloned().collect(),
+        });
+    }
+    set.unwrap_or_default()
+}
+
+// The dataflow check assumes every successor exists
+fn all_targets_exist(func: &Function) -> bool {
+    func.blocks
+        .iter()
+        .all(|b| b.term.successors().iter().all(|s| s.0 < func.blocks.len()))
+}
+
+impl fmt::Display for VReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Width::I8 => "i8",
+            Width::I16 => "i16",
+            Width::I32 => "i32",
+            Width::I64 => "i64",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Inst::*;
+        match self {
+            Const { dst, val } => write!(f, "{} = const {}", dst, val),
+            Copy { dst, src } => write!(f, "{} = copy {}", dst, src),
+            Bin { dst, op, lhs, rhs } => {
+                let op = format!("{:?}", op).to_lowercase();
+                write!(f, "{} = {} {}, {}", dst, op, lhs, rhs)
+            }
+            Un { dst, op, src } => match op {
+                UnOp::Not => write!(f, "{} = not {}", dst, src),
+                UnOp::Sext(width) => write!(f, "{} = sext {} {}", dst, width, src),
+            },
+            Cmp {
+                dst,
+                cond,
+                lhs,
+                rhs,
+            } => {
+                let cond = format!("{:?}", cond).to_lowercase();
+                write!(f, "{} = cmp {} {}, {}", dst, cond, lhs, rhs)
+            }
+            Load { dst, width, addr } => write!(f, "{} = load {} {}", dst, width, addr),
+            Store { width, addr, val } => write!(f, "store {} {}, {}", width, addr, val),
+            FrameAddr { dst, offset } => write!(f, "{} = frameaddr {}", dst, offset),
+            GlobalAddr { dst, name } => write!(f, "{} = globaladdr {}", dst, name),
+            Param { dst, index } => write!(f, "{} = param {}", dst, index),
+            Loc { line, col } => write!(f, "loc {}:{}", line, col),
+            Call { dst, callee, args } => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                match callee {
+                    Callee::Direct(name) => {
+                        write!(f, "{} = call {}({})", dst, name, args.join(", "))
+                    }
+                    Callee::Indirect(r) => write!(f, "{} = call *{}({})", dst, r, args.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Terminator::*;
+        match self {
+            Jump(to) => write!(f, "jmp {}", to),
+            Branch { cond, then, els } => write!(f, "br {}, {}, {}", cond, then, els),
+            Switch {
+                val,
+                cases,
+                default,
+            } => {
+                let cases: Vec<String> = cases
+                    .iter()
+                    .map(|(v, b)| format!("{} => {}", v, b))
+                    .collect();
+                write!(
+                    f,
+                    "switch {} [{}] default {}",
+                    val,
+                    cases.join(", "),
+                    default
+                )
+            }
+            Ret(val) => write!(f, "ret {}", val),
+            Unterminated => write!(f, "<unterminated>"),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "func {}({} params, frame {}) {{",
+            self.name, self.num_params, self.frame_size
+        )?;
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "bb{}:", id)?;
+            for inst in block.insts.iter() {
+                writeln!(f, "  {}", inst)?;
+            }
+            writeln!(f, "  {}", block.term)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for g in self.globals.iter() {
+            writeln!(f, "global {} [{} bytes]", g.name, g.size)?;
+            for datum in g.init.iter() {
+                writeln!(f, "  +{} {} {}", datum.offset, datum.width, datum.val)?;
+            }
+        }
+        for (pos, literal) in self.literals.iter().enumerate() {
+            writeln!(f, "literal {}{} \"{}\"", LITERAL_HEAD, pos, literal)?;
+        }
+        for func in self.functions.iter() {
+            writeln!(f)?;
+            write!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConstVal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstVal::Int(v) => write!(f, "{}", v),
+            ConstVal::Addr(sym, 0) => write!(f, "{}", sym),
+            ConstVal::Addr(sym, ofs) => write!(f, "{}{:+}", sym, ofs),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+// In-memory execution for --jit. The object the built-in assembler makes
+// for -c is loaded the way a dynamic linker would load a shared library:
+// its sections are copied into anonymous pages, the symbols it leaves
+// undefined are looked up in the running process with dlsym, relocations
+// are applied, and the pages get their final protection before main is
+// called.
+//
+// The C library is mapped far from the pages we get, beyond the reach of
+// the 32-bit displacements in the code. Calls to it go through stubs
+// that jump to an address stored next to them, and loads of its
+// addresses through GOT slots filled in here.
+use crate::elf::{self, Object, Place, Section, Target};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::ptr;
+
+const PAGE: u64 = 4096;
+const STUB_SIZE: u64 = 16; // jmp qword ptr [rip], then the address
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const PROT_EXEC: c_int = 4;
+const MAP_PRIVATE: c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+const RTLD_DEFAULT: *mut c_void = ptr::null_mut();
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        off: i64,
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn dlsym(handle: *mut c_void, name: *const c_char) -> *mut c_void;
+    static environ: *const *const c_char;
+}
+
+type Main = extern "C" fn(c_int, *const *const c_char, *const *const c_char) -> c_int;
+
+// Pages grouped by the protection they end up with, in this order
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Segment {
+    Text,
+    Rodata,
+    Data,
+}
+
+impl Segment {
+    fn of(flags: u64) -> Self {
+        if flags & elf::SHF_EXECINSTR != 0 {
+            Segment::Text
+        } else if flags & elf::SHF_WRITE != 0 {
+            Segment::Data
+        } else {
+            Segment::Rodata
+        }
+    }
+
+    fn prot(self) -> c_int {
+        match self {
+            Segment::Text => PROT_READ | PROT_EXEC,
+            Segment::Rodata => PROT_READ,
+            Segment::Data => PROT_READ | PROT_WRITE,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Layout {
+    sections: Vec<Option<u64>>, // Offset of each section, None if not loaded
+    commons: HashMap<usize, u64>,
+    stubs: HashMap<usize, u64>, // For calls to undefined symbols
+    got: HashMap<usize, u64>,   // For loads of symbol addresses
+    segments: Vec<(Segment, u64, u64)>,
+    size: u64,
+}
+
+/// Loads obj into memory and calls its main with args as argv
+pub fn run(obj: &Object, args: &[String]) -> Result<i32, String> {
+    let layout = layout(obj);
+    let base = map(layout.size)?;
+
+    // Where each symbol ended up, or where the process has it
+    let mut addrs = Vec::with_capacity(obj.symbols.len());
+    for (i, sym) in obj.symbols.iter().enumerate() {
+        let addr = match sym.place {
+            Place::Section(sec) => match layout.sections[sec] {
+                Some(offset) => base + offset + sym.value,
+                None => 0,
+            },
+            Place::Common => base + layout.commons[&i],
+            Place::Undefined => lookup(&sym.name)?,
+        };
+        addrs.push(addr);
+    }
+
+    for (i, sec) in obj.sections.iter().enumerate() {
+        if let Some(offset) = layout.sections[i] {
+            write(base + offset, &sec.data);
+        }
+    }
+    for (&sym, &offset) in layout.stubs.iter() {
+        let mut stub = vec![0xff, 0x25, 0, 0, 0, 0];
+        stub.extend_from_slice(&addrs[sym].to_le_bytes());
+        write(base + offset, &stub);
+    }
+    for (&sym, &offset) in layout.got.iter() {
+        write(base + offset, &addrs[sym].to_le_bytes());
+    }
+
+    for (i, sec) in obj.sections.iter().enumerate() {
+        let offset = match layout.sections[i] {
+            Some(offset) => offset,
+            None => continue,
+        };
+        for reloc in sec.relocs.iter() {
+            let place = base + offset + reloc.offset;
+            let target = match reloc.target {
+                Target::Section(s) => match layout.sections[s] {
+                    Some(at) => base + at,
+                    None => {
+                        return Err(format!(
+                            "Reference to {}, which is not loaded",
+                            obj.sections[s].name
+                        ))
+                    }
+                },
+                Target::Symbol(s) => match reloc.kind {
+                    elf::R_X86_64_REX_GOTPCRELX => base + layout.got[&s],
+                    elf::R_X86_64_PC32 | elf::R_X86_64_PLT32 if layout.stubs.contains_key(&s) => {
+                        base + layout.stubs[&s]
+                    }
+                    _ if addrs[s] == 0 => {
+                        return Err(format!(
+                            "Reference to {}, which is not loaded",
+                            obj.symbols[s].name
+                        ))
+                    }
+                    _ => addrs[s],
+                },
+            };
+            let val = target.wrapping_add(reloc.addend as u64);
+            let (val, fits) = match reloc.kind {
+                elf::R_X86_64_64 => (val, true),
+                elf::R_X86_64_PC32 | elf::R_X86_64_PLT32 | elf::R_X86_64_REX_GOTPCRELX => {
+                    let rel = val.wrapping_sub(place) as i64;
+                    (rel as u64, rel == rel as i32 as i64)
+                }
+                elf::R_X86_64_32 => (val, val <= u32::MAX as u64),
+                elf::R_X86_64_32S => (val, val as i64 == val as i64 as i32 as i64),
+                kind => return Err(format!("Unsupported relocation type {}", kind)),
+            };
+            if !fits {
+                return Err(format!(
+                    "Relocation at {:#x} in {} does not reach its target",
+                    reloc.offset, sec.name
+                ));
+            }
+            let width = if reloc.kind == elf::R_X86_64_64 { 8 } else { 4 };
+            write(place, &val.to_le_bytes()[..width]);
+        }
+    }
+
+    for &(segment, start, end) in layout.segments.iter() {
+        if end > start {
+            protect(base + start, end - start, segment.prot())?;
+        }
+    }
+
+    let main = obj
+        .symbols
+        .iter()
+        .position(|sym| sym.name == "main" && matches!(sym.place, Place::Section(_)))
+        .ok_or_else(|| "No definition of main".to_string())?;
+    let args: Vec<CString> = args
+        .iter()
+        .map(|arg| CString::new(arg.as_str()).map_err(|_| format!("NUL in argument {:?}", arg)))
+        .collect::<Result<_, _>>()?;
+    let mut argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+    argv.push(ptr::null());
+    // SAFETY: main was compiled from C, taking the arguments a process would
+    let main: Main = unsafe { std::mem::transmute(addrs[main] as usize) };
+    Ok(main(args.len() as c_int, argv.as_ptr(), unsafe { environ }))
+}
+
+// Places the loaded sections, then the stubs and GOT slots, each segment
+// starting on a page of its own
+fn layout(obj: &Object) -> Layout {
+    let loaded =
+        |sec: &Section| sec.flags & elf::SHF_ALLOC != 0 && sec.kind != elf::SHT_X86_64_UNWIND;
+    let mut stubs = Vec::new();
+    let mut got = Vec::new();
+    for reloc in obj
+        .sections
+        .iter()
+        .filter(|sec| loaded(sec))
+        .flat_map(|sec| sec.relocs.iter())
+    {
+        let sym = match reloc.target {
+            Target::Symbol(s) => s,
+            Target::Section(_) => continue,
+        };
+        let slots = match reloc.kind {
+            elf::R_X86_64_PC32 | elf::R_X86_64_PLT32
+                if obj.symbols[sym].place == Place::Undefined =>
+            {
+                &mut stubs
+            }
+            elf::R_X86_64_REX_GOTPCRELX => &mut got,
+            _ => continue,
+        };
+        if !slots.contains(&sym) {
+            slots.push(sym);
+        }
+    }
+
+    let mut layout = Layout {
+        sections: vec![None; obj.sections.len()],
+        ..Layout::default()
+    };
+    let mut offset = 0u64;
+    let none = Vec::new();
+    for segment in [Segment::Text, Segment::Rodata, Segment::Data] {
+        let start = offset;
+        for (i, sec) in obj.sections.iter().enumerate() {
+            if loaded(sec) && Segment::of(sec.flags) == segment {
+                offset = offset.next_multiple_of(sec.align.max(1));
+                layout.sections[i] = Some(offset);
+                offset += sec.size;
+            }
+        }
+        let (slots, size, table) = match segment {
+            Segment::Text => (&stubs, STUB_SIZE, &mut layout.stubs),
+            Segment::Rodata => (&got, 8, &mut layout.got),
+            Segment::Data => (&none, 0, &mut layout.commons),
+        };
+        for &sym in slots.iter() {
+            offset = offset.next_multiple_of(8);
+            table.insert(sym, offset);
+            offset += size;
+        }
+        if segment == Segment::Data {
+            for (i, sym) in obj.symbols.iter().enumerate() {
+                if sym.place == Place::Common {
+                    offset = offset.next_multiple_of(sym.value.max(1));
+                    layout.commons.insert(i, offset);
+                    offset += sym.size;
+                }
+            }
+        }
+        layout.segments.push((segment, start, offset));
+        offset = offset.next_multiple_of(PAGE);
+    }
+    layout.size = offset.max(PAGE);
+    layout
+}
+
+// Address of a symbol in the process, such as a function of the C library
+fn lookup(name: &str) -> Result<u64, String> {
+    let cname = CString::new(name).unwrap();
+    // SAFETY: the name is NUL-terminated and RTLD_DEFAULT searches the
+    // libraries already loaded
+    let addr = unsafe { dlsym(RTLD_DEFAULT, cname.as_ptr()) };
+    if addr.is_null() {
+        return Err(format!("Undefined symbol {}", name));
+    }
+    Ok(addr as u64)
+}
+
+fn map(size: u64) -> Result<u64, String> {
+    let prot = PROT_READ | PROT_WRITE;
+    // SAFETY: a fresh anonymous mapping aliases nothing
+    let addr = unsafe {
+        mmap(
+            ptr::null_mut(),
+            size as usize,
+            prot,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if addr as isize == -1 {
+        return Err(format!("Cannot map {} bytes", size));
+    }
+    Ok(addr as u64)
+}
+
+fn protect(addr: u64, size: u64, prot: c_int) -> Result<(), String> {
+    // SAFETY: the range lies within the mapping, whose contents are in place
+    if unsafe { mprotect(addr as *mut c_void, size as usize, prot) } != 0 {
+        return Err(format!("Cannot protect {} bytes at {:#x}", size, addr));
+    }
+    Ok(())
+}
+
+fn write(addr: u64, bytes: &[u8]) {
+    // SAFETY: every write is to the mapping, while it is still writable
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, bytes.len()) }
+}
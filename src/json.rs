@@ -0,0 +1,289 @@
+// Just enough JSON for the messages rcc-lsp exchanges with an editor.
+// Objects keep their keys in order, so that what is written out reads the
+// way it was built.
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+static NULL: Json = Json::Null;
+
+impl Json {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut chars = s.chars().peekable();
+        let val = value(&mut chars)?;
+        skip_space(&mut chars);
+        match chars.next() {
+            None => Ok(val),
+            Some(c) => Err(format!("Unexpected '{}' after the value", c)),
+        }
+    }
+
+    pub fn object<const N: usize>(fields: [(&str, Json); N]) -> Self {
+        Json::Object(
+            IntoIterator::into_iter(fields)
+                .map(|(key, val)| (key.to_string(), val))
+                .collect(),
+        )
+    }
+
+    /// Member key of an object, or null if there is none
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map_or(&NULL, |(_, val)| val),
+            _ => &NULL,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Json::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as u64),
+            _ => None,
+        }
+    }
+
+    /// Elements of an array; nothing if it is not one
+    pub fn elems(&self) -> &[Json] {
+        match self {
+            Json::Array(elems) => elems,
+            _ => &[],
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(elems: Vec<Json>) -> Self {
+        Json::Array(elems)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // Integers, as ids and positions are, go without a fraction
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_str(f, s),
+            Json::Array(elems) => {
+                write!(f, "[")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{}", val)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+type Input<'a> = Peekable<Chars<'a>>;
+
+fn skip_space(chars: &mut Input) {
+    while matches!(chars.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+        chars.next();
+    }
+}
+
+fn value(chars: &mut Input) -> Result<Json, String> {
+    skip_space(chars);
+    match chars.peek() {
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_space(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                skip_space(chars);
+                if chars.next() != Some('"') {
+                    return Err("Expected a string as the key".to_string());
+                }
+                let key = string(chars)?;
+                skip_space(chars);
+                if chars.next() != Some(':') {
+                    return Err(format!("Expected ':' after \"{}\"", key));
+                }
+                fields.push((key, value(chars)?));
+                skip_space(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => return Ok(Json::Object(fields)),
+                    _ => return Err("Expected ',' or '}' in an object".to_string()),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut elems = Vec::new();
+            skip_space(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Ok(Json::Array(elems));
+            }
+            loop {
+                elems.push(value(chars)?);
+                skip_space(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Json::Array(elems)),
+                    _ => return Err("Expected ',' or ']' in an array".to_string()),
+                }
+            }
+        }
+        Some('"') => {
+            chars.next();
+            Ok(Json::String(string(chars)?))
+        }
+        Some('-' | '0'..='9') => {
+            let mut num = String::new();
+            while let Some(&c) = chars.peek() {
+                if !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9') {
+                    break;
+                }
+                num.push(c);
+                chars.next();
+            }
+            num.parse()
+                .map(Json::Number)
+                .map_err(|_| format!("Invalid number {}", num))
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if !c.is_ascii_alphabetic() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            match word.as_str() {
+                "null" => Ok(Json::Null),
+                "true" => Ok(Json::Bool(true)),
+                "false" => Ok(Json::Bool(false)),
+                _ => Err(format!("Unexpected '{}'", word)),
+            }
+        }
+        None => Err("Unexpected end of input".to_string()),
+    }
+}
+
+// Reads the rest of a string whose opening quote has been read
+fn string(chars: &mut Input) -> Result<String, String> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some('"') => s.push('"'),
+                Some('\\') => s.push('\\'),
+                Some('/') => s.push('/'),
+                Some('b') => s.push('\u{8}'),
+                Some('f') => s.push('\u{c}'),
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                Some('u') => {
+                    let mut code = hex4(chars)?;
+                    // Characters beyond the BMP come as a surrogate pair
+                    if (0xd800..0xdc00).contains(&code) {
+                        if chars.next() != Some('\\') || chars.next() != Some('u') {
+                            return Err("Unpaired surrogate in a string".to_string());
+                        }
+                        let low = hex4(chars)?;
+                        code =
+                            0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                    }
+                    s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                _ => return Err("Invalid escape in a string".to_string()),
+            },
+            Some(c) => s.push(c),
+            None => return Err("Unterminated string".to_string()),
+        }
+    }
+}
+
+fn hex4(chars: &mut Input) -> Result<u32, String> {
+    let digits: String = chars.take(4).collect();
+    u32::from_str_radix(&digits, 16).map_err(|_| format!("Invalid escape \\u{}", digits))
+}
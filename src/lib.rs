@@ -0,0 +1,41 @@
+// yarcc: a C compiler. The rcc binary drives the whole pipeline, while
+// rcc-lsp uses the front end to serve editors.
+#![allow(clippy::upper_case_acronyms)]
+// The modules were written for the binary; exporting them is not meant to
+// commit to a library API
+#![allow(clippy::new_without_default, clippy::should_implement_trait)]
+
+macro_rules! gen_line {
+    ($dst:expr, $($arg: tt)*) => {
+        write!($dst, $($arg)*).unwrap()
+    }
+}
+
+pub mod asm;
+pub mod assembler;
+pub mod cenv;
+pub mod codegen;
+pub mod constexpr;
+pub mod ctype;
+pub mod dump;
+pub mod dwarf;
+pub mod elf;
+pub mod encoder;
+pub mod interp;
+pub mod ir;
+// Loading code into memory needs mmap, dlsym and the x86-64 relocations
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod jit;
+pub mod json;
+pub mod lower;
+pub mod lsp;
+pub mod node;
+pub mod opt;
+pub mod options;
+pub mod parser;
+pub mod peephole;
+pub mod printer;
+pub mod regalloc;
+pub mod repl;
+pub mod sema;
+pub mod tokenizer;
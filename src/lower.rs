@@ -0,0 +1,763 @@
+// Lowering from the AST into the IR
+use crate::constexpr::{self, ConstVal};
+use crate::ctype::Type;
+use crate::ir::*;
+use crate::node::{AssignMode, Node, NodeKind};
+use crate::parser::{GlobalInit, Program};
+
+/// Translates a whole parsed program into an IR module. With debug set,
+/// statements are preceded by their source positions.
+pub fn lower(prog: &Program, debug: bool) -> Module {
+    let globals = prog
+        .globals
+        .iter()
+        .filter(|gvar| !gvar.is_extern)
+        .map(|gvar| {
+            let init = prog.global_inits.iter().find(|init| init.name == gvar.name);
+            Global {
+                name: gvar.name.clone(),
+                size: gvar.ty.total_size(),
+                init: init.map_or_else(Vec::new, global_data),
+                tentative: init.is_none(),
+                readonly: gvar.ty.is_readonly(),
+            }
+        })
+        .collect();
+    let literals = prog.literals.iter().cloned().collect();
+
+    let mut functions = Vec::new();
+    for node in prog.nodes.iter() {
+        if let NodeKind::NDFUNCDEF { .. } = node.kind {
+            functions.push(FuncLowerer::lower(node, debug));
+        }
+    }
+
+    Module {
+        globals,
+        literals,
+        functions,
+    }
+}
+
+// The parser has already checked that every value is a constant
+fn global_data(init: &GlobalInit) -> Vec<Datum> {
+    let mut data: Vec<Datum> = Vec::new();
+    for init in init.vals.iter() {
+        let val = match constexpr::eval(&init.val).unwrap() {
+            ConstVal::Int(v) => ConstVal::Int(constexpr::truncate(v, &init.ty)),
+            addr => addr,
+        };
+        let (val, bits) = match (val, init.bits) {
+            (ConstVal::Int(v), Some((bit, width))) => {
+                let mask = u64::MAX >> (64 - width);
+                (ConstVal::Int(((v as u64 & mask) << bit) as i64), true)
+            }
+            (val, _) => (val, false),
+        };
+        // Bit-fields sharing a unit are packed into one datum
+        match (data.last_mut(), val) {
+            (Some(last), ConstVal::Int(v)) if bits && last.offset == init.offset => {
+                if let ConstVal::Int(ref mut packed) = last.val {
+                    *packed |= v;
+                }
+            }
+            (_, val) => data.push(Datum {
+                offset: init.offset,
+                width: Width::from_size(init.ty.size()),
+                val,
+            }),
+        }
+    }
+    data
+}
+
+// Where the innermost switch sends its case and default labels
+struct SwitchCtx {
+    cases: Vec<BlockId>,
+    default: Option<BlockId>,
+}
+
+struct FuncLowerer {
+    func: Function,
+    cur: BlockId,
+    breaks: Vec<BlockId>,
+    continues: Vec<BlockId>,
+    switches: Vec<SwitchCtx>,
+    debug: bool, // Whether to emit source positions
+}
+
+impl FuncLowerer {
+    fn lower(node: &Node, debug: bool) -> Function {
+        if let NodeKind::NDFUNCDEF {
+            ref name,
+            ref argvars,
+            ref stmts,
+            lvars_offset,
+        } = node.kind
+        {
+            let mut l = FuncLowerer {
+                func: Function {
+                    name: name.clone(),
+                    num_params: argvars.len(),
+                    frame_size: lvars_offset,
+                    blocks: Vec::new(),
+                    num_vregs: 0,
+                },
+                cur: BlockId(0),
+                breaks: Vec::new(),
+                continues: Vec::new(),
+                switches: Vec::new(),
+                debug,
+            };
+            l.cur = l.new_block();
+
+            // Read every argument before anything else can clobber them
+            let params: Vec<VReg> = (0..argvars.len())
+                .map(|index| {
+                    let dst = l.func.new_vreg();
+                    l.emit(Inst::Param { dst, index });
+                    dst
+                })
+                .collect();
+            for (var, val) in argvars.iter().zip(params) {
+                let addr = l.frame_addr(var.offset.unwrap());
+                l.emit(Inst::Store {
+                    width: Width::from_size(var.ty.size()),
+                    addr,
+                    val,
+                });
+            }
+
+            // Falling off the end returns the value of the last statement
+            let last = l.stmts(stmts.iter());
+            let ret = match last {
+                Some(val) => val,
+                None => l.konst(0),
+            };
+            l.terminate(Terminator::Ret(ret));
+            l.func.remove_unreachable_blocks();
+            l.func
+        } else {
+            panic!("lower: Expected a function definition");
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.func.blocks.push(Block {
+            insts: Vec::new(),
+            term: Terminator::Unterminated,
+        });
+        BlockId(self.func.blocks.len() - 1)
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.func.blocks[self.cur.0].insts.push(inst);
+    }
+
+    // Ends the current block. Whatever follows goes into a fresh block
+    // which stays unreachable unless something jumps to it.
+    fn terminate(&mut self, term: Terminator) {
+        self.func.blocks[self.cur.0].term = term;
+        self.cur = self.new_block();
+    }
+
+    // Falls through from the current block into next
+    fn enter(&mut self, next: BlockId) {
+        self.func.blocks[self.cur.0].term = Terminator::Jump(next);
+        self.cur = next;
+    }
+
+    // Marks where the code for node starts
+    fn loc(&mut self, node: &Node) {
+        if let (true, Some(span)) = (self.debug, node.span) {
+            self.emit(Inst::Loc {
+                line: span.lo.line,
+                col: span.lo.col,
+            });
+        }
+    }
+
+    fn konst(&mut self, val: i64) -> VReg {
+        let dst = self.func.new_vreg();
+        self.emit(Inst::Const { dst, val });
+        dst
+    }
+
+    fn frame_addr(&mut self, offset: usize) -> VReg {
+        let dst = self.func.new_vreg();
+        self.emit(Inst::FrameAddr { dst, offset });
+        dst
+    }
+
+    fn global_addr(&mut self, name: String) -> VReg {
+        let dst = self.func.new_vreg();
+        self.emit(Inst::GlobalAddr { dst, name });
+        dst
+    }
+
+    fn bin(&mut self, op: BinOp, lhs: VReg, rhs: VReg) -> VReg {
+        let dst = self.func.new_vreg();
+        self.emit(Inst::Bin { dst, op, lhs, rhs });
+        dst
+    }
+
+    fn cmp(&mut self, cond: Cond, lhs: VReg, rhs: VReg) -> VReg {
+        let dst = self.func.new_vreg();
+        self.emit(Inst::Cmp {
+            dst,
+            cond,
+            lhs,
+            rhs,
+        });
+        dst
+    }
+
+    fn load(&mut self, size: usize, addr: VReg) -> VReg {
+        let dst = self.func.new_vreg();
+        self.emit(Inst::Load {
+            dst,
+            width: Width::from_size(size),
+            addr,
+        });
+        dst
+    }
+
+    fn un(&mut self, op: UnOp, src: VReg) -> VReg {
+        let dst = self.func.new_vreg();
+        self.emit(Inst::Un { dst, op, src });
+        dst
+    }
+
+    // Reads an lvalue of type ty at addr; bit-fields are extracted from their unit
+    fn load_lvalue(&mut self, lvalue: &Node, ty: &Type, addr: VReg) -> VReg {
+        let unit = self.load(ty.size(), addr);
+        match bit_field(lvalue) {
+            Some((offset, width)) => {
+                let up = self.konst((64 - offset - width) as i64);
+                let top = self.bin(BinOp::Shl, unit, up);
+                let down = self.konst((64 - width) as i64);
+                self.bin(BinOp::Sar, top, down)
+            }
+            None => unit,
+        }
+    }
+
+    // Writes val to an lvalue, leaving the bits around a bit-field untouched
+    fn store_lvalue(&mut self, lvalue: &Node, ty: &Type, addr: VReg, val: VReg) {
+        let width = Width::from_size(ty.size());
+        let val = match bit_field(lvalue) {
+            Some((offset, bits)) => {
+                let mask = (u64::MAX >> (64 - bits)) << offset;
+                let unit = self.load(ty.size(), addr);
+                let keep = self.konst(!mask as i64);
+                let kept = self.bin(BinOp::And, unit, keep);
+                let shift = self.konst(offset as i64);
+                let shifted = self.bin(BinOp::Shl, val, shift);
+                let m = self.konst(mask as i64);
+                let inserted = self.bin(BinOp::And, shifted, m);
+                self.bin(BinOp::Or, kept, inserted)
+            }
+            None => val,
+        };
+        self.emit(Inst::Store { width, addr, val });
+    }
+
+    // Copies size bytes from src to dst, eight at a time where possible
+    fn copy(&mut self, dst: VReg, src: VReg, size: usize) {
+        let mut ofs = 0;
+        for width in [8, 4, 2, 1] {
+            while size - ofs >= width {
+                let at = self.konst(ofs as i64);
+                let from = self.bin(BinOp::Add, src, at);
+                let val = self.load(width, from);
+                let to = self.bin(BinOp::Add, dst, at);
+                self.emit(Inst::Store {
+                    width: Width::from_size(width),
+                    addr: to,
+                    val,
+                });
+                ofs += width;
+            }
+        }
+    }
+
+    fn scale(&mut self, val: VReg, by: usize) -> VReg {
+        let by = self.konst(by as i64);
+        self.bin(BinOp::Mul, val, by)
+    }
+
+    // Branches on val being non-zero
+    fn branch(&mut self, val: VReg, then: BlockId, els: BlockId) {
+        self.func.blocks[self.cur.0].term = Terminator::Branch {
+            cond: val,
+            then,
+            els,
+        };
+    }
+
+    // Lowers a statement list, returning the value of the last statement
+    fn stmts<'a, I: Iterator<Item = &'a Node>>(&mut self, stmts: I) -> Option<VReg> {
+        let mut last = None;
+        for stmt in stmts {
+            last = self.stmt(stmt);
+        }
+        last
+    }
+
+    fn opt_stmt(&mut self, stmt: &Option<Box<Node>>) -> Option<VReg> {
+        match stmt {
+            Some(stmt) => self.stmt(stmt),
+            None => None,
+        }
+    }
+
+    // Returns the value of expression statements; other statements have none
+    fn stmt(&mut self, node: &Node) -> Option<VReg> {
+        use NodeKind::*;
+
+        // The statements within carry their own positions
+        if !matches!(node.kind, NDBLOCK { .. } | NDCASE { .. } | NDDEFAULT { .. }) {
+            self.loc(node);
+        }
+        match node.kind {
+            NDRETURN { node: ref operand } => {
+                let val = self.expr(operand);
+                self.terminate(Terminator::Ret(val));
+                None
+            }
+            NDIF {
+                ref cond,
+                ref ifnode,
+                ref elsenode,
+            } => {
+                let then = self.new_block();
+                let els = self.new_block();
+                let end = self.new_block();
+                let c = self.expr(cond);
+                self.branch(c, then, els);
+
+                self.cur = then;
+                self.opt_stmt(ifnode);
+                self.terminate(Terminator::Jump(end));
+                self.cur = els;
+                self.opt_stmt(elsenode);
+                self.enter(end);
+                None
+            }
+            NDSWITCH {
+                ref ctrl,
+                ref stmt,
+                ref cases,
+                has_default,
+            } => {
+                let val = self.expr(ctrl);
+                let case_blocks: Vec<BlockId> = cases.iter().map(|_| self.new_block()).collect();
+                let default = if has_default {
+                    Some(self.new_block())
+                } else {
+                    None
+                };
+                let end = self.new_block();
+                self.func.blocks[self.cur.0].term = Terminator::Switch {
+                    val,
+                    cases: cases
+                        .iter()
+                        .map(|v| *v as i64)
+                        .zip(case_blocks.iter().cloned())
+                        .collect(),
+                    default: default.unwrap_or(end),
+                };
+                // Code before the first label is unreachable
+                self.cur = self.new_block();
+
+                self.breaks.push(end);
+                self.switches.push(SwitchCtx {
+                    cases: case_blocks,
+                    default,
+                });
+                self.opt_stmt(stmt);
+                self.switches.pop();
+                self.breaks.pop();
+                self.enter(end);
+                None
+            }
+            NDCASE { ref stmt, pos, .. } => {
+                let target = match self.switches.last() {
+                    Some(ctx) => ctx.cases[pos.unwrap()],
+                    None => panic!("lower: case outside of switch"),
+                };
+                self.enter(target);
+                self.opt_stmt(stmt)
+            }
+            NDDEFAULT { ref stmt } => {
+                let target = match self.switches.last() {
+                    Some(ctx) => ctx.default.unwrap(),
+                    None => panic!("lower: default outside of switch"),
+                };
+                self.enter(target);
+                self.opt_stmt(stmt)
+            }
+            NDBREAK => {
+                let target = *self.breaks.last().expect("lower: stray break");
+                self.terminate(Terminator::Jump(target));
+                None
+            }
+            NDCONTINUE => {
+                let target = *self.continues.last().expect("lower: stray continue");
+                self.terminate(Terminator::Jump(target));
+                None
+            }
+            NDWHILE {
+                ref cond,
+                ref repnode,
+            } => {
+                let begin = self.new_block();
+                let body = self.new_block();
+                let end = self.new_block();
+                self.enter(begin);
+                // Each iteration tests again, so mark the condition too
+                self.loc(cond);
+                let c = self.expr(cond);
+                self.branch(c, body, end);
+
+                self.cur = body;
+                self.loop_body(repnode, end, begin);
+                self.terminate(Terminator::Jump(begin));
+                self.cur = end;
+                None
+            }
+            NDDOWHILE {
+                ref cond,
+                ref repnode,
+            } => {
+                let begin = self.new_block();
+                let test = self.new_block();
+                let end = self.new_block();
+                self.enter(begin);
+                self.loop_body(repnode, end, test);
+                self.enter(test);
+                self.loc(cond);
+                let c = self.expr(cond);
+                self.branch(c, begin, end);
+                self.cur = end;
+                None
+            }
+            NDFOR {
+                ref init,
+                ref cond,
+                ref step,
+                ref repnode,
+            } => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                let begin = self.new_block();
+                let body = self.new_block();
+                let next = self.new_block();
+                let end = self.new_block();
+                self.enter(begin);
+                match cond {
+                    Some(cond) => {
+                        self.loc(cond);
+                        let c = self.expr(cond);
+                        self.branch(c, body, end);
+                    }
+                    None => self.func.blocks[begin.0].term = Terminator::Jump(body),
+                }
+
+                self.cur = body;
+                self.loop_body(repnode, end, next);
+                self.enter(next);
+                if let Some(step) = step {
+                    self.loc(step);
+                    self.expr(step);
+                }
+                self.terminate(Terminator::Jump(begin));
+                self.cur = end;
+                None
+            }
+            NDBLOCK { ref stmts } => self.stmts(stmts.iter()),
+            NDDECL { ref inits, .. } => {
+                for init in inits.iter() {
+                    self.expr(init);
+                }
+                None
+            }
+            NDFUNCDEF { .. } => panic!("lower: Nested function definition"),
+            _ => Some(self.expr(node)),
+        }
+    }
+
+    fn loop_body(&mut self, body: &Option<Box<Node>>, brk: BlockId, cont: BlockId) {
+        self.breaks.push(brk);
+        self.continues.push(cont);
+        self.opt_stmt(body);
+        self.continues.pop();
+        self.breaks.pop();
+    }
+
+    // Address of an lvalue
+    fn addr(&mut self, node: &Node) -> VReg {
+        use NodeKind::*;
+
+        match node.kind {
+            NDLVAR { offset, .. } => self.frame_addr(offset),
+            NDGVAR { ref name } | NDPROTOTY { ref name } => self.global_addr(name.clone()),
+            NDSTR { pos } => self.global_addr(format!("{}{}", LITERAL_HEAD, pos)),
+            NDDEREF { node: ref operand } => self.expr(operand),
+            NDMEMBER {
+                node: ref varnode,
+                offset,
+                ..
+            } => {
+                let base = self.addr(varnode);
+                let ofs = self.konst(offset.unwrap() as i64);
+                self.bin(BinOp::Add, base, ofs)
+            }
+            _ => panic!("lower: Not an lvalue: {}", node.kind.name()),
+        }
+    }
+
+    // Value of an expression
+    fn expr(&mut self, node: &Node) -> VReg {
+        use NodeKind::*;
+
+        match node.kind {
+            NDINT { val } => self.konst(val as i64),
+            NDSTR { .. } | NDPROTOTY { .. } => self.addr(node),
+            NDLVAR { .. } | NDGVAR { .. } | NDMEMBER { .. } | NDDEREF { .. } => {
+                let ty = node.ty.as_ref().unwrap();
+                let addr = self.addr(node);
+                // Arrays and functions decay into their address, and
+                // aggregates that fit no register are copied from it
+                if ty.is_array() || ty.is_function() || ty.in_memory() {
+                    addr
+                } else {
+                    self.load_lvalue(node, ty, addr)
+                }
+            }
+            NDADDR { node: ref operand } => self.addr(operand),
+            NDASSIGN {
+                ref lhs,
+                ref rhs,
+                scale_lhs,
+                eval_pre,
+                assign_mode,
+                ..
+            } => {
+                let ty = node.ty.as_ref().unwrap();
+                let addr = self.addr(lhs);
+                if assign_mode == AssignMode::DEFAULT && ty.in_memory() {
+                    let src = self.expr(rhs);
+                    self.copy(addr, src, ty.size());
+                    return addr;
+                }
+                if assign_mode == AssignMode::DEFAULT {
+                    let val = self.expr(rhs);
+                    self.store_lvalue(lhs, ty, addr, val);
+                    return val;
+                }
+
+                let old = self.load_lvalue(lhs, ty, addr);
+                let mut val = self.expr(rhs);
+                if scale_lhs == Some(true) {
+                    if assign_mode == AssignMode::MUL || assign_mode == AssignMode::DIV {
+                        panic!("Scaling should not be allowed for this node.");
+                    }
+                    val = self.scale(val, ty.base_size());
+                }
+                let new = self.bin(compound_op(assign_mode), old, val);
+                self.store_lvalue(lhs, ty, addr, new);
+                if eval_pre {
+                    new
+                } else {
+                    old
+                }
+            }
+            NDADD {
+                ref lhs,
+                ref rhs,
+                scale_lhs,
+            } => {
+                let mut l = self.expr(lhs);
+                let mut r = self.expr(rhs);
+                let ty = node.ty.as_ref().unwrap();
+                if ty.is_ptr_like() {
+                    if scale_lhs.unwrap() {
+                        r = self.scale(r, ty.base_size());
+                    } else {
+                        l = self.scale(l, ty.base_size());
+                    }
+                }
+                self.bin(BinOp::Add, l, r)
+            }
+            NDSUB {
+                ref lhs, ref rhs, ..
+            } => {
+                let l = self.expr(lhs);
+                let mut r = self.expr(rhs);
+                let ty = node.ty.as_ref().unwrap();
+                if ty.is_ptr_like() {
+                    r = self.scale(r, ty.base_size());
+                }
+                let diff = self.bin(BinOp::Sub, l, r);
+                // The distance between two pointers counts elements
+                let lty = lhs.ty.as_ref().unwrap();
+                if lty.is_ptr_like() && rhs.ty.as_ref().unwrap().is_ptr_like() {
+                    let size = self.konst(lty.base_size() as i64);
+                    self.bin(BinOp::Div, diff, size)
+                } else {
+                    diff
+                }
+            }
+            NDMUL { ref lhs, ref rhs } => self.binary(BinOp::Mul, lhs, rhs),
+            NDDIV { ref lhs, ref rhs } => self.binary(BinOp::Div, lhs, rhs),
+            NDMOD { ref lhs, ref rhs } => self.binary(BinOp::Mod, lhs, rhs),
+            NDBITAND { ref lhs, ref rhs } => self.binary(BinOp::And, lhs, rhs),
+            NDBITXOR { ref lhs, ref rhs } => self.binary(BinOp::Xor, lhs, rhs),
+            NDBITOR { ref lhs, ref rhs } => self.binary(BinOp::Or, lhs, rhs),
+            NDSHL { ref lhs, ref rhs } => self.binary(BinOp::Shl, lhs, rhs),
+            NDSHR { ref lhs, ref rhs } => self.binary(BinOp::Shr, lhs, rhs),
+            NDEQ { ref lhs, ref rhs } => self.compare(Cond::Eq, lhs, rhs),
+            NDNEQ { ref lhs, ref rhs } => self.compare(Cond::Ne, lhs, rhs),
+            NDLT { ref lhs, ref rhs } => self.compare(Cond::Lt, lhs, rhs),
+            NDLEQ { ref lhs, ref rhs } => self.compare(Cond::Le, lhs, rhs),
+            NDBITNOT { node: ref operand } => {
+                let src = self.expr(operand);
+                self.un(UnOp::Not, src)
+            }
+            NDCAST { node: ref operand } => {
+                let val = self.expr(operand);
+                let ty = node.ty.as_ref().unwrap();
+                let from = operand.ty.as_ref().unwrap();
+                // Registers hold 64 bits, so narrowing truncates to the new
+                // type and widening to the old one, whose arithmetic may
+                // have carried past its width
+                let size = if from.is_integral() && from.size() < ty.size() {
+                    from.size()
+                } else {
+                    ty.size()
+                };
+                if ty.is_integral() && size < 8 {
+                    self.un(UnOp::Sext(Width::from_size(size)), val)
+                } else {
+                    val
+                }
+            }
+            NDCOND {
+                ref cond,
+                ref then,
+                ref els,
+            } => {
+                let result = self.func.new_vreg();
+                let then_block = self.new_block();
+                let els_block = self.new_block();
+                let end = self.new_block();
+                let c = self.expr(cond);
+                self.branch(c, then_block, els_block);
+
+                self.cur = then_block;
+                let src = self.expr(then);
+                self.emit(Inst::Copy { dst: result, src });
+                self.terminate(Terminator::Jump(end));
+                self.cur = els_block;
+                let src = self.expr(els);
+                self.emit(Inst::Copy { dst: result, src });
+                self.enter(end);
+                result
+            }
+            NDLOGAND { ref lhs, ref rhs } => self.logical(lhs, rhs, true),
+            NDLOGOR { ref lhs, ref rhs } => self.logical(lhs, rhs, false),
+            NDCALL {
+                ref prototy,
+                ref args,
+            } => {
+                let callee = match prototy.kind {
+                    NDPROTOTY { ref name } => Callee::Direct(name.clone()),
+                    _ => Callee::Indirect(self.expr(prototy)),
+                };
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                let dst = self.func.new_vreg();
+                self.emit(Inst::Call { dst, callee, args });
+                dst
+            }
+            _ => panic!("lower: {} is not an expression", node.kind.name()),
+        }
+    }
+
+    fn binary(&mut self, op: BinOp, lhs: &Node, rhs: &Node) -> VReg {
+        let l = self.expr(lhs);
+        let r = self.expr(rhs);
+        self.bin(op, l, r)
+    }
+
+    // Addresses order as unsigned values
+    fn compare(&mut self, cond: Cond, lhs: &Node, rhs: &Node) -> VReg {
+        let is_ptr = |node: &Node| node.ty.as_ref().unwrap().is_ptr_like();
+        let cond = match cond {
+            Cond::Lt if is_ptr(lhs) || is_ptr(rhs) => Cond::Ult,
+            Cond::Le if is_ptr(lhs) || is_ptr(rhs) => Cond::Ule,
+            _ => cond,
+        };
+        let l = self.expr(lhs);
+        let r = self.expr(rhs);
+        self.cmp(cond, l, r)
+    }
+
+    // Only evaluate the rhs if the lhs does not decide the result
+    // as per C89 6.3.13 and 6.3.14
+    fn logical(&mut self, lhs: &Node, rhs: &Node, is_and: bool) -> VReg {
+        let result = self.func.new_vreg();
+        let zero = self.konst(0);
+        let l = self.expr(lhs);
+        self.emit(Inst::Cmp {
+            dst: result,
+            cond: Cond::Ne,
+            lhs: l,
+            rhs: zero,
+        });
+        let eval_rhs = self.new_block();
+        let end = self.new_block();
+        if is_and {
+            self.branch(result, eval_rhs, end);
+        } else {
+            self.branch(result, end, eval_rhs);
+        }
+
+        self.cur = eval_rhs;
+        let r = self.expr(rhs);
+        self.emit(Inst::Cmp {
+            dst: result,
+            cond: Cond::Ne,
+            lhs: r,
+            rhs: zero,
+        });
+        self.enter(end);
+        result
+    }
+}
+
+// Bit offset and width of a bit-field member
+pub fn bit_field(node: &Node) -> Option<(usize, usize)> {
+    match node.kind {
+        NodeKind::NDMEMBER { bits, .. } => bits,
+        _ => None,
+    }
+}
+
+pub fn compound_op(mode: AssignMode) -> BinOp {
+    use AssignMode::*;
+    match mode {
+        ADD => BinOp::Add,
+        SUB => BinOp::Sub,
+        MUL => BinOp::Mul,
+        DIV => BinOp::Div,
+        MOD => BinOp::Mod,
+        SHL => BinOp::Shl,
+        SHR => BinOp::Shr,
+        AND => BinOp::And,
+        OR => BinOp::Or,
+        XOR => BinOp::Xor,
+        DEFAULT => panic!("Default assignment shouldn't reach here."),
+    }
+}
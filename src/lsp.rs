@@ -0,0 +1,561 @@
+// Language server behind rcc-lsp, speaking LSP over stdin and stdout.
+// Every change to a document parses and checks it again, and whatever the
+// parser or sema reject or warn about is published as diagnostics. What
+// the parser recorded along the way, i.e. the identifiers it resolved
+// through the scopes and the member accesses it read, is kept with the
+// document to answer hover, definition, document symbol and completion
+// requests, also for the part of a text that parsed before an error.
+use crate::cenv::{Ref, RefKind};
+use crate::ctype::{self, Type};
+use crate::json::Json;
+use crate::node::{Node, NodeKind};
+use crate::parser::{self, Parser, Program};
+use crate::sema;
+use crate::tokenizer::{Pos, Span, Tokenizer};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+
+// Error codes of JSON-RPC
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+// Values the protocol gives its enums
+const SYNC_FULL: usize = 1;
+const SEVERITY_ERROR: usize = 1;
+const SEVERITY_WARNING: usize = 2;
+const COMPLETION_FIELD: usize = 5;
+const SYMBOL_ENUM: usize = 10;
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_VARIABLE: usize = 13;
+const SYMBOL_ENUM_MEMBER: usize = 22;
+const SYMBOL_STRUCT: usize = 23;
+
+type Reply = Result<Json, (i32, String)>;
+
+struct Server {
+    docs: HashMap<String, Document>,
+    shut_down: bool,
+}
+
+struct Document {
+    text: String,
+    prog: Option<Program>, // None if the text does not parse
+    refs: Vec<Ref>,
+    accesses: Vec<(Span, Type)>,
+    diagnostics: Vec<Json>,
+}
+
+/// Serves requests from stdin until the client says to exit, giving the
+/// exit status the protocol asks for
+pub fn run() -> i32 {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = Server {
+        docs: HashMap::new(),
+        shut_down: false,
+    };
+    loop {
+        let msg = match read_message(&mut input) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => return if server.shut_down { 0 } else { 1 },
+            Err(msg) => {
+                send(&reply(&Json::Null, Err((PARSE_ERROR, msg))));
+                continue;
+            }
+        };
+        if let Some(code) = server.handle(&msg) {
+            return code;
+        }
+    }
+}
+
+impl Server {
+    // Handles a request or notification, giving the exit status if it is
+    // time to exit
+    fn handle(&mut self, msg: &Json) -> Option<i32> {
+        let method = msg.get("method").as_str().unwrap_or("");
+        let params = msg.get("params");
+        let id = msg.get("id");
+        if id.is_null() {
+            self.notify(method, params);
+            return if method == "exit" {
+                Some(if self.shut_down { 0 } else { 1 })
+            } else {
+                None
+            };
+        }
+        // Responses to requests of ours; there are none
+        if method.is_empty() {
+            return None;
+        }
+
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/hover" => self.locate(params).map(|(doc, pos)| doc.hover(pos)),
+            "textDocument/definition" => self
+                .locate(params)
+                .map(|(doc, pos)| doc.definition(uri_of(params), pos)),
+            "textDocument/documentSymbol" => self.document(params).map(Document::symbols),
+            "textDocument/completion" => self.locate(params).map(|(doc, pos)| doc.completion(pos)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        };
+        send(&reply(id, result));
+        None
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) {
+        let uri = uri_of(params);
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str();
+                self.update(uri, text.unwrap_or(""));
+            }
+            // Only whole texts are asked for, see capabilities()
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").elems();
+                if let Some(text) = changes.last().and_then(|c| c.get("text").as_str()) {
+                    self.update(uri, text);
+                }
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(uri);
+                publish(uri, Vec::new());
+            }
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, uri: &str, text: &str) {
+        let doc = Document::analyze(text.to_string());
+        publish(uri, doc.diagnostics.clone());
+        self.docs.insert(uri.to_string(), doc);
+    }
+
+    fn document(&self, params: &Json) -> Result<&Document, (i32, String)> {
+        let uri = uri_of(params);
+        self.docs
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown document {}", uri)))
+    }
+
+    // The document and the position in it that a request is about
+    fn locate(&self, params: &Json) -> Result<(&Document, Pos), (i32, String)> {
+        let doc = self.document(params)?;
+        let pos = params.get("position");
+        match (pos.get("line").as_u64(), pos.get("character").as_u64()) {
+            (Some(line), Some(character)) => Ok((doc, doc.pos(line as usize, character as usize))),
+            _ => Err((INVALID_PARAMS, "Missing position".to_string())),
+        }
+    }
+}
+
+impl Document {
+    fn analyze(text: String) -> Self {
+        let mut doc = Document {
+            text,
+            prog: None,
+            refs: Vec::new(),
+            accesses: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        let text = doc.text.clone();
+        let tokens = match Tokenizer::new().tokenize(text) {
+            Ok(tokens) => tokens,
+            Err((span, msg)) => {
+                doc.diagnose(span, &msg);
+                return doc;
+            }
+        };
+
+        let mut parser = Parser::new(tokens);
+        let parsed = catch_error(|| parser.parse());
+        doc.refs = parser.refs().to_vec();
+        doc.accesses = parser.accesses().to_vec();
+        let mut prog = match parsed {
+            Ok(prog) => prog,
+            Err(msg) => {
+                for (span, msg) in parser.take_warnings().iter() {
+                    doc.warn(*span, msg);
+                }
+                // Anything else is a bug of the compiler, not of the text
+                match msg.strip_prefix("error: ") {
+                    Some(msg) => doc.diagnose(parser.error_span(), msg),
+                    None => eprintln!("rcc-lsp: internal error: {}", msg),
+                }
+                return doc;
+            }
+        };
+        let checked = sema::analyze(&mut prog);
+        for (span, msg) in prog.warnings.iter() {
+            doc.warn(*span, msg);
+        }
+        if let Err(errors) = checked {
+            for (span, msg) in errors.iter() {
+                doc.diagnose(*span, msg);
+            }
+        }
+        doc.prog = Some(prog);
+        doc
+    }
+
+    fn diagnose(&mut self, span: Span, msg: &str) {
+        self.publish(span, SEVERITY_ERROR, msg);
+    }
+
+    fn warn(&mut self, span: Span, msg: &str) {
+        self.publish(span, SEVERITY_WARNING, msg);
+    }
+
+    fn publish(&mut self, span: Span, severity: usize, msg: &str) {
+        let diagnostic = Json::object([
+            ("range", self.range(span)),
+            ("severity", severity.into()),
+            ("source", "rcc".into()),
+            ("message", msg.into()),
+        ]);
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn hover(&self, pos: Pos) -> Json {
+        let (text, span) = match self.ref_at(pos) {
+            Some(r) => (describe(r), r.span),
+            None => match self.expr_at(pos) {
+                Some(node) => (node.ty.as_ref().unwrap().to_string(), node.span.unwrap()),
+                None => return Json::Null,
+            },
+        };
+        let contents = Json::object([
+            ("kind", "markdown".into()),
+            ("value", format!("```c\n{}\n```", text).into()),
+        ]);
+        Json::object([("contents", contents), ("range", self.range(span))])
+    }
+
+    fn definition(&self, uri: &str, pos: Pos) -> Json {
+        match self.ref_at(pos) {
+            Some(r) => Json::object([
+                ("uri", uri.into()),
+                ("range", self.range(self.defining(r).decl)),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    // Declarations at file scope, the last of each name but the definition
+    // of a function
+    fn symbols(&self) -> Json {
+        let mut decls: Vec<&Ref> = Vec::new();
+        for r in self.refs.iter() {
+            if r.span != r.decl || r.scope != 0 {
+                continue;
+            }
+            let same = |d: &&Ref| {
+                d.name == r.name && mem::discriminant(&d.kind) == mem::discriminant(&r.kind)
+            };
+            match decls.iter().position(same) {
+                Some(i) => decls[i] = r,
+                None => decls.push(r),
+            }
+        }
+
+        decls.sort_by(|a, b| a.span.lo.partial_cmp(&b.span.lo).unwrap());
+        let symbols = decls
+            .into_iter()
+            .map(|r| {
+                let r = self.defining(r);
+                let (kind, detail) = match r.kind {
+                    RefKind::Var => (SYMBOL_VARIABLE, r.ty.to_string()),
+                    RefKind::Func => (SYMBOL_FUNCTION, r.ty.to_string()),
+                    RefKind::Tag if r.ty.is_struct() => (SYMBOL_STRUCT, r.ty.to_string()),
+                    RefKind::Tag => (SYMBOL_ENUM, "enum".to_string()),
+                    RefKind::Const(val) => (SYMBOL_ENUM_MEMBER, val.to_string()),
+                };
+                // A function definition spans its body
+                let span = match r.kind {
+                    RefKind::Func => self.funcdef(&r.name).and_then(|node| node.span),
+                    _ => None,
+                };
+                Json::object([
+                    ("name", r.name.as_str().into()),
+                    ("detail", detail.into()),
+                    ("kind", kind.into()),
+                    ("range", self.range(span.unwrap_or(r.span))),
+                    ("selectionRange", self.range(r.span)),
+                ])
+            })
+            .collect();
+        Json::Array(symbols)
+    }
+
+    // Members of the struct or union before the "." or "->" that pos
+    // follows, possibly with part of a member name in between
+    fn completion(&self, pos: Pos) -> Json {
+        let line: Vec<char> = self.line(pos.line).chars().take(pos.col - 1).collect();
+        let mut start = line.len();
+        while start > 0 && is_ident_char(line[start - 1]) {
+            start -= 1;
+        }
+        let op = if line[..start].ends_with(&['-', '>']) {
+            start - 2
+        } else if line[..start].ends_with(&['.']) {
+            start - 1
+        } else {
+            return Json::Array(Vec::new());
+        };
+        let at = Pos {
+            line: pos.line,
+            col: op + 1,
+        };
+        let ty = match self.accesses.iter().rev().find(|(span, _)| span.lo == at) {
+            Some((_, ty)) => ty,
+            None => return Json::Array(Vec::new()),
+        };
+        let def = match ty.struct_def() {
+            Some(def) => def.borrow(),
+            None => return Json::Array(Vec::new()),
+        };
+        let items = def
+            .members()
+            .iter()
+            .filter(|m| !m.name.is_empty())
+            .map(|m| {
+                Json::object([
+                    ("label", m.name.as_str().into()),
+                    ("kind", COMPLETION_FIELD.into()),
+                    ("detail", m.ty.to_string().into()),
+                ])
+            })
+            .collect();
+        Json::Array(items)
+    }
+
+    // The identifier at pos, or one that ends there
+    fn ref_at(&self, pos: Pos) -> Option<&Ref> {
+        let refs = &self.refs;
+        refs.iter()
+            .find(|r| r.span.lo <= pos && pos < r.span.hi)
+            .or_else(|| refs.iter().find(|r| r.span.hi == pos))
+    }
+
+    // A function is found at its definition rather than at a prototype
+    fn defining<'a>(&'a self, r: &'a Ref) -> &'a Ref {
+        if r.kind != RefKind::Func {
+            return r;
+        }
+        let body = match self.funcdef(&r.name).and_then(|node| node.span) {
+            Some(span) => span,
+            None => return r,
+        };
+        self.refs
+            .iter()
+            .find(|d| {
+                d.kind == RefKind::Func
+                    && d.name == r.name
+                    && d.span == d.decl
+                    && body.lo <= d.span.lo
+                    && d.span.hi <= body.hi
+            })
+            .unwrap_or(r)
+    }
+
+    fn funcdef(&self, name: &str) -> Option<&Node> {
+        self.prog
+            .as_ref()?
+            .nodes
+            .iter()
+            .find(|node| matches!(node.kind, NodeKind::NDFUNCDEF { name: ref n, .. } if n == name))
+    }
+
+    // The innermost expression at pos
+    fn expr_at(&self, pos: Pos) -> Option<&Node> {
+        let prog = self.prog.as_ref()?;
+        let mut found = None;
+        let inits = prog.global_inits.iter().flat_map(|init| init.vals.iter());
+        for node in prog.nodes.iter().chain(inits.map(|val| &val.val)) {
+            expr_within(node, pos, &mut found);
+        }
+        found
+    }
+
+    // Line n of the text, counting from 1 as Pos does
+    fn line(&self, n: usize) -> &str {
+        self.text.split('\n').nth(n.saturating_sub(1)).unwrap_or("")
+    }
+
+    // The protocol counts from 0, and counts characters in UTF-16
+    fn pos(&self, line: usize, character: usize) -> Pos {
+        let mut units = 0;
+        let col = self
+            .line(line + 1)
+            .chars()
+            .take_while(|c| {
+                units += c.len_utf16();
+                units <= character
+            })
+            .count();
+        Pos {
+            line: line + 1,
+            col: col + 1,
+        }
+    }
+
+    fn position(&self, pos: Pos) -> Json {
+        let character: usize = self
+            .line(pos.line)
+            .chars()
+            .take(pos.col.saturating_sub(1))
+            .map(char::len_utf16)
+            .sum();
+        Json::object([
+            ("line", pos.line.saturating_sub(1).into()),
+            ("character", character.into()),
+        ])
+    }
+
+    fn range(&self, span: Span) -> Json {
+        Json::object([
+            ("start", self.position(span.lo)),
+            ("end", self.position(span.hi)),
+        ])
+    }
+}
+
+// Where nodes share a span, as those the parser makes up for a[i] do, the
+// outermost of them is what was written
+fn expr_within<'a>(node: &'a Node, pos: Pos, found: &mut Option<&'a Node>) {
+    if let Some(span) = node.span {
+        if pos < span.lo || span.hi <= pos {
+            return;
+        }
+        if node.ty.is_some() && found.and_then(|f| f.span) != Some(span) {
+            *found = Some(node);
+        }
+    }
+    for child in node.children() {
+        expr_within(child, pos, found);
+    }
+}
+
+// The text of a hover over an identifier
+fn describe(r: &Ref) -> String {
+    match r.kind {
+        RefKind::Var | RefKind::Func => r.ty.declare(&r.name),
+        RefKind::Const(val) => format!("{} = {}", r.name, val),
+        RefKind::Tag if r.ty.is_struct() => {
+            let def = r.ty.struct_def().unwrap().borrow();
+            if def.is_complete() {
+                ctype::define_struct(&def)
+            } else {
+                def.spelling()
+            }
+        }
+        RefKind::Tag => {
+            let members: Vec<String> =
+                r.ty.enum_members()
+                    .iter()
+                    .map(|m| format!("{} = {}", m.name, m.val))
+                    .collect();
+            if members.is_empty() {
+                format!("enum {}", r.name)
+            } else {
+                format!("enum {} {{ {} }}", r.name, members.join(", "))
+            }
+        }
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// The parser reports errors by panicking, which become
+// diagnostics here. A panic anywhere else is a bug, and the default hook
+// still prints it to stderr.
+fn catch_error<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+    result.map_err(parser::error_message)
+}
+
+fn uri_of(params: &Json) -> &str {
+    params.get("textDocument").get("uri").as_str().unwrap_or("")
+}
+
+fn capabilities() -> Json {
+    let completion = Json::object([("triggerCharacters", vec![".".into(), ">".into()].into())]);
+    let capabilities = Json::object([
+        ("textDocumentSync", SYNC_FULL.into()),
+        ("hoverProvider", true.into()),
+        ("definitionProvider", true.into()),
+        ("documentSymbolProvider", true.into()),
+        ("completionProvider", completion),
+    ]);
+    Json::object([
+        ("capabilities", capabilities),
+        ("serverInfo", Json::object([("name", "rcc-lsp".into())])),
+    ])
+}
+
+fn reply(id: &Json, result: Reply) -> Json {
+    let (key, val) = match result {
+        Ok(result) => ("result", result),
+        Err((code, msg)) => (
+            "error",
+            Json::object([("code", Json::Number(code as f64)), ("message", msg.into())]),
+        ),
+    };
+    Json::object([("jsonrpc", "2.0".into()), ("id", id.clone()), (key, val)])
+}
+
+fn publish(uri: &str, diagnostics: Vec<Json>) {
+    let params = Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]);
+    send(&Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        ("params", params),
+    ]));
+}
+
+// Reads the next message, or None at the end of the input
+fn read_message(input: &mut impl BufRead) -> Result<Option<Json>, String> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        match input.read_line(&mut header) {
+            Ok(0) | Err(_) => return Ok(None),
+            Ok(_) => (),
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, val)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                len = val.trim().parse().ok();
+            }
+        }
+    }
+    let len = len.ok_or_else(|| "Missing Content-Length".to_string())?;
+    let mut body = vec![0; len];
+    if input.read_exact(&mut body).is_err() {
+        return Ok(None);
+    }
+    let body = String::from_utf8(body).map_err(|_| "Message is not UTF-8".to_string())?;
+    Json::parse(&body).map(Some)
+}
+
+fn send(msg: &Json) {
+    let body = msg.to_string();
+    let mut out = io::stdout().lock();
+    let _ = write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = out.flush();
+}
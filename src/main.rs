@@ -1,54 +1,232 @@
 use std::env;
-use std::fs::{self, File};
-use std::path::Path;
+use std::fs::File;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::thread;
 
-#[macro_use]
-macro_rules! gen_line {
-    ($dst:expr, $($arg: tt)*) => {
-        write!($dst, $($arg)*).unwrap()
+use rcc::codegen::CodeGen;
+use rcc::elf::Object;
+use rcc::options::{self, Options};
+use rcc::parser::{self, Parser};
+use rcc::tokenizer::{Span, Tokenizer};
+use rcc::{assembler, constexpr, dump, dwarf, interp, ir, lower, opt, printer, repl, sema};
+
+// Interpreted programs recurse on the stack of the compiler, which needs
+// more of it than the main thread gets
+const STACK_SIZE: usize = 512 << 20;
+
+fn main() {
+    let compiler = thread::Builder::new().stack_size(STACK_SIZE).spawn(compile);
+    if compiler.unwrap().join().is_err() {
+        process::exit(101);
     }
 }
 
-mod cenv;
-mod codegen;
-mod ctype;
-mod node;
-mod parser;
-mod tokenizer;
+fn compile() {
+    let args: Vec<String> = env::args().collect();
 
-use codegen::CodeGen;
-use parser::Parser;
-use tokenizer::Tokenizer;
+    let opts = match Options::parse(&args[1..]) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("yarcc: {}", msg);
+            process::exit(1);
+        }
+    };
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+    if opts.repl {
+        process::exit(repl::run());
+    }
+
+    let in_str = opts.read_source();
+    let tokens = match Tokenizer::new().tokenize(in_str.clone()) {
+        Ok(tokens) => tokens,
+        Err((span, msg)) => {
+            eprintln!("yarcc: {}: {}", span.lo, msg);
+            process::exit(1);
+        }
+    };
+    if opts.dump_tokens {
+        print!("{}", dump::dump_tokens(&tokens));
+        return;
+    }
 
-    match args.len() {
-        2 => {
-            let mut f = match File::create("tmp.s") {
-                Err(why) => panic!("yarcc: Couldn't create tmp.s because {}", why.to_string()),
-                Ok(f) => f,
-            };
-
-            let in_str = if Path::new(&args[1]).exists() {
-                if let Ok(text) = fs::read_to_string(&args[1]) {
-                    text
-                } else {
-                    panic!("yarcc: Cannot read the provided file.");
+    // Warnings found before an error still get printed
+    let mut parser = Parser::new(tokens);
+    let mut parsed_program = match panic::catch_unwind(AssertUnwindSafe(|| parser.parse())) {
+        Ok(prog) => prog,
+        Err(payload) => {
+            warn(&parser.take_warnings());
+            panic::resume_unwind(payload);
+        }
+    };
+    warn(&parsed_program.warnings);
+    let parsed = parsed_program.warnings.len();
+    if opts.dump_c {
+        print!("{}", printer::print_program(&parsed_program));
+        return;
+    }
+    if opts.round_trip {
+        round_trip(&parsed_program);
+        return;
+    }
+
+    let checked = sema::analyze(&mut parsed_program);
+    warn(&parsed_program.warnings[parsed..]);
+    if let Err(errors) = checked {
+        for (span, msg) in errors.iter() {
+            eprintln!("yarcc: {}: {}", span.lo, msg);
+        }
+        process::exit(1);
+    }
+    // Types are final once sema is done, and folding has not yet replaced
+    // any expression with its value
+    if opts.dump_ast {
+        print!("{}", dump::dump_program(&parsed_program, true));
+        return;
+    }
+    constexpr::fold_program(&mut parsed_program);
+    if opts.run {
+        match interp::run(&parsed_program, opts.source_name(), &in_str, opts.check) {
+            Ok(code) => process::exit(code),
+            Err(msg) => {
+                eprintln!("yarcc: run: {}", msg);
+                process::exit(1);
+            }
+        }
+    }
+    let mut module = lower::lower(&parsed_program, opts.debug);
+    check_ir(&module);
+    match opts.passes {
+        Some(ref names) => {
+            for name in names.iter() {
+                if opt::find_pass(name).is_none() {
+                    eprintln!("yarcc: Unknown pass {}", name);
+                    process::exit(1);
                 }
-            } else {
-                args[1].to_string()
-            };
+            }
+            let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+            opt::run(&mut module, &names, false);
+        }
+        None => opt::run(&mut module, &opt::pipeline(opts.opt_level), true),
+    }
+    // Passes must leave well-formed IR behind
+    check_ir(&module);
+    if opts.dump_ir {
+        print!("{}", module);
+        return;
+    }
 
-            let tokens = Tokenizer::new().tokenize(in_str);
-            let parser = Parser::new(tokens);
-            let parsed_program = parser.parse();
-            let mut codegen = CodeGen::new(&mut f, parsed_program);
+    let peephole = !opts.no_peephole;
+    let debug = if opts.debug {
+        Some(dwarf::DebugInfo::new(&parsed_program, opts.source_name()))
+    } else {
+        None
+    };
+    if opts.dump_asm {
+        CodeGen::new(
+            &mut io::stdout(),
+            module,
+            peephole,
+            opts.code_model,
+            opts.syntax,
+            debug,
+        )
+        .gen_all();
+        return;
+    }
 
-            codegen.gen_all();
+    if opts.object || opts.jit {
+        let mut text = Vec::new();
+        // The built-in assembler reads Intel syntax
+        let syntax = options::AsmSyntax::Intel;
+        // Memory for --jit could be anywhere, so addresses can't be fixed
+        let code_model = match opts.code_model {
+            options::CodeModel::Absolute if opts.jit => options::CodeModel::Pie,
+            model => model,
+        };
+        CodeGen::new(&mut text, module, peephole, code_model, syntax, debug).gen_all();
+        let obj = match assembler::assemble(&String::from_utf8(text).unwrap()) {
+            Ok(obj) => obj,
+            Err(msg) => panic!("yarcc: Assembler: {}", msg),
+        };
+        if opts.jit {
+            let mut args = vec![opts.input.clone()];
+            args.extend(opts.args.iter().cloned());
+            match run_jit(&obj, &args) {
+                Ok(code) => process::exit(code),
+                Err(msg) => {
+                    eprintln!("yarcc: jit: {}", msg);
+                    process::exit(1);
+                }
+            }
         }
-        _ => {
-            eprintln!("yarcc: Wrong number of arguments!");
+        let name = opts.object_name();
+        let mut f = match File::create(&name) {
+            Err(why) => panic!("yarcc: Couldn't create {} because {}", name, why),
+            Ok(f) => f,
+        };
+        if let Err(why) = obj.write(&mut f) {
+            panic!("yarcc: Couldn't write {} because {}", name, why);
         }
+        return;
+    }
+
+    let mut f = match File::create("tmp.s") {
+        Err(why) => panic!("yarcc: Couldn't create tmp.s because {}", why),
+        Ok(f) => f,
+    };
+    let mut codegen = CodeGen::new(
+        &mut f,
+        module,
+        peephole,
+        opts.code_model,
+        opts.syntax,
+        debug,
+    );
+
+    codegen.gen_all();
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn run_jit(obj: &Object, args: &[String]) -> Result<i32, String> {
+    rcc::jit::run(obj, args)
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn run_jit(_obj: &Object, _args: &[String]) -> Result<i32, String> {
+    Err("unsupported on this platform".to_string())
+}
+
+fn check_ir(module: &ir::Module) {
+    if let Err(errors) = ir::verify(module) {
+        for e in errors.iter() {
+            eprintln!("yarcc: ir: {}", e);
+        }
+        process::exit(1);
+    }
+}
+
+fn warn(warnings: &[(Span, String)]) {
+    for (span, msg) in warnings.iter() {
+        eprintln!("warning: {}: {}", span.lo, msg);
+    }
+}
+
+// Reparses the pretty-printed program and checks that the ASTs agree
+fn round_trip(prog: &parser::Program) {
+    let printed = printer::print_program(prog);
+    let tokens = Tokenizer::new().tokenize(printed.clone());
+    let reparsed = Parser::new(tokens.expect("The printed source does not tokenize")).parse();
+
+    let before = dump::dump_program(prog, false);
+    let after = dump::dump_program(&reparsed, false);
+    if before != after {
+        eprintln!(
+            "yarcc: round-trip mismatch\n--- printed source\n{}",
+            printed
+        );
+        eprintln!("--- original AST\n{}--- reparsed AST\n{}", before, after);
+        process::exit(1);
     }
 }
@@ -1,11 +1,13 @@
 // AST node
 use crate::cenv::Var;
 use crate::ctype::Type;
+use crate::tokenizer::Span;
 use std::collections::LinkedList;
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub ty: Option<Type>,
+    pub span: Option<Span>, // Filled in by the parser
     pub kind: NodeKind,
 }
 
@@ -110,6 +112,17 @@ pub enum NodeKind {
         node: Box<Node>,
         name: String,
         offset: Option<usize>,
+        bits: Option<(usize, usize)>, // Bit offset and width of a bit-field
+    },
+    // Conversion to the type of this node
+    NDCAST {
+        node: Box<Node>,
+    },
+    // cond ? then : els
+    NDCOND {
+        cond: Box<Node>,
+        then: Box<Node>,
+        els: Box<Node>,
     },
     // No operand
     NDBREAK,
@@ -164,10 +177,12 @@ pub enum NodeKind {
     }, // function definition
     // decl
     NDDECL {
+        vars: LinkedList<Var>,
         inits: LinkedList<Node>,
     }, // declaration
     // variables
     NDLVAR {
+        name: String,
         offset: usize,
     }, // local var
     NDGVAR {
@@ -178,18 +193,76 @@ pub enum NodeKind {
     }, // function prototype
 }
 
+impl NodeKind {
+    /// Name of the variant, used by the AST dump
+    pub fn name(&self) -> &'static str {
+        use NodeKind::*;
+        match self {
+            NDINT { .. } => "NDINT",
+            NDSTR { .. } => "NDSTR",
+            NDADD { .. } => "NDADD",
+            NDSUB { .. } => "NDSUB",
+            NDMUL { .. } => "NDMUL",
+            NDDIV { .. } => "NDDIV",
+            NDMOD { .. } => "NDMOD",
+            NDEQ { .. } => "NDEQ",
+            NDNEQ { .. } => "NDNEQ",
+            NDLEQ { .. } => "NDLEQ",
+            NDLT { .. } => "NDLT",
+            NDASSIGN { .. } => "NDASSIGN",
+            NDBITAND { .. } => "NDBITAND",
+            NDBITXOR { .. } => "NDBITXOR",
+            NDBITOR { .. } => "NDBITOR",
+            NDLOGAND { .. } => "NDLOGAND",
+            NDLOGOR { .. } => "NDLOGOR",
+            NDSHL { .. } => "NDSHL",
+            NDSHR { .. } => "NDSHR",
+            NDBITNOT { .. } => "NDBITNOT",
+            NDADDR { .. } => "NDADDR",
+            NDDEREF { .. } => "NDDEREF",
+            NDRETURN { .. } => "NDRETURN",
+            NDMEMBER { .. } => "NDMEMBER",
+            NDCAST { .. } => "NDCAST",
+            NDCOND { .. } => "NDCOND",
+            NDBREAK => "NDBREAK",
+            NDCONTINUE => "NDCONTINUE",
+            NDIF { .. } => "NDIF",
+            NDSWITCH { .. } => "NDSWITCH",
+            NDWHILE { .. } => "NDWHILE",
+            NDDOWHILE { .. } => "NDDOWHILE",
+            NDFOR { .. } => "NDFOR",
+            NDBLOCK { .. } => "NDBLOCK",
+            NDCASE { .. } => "NDCASE",
+            NDDEFAULT { .. } => "NDDEFAULT",
+            NDCALL { .. } => "NDCALL",
+            NDFUNCDEF { .. } => "NDFUNCDEF",
+            NDDECL { .. } => "NDDECL",
+            NDLVAR { .. } => "NDLVAR",
+            NDGVAR { .. } => "NDGVAR",
+            NDPROTOTY { .. } => "NDPROTOTY",
+        }
+    }
+}
+
 impl Node {
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
     pub fn new_int(val: i32) -> Self {
         Node {
             ty: Some(Type::new_base("int")),
-            kind: NodeKind::NDINT { val: val },
+            span: None,
+            kind: NodeKind::NDINT { val },
         }
     }
 
     pub fn new_str(pos: usize) -> Self {
         Node {
             ty: None,
-            kind: NodeKind::NDSTR { pos: pos },
+            span: None,
+            kind: NodeKind::NDSTR { pos },
         }
     }
 
@@ -268,7 +341,8 @@ impl Node {
 
         Node {
             ty: None,
-            kind: kind,
+            span: None,
+            kind,
         }
     }
 
@@ -293,7 +367,8 @@ impl Node {
 
         Node {
             ty: None,
-            kind: kind,
+            span: None,
+            kind,
         }
     }
 
@@ -308,18 +383,20 @@ impl Node {
 
         Node {
             ty: None,
-            kind: kind,
+            span: None,
+            kind,
         }
     }
 
     pub fn new_init(mode: AssignMode, lhs: Self, rhs: Self, eval_pre: bool) -> Self {
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDASSIGN {
                 lhs: Box::new(lhs),
                 rhs: Box::new(rhs),
                 scale_lhs: None,
-                eval_pre: eval_pre,
+                eval_pre,
                 assign_mode: mode,
                 is_init: true,
             },
@@ -329,11 +406,12 @@ impl Node {
     pub fn new_assign(mode: AssignMode, lhs: Self, rhs: Self, eval_pre: bool) -> Self {
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDASSIGN {
                 lhs: Box::new(lhs),
                 rhs: Box::new(rhs),
                 scale_lhs: None,
-                eval_pre: eval_pre,
+                eval_pre,
                 assign_mode: mode,
                 is_init: false,
             },
@@ -341,18 +419,11 @@ impl Node {
     }
 
     pub fn new_if(cond: Self, ifnode: Option<Self>, elsenode: Option<Self>) -> Self {
-        let if_to_use = if let Some(n) = ifnode {
-            Some(Box::new(n))
-        } else {
-            None
-        };
-        let else_to_use = if let Some(n) = elsenode {
-            Some(Box::new(n))
-        } else {
-            None
-        };
+        let if_to_use = ifnode.map(Box::new);
+        let else_to_use = elsenode.map(Box::new);
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDIF {
                 cond: Box::new(cond),
                 ifnode: if_to_use,
@@ -362,14 +433,11 @@ impl Node {
     }
 
     pub fn new_switch(ctrl: Self, stmt: Option<Self>) -> Self {
-        let stmt_to_use = if let Some(n) = stmt {
-            Some(Box::new(n))
-        } else {
-            None
-        };
+        let stmt_to_use = stmt.map(Box::new);
 
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDSWITCH {
                 ctrl: Box::new(ctrl),
                 stmt: stmt_to_use,
@@ -380,13 +448,10 @@ impl Node {
     }
 
     pub fn new_while(cond: Self, repnode: Option<Self>) -> Self {
-        let repnode_to_use = if let Some(n) = repnode {
-            Some(Box::new(n))
-        } else {
-            None
-        };
+        let repnode_to_use = repnode.map(Box::new);
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDWHILE {
                 cond: Box::new(cond),
                 repnode: repnode_to_use,
@@ -396,13 +461,10 @@ impl Node {
 
     // TODO: COnsolidate with above
     pub fn new_dowhile(cond: Self, repnode: Option<Self>) -> Self {
-        let repnode_to_use = if let Some(n) = repnode {
-            Some(Box::new(n))
-        } else {
-            None
-        };
+        let repnode_to_use = repnode.map(Box::new);
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDDOWHILE {
                 cond: Box::new(cond),
                 repnode: repnode_to_use,
@@ -417,29 +479,14 @@ impl Node {
         repnode: Option<Self>,
     ) -> Self {
         // TODO: This doesn't look cute
-        let init_to_use = if let Some(n) = init {
-            Some(Box::new(n))
-        } else {
-            None
-        };
-        let cond_to_use = if let Some(n) = cond {
-            Some(Box::new(n))
-        } else {
-            None
-        };
-        let step_to_use = if let Some(n) = step {
-            Some(Box::new(n))
-        } else {
-            None
-        };
-        let repnode_to_use = if let Some(n) = repnode {
-            Some(Box::new(n))
-        } else {
-            None
-        };
+        let init_to_use = init.map(Box::new);
+        let cond_to_use = cond.map(Box::new);
+        let step_to_use = step.map(Box::new);
+        let repnode_to_use = repnode.map(Box::new);
 
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDFOR {
                 init: init_to_use,
                 cond: cond_to_use,
@@ -452,36 +499,31 @@ impl Node {
     pub fn new_block(stmts: LinkedList<Self>) -> Self {
         Node {
             ty: None,
-            kind: NodeKind::NDBLOCK { stmts: stmts },
+            span: None,
+            kind: NodeKind::NDBLOCK { stmts },
         }
     }
 
     pub fn new_case(val: i32, stmt: Option<Self>) -> Self {
-        let stmt_to_use = if let Some(n) = stmt {
-            Some(Box::new(n))
-        } else {
-            None
-        };
+        let stmt_to_use = stmt.map(Box::new);
 
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDCASE {
                 stmt: stmt_to_use,
-                val: val,
+                val,
                 pos: None, // Sema analyzer fills this field
             },
         }
     }
 
     pub fn new_default(stmt: Option<Self>) -> Self {
-        let stmt_to_use = if let Some(n) = stmt {
-            Some(Box::new(n))
-        } else {
-            None
-        };
+        let stmt_to_use = stmt.map(Box::new);
 
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDDEFAULT { stmt: stmt_to_use },
         }
     }
@@ -489,9 +531,10 @@ impl Node {
     pub fn new_call(prototy: Self, args: LinkedList<Self>) -> Self {
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDCALL {
                 prototy: Box::new(prototy),
-                args: args,
+                args,
             },
         }
     }
@@ -504,121 +547,437 @@ impl Node {
     ) -> Self {
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDFUNCDEF {
-                name: name,
-                argvars: argvars,
-                stmts: stmts,
-                lvars_offset: lvars_offset,
+                name,
+                argvars,
+                stmts,
+                lvars_offset,
             },
         }
     }
 
-    pub fn new_decl(inits: LinkedList<Self>) -> Self {
+    pub fn new_decl(vars: LinkedList<Var>, inits: LinkedList<Self>) -> Self {
         Node {
             ty: None,
-            kind: NodeKind::NDDECL { inits: inits },
+            span: None,
+            kind: NodeKind::NDDECL { vars, inits },
         }
     }
 
-    pub fn new_lvar(offset: usize, ty: Type) -> Self {
+    pub fn new_lvar(name: String, offset: usize, ty: Type) -> Self {
         Node {
             ty: Some(ty),
-            kind: NodeKind::NDLVAR { offset: offset },
+            span: None,
+            kind: NodeKind::NDLVAR { name, offset },
         }
     }
 
     pub fn new_gvar(name: String, ty: Type) -> Self {
         Node {
             ty: Some(ty),
-            kind: NodeKind::NDGVAR { name: name },
+            span: None,
+            kind: NodeKind::NDGVAR { name },
         }
     }
 
-    pub fn new_prototy(name: String) -> Self {
+    pub fn new_prototy(name: String, ty: Type) -> Self {
         Node {
-            ty: None,
-            kind: NodeKind::NDPROTOTY { name: name },
+            ty: Some(ty),
+            span: None,
+            kind: NodeKind::NDPROTOTY { name },
         }
     }
 
     pub fn new_member(node: Self, name: String) -> Self {
         Node {
             ty: None,
+            span: None,
             kind: NodeKind::NDMEMBER {
                 node: Box::new(node),
-                name: name,
+                name,
                 offset: None,
+                bits: None,
             },
         }
     }
 
-    pub fn populate_switch(&mut self) {
-        // Use offset to communicate the relative position in the
-        // order of appearance
+    pub fn new_cast(node: Self, ty: Type) -> Self {
+        Node {
+            ty: Some(ty),
+            span: None,
+            kind: NodeKind::NDCAST {
+                node: Box::new(node),
+            },
+        }
+    }
+
+    pub fn new_cond(cond: Self, then: Self, els: Self) -> Self {
+        Node {
+            ty: None,
+            span: None,
+            kind: NodeKind::NDCOND {
+                cond: Box::new(cond),
+                then: Box::new(then),
+                els: Box::new(els),
+            },
+        }
+    }
+
+    /// The nodes directly below this one, in source order
+    pub fn children(&self) -> Vec<&Node> {
         use NodeKind::*;
 
         match self.kind {
+            NDADD {
+                ref lhs, ref rhs, ..
+            }
+            | NDSUB {
+                ref lhs, ref rhs, ..
+            }
+            | NDMUL { ref lhs, ref rhs }
+            | NDDIV { ref lhs, ref rhs }
+            | NDMOD { ref lhs, ref rhs }
+            | NDEQ { ref lhs, ref rhs }
+            | NDNEQ { ref lhs, ref rhs }
+            | NDLEQ { ref lhs, ref rhs }
+            | NDLT { ref lhs, ref rhs }
+            | NDASSIGN {
+                ref lhs, ref rhs, ..
+            }
+            | NDBITAND { ref lhs, ref rhs }
+            | NDBITXOR { ref lhs, ref rhs }
+            | NDBITOR { ref lhs, ref rhs }
+            | NDLOGAND { ref lhs, ref rhs }
+            | NDLOGOR { ref lhs, ref rhs }
+            | NDSHL { ref lhs, ref rhs }
+            | NDSHR { ref lhs, ref rhs } => vec![lhs, rhs],
+            NDBITNOT { ref node }
+            | NDADDR { ref node }
+            | NDDEREF { ref node }
+            | NDRETURN { ref node }
+            | NDMEMBER { ref node, .. }
+            | NDCAST { ref node } => vec![node],
+            NDCOND {
+                ref cond,
+                ref then,
+                ref els,
+            } => vec![cond, then, els],
+            NDIF {
+                ref cond,
+                ref ifnode,
+                ref elsenode,
+            } => [Some(&**cond), ifnode.as_deref(), elsenode.as_deref()]
+                .iter()
+                .flatten()
+                .copied()
+                .collect(),
             NDSWITCH {
+                ref ctrl, ref stmt, ..
+            } => [Some(&**ctrl), stmt.as_deref()]
+                .iter()
+                .flatten()
+                .copied()
+                .collect(),
+            NDWHILE {
+                ref cond,
+                ref repnode,
+            } => [Some(&**cond), repnode.as_deref()]
+                .iter()
+                .flatten()
+                .copied()
+                .collect(),
+            NDDOWHILE {
+                ref cond,
+                ref repnode,
+            } => [repnode.as_deref(), Some(&**cond)]
+                .iter()
+                .flatten()
+                .copied()
+                .collect(),
+            NDFOR {
+                ref init,
+                ref cond,
+                ref step,
+                ref repnode,
+            } => [
+                init.as_deref(),
+                cond.as_deref(),
+                step.as_deref(),
+                repnode.as_deref(),
+            ]
+            .iter()
+            .flatten()
+            .copied()
+            .collect(),
+            NDCASE { ref stmt, .. } | NDDEFAULT { ref stmt } => {
+                stmt.as_deref().into_iter().collect()
+            }
+            NDCALL {
+                ref prototy,
+                ref args,
+            } => std::iter::once(&**prototy).chain(args.iter()).collect(),
+            NDBLOCK { ref stmts } | NDFUNCDEF { ref stmts, .. } => stmts.iter().collect(),
+            NDDECL { ref inits, .. } => inits.iter().collect(),
+            NDINT { .. }
+            | NDSTR { .. }
+            | NDBREAK
+            | NDCONTINUE
+            | NDLVAR { .. }
+            | NDGVAR { .. }
+            | NDPROTOTY { .. } => Vec::new(),
+        }
+    }
+
+    /// The nodes directly below this one, in source order, for changing them
+    pub fn children_mut(&mut self) -> Vec<&mut Node> {
+        use NodeKind::*;
+
+        match self.kind {
+            NDADD {
+                ref mut lhs,
+                ref mut rhs,
+                ..
+            }
+            | NDSUB {
+                ref mut lhs,
+                ref mut rhs,
+                ..
+            }
+            | NDMUL {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDDIV {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDMOD {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDEQ {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDNEQ {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDLEQ {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDLT {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDASSIGN {
+                ref mut lhs,
+                ref mut rhs,
+                ..
+            }
+            | NDBITAND {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDBITXOR {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDBITOR {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDLOGAND {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDLOGOR {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDSHL {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDSHR {
+                ref mut lhs,
+                ref mut rhs,
+            } => vec![lhs, rhs],
+            NDBITNOT { ref mut node }
+            | NDADDR { ref mut node }
+            | NDDEREF { ref mut node }
+            | NDRETURN { ref mut node }
+            | NDMEMBER { ref mut node, .. }
+            | NDCAST { ref mut node } => vec![node],
+            NDCOND {
+                ref mut cond,
+                ref mut then,
+                ref mut els,
+            } => vec![cond, then, els],
+            NDIF {
+                ref mut cond,
+                ref mut ifnode,
+                ref mut elsenode,
+            } => vec![
+                Some(&mut **cond),
+                ifnode.as_deref_mut(),
+                elsenode.as_deref_mut(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            NDSWITCH {
+                ref mut ctrl,
                 ref mut stmt,
-                ref mut cases,
-                ref mut has_default,
                 ..
-            } => {
-                if stmt.is_none() {
-                    return;
+            } => vec![Some(&mut **ctrl), stmt.as_deref_mut()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            NDWHILE {
+                ref mut cond,
+                ref mut repnode,
+            } => vec![Some(&mut **cond), repnode.as_deref_mut()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            NDDOWHILE {
+                ref mut cond,
+                ref mut repnode,
+            } => vec![repnode.as_deref_mut(), Some(&mut **cond)]
+                .into_iter()
+                .flatten()
+                .collect(),
+            NDFOR {
+                ref mut init,
+                ref mut cond,
+                ref mut step,
+                ref mut repnode,
+            } => vec![
+                init.as_deref_mut(),
+                cond.as_deref_mut(),
+                step.as_deref_mut(),
+                repnode.as_deref_mut(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            NDCASE { ref mut stmt, .. } | NDDEFAULT { ref mut stmt } => {
+                stmt.as_deref_mut().into_iter().collect()
+            }
+            NDCALL {
+                ref mut prototy,
+                ref mut args,
+            } => std::iter::once(&mut **prototy)
+                .chain(args.iter_mut())
+                .collect(),
+            NDBLOCK { ref mut stmts } | NDFUNCDEF { ref mut stmts, .. } => {
+                stmts.iter_mut().collect()
+            }
+            NDDECL { ref mut inits, .. } => inits.iter_mut().collect(),
+            NDINT { .. }
+            | NDSTR { .. }
+            | NDBREAK
+            | NDCONTINUE
+            | NDLVAR { .. }
+            | NDGVAR { .. }
+            | NDPROTOTY { .. } => Vec::new(),
+        }
+    }
+
+    /// Gives the uses of a global array that came before its size was known
+    /// the completed type from arrays, and retypes the expressions over
+    /// them. Returns whether the type of any node below changed.
+    pub fn complete_arrays(&mut self, arrays: &[Var]) -> bool {
+        let mut changed = false;
+        for child in self.children_mut() {
+            changed |= child.complete_arrays(arrays);
+        }
+        if let NodeKind::NDGVAR { ref name } = self.kind {
+            if self.ty.as_ref().is_some_and(|ty| ty.is_incomplete()) {
+                if let Some(var) = arrays.iter().find(|var| &var.name == name) {
+                    self.ty = Some(var.ty.clone());
+                    return true;
                 }
+            }
+        }
+        if changed {
+            // Types given by the parser rather than computed stay as they are
+            let old = self.ty.take();
+            self.populate_ty();
+            if self.ty.is_none() {
+                self.ty = old;
+            }
+        }
+        changed
+    }
 
-                let switch_stmt = stmt.as_mut().unwrap();
-
-                // TODO: CLean up this logic
-                match switch_stmt.kind {
-                    NDCASE {
-                        ref val,
-                        ref mut pos,
-                        ..
-                    } => {
-                        cases.push_back(val.clone());
-                        *pos = Some(0);
-                    }
-                    NDDEFAULT { .. } => {
-                        *has_default = true;
-                    }
-                    NDBLOCK { ref mut stmts } => {
-                        let mut iter = stmts.iter_mut();
-                        let mut counter = 0;
-                        while let Some(blockstmt) = iter.next() {
-                            match blockstmt.kind {
-                                NDCASE {
-                                    ref val,
-                                    ref mut pos,
-                                    ..
-                                } => {
-                                    cases.push_back(val.clone());
-                                    *pos = Some(counter);
-                                    counter += 1;
-                                }
-                                NDDEFAULT { .. } => {
-                                    *has_default = true;
-                                }
-                                _ => (),
-                            }
-                        }
+    pub fn populate_switch(&mut self) {
+        // Use offset to communicate the relative position in the
+        // order of appearance
+        use NodeKind::*;
+
+        if let NDSWITCH {
+            ref mut stmt,
+            ref mut cases,
+            ref mut has_default,
+            ..
+        } = self.kind
+        {
+            let switch_stmt = match stmt.as_mut() {
+                Some(switch_stmt) => switch_stmt,
+                None => return,
+            };
+
+            match switch_stmt.kind {
+                NDBLOCK { ref mut stmts } => {
+                    for blockstmt in stmts.iter_mut() {
+                        blockstmt.populate_labels(cases, has_default);
                     }
-                    _ => (),
                 }
+                _ => switch_stmt.populate_labels(cases, has_default),
             }
-            _ => (),
+        }
+    }
+
+    // Records a case or default label, along with those stacked right
+    // after it as in "case 1: case 2:"
+    fn populate_labels(&mut self, cases: &mut LinkedList<i32>, has_default: &mut bool) {
+        use NodeKind::*;
+
+        let stmt = match self.kind {
+            NDCASE {
+                val,
+                ref mut pos,
+                ref mut stmt,
+            } => {
+                *pos = Some(cases.len());
+                cases.push_back(val);
+                stmt
+            }
+            NDDEFAULT { ref mut stmt } => {
+                *has_default = true;
+                stmt
+            }
+            _ => return,
+        };
+        if let Some(stmt) = stmt {
+            stmt.populate_labels(cases, has_default);
         }
     }
 
     pub fn populate_ty(&mut self) {
         use NodeKind::*;
 
-        if !self.ty.is_none() {
+        if self.ty.is_some() {
             return;
         }
 
         self.ty = match self.kind {
+            // As sema types it, not as the array it is
+            NDSTR { .. } => Some(Type::new_ptr(Type::new_base("char"))),
             NDADD {
                 ref mut lhs,
                 ref mut rhs,
@@ -635,33 +994,73 @@ impl Node {
                 let l_ty = lhs.ty.as_ref().unwrap();
                 let r_ty = rhs.ty.as_ref().unwrap();
 
-                if l_ty.is_ptr_like() {
-                    if r_ty.is_ptr_like() {
-                        panic!("Parser: Both sides of add/sub are pointers...");
-                    }
+                if l_ty.is_ptr_like() && r_ty.is_ptr_like() {
+                    // Left for the semantic analysis to diagnose
+                    Some(Type::new_base("long"))
+                } else if l_ty.is_ptr_like() {
                     *scale_lhs = Some(true);
-                    Some(l_ty.clone())
+                    Some(l_ty.decay())
                 } else if r_ty.is_ptr_like() {
                     // Already checked above that l_ty is not a pointer
                     *scale_lhs = Some(false);
-                    Some(r_ty.clone())
+                    Some(r_ty.decay())
                 } else {
-                    // TODO: Update this
-                    Some(Type::new_base("long"))
+                    Some(arith_type(l_ty, r_ty))
                 }
             }
-            NDMUL { .. }
-            | NDDIV { .. }
-            | NDEQ { .. }
+            NDMUL {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDDIV {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDMOD {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDBITAND {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDBITXOR {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDBITOR {
+                ref mut lhs,
+                ref mut rhs,
+            } => {
+                lhs.populate_ty();
+                rhs.populate_ty();
+                Some(arith_type(
+                    lhs.ty.as_ref().unwrap(),
+                    rhs.ty.as_ref().unwrap(),
+                ))
+            }
+            // A shift has the type of its promoted left operand
+            NDSHL { ref mut lhs, .. }
+            | NDSHR { ref mut lhs, .. }
+            | NDBITNOT { node: ref mut lhs } => {
+                lhs.populate_ty();
+                let ty = lhs.ty.as_ref().unwrap();
+                Some(arith_type(ty, ty))
+            }
+            NDEQ { .. }
             | NDNEQ { .. }
             | NDLEQ { .. }
-            | NDLT { .. } => {
-                // TODO: Update this
-                Some(Type::new_base("int"))
-            }
-            NDCALL { .. } => {
-                // TODO: Currently the only retval is INT
-                Some(Type::new_base("int"))
+            | NDLT { .. }
+            | NDLOGAND { .. }
+            | NDLOGOR { .. } => Some(Type::new_base("int")),
+            NDCALL {
+                ref mut prototy, ..
+            } => {
+                prototy.populate_ty();
+                match prototy.ty.as_ref().unwrap().callee_function() {
+                    Some(functy) => Some(functy.func_ret().clone()),
+                    None => panic!("Calling something that is not a function"),
+                }
             }
             NDADDR { ref mut node } => {
                 node.populate_ty();
@@ -672,7 +1071,6 @@ impl Node {
                 ref mut rhs,
                 ref mut scale_lhs,
                 assign_mode,
-                is_init,
                 ..
             } => {
                 use AssignMode::*;
@@ -680,36 +1078,51 @@ impl Node {
                 rhs.populate_ty();
 
                 let l_ty = lhs.ty.as_ref().unwrap();
-                if l_ty.is_const && !is_init {
-                    panic!("Trying to assign to const!")
-                }
-                if assign_mode == ADD || assign_mode == SUB {
-                    if l_ty.is_ptr_like() {
-                        *scale_lhs = Some(true);
-                    }
+                if (assign_mode == ADD || assign_mode == SUB) && l_ty.is_ptr_like() {
+                    *scale_lhs = Some(true);
                 }
                 Some(l_ty.clone())
             }
             NDDEREF { ref mut node } => {
                 node.populate_ty();
                 // What lhs's type points to should be my type.
-                Some(node.ty.as_ref().unwrap().clone_base())
+                match node.ty {
+                    Some(ref ty) if ty.is_ptr_like() || ty.is_function() => Some(ty.clone_base()),
+                    // Left for the semantic analysis to diagnose
+                    _ => Some(Type::new_base("int")),
+                }
             }
             NDMEMBER {
                 ref mut node,
                 ref name,
                 ref mut offset,
+                ref mut bits,
             } => {
                 // Should be the member's type
                 node.populate_ty();
-                if let Some((ofs, ty)) = node.ty.as_ref().unwrap().get_member_offset(name.as_str())
-                {
-                    *offset = Some(ofs);
-                    Some(ty)
+                if let Some(member) = node.ty.as_ref().unwrap().get_member(name.as_str()) {
+                    *offset = Some(member.offset);
+                    *bits = member.bits;
+                    Some(member.ty)
                 } else {
                     panic!("No member with name {} found!", name);
                 }
             }
+            NDCOND {
+                ref mut cond,
+                ref mut then,
+                ref mut els,
+            } => {
+                cond.populate_ty();
+                then.populate_ty();
+                els.populate_ty();
+                // A pointer operand decides the type, e.g. "c ? p : 0"
+                match (&then.ty, &els.ty) {
+                    (Some(t), _) if t.is_ptr_like() => Some(t.decay()),
+                    (_, Some(e)) if e.is_ptr_like() => Some(e.decay()),
+                    (t, _) => t.clone(),
+                }
+            }
             _ => None,
         }
     }
@@ -749,3 +1162,13 @@ impl AssignMode {
         }
     }
 }
+
+// Type of arithmetic on operands of these types after the usual arithmetic
+// conversions: int, or long if either of them is long
+fn arith_type(l: &Type, r: &Type) -> Type {
+    if l.size() == 8 || r.size() == 8 {
+        Type::new_base("long")
+    } else {
+        Type::new_base("int")
+    }
+}
@@ -0,0 +1,551 @@
+// IR optimization passes and the pass manager behind -O1/-O2.
+// Registers are not SSA, so values are only propagated from registers with
+// a single definition. verify() guarantees such a definition dominates every
+// use, which makes the register hold the same value wherever it is read.
+use crate::ir::{
+    BinOp, Block, BlockId, Cond, Function, Inst, Module, Terminator, UnOp, VReg, Width,
+};
+use std::collections::HashMap;
+
+pub struct Pass {
+    pub name: &'static str,
+    run: fn(&mut Function) -> bool, // Returns whether anything changed
+}
+
+pub const PASSES: [Pass; 6] = [
+    Pass {
+        name: "constprop",
+        run: constprop,
+    },
+    Pass {
+        name: "copyprop",
+        run: copyprop,
+    },
+    Pass {
+        name: "cse",
+        run: cse,
+    },
+    Pass {
+        name: "strength",
+        run: strength_reduce,
+    },
+    Pass {
+        name: "dce",
+        run: dce,
+    },
+    Pass {
+        name: "jumpthread",
+        run: jump_thread,
+    },
+];
+
+/// Passes run by each optimization level, in order
+pub fn pipeline(level: u8) -> Vec<&'static str> {
+    match level {
+        0 => vec![],
+        1 => vec!["constprop", "copyprop", "dce", "jumpthread"],
+        _ => vec![
+            "constprop",
+            "copyprop",
+            "cse",
+            "strength",
+            "dce",
+            "jumpthread",
+        ],
+    }
+}
+
+pub fn find_pass(name: &str) -> Option<&'static Pass> {
+    PASSES.iter().find(|p| p.name == name)
+}
+
+/// Runs the named passes over every function. With repeat set the sequence
+/// is rerun until nothing changes, as one pass often exposes work for another.
+pub fn run(module: &mut Module, names: &[&str], repeat: bool) {
+    let passes: Vec<&Pass> = names
+        .iter()
+        .map(|n| find_pass(n).unwrap_or_else(|| panic!("yarcc: Unknown pass {}", n)))
+        .collect();
+    for func in module.functions.iter_mut() {
+        // A bound in case two passes keep undoing each other
+        for _ in 0..10 {
+            let mut changed = false;
+            for pass in passes.iter() {
+                changed |= (pass.run)(func);
+            }
+            if !repeat || !changed {
+                break;
+            }
+        }
+    }
+}
+
+fn def_counts(func: &Function) -> Vec<usize> {
+    let mut counts = vec![0; func.num_vregs];
+    for block in func.blocks.iter() {
+        for inst in block.insts.iter() {
+            if let Some(dst) = inst.def() {
+                counts[dst.0] += 1;
+            }
+        }
+    }
+    counts
+}
+
+fn use_counts(func: &Function) -> Vec<usize> {
+    let mut counts = vec![0; func.num_vregs];
+    for block in func.blocks.iter() {
+        for inst in block.insts.iter() {
+            for r in inst.uses() {
+                counts[r.0] += 1;
+            }
+        }
+        for r in block.term.uses() {
+            counts[r.0] += 1;
+        }
+    }
+    counts
+}
+
+// Value of every register defined once by a constant
+fn known_consts(func: &Function) -> Vec<Option<i64>> {
+    let defs = def_counts(func);
+    let mut consts = vec![None; func.num_vregs];
+    for block in func.blocks.iter() {
+        for inst in block.insts.iter() {
+            if let Inst::Const { dst, val } = *inst {
+                if defs[dst.0] == 1 {
+                    consts[dst.0] = Some(val);
+                }
+            }
+        }
+    }
+    consts
+}
+
+// Same semantics as the instructions codegen emits
+pub fn eval_bin(op: BinOp, l: i64, r: i64) -> Option<i64> {
+    use BinOp::*;
+    Some(match op {
+        Add => l.wrapping_add(r),
+        Sub => l.wrapping_sub(r),
+        Mul => l.wrapping_mul(r),
+        Div => l.checked_div(r)?,
+        Mod => l.checked_rem(r)?,
+        And => l & r,
+        Or => l | r,
+        Xor => l ^ r,
+        Shl => l.wrapping_shl(r as u32 & 63),
+        Shr => ((l as u64) >> (r & 63)) as i64,
+        Sar => l >> (r & 63),
+    })
+}
+
+pub fn eval_cmp(cond: Cond, l: i64, r: i64) -> i64 {
+    let res = match cond {
+        Cond::Eq => l == r,
+        Cond::Ne => l != r,
+        Cond::Lt => l < r,
+        Cond::Le => l <= r,
+        Cond::Ult => (l as u64) < r as u64,
+        Cond::Ule => l as u64 <= r as u64,
+    };
+    res as i64
+}
+
+pub fn eval_un(op: UnOp, v: i64) -> i64 {
+    match op {
+        UnOp::Not => !v,
+        UnOp::Sext(Width::I8) => v as i8 as i64,
+        UnOp::Sext(Width::I16) => v as i16 as i64,
+        UnOp::Sext(Width::I32) => v as i32 as i64,
+        UnOp::Sext(Width::I64) => v,
+    }
+}
+
+fn fold(inst: &Inst, consts: &[Option<i64>]) -> Option<i64> {
+    match *inst {
+        Inst::Copy { src, .. } => consts[src.0],
+        Inst::Bin { op, lhs, rhs, .. } => eval_bin(op, consts[lhs.0]?, consts[rhs.0]?),
+        Inst::Cmp { cond, lhs, rhs, .. } => Some(eval_cmp(cond, consts[lhs.0]?, consts[rhs.0]?)),
+        Inst::Un { op, src, .. } => Some(eval_un(op, consts[src.0]?)),
+        _ => None,
+    }
+}
+
+// Operations with a zero right operand that leave the left one unchanged,
+// e.g. the offset of the first array element
+fn identity(inst: &Inst, consts: &[Option<i64>]) -> Option<VReg> {
+    use BinOp::*;
+    match *inst {
+        Inst::Bin {
+            op: Add | Sub | Or | Xor | Shl | Shr | Sar,
+            lhs,
+            rhs,
+            ..
+        } if consts[rhs.0] == Some(0) => Some(lhs),
+        _ => None,
+    }
+}
+
+/// Evaluates instructions whose operands are all known constants, drops
+/// additions of zero and resolves branches on constants
+pub fn constprop(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let consts = known_consts(func);
+        let mut folded = false;
+        for block in func.blocks.iter_mut() {
+            for inst in block.insts.iter_mut() {
+                if let Some(val) = fold(inst, &consts) {
+                    *inst = Inst::Const {
+                        dst: inst.def().unwrap(),
+                        val,
+                    };
+                    folded = true;
+                } else if let Some(src) = identity(inst, &consts) {
+                    *inst = Inst::Copy {
+                        dst: inst.def().unwrap(),
+                        src,
+                    };
+                    folded = true;
+                }
+            }
+            let target = match block.term {
+                Terminator::Branch { cond, then, els } => {
+                    consts[cond.0].map(|c| if c != 0 { then } else { els })
+                }
+                Terminator::Switch {
+                    val,
+                    ref cases,
+                    default,
+                } => consts[val.0].map(|v| {
+                    cases
+                        .iter()
+                        .find(|(c, _)| *c == v)
+                        .map_or(default, |(_, b)| *b)
+                }),
+                _ => None,
+            };
+            if let Some(to) = target {
+                block.term = Terminator::Jump(to);
+                folded = true;
+            }
+        }
+        if !folded {
+            break;
+        }
+        changed = true;
+    }
+    if changed {
+        func.remove_unreachable_blocks();
+    }
+    changed
+}
+
+/// Replaces uses of a copy with its source
+pub fn copyprop(func: &mut Function) -> bool {
+    let defs = def_counts(func);
+    let mut source: Vec<VReg> = (0..func.num_vregs).map(VReg).collect();
+    for block in func.blocks.iter() {
+        for inst in block.insts.iter() {
+            if let Inst::Copy { dst, src } = *inst {
+                if defs[dst.0] == 1 && defs[src.0] == 1 {
+                    source[dst.0] = src;
+                }
+            }
+        }
+    }
+    // Chains of copies resolve to the first source. Every link points to a
+    // dominating definition, so the walk cannot cycle.
+    let resolve = |mut r: VReg| {
+        while source[r.0] != r {
+            r = source[r.0];
+        }
+        r
+    };
+
+    let mut changed = false;
+    for block in func.blocks.iter_mut() {
+        let uses = block
+            .insts
+            .iter_mut()
+            .flat_map(|i| i.uses_mut())
+            .chain(block.term.uses_mut());
+        for r in uses {
+            let new = resolve(*r);
+            if new != *r {
+                *r = new;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Operand {
+    Reg(VReg),
+    Imm(i64),
+    Frame(usize),
+    Global(String),
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Expr {
+    Bin(BinOp, Operand, Operand),
+    Cmp(Cond, Operand, Operand),
+    Un(UnOp, Operand),
+}
+
+// What each single-definition register is known to hold. Constants and
+// addresses compare by value since every use materializes them into a new
+// register, and copies compare as their source.
+fn value_numbers(func: &Function, defs: &[usize]) -> Vec<Option<Operand>> {
+    let mut vals: Vec<Option<Operand>> = (0..func.num_vregs)
+        .map(|r| Some(Operand::Reg(VReg(r))).filter(|_| defs[r] == 1))
+        .collect();
+    for id in func.reverse_postorder() {
+        for inst in func.blocks[id.0].insts.iter() {
+            let dst = match inst.def() {
+                Some(dst) if defs[dst.0] == 1 => dst,
+                _ => continue,
+            };
+            vals[dst.0] = match *inst {
+                Inst::Const { val, .. } => Some(Operand::Imm(val)),
+                Inst::FrameAddr { offset, .. } => Some(Operand::Frame(offset)),
+                Inst::GlobalAddr { ref name, .. } => Some(Operand::Global(name.clone())),
+                // The source is defined earlier in reverse postorder
+                Inst::Copy { src, .. } if defs[src.0] == 1 => vals[src.0].clone(),
+                _ => continue,
+            };
+        }
+    }
+    vals
+}
+
+fn expr_key(inst: &Inst, vals: &[Option<Operand>]) -> Option<Expr> {
+    let operand = |r: VReg| vals[r.0].clone();
+    match *inst {
+        Inst::Bin { op, lhs, rhs, .. } => {
+            let (mut l, mut r) = (operand(lhs)?, operand(rhs)?);
+            let commutes = matches!(
+                op,
+                BinOp::Add | BinOp::Mul | BinOp::And | BinOp::Or | BinOp::Xor
+            );
+            if commutes && matches!(l, Operand::Imm(_)) {
+                std::mem::swap(&mut l, &mut r);
+            }
+            Some(Expr::Bin(op, l, r))
+        }
+        Inst::Cmp { cond, lhs, rhs, .. } => Some(Expr::Cmp(cond, operand(lhs)?, operand(rhs)?)),
+        Inst::Un { op, src, .. } => Some(Expr::Un(op, operand(src)?)),
+        _ => None,
+    }
+}
+
+/// Reuses the result of an identical computation that dominates the
+/// instruction. Loads and calls are never merged since memory may change.
+pub fn cse(func: &mut Function) -> bool {
+    let defs = def_counts(func);
+    let mut vals = value_numbers(func, &defs);
+    let idom = func.immediate_dominators();
+    let mut children: Vec<Vec<BlockId>> = vec![Vec::new(); func.blocks.len()];
+    for (id, dom) in idom.iter().enumerate() {
+        if let Some(dom) = dom {
+            children[dom.0].push(BlockId(id));
+        }
+    }
+
+    // Preorder walk of the dominator tree; each block sees what its
+    // dominators computed
+    let mut changed = false;
+    let mut available: HashMap<Expr, VReg> = HashMap::new();
+    let mut scopes: Vec<Vec<Expr>> = Vec::new();
+    let mut work = vec![(BlockId(0), false)];
+    while let Some((id, leaving)) = work.pop() {
+        if leaving {
+            for e in scopes.pop().unwrap() {
+                available.remove(&e);
+            }
+            continue;
+        }
+        let mut added = Vec::new();
+        for inst in func.blocks[id.0].insts.iter_mut() {
+            let dst = match inst.def() {
+                Some(dst) if defs[dst.0] == 1 => dst,
+                _ => continue,
+            };
+            let e = match expr_key(inst, &vals) {
+                Some(e) => e,
+                None => continue,
+            };
+            match available.get(&e) {
+                Some(&src) => {
+                    *inst = Inst::Copy { dst, src };
+                    vals[dst.0] = vals[src.0].clone();
+                    changed = true;
+                }
+                None => {
+                    available.insert(e, dst);
+                    added.push(expr_key(inst, &vals).unwrap());
+                }
+            }
+        }
+        scopes.push(added);
+        work.push((id, true));
+        for &child in children[id.0].iter().rev() {
+            work.push((child, false));
+        }
+    }
+    changed
+}
+
+/// Turns multiplications by a power of two, e.g. pointer scaling, into shifts
+pub fn strength_reduce(func: &mut Function) -> bool {
+    let consts = known_consts(func);
+    let mut changed = false;
+    for b in 0..func.blocks.len() {
+        let insts = std::mem::take(&mut func.blocks[b].insts);
+        let mut reduced = Vec::with_capacity(insts.len());
+        for inst in insts {
+            if let Inst::Bin {
+                dst,
+                op: BinOp::Mul,
+                lhs,
+                rhs,
+            } = inst
+            {
+                let (other, factor) = match (consts[lhs.0], consts[rhs.0]) {
+                    (_, Some(f)) => (lhs, f),
+                    (Some(f), _) => (rhs, f),
+                    _ => {
+                        reduced.push(inst);
+                        continue;
+                    }
+                };
+                if factor == 1 {
+                    reduced.push(Inst::Copy { dst, src: other });
+                    changed = true;
+                    continue;
+                }
+                if factor > 1 && factor.count_ones() == 1 {
+                    let amount = func.new_vreg();
+                    reduced.push(Inst::Const {
+                        dst: amount,
+                        val: factor.trailing_zeros() as i64,
+                    });
+                    reduced.push(Inst::Bin {
+                        dst,
+                        op: BinOp::Shl,
+                        lhs: other,
+                        rhs: amount,
+                    });
+                    changed = true;
+                    continue;
+                }
+            }
+            reduced.push(inst);
+        }
+        func.blocks[b].insts = reduced;
+    }
+    changed
+}
+
+/// Removes instructions whose results are never read and blocks that
+/// cannot be reached
+pub fn dce(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let uses = use_counts(func);
+        let mut removed = false;
+        for block in func.blocks.iter_mut() {
+            let before = block.insts.len();
+            block
+                .insts
+                .retain(|i| !i.is_pure() || i.def().is_none_or(|d| uses[d.0] > 0));
+            removed |= block.insts.len() != before;
+        }
+        if !removed {
+            break;
+        }
+        changed = true;
+    }
+    let before = func.blocks.len();
+    func.remove_unreachable_blocks();
+    changed || func.blocks.len() != before
+}
+
+// Blocks holding nothing but source positions count as empty, so that -g
+// leaves the control flow as it is without
+fn is_empty(block: &Block) -> bool {
+    block.insts.iter().all(|i| matches!(i, Inst::Loc { .. }))
+}
+
+/// Simplifies control flow: jumps through empty blocks go straight to their
+/// final target, empty blocks that only return or branch are copied into
+/// jumping predecessors, and a block with a single jumping predecessor is
+/// merged into it.
+pub fn jump_thread(func: &mut Function) -> bool {
+    let mut changed = false;
+
+    // Where control ends up when entering a block; cycles of empty blocks
+    // are left alone
+    let forward = |func: &Function, mut id: BlockId| {
+        let mut steps = 0;
+        while let Terminator::Jump(to) = func.blocks[id.0].term {
+            if !is_empty(&func.blocks[id.0]) || steps > func.blocks.len() {
+                break;
+            }
+            id = to;
+            steps += 1;
+        }
+        id
+    };
+    for b in 0..func.blocks.len() {
+        let mut term = func.blocks[b].term.clone();
+        for succ in term.successors_mut() {
+            let to = forward(func, *succ);
+            if to != *succ {
+                *succ = to;
+                changed = true;
+            }
+        }
+        if let Terminator::Branch { then, els, .. } = term {
+            if then == els {
+                term = Terminator::Jump(then);
+                changed = true;
+            }
+        }
+        if let Terminator::Jump(to) = term {
+            let target = &func.blocks[to.0];
+            let small = matches!(target.term, Terminator::Ret(_) | Terminator::Branch { .. });
+            if to.0 != b && is_empty(target) && small {
+                term = target.term.clone();
+                changed = true;
+            }
+        }
+        func.blocks[b].term = term;
+    }
+
+    loop {
+        let preds = func.predecessors();
+        let mergeable = (0..func.blocks.len()).find_map(|b| match func.blocks[b].term {
+            Terminator::Jump(to) if to.0 != b && to.0 != 0 && preds[to.0].len() == 1 => {
+                Some((b, to.0))
+            }
+            _ => None,
+        });
+        let (b, to) = match mergeable {
+            Some(pair) => pair,
+            None => break,
+        };
+        let mut insts = std::mem::take(&mut func.blocks[to].insts);
+        let term = std::mem::replace(&mut func.blocks[to].term, Terminator::Jump(BlockId(to)));
+        func.blocks[b].insts.append(&mut insts);
+        func.blocks[b].term = term;
+        changed = true;
+    }
+    func.remove_unreachable_blocks();
+    changed
+}
@@ -0,0 +1,142 @@
+// Command line options
+use std::fs;
+use std::path::Path;
+
+/// How the generated code refers to addresses
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum CodeModel {
+    Absolute, // -fno-pic: symbols are at fixed addresses
+    #[default]
+    Pie, // -fPIE: relative to rip; symbols defined elsewhere go through the GOT
+    Pic,      // -fPIC: as -fPIE, but symbols may be preempted by other objects
+}
+
+/// Dialect of the assembly written out
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum AsmSyntax {
+    #[default]
+    Intel, // -masm=intel: "mov rax, qword ptr [rbp-8]"
+    Att, // -masm=att: "mov -8(%rbp), %rax"
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub input: String,               // Path to the source, or the raw source itself
+    pub dump_tokens: bool,           // --dump-tokens
+    pub dump_ast: bool,              // --dump-ast
+    pub dump_c: bool,                // --dump-c
+    pub round_trip: bool,            // --round-trip
+    pub dump_ir: bool,               // --dump-ir
+    pub dump_asm: bool,              // --dump-asm
+    pub no_peephole: bool,           // --no-peephole
+    pub opt_level: u8,               // -O0, -O1 or -O2
+    pub passes: Option<Vec<String>>, // --passes=a,b runs exactly these passes once
+    pub code_model: CodeModel,       // -fPIE (default), -fPIC or -fno-pic
+    pub debug: bool,                 // -g
+    pub object: bool,                // -c writes an object instead of tmp.s as -S does
+    pub syntax: AsmSyntax,           // -masm=intel (default) or -masm=att
+    pub run: bool,                   // --run interprets the program instead
+    pub check: bool,                 // --check interprets it stopping at undefined behaviour
+    pub jit: bool,                   // --jit compiles into memory and runs the program
+    pub args: Vec<String>,           // Following --, passed to the program run by --jit
+    pub repl: bool,                  // --repl reads C from stdin and runs it as it goes
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut opts = Options::default();
+        let mut input: Option<String> = None;
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--" => opts.args = iter.by_ref().cloned().collect(),
+                "--dump-tokens" => opts.dump_tokens = true,
+                "--dump-ast" => opts.dump_ast = true,
+                "--dump-c" => opts.dump_c = true,
+                "--round-trip" => opts.round_trip = true,
+                "--dump-ir" => opts.dump_ir = true,
+                "--dump-asm" => opts.dump_asm = true,
+                "--run" => opts.run = true,
+                "--check" => {
+                    opts.run = true;
+                    opts.check = true;
+                }
+                "--jit" => opts.jit = true,
+                "--repl" => opts.repl = true,
+                "--no-peephole" => opts.no_peephole = true,
+                "-O0" => opts.opt_level = 0,
+                "-O1" => opts.opt_level = 1,
+                "-O2" => opts.opt_level = 2,
+                "-fPIE" | "-fpie" => opts.code_model = CodeModel::Pie,
+                "-fPIC" | "-fpic" => opts.code_model = CodeModel::Pic,
+                "-fno-pic" | "-fno-pie" => opts.code_model = CodeModel::Absolute,
+                "-g" => opts.debug = true,
+                "-c" => opts.object = true,
+                "-S" => opts.object = false,
+                "-masm=intel" => opts.syntax = AsmSyntax::Intel,
+                "-masm=att" => opts.syntax = AsmSyntax::Att,
+                flag if flag.starts_with("-masm=") => {
+                    return Err(format!(
+                        "Unknown assembler dialect {}",
+                        &flag["-masm=".len()..]
+                    ));
+                }
+                flag if flag.starts_with("--passes=") => {
+                    let names = flag["--passes=".len()..].split(',');
+                    opts.passes = Some(names.filter(|n| !n.is_empty()).map(String::from).collect());
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(format!("Unknown option {}", flag));
+                }
+                other => {
+                    if input.is_some() {
+                        return Err("Wrong number of arguments!".to_string());
+                    }
+                    input = Some(other.to_string());
+                }
+            }
+        }
+
+        match input {
+            Some(i) => opts.input = i,
+            None if opts.repl => (),
+            None => return Err("Wrong number of arguments!".to_string()),
+        }
+        if !opts.args.is_empty() && !opts.jit {
+            return Err("Program arguments are only passed on with --jit".to_string());
+        }
+        Ok(opts)
+    }
+
+    /// Name of the source file for debug information
+    pub fn source_name(&self) -> &str {
+        if Path::new(&self.input).exists() {
+            &self.input
+        } else {
+            "<command line>"
+        }
+    }
+
+    /// Object written by -c: foo.o for foo.c, or tmp.o for raw source
+    pub fn object_name(&self) -> String {
+        let path = Path::new(&self.input);
+        match path.file_stem() {
+            Some(stem) if path.exists() => format!("{}.o", stem.to_string_lossy()),
+            _ => "tmp.o".to_string(),
+        }
+    }
+
+    /// Reads the source either from the file named by input or input itself
+    pub fn read_source(&self) -> String {
+        if Path::new(&self.input).exists() {
+            if let Ok(text) = fs::read_to_string(&self.input) {
+                text
+            } else {
+                panic!("yarcc: Cannot read the provided file.");
+            }
+        } else {
+            self.input.to_string()
+        }
+    }
+}
@@ -1,8 +1,10 @@
 // Recursive-descent parser
-use crate::cenv::{Env, Var};
-use crate::ctype::{EnumMember, IncompleteKind, StructMember, Type, TypeConfig};
-use crate::node::{AssignMode, Node};
-use crate::tokenizer::{TokenIter, TokenKind};
+use crate::cenv::{Env, Prototype, Ref, RefKind, Var};
+use crate::constexpr::{self, ConstVal};
+use crate::ctype::{EnumMember, IncompleteKind, StructMember, StructRef, Type, TypeConfig};
+use crate::node::{AssignMode, Node, NodeKind};
+use crate::tokenizer::{unescape, Pos, Span, TokenIter, TokenKind};
+use std::any::Any;
 use std::collections::{LinkedList, VecDeque};
 
 // Parser returns this context;
@@ -11,31 +13,201 @@ pub struct Program {
     pub nodes: LinkedList<Node>,
     pub globals: Vec<Var>,
     pub literals: VecDeque<String>,
+    pub prototypes: Vec<Prototype>,
+    pub structs: Vec<StructRef>,
+    pub enums: Vec<Type>,
+    pub global_inits: Vec<GlobalInit>,
+    pub warnings: Vec<(Span, String)>, // Of the parser, then of sema
 }
 
+// Initial value of a global variable, broken down into scalars
+#[derive(Clone)]
+pub struct GlobalInit {
+    pub name: String,
+    pub vals: Vec<InitVal>,
+}
+
+/// A scalar in the initializer of a global
+#[derive(Clone)]
+pub struct InitVal {
+    pub offset: usize, // Byte offset into the global
+    pub ty: Type,
+    pub bits: Option<(usize, usize)>, // Bit offset and width of a bit-field
+    pub val: Node,
+}
+
+// A scalar in an initializer, at a byte offset into the object. A bit-field
+// also names the struct it is a member of, which starts at the offset.
+struct InitLeaf {
+    offset: usize,
+    ty: Type,
+    field: Option<(Type, String)>,
+    val: Node,
+}
+
+/// Message of a parse error, which the parser reports by panicking
+pub fn error_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "error: Invalid input".to_string(),
+        },
+    }
+}
+
+/// Start of the names of the functions --repl puts its statements in
+pub const ENTRY_HEAD: &str = "__repl_";
+
 pub struct Parser {
     iter: TokenIter,
     env: Env,
+    global_inits: Vec<GlobalInit>,
+    funcdefs: Vec<String>,           // Names of the functions defined so far
+    entries: usize,                  // Statements given to --repl so far
+    decl_names: Vec<(String, Span)>, // Names in the declarators of the current declaration
+    refs: Vec<Ref>,                  // Identifiers read so far, for rcc-lsp
+    accesses: Vec<(Span, Type)>,     // Each "." and "->" with the type it selects from
+    // Under --repl, assignments for the global initializers that are not constant
+    entry_inits: Option<LinkedList<Node>>,
+    error_span: Option<Span>, // Of an error found away from the last token
+    warnings: Vec<(Span, String)>,
+    defaults: Vec<bool>, // Whether each switch being read has a default label yet
+}
+
+/// What a REPL parser goes back to when an input fails
+pub struct Saved {
+    env: Env,
+    global_inits: usize,
+    funcdefs: usize,
 }
 
 impl Parser {
     pub fn new(iter: TokenIter) -> Self {
         Parser {
-            iter: iter,
+            iter,
             env: Env::new(),
+            global_inits: Vec::new(),
+            funcdefs: Vec::new(),
+            entries: 0,
+            decl_names: Vec::new(),
+            refs: Vec::new(),
+            accesses: Vec::new(),
+            entry_inits: None,
+            error_span: None,
+            warnings: Vec::new(),
+            defaults: Vec::new(),
         }
     }
 
-    pub fn parse(mut self) -> Program {
+    /// Parses one input of --repl against what the earlier ones declared.
+    /// Declarations and function definitions are at file scope as in a
+    /// source file, while every other statement becomes a function of its
+    /// own, named from ENTRY_HEAD, for the REPL to call. So does a global
+    /// initializer that is not constant, assigning it when it runs, which
+    /// lets it use what earlier inputs computed. The program has
+    /// the symbols of all inputs so far but only the nodes and initializers
+    /// of this one.
+    pub fn parse_input(&mut self, iter: TokenIter) -> Program {
+        self.iter = iter;
+        let old_inits = self.global_inits.len();
+        let mut nodes = LinkedList::new();
+        while !self.iter.at_eof() {
+            if self.iter.starts_declaration(0) {
+                self.entry_inits = Some(LinkedList::new());
+                if let Some(node) = self.external_decl() {
+                    nodes.push_back(node);
+                }
+                let inits = self.entry_inits.take().unwrap();
+                if !inits.is_empty() {
+                    let block = Node::new_block(inits);
+                    nodes.push_back(self.entry(std::iter::once(block).collect(), 0));
+                }
+                continue;
+            }
+            self.env.scopes.add_scope();
+            let stmts = self.stmt().into_iter().collect();
+            let lvars_offset = self.env.scopes.remove_scope().unwrap();
+            nodes.push_back(self.entry(stmts, lvars_offset));
+        }
+        Program {
+            nodes,
+            globals: self.env.scopes.globals().cloned().collect(),
+            literals: self.env.literals.clone(),
+            prototypes: self.env.prototypes.clone(),
+            structs: self.env.structs.clone(),
+            enums: self.env.enums.clone(),
+            global_inits: self.global_inits[old_inits..].to_vec(),
+            warnings: self.take_warnings(),
+        }
+    }
+
+    // Function the REPL calls to run the statements
+    fn entry(&mut self, stmts: LinkedList<Node>, lvars_offset: usize) -> Node {
+        let name = format!("{}{}", ENTRY_HEAD, self.entries);
+        self.entries += 1;
+        Node::new_funcdef(name, LinkedList::new(), stmts, lvars_offset)
+    }
+
+    pub fn save(&self) -> Saved {
+        Saved {
+            env: self.env.clone(),
+            global_inits: self.global_inits.len(),
+            funcdefs: self.funcdefs.len(),
+        }
+    }
+
+    pub fn restore(&mut self, saved: Saved) {
+        self.env = saved.env;
+        self.global_inits.truncate(saved.global_inits);
+        self.funcdefs.truncate(saved.funcdefs);
+        self.defaults.clear();
+    }
+
+    pub fn parse(&mut self) -> Program {
         let nodes = self.program();
-        let (g, l) = self.env.get_symbols();
+        let mut env = std::mem::replace(&mut self.env, Env::new());
+        let enums = std::mem::take(&mut env.enums);
+        let (g, l, p, s) = env.get_symbols();
         Program {
-            nodes: nodes,
+            nodes,
             globals: g,
             literals: l,
+            prototypes: p,
+            structs: s,
+            enums,
+            global_inits: std::mem::take(&mut self.global_inits),
+            warnings: self.take_warnings(),
         }
     }
 
+    /// Identifiers read so far and what each of them refers to
+    pub fn refs(&self) -> &[Ref] {
+        &self.refs
+    }
+
+    /// The "." and "->" read so far, each with the type whose member it
+    /// selects
+    pub fn accesses(&self) -> &[(Span, Type)] {
+        &self.accesses
+    }
+
+    /// Warnings found since they were last taken, which the program
+    /// parsed gets, and which are all there is if parsing failed
+    pub fn take_warnings(&mut self) -> Vec<(Span, String)> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Span of the last token read, where parsing stopped if it failed
+    pub fn last_span(&self) -> Span {
+        self.iter.last_span()
+    }
+
+    /// Where the error that stopped parsing is, usually the last token
+    pub fn error_span(&self) -> Span {
+        self.error_span.unwrap_or_else(|| self.iter.last_span())
+    }
+
     // program = external_decl*
     fn program(&mut self) -> LinkedList<Node> {
         let mut nodes = LinkedList::new();
@@ -44,6 +216,49 @@ impl Parser {
                 nodes.push_back(node);
             }
         }
+
+        // A definition left without a size gets one element, as gcc does
+        let sizeless: Vec<Var> = self
+            .env
+            .scopes
+            .globals()
+            .filter(|var| !var.is_extern && (var.ty.is_incomplete() || var.ty.is_void()))
+            .cloned()
+            .collect();
+        for var in sizeless {
+            if !var.ty.is_array() {
+                let msg = format!(
+                    "Variable '{}' has the incomplete type '{}'",
+                    var.name, var.ty
+                );
+                self.error_at(var.span, &msg);
+            }
+            self.warn_at(
+                var.span,
+                &format!("Array '{}' is assumed to have one element.", var.name),
+            );
+            let ty = Type::new_array(var.ty.clone_base(), 1);
+            if let Err(msg) = self.env.add_global(var.name, ty, false, var.span) {
+                self.error(&msg);
+            }
+        }
+
+        // Uses before the size was known saw an incomplete type
+        let arrays: Vec<Var> = self
+            .env
+            .scopes
+            .globals()
+            .filter(|var| var.ty.is_array() && !var.ty.is_incomplete())
+            .cloned()
+            .collect();
+        for node in nodes.iter_mut() {
+            node.complete_arrays(&arrays);
+        }
+        for init in self.global_inits.iter_mut() {
+            for val in init.vals.iter_mut() {
+                val.val.complete_arrays(&arrays);
+            }
+        }
         nodes
     }
 
@@ -51,106 +266,254 @@ impl Parser {
     // This handles the shared part of funcdef and decl
     // and delegates the rest of the work to the respective funcitons.
     fn external_decl(&mut self) -> Option<Node> {
-        let basety = match self.decl_spec() {
+        let lo = self.iter.peek_pos();
+        self.decl_names.clear();
+        let (basety, storage) = match self.decl_spec() {
             Some(t) => t,
             None => self.error("Expected type specifier"),
         };
+        let is_extern = match storage.as_deref() {
+            Some("extern") => true,
+            Some(s) => self.error(&format!("Storage class '{}' at file scope", s)),
+            None => false,
+        };
 
         if self.iter.consume(";") {
-            if !basety.is_struct() {
+            // Declaring a tag or enum constants is all it takes
+            if !basety.is_struct() && !basety.is_enum() {
                 self.warn("This is a useless empty declaration.");
             }
             // TODO: Clean this up
             return None;
         }
 
+        let decl_lo = self.iter.peek_pos();
         let (name, ty) = self.declarator(basety.clone());
+        let decl_span = self.iter.span_from(decl_lo);
         if self.iter.consume("{") {
-            self.funcdef(name, ty)
+            let node = self.funcdef(name, ty, decl_span);
+            node.map(|n| n.span(self.iter.span_from(lo)))
         } else {
-            self.global_declaration(name, ty, basety);
+            self.global_declaration(name, ty, basety, is_extern, decl_span);
             None
         }
     }
 
     // decl = decl_spec (init_decl ("," init_decl)*)? ";"
     // init_decl = declarator ("=" initializer)?
-    // TODO: Support global var init
-    fn global_declaration(&mut self, name: String, ty: Type, basety: Type) {
+    fn global_declaration(
+        &mut self,
+        name: String,
+        ty: Type,
+        basety: Type,
+        is_extern: bool,
+        span: Span,
+    ) {
         // Pick up from the first declarator
-        let mut _inits: LinkedList<Node> = LinkedList::new();
-
         if ty.is_function() {
-            self.env.add_prototype(name, ty);
+            self.add_prototype(name, ty, span);
         } else {
-            let var = self.env.scopes.add_var(name, ty);
-            if self.iter.consume("=") {
-                _inits.append(&mut self.initializer(var));
-            }
+            self.global_var(name, ty, is_extern);
         }
 
         while self.iter.consume(",") {
+            let lo = self.iter.peek_pos();
             let (name, ty) = self.declarator(basety.clone());
 
             if ty.is_function() {
-                self.env.add_prototype(name, ty);
+                self.add_prototype(name, ty, self.iter.span_from(lo));
             } else {
-                let var = self.env.scopes.add_var(name, ty);
-                if self.iter.consume("=") {
-                    _inits.append(&mut self.initializer(var));
-                }
+                self.global_var(name, ty, is_extern);
             }
         }
         self.iter.expect(";");
     }
 
+    // An initializer makes even an extern declaration a definition
+    fn global_var(&mut self, name: String, ty: Type, is_extern: bool) {
+        if !self.iter.consume("=") {
+            self.add_global(name, ty, is_extern);
+            return;
+        }
+        if self.global_inits.iter().any(|init| init.name == name) {
+            self.error(&format!("Redefinition of '{}'", name));
+        }
+        // An array of unknown size is completed by its initializer before
+        // it is merged with earlier declarations
+        let var = if ty.is_array() && ty.is_incomplete() {
+            let (ty, leaves) = self.initializer_leaves(&ty);
+            let var = self.add_global(name, ty, false);
+            self.global_initializer(&var, leaves);
+            var
+        } else {
+            let var = self.add_global(name, ty, false);
+            if var.ty.is_void() {
+                let msg = format!("Initializing '{}' of incomplete type", var.name);
+                self.error_at(var.span, &msg);
+            }
+            let (_, leaves) = self.initializer_leaves(&var.ty);
+            self.global_initializer(&var, leaves);
+            var
+        };
+        if var.ty.is_incomplete() {
+            self.error(&format!("Initializing '{}' of incomplete type", var.name));
+        }
+    }
+
+    fn add_global(&mut self, name: String, ty: Type, is_extern: bool) -> Var {
+        let span = self.name_span(&name);
+        match self.env.add_global(name, ty, is_extern, span) {
+            Ok(var) => {
+                self.declare_ref(&var.name, RefKind::Var, &var.ty, span);
+                var
+            }
+            Err(msg) => self.error(&msg),
+        }
+    }
+
+    fn add_var(&mut self, name: String, ty: Type) -> Var {
+        let span = self.name_span(&name);
+        match self.env.scopes.add_var(name, ty, span) {
+            Ok(var) => {
+                self.declare_ref(&var.name, RefKind::Var, &var.ty, span);
+                var
+            }
+            Err(msg) => self.error(&msg),
+        }
+    }
+
+    fn add_prototype(&mut self, name: String, ty: Type, span: Span) {
+        let name_span = self.name_span(&name);
+        self.declare_ref(&name, RefKind::Func, &ty, name_span);
+        if let Err(msg) = self.env.add_prototype(name, ty, span) {
+            self.error(&msg);
+        }
+    }
+
+    // Span of the name in the declarator that declared it last
+    fn name_span(&self, name: &str) -> Span {
+        self.decl_names
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map_or(Span::default(), |&(_, span)| span)
+    }
+
+    // Records a declaration in the current scope
+    fn declare_ref(&mut self, name: &str, kind: RefKind, ty: &Type, span: Span) {
+        if name.is_empty() || span == Span::default() {
+            return;
+        }
+        self.refs.push(Ref {
+            name: name.to_string(),
+            kind,
+            ty: ty.clone(),
+            span,
+            decl: span,
+            scope: self.env.scopes.level(),
+        });
+    }
+
+    // Records a use of the latest declaration that is_decl accepts
+    fn use_ref(&mut self, span: Span, ty: &Type, is_decl: impl Fn(&Ref) -> bool) {
+        let found = self
+            .refs
+            .iter()
+            .rev()
+            .find(|r| r.span == r.decl && is_decl(r));
+        if let Some(decl) = found {
+            let r = Ref {
+                span,
+                ty: ty.clone(),
+                ..decl.clone()
+            };
+            self.refs.push(r);
+        }
+    }
+
     // decl = decl_spec (init_decl ("," init_decl)*)? ";"
     // init_decl = declarator ("=" initializer)?
     fn local_declaration(&mut self) -> Option<Node> {
-        let basety = match self.decl_spec() {
+        self.decl_names.clear();
+        let (basety, storage) = match self.decl_spec() {
             Some(t) => t,
             None => {
                 return None;
             }
         };
+        // auto and register make no difference here
+        let is_extern = storage.as_deref() == Some("extern");
 
+        let mut vars: LinkedList<Var> = LinkedList::new();
         let mut inits: LinkedList<Node> = LinkedList::new();
 
         if self.iter.consume(";") {
-            if !basety.is_struct() {
+            if !basety.is_struct() && !basety.is_enum() {
                 self.warn("This is a useless empty declaration.");
             }
-            return Some(Node::new_decl(inits));
+            return Some(Node::new_decl(vars, inits));
         }
 
         loop {
+            let lo = self.iter.peek_pos();
             let (name, ty) = self.declarator(basety.clone());
-            let var = self.env.scopes.add_var(name, ty);
-            if self.iter.consume("=") {
-                inits.append(&mut self.initializer(var));
+            if ty.is_function() {
+                self.add_prototype(name, ty, self.iter.span_from(lo));
+                if !self.iter.consume(",") {
+                    break;
+                }
+                continue;
+            }
+            if is_extern {
+                if self.iter.peek_is(0, "=") {
+                    self.error(&format!("'{}' has both 'extern' and initializer", name));
+                }
+                vars.push_back(self.add_global(name, ty, true));
+            } else if ty.is_array() && ty.is_incomplete() {
+                if !self.iter.consume("=") {
+                    self.error(&format!("Array size missing in '{}'", name));
+                }
+                let (ty, leaves) = self.initializer_leaves(&ty);
+                let var = self.add_var(name, ty);
+                vars.push_back(var.clone());
+                inits.append(&mut self.initializer(&var, leaves));
+            } else {
+                if ty.is_incomplete() || ty.is_void() {
+                    self.error(&format!(
+                        "Variable '{}' has the incomplete type '{}'",
+                        name, ty
+                    ));
+                }
+                let var = self.add_var(name, ty);
+                vars.push_back(var.clone());
+                if self.iter.consume("=") {
+                    let (_, leaves) = self.initializer_leaves(&var.ty);
+                    inits.append(&mut self.initializer(&var, leaves));
+                }
             }
             if !self.iter.consume(",") {
                 break;
             }
         }
         self.iter.expect(";");
-        Some(Node::new_decl(inits))
+        Some(Node::new_decl(vars, inits))
     }
 
     // decl_spec = (storage-class-spec | type-spec | type-qual)*
-    fn decl_spec(&mut self) -> Option<Type> {
+    fn decl_spec(&mut self) -> Option<(Type, Option<String>)> {
         self.storage_typespec_typequal(true)
     }
 
     // spec_qual = (type-spec | type-qual)*
     fn spec_qual(&mut self) -> Option<Type> {
-        self.storage_typespec_typequal(false)
+        self.storage_typespec_typequal(false).map(|(ty, _)| ty)
     }
 
     // Reads a storage class, type specifiers, and type qualifiers
-    fn storage_typespec_typequal(&mut self, allow_storage: bool) -> Option<Type> {
+    fn storage_typespec_typequal(&mut self, allow_storage: bool) -> Option<(Type, Option<String>)> {
         let mut maybe_ty: Option<Type> = None;
         let mut ty_config = TypeConfig::new();
+        let mut storage: Option<String> = None;
 
         let mut is_const = false;
         let mut is_volatile = false;
@@ -158,13 +521,30 @@ impl Parser {
         let mut no_token_read = true;
 
         loop {
+            if allow_storage {
+                if let Some(scstr) = self.iter.consume_storage_class() {
+                    if storage.is_some() {
+                        self.error("Multiple storage classes in declaration specifiers");
+                    }
+                    if scstr == "static" || scstr == "typedef" {
+                        self.error(&format!("Storage class '{}' is not supported yet", scstr));
+                    }
+                    storage = Some(scstr);
+                    no_token_read = false;
+                    continue;
+                }
+            }
+
             if let Some(tystr) = self.iter.consume_type() {
-                if !maybe_ty.is_none() {
+                if maybe_ty.is_some() {
                     self.error("Trying to add an additional type to enum/string.")
                 }
                 match tystr.as_str() {
                     "struct" => {
-                        maybe_ty = Some(self.struct_spec());
+                        maybe_ty = Some(self.struct_spec(false));
+                    }
+                    "union" => {
+                        maybe_ty = Some(self.struct_spec(true));
                     }
                     "enum" => {
                         maybe_ty = Some(self.enum_spec());
@@ -209,7 +589,7 @@ impl Parser {
             }
         };
         ty.set_type_qual(is_const, is_volatile);
-        Some(ty)
+        Some((ty, storage))
     }
 
     // Assumes type "enum" has already been read
@@ -217,6 +597,7 @@ impl Parser {
     //           | "enum" ident
     fn enum_spec(&mut self) -> Type {
         let maybe_name: Option<String> = self.iter.consume_ident();
+        let name_span = self.iter.last_span();
         let mut maybe_ty: Option<Type> = None;
 
         if self.iter.consume("{") {
@@ -225,15 +606,19 @@ impl Parser {
 
             loop {
                 let name = self.iter.expect_ident();
+                let span = self.iter.last_span();
                 if self.iter.consume("=") {
-                    // TODO: Replace with constexpr
-                    val = self.iter.expect_number();
+                    let v = self.constant_expr();
+                    if v != v as i32 as i64 {
+                        self.error("Enumerator value is out of range of int");
+                    }
+                    val = v as i32;
                 }
-                let ec = EnumMember {
-                    name: name,
-                    val: val,
-                };
-                self.env.scopes.add_const(ec.clone());
+                let ec = EnumMember { name, val };
+                if let Err(msg) = self.env.scopes.add_const(ec.clone(), span) {
+                    self.error(&msg);
+                }
+                self.declare_ref(&ec.name, RefKind::Const(val), &Type::new_base("int"), span);
                 members.push(ec);
                 val += 1;
                 if self.iter.consume("}") {
@@ -242,24 +627,27 @@ impl Parser {
                 self.iter.expect(",")
             }
 
-            maybe_ty = Some(Type::new_enum(members));
+            maybe_ty = Some(self.env.add_enum(maybe_name.clone(), members));
         }
 
         match (maybe_name, maybe_ty) {
             (Some(name), Some(ty)) => {
-                self.env.scopes.add_tag(name.clone(), ty.clone());
+                self.env.scopes.add_tag(name.clone(), ty.clone(), name_span);
+                self.declare_ref(&name, RefKind::Tag, &ty, name_span);
                 ty
             }
             (Some(name), None) => {
-                if let Some(found_tag) = self.env.scopes.find_tag(name.as_str()) {
+                if let Some(found_tag) = self.env.scopes.find_tag(name.as_str()).cloned() {
                     if !found_tag.ty.is_enum() {
                         self.error("This tag is not defined as enum.")
                     }
-                    found_tag.ty.clone()
+                    self.use_ref(name_span, &found_tag.ty, |r| r.decl == found_tag.span);
+                    found_tag.ty
                 } else {
                     // Define an incomplete enum
                     let ty = Type::new_incomplete(IncompleteKind::ENUM);
-                    self.env.scopes.add_tag(name.clone(), ty.clone());
+                    self.env.scopes.add_tag(name.clone(), ty.clone(), name_span);
+                    self.declare_ref(&name, RefKind::Tag, &ty, name_span);
                     ty
                 }
             }
@@ -270,76 +658,144 @@ impl Parser {
         }
     }
 
-    // Assumes type "struct" has already been read
+    // Assumes type "struct" or "union" has already been read
     // struct-or-union-specifier
     //      = struct-or-union ident? "{" (struct-decl ";")+ "}"
     //      | struct-or-union ident
-    fn struct_spec(&mut self) -> Type {
+    fn struct_spec(&mut self, is_union: bool) -> Type {
         let maybe_name: Option<String> = self.iter.consume_ident();
-        let mut maybe_ty: Option<Type> = None;
+        let name_span = self.iter.last_span();
 
         if self.iter.consume("{") {
-            if let Some(ref name) = maybe_name {
-                // Add itself as an incomplete type
-                let incomplete = Type::new_incomplete(IncompleteKind::STRUCT);
-                self.env.scopes.add_tag(name.clone(), incomplete);
-            }
+            // The tag is visible, though incomplete, within its own body
+            let def = match maybe_name {
+                Some(ref name) => self.struct_tag_for_body(name, is_union, name_span),
+                None => self.env.add_struct(None, is_union),
+            };
             // C89 6.5.2.1 stipulates that an empty struct-decl shall
             // result in undefined behavior, so I'm just going to enforce
             // 1+ members here.
             let mut size = 0;
             let mut members: Vec<StructMember> = Vec::new();
+            // Offset and bits used of the unit adjacent bit-fields are packed into
+            let mut unit: Option<(usize, usize)> = None;
             loop {
                 let mut decls = self.struct_declaration();
-                while let Some((name, ty)) = decls.pop_front() {
-                    let mysize = ty.total_size();
+                while let Some((name, ty, width)) = decls.pop_front() {
+                    if ty.is_incomplete() || ty.is_void() || ty.is_function() {
+                        let msg = format!("Member '{}' has the incomplete type '{}'", name, ty);
+                        self.error(&msg);
+                    }
+                    let (offset, bits) = match width {
+                        // Members of a union overlap
+                        _ if is_union => {
+                            size = size.max(ty.total_size());
+                            (0, width.map(|width| (0, width)))
+                        }
+                        None => {
+                            unit = None;
+                            let offset = size;
+                            size += ty.total_size();
+                            (offset, None)
+                        }
+                        Some(0) => {
+                            // Closes the current unit
+                            unit = None;
+                            (size, Some((0, 0)))
+                        }
+                        Some(width) => {
+                            let unit_bits = ty.size() * 8;
+                            let (offset, used) = match unit {
+                                Some((offset, used)) if used + width <= unit_bits => (offset, used),
+                                _ => {
+                                    let offset = size;
+                                    size += ty.size();
+                                    (offset, 0)
+                                }
+                            };
+                            unit = Some((offset, used + width));
+                            (offset, Some((used, width)))
+                        }
+                    };
                     members.push(StructMember {
-                        name: name,
-                        ty: ty,
-                        offset: size,
+                        name,
+                        ty,
+                        offset,
+                        bits,
                     });
-                    size += mysize;
                 }
                 if self.iter.consume("}") {
                     break;
                 }
             }
-            maybe_ty = Some(Type::new_struct(size, members));
+            def.borrow_mut().complete(size, members);
+            return Type::new_struct(def);
         }
 
-        match (maybe_name, maybe_ty) {
-            (Some(name), Some(ty)) => {
-                self.env.scopes.update_tag(name.as_str(), ty.clone());
-                ty
-            }
-            (Some(name), None) => {
-                if let Some(found_tag) = self.env.scopes.find_tag(name.as_str()) {
-                    if !found_tag.ty.is_struct() {
-                        self.error("This tag is not defined as struct.")
-                    }
-                    found_tag.ty.clone()
-                } else {
-                    // Define an incomplete struct
-                    let ty = Type::new_incomplete(IncompleteKind::STRUCT);
-                    self.env.scopes.add_tag(name.clone(), ty.clone());
-                    ty
-                }
-            }
-            (None, Some(ty)) => ty,
-            (None, None) => {
-                self.error("Expected identifier or '{'");
+        let name = match maybe_name {
+            Some(name) => name,
+            None => self.error("Expected identifier or '{'"),
+        };
+        if let Some(tag) = self.env.scopes.find_tag(&name).cloned() {
+            self.check_tag_kind(&tag.ty, is_union);
+            self.use_ref(name_span, &tag.ty, |r| r.decl == tag.span);
+            return tag.ty;
+        }
+        // Declare an incomplete struct or union, to be completed later
+        let ty = Type::new_struct(self.env.add_struct(Some(name.clone()), is_union));
+        self.declare_ref(&name, RefKind::Tag, &ty, name_span);
+        self.env.scopes.add_tag(name, ty.clone(), name_span);
+        ty
+    }
+
+    // A body completes the tag if it was declared in this scope, and
+    // otherwise introduces a new one that hides any outer tag
+    fn struct_tag_for_body(&mut self, name: &str, is_union: bool, span: Span) -> StructRef {
+        if let Some(found_tag) = self.env.scopes.find_tag_in_scope(name) {
+            let ty = found_tag.ty.clone();
+            self.declare_ref(name, RefKind::Tag, &ty, span);
+            self.check_tag_kind(&ty, is_union);
+            if !ty.is_incomplete() {
+                let keyword = if is_union { "union" } else { "struct" };
+                self.error(&format!("Redefinition of '{} {}'", keyword, name));
             }
+            return ty.struct_def().unwrap().clone();
+        }
+        let def = self.env.add_struct(Some(name.to_string()), is_union);
+        let ty = Type::new_struct(def.clone());
+        self.declare_ref(name, RefKind::Tag, &ty, span);
+        self.env.scopes.add_tag(name.to_string(), ty, span);
+        def
+    }
+
+    fn check_tag_kind(&self, ty: &Type, is_union: bool) {
+        if !ty.is_struct() || ty.is_union() != is_union {
+            self.error(if is_union {
+                "This tag is not defined as union."
+            } else {
+                "This tag is not defined as struct."
+            })
         }
     }
 
-    // struct_declaration = spec_qual declarator ("," declarator)* ";"
-    // TODO: Support bitfield etc
-    fn struct_declaration(&mut self) -> VecDeque<(String, Type)> {
-        let mut decls: VecDeque<(String, Type)> = VecDeque::new();
+    // struct_declaration = spec_qual struct_declarator ("," struct_declarator)* ";"
+    // struct_declarator = declarator | declarator? ":" constexpr
+    // Bit-fields come with their width; unnamed ones have an empty name.
+    fn struct_declaration(&mut self) -> VecDeque<(String, Type, Option<usize>)> {
+        let mut decls: VecDeque<(String, Type, Option<usize>)> = VecDeque::new();
         let base = self.spec_qual().unwrap();
         loop {
-            let (name, ty) = self.declarator(base.clone());
-            decls.push_back((name, ty));
+            let (name, ty) = if self.iter.peek_is(0, ":") {
+                (String::new(), base.clone())
+            } else {
+                self.declarator(base.clone())
+            };
+            let width = if self.iter.consume(":") {
+                Some(self.bit_field_width(&name, &ty))
+            } else {
+                None
+            };
+            decls.push_back((name, ty, width));
             if !self.iter.consume(",") {
                 break;
             }
@@ -349,51 +805,108 @@ impl Parser {
         decls
     }
 
-    // declarator =
-    //      pointer (ident | "(" declarator ")") ("[" num "]" | "(" parameter-type-list? ")")?
+    fn bit_field_width(&mut self, name: &str, ty: &Type) -> usize {
+        if !ty.is_integral() {
+            self.error("Bit-field has a non-integral type");
+        }
+        let width = self.constant_expr();
+        if width < 0 || width as usize > ty.size() * 8 {
+            self.error("Bit-field width is out of range for its type");
+        }
+        if width == 0 && !name.is_empty() {
+            self.error("Named bit-field has zero width");
+        }
+        width as usize
+    }
+
+    // declarator = pointer direct_declarator
+    // direct_declarator = (ident | "(" declarator ")")
+    //      ("[" constant_expr "]" | "(" parameter_type_list? ")")*
     fn declarator(&mut self, basety: Type) -> (String, Type) {
-        let basety = self.pointer(basety);
-        let mut ident_name = "unseen".to_string();
+        match self.any_declarator(basety) {
+            (Some(name), ty) => (name, ty),
+            (None, _) => self.error("Expected an identifier in the declarator"),
+        }
+    }
 
-        let is_nested = if let Some(name) = self.iter.consume_ident() {
-            ident_name = name;
-            false
-        } else {
-            self.delay_declarator();
-            true
-        };
+    // abstract_declarator = pointer direct_abstract_declarator?
+    // direct_abstract_declarator = ("(" abstract_declarator ")")?
+    //      ("[" constant_expr "]" | "(" parameter_type_list? ")")*
+    fn abstract_declarator(&mut self, basety: Type) -> Type {
+        match self.any_declarator(basety) {
+            (None, ty) => ty,
+            (Some(_), _) => self.error("Unexpected identifier in a type name"),
+        }
+    }
 
-        let var_type = self.recurse_array_func(basety);
+    // Either kind of declarator, which parameters may use
+    fn any_declarator(&mut self, basety: Type) -> (Option<String>, Type) {
+        let ty = self.pointer(basety);
 
-        match is_nested {
-            true => {
-                self.iter.commit_delay();
-                self.declarator(var_type)
-            }
-            false => (ident_name, var_type),
+        if let Some(name) = self.iter.consume_ident() {
+            self.decl_names.push((name.clone(), self.iter.last_span()));
+            return (Some(name), self.recurse_array_func(ty));
         }
+        if !self.at_nested_declarator() {
+            return (None, self.recurse_array_func(ty));
+        }
+
+        // The suffixes after the parentheses apply to ty before anything
+        // inside them does, so come back for the inner part afterwards
+        let mark = self.iter.delay_mark();
+        self.delay_declarator();
+        let ty = self.recurse_array_func(ty);
+        self.iter.commit_delay(mark);
+        self.any_declarator(ty)
+    }
+
+    // Whether "(" opens a nested declarator rather than a parameter list
+    fn at_nested_declarator(&self) -> bool {
+        self.iter.peek_is(0, "(")
+            && (self.iter.peek_is(1, "*")
+                || self.iter.peek_is(1, "(")
+                || self.iter.peek_is(1, "[")
+                || self.iter.peek_is_ident(1))
     }
 
     fn recurse_array_func(&mut self, basety: Type) -> Type {
         if self.iter.consume("[") {
-            let array_size = self.iter.expect_number() as usize;
+            // The size may be left out, to be completed later
+            let array_size = if self.iter.peek_is(0, "]") {
+                None
+            } else {
+                Some(self.constant_expr())
+            };
+            if matches!(array_size, Some(n) if n < 0) {
+                self.error("Array size is negative");
+            }
             self.iter.expect("]");
-            return Type::new_array(self.recurse_array_func(basety), array_size);
+            let elem = self.recurse_array_func(basety);
+            if elem.is_incomplete() || elem.is_void() || elem.is_function() {
+                self.error("Array has an incomplete element type");
+            }
+            return match array_size {
+                Some(n) => Type::new_array(elem, n as usize),
+                None => Type::new_incomplete_array(elem),
+            };
         }
         if self.iter.consume("(") {
             // This is a function declarator
-            let args = if self.iter.consume(")") {
-                Vec::new()
+            let (args, variadic) = if self.iter.consume(")") {
+                (Vec::new(), false)
             } else {
                 let tmp = self.parameter_type_list();
                 self.iter.expect(")");
                 tmp
             };
-            let _tmp = self.recurse_array_func(basety);
-            return Type::new_function(args);
+            let ret = self.recurse_array_func(basety);
+            if ret.is_function() || ret.is_array() {
+                self.error("Function returning a function or an array");
+            }
+            return Type::new_function(ret, args, variadic);
         }
 
-        return basety;
+        basety
     }
 
     fn delay_declarator(&mut self) {
@@ -454,11 +967,24 @@ impl Parser {
         ty
     }
 
+    // type_name = spec_qual abstract_declarator
+    fn type_name(&mut self) -> Type {
+        let basety = match self.spec_qual() {
+            Some(t) => t,
+            None => self.error("Expected a type name"),
+        };
+        self.abstract_declarator(basety)
+    }
+
+    // Whether a parenthesized type name follows, as in casts and sizeof
+    fn at_paren_type_name(&self) -> bool {
+        self.iter.peek_is(0, "(") && self.iter.starts_type_name(1)
+    }
+
     // parameter-type-list
     //      = parameter-declaration ("," parameter-declaration)* ("," ...)?
-    // TODO Support variadic fnct
     // TODO Stop if the first elem it sees is void
-    fn parameter_type_list(&mut self) -> Vec<(String, Type)> {
+    fn parameter_type_list(&mut self) -> (Vec<(String, Type)>, bool) {
         let mut argtypes: Vec<(String, Type)> = Vec::new();
 
         loop {
@@ -466,210 +992,404 @@ impl Parser {
             argtypes.push((name, ty));
 
             if !self.iter.consume(",") {
-                break;
+                return (argtypes, false);
+            }
+            if self.iter.consume("...") {
+                return (argtypes, true);
             }
         }
-        argtypes
     }
 
-    // decl_spec declarator
+    // decl_spec (declarator | abstract_declarator)
     fn parameter_declaration(&mut self) -> (String, Type) {
         let ty = match self.decl_spec() {
-            Some(t) => t,
-            None => panic!("Parameter declaration expects a declaration specifier."),
+            Some((t, None)) => t,
+            Some((t, Some(s))) if s == "register" => t,
+            Some((_, Some(s))) => self.error(&format!("Storage class '{}' for a parameter", s)),
+            None => self.error("Parameter declaration expects a declaration specifier."),
         };
 
-        self.declarator(ty)
+        let (name, ty) = self.any_declarator(ty);
+        // A parameter of function type is a pointer to the function, and
+        // one of array type a pointer to the first element
+        let ty = if ty.is_function() {
+            ty.new_ptr_to()
+        } else if ty.is_array() {
+            Type::new_ptr(ty.clone_base())
+        } else {
+            ty
+        };
+        (name.unwrap_or_default(), ty)
     }
 
-    // initializer = assign | "{" ( assign "," )* "}"
-    fn initializer(&mut self, var: Var) -> LinkedList<Node> {
-        if var.ty.is_scalar() {
-            let mut inits: LinkedList<Node> = LinkedList::new();
-            let val = self.scalar_initializer(&var.ty);
-            let mut init = self.generate_scalar_init(var.offset.unwrap(), &var.ty, val);
-            init.populate_ty();
-            inits.push_back(init);
-            return inits;
-        }
+    // Assigns the leaves of the initializer to the local var
+    fn initializer(&mut self, var: &Var, leaves: Vec<InitLeaf>) -> LinkedList<Node> {
+        let top = var.offset.unwrap();
+        leaves
+            .into_iter()
+            .map(|leaf| {
+                // Locals grow down from rbp, so the byte at offset b within
+                // the variable is b bytes closer
+                let lhs = match leaf.field {
+                    Some((structty, name)) => Node::new_member(
+                        Node::new_lvar(var.name.clone(), top - leaf.offset, structty),
+                        name,
+                    ),
+                    None => Node::new_lvar(var.name.clone(), top - leaf.offset, leaf.ty),
+                };
+                let mut init = Node::new_init(AssignMode::DEFAULT, lhs, leaf.val, false);
+                init.populate_ty();
+                init
+            })
+            .collect()
+    }
 
-        if var.ty.is_array() {
-            let vals = self.array_initializer(&var.ty);
-            return self.generate_array_inits(var.offset.unwrap(), &var.ty, vals);
+    // Globals are initialized with constants laid out by the assembler
+    fn global_initializer(&mut self, var: &Var, leaves: Vec<InitLeaf>) {
+        let mut vals: Vec<InitVal> = Vec::new();
+        for mut leaf in leaves {
+            let (offset, bits) = match leaf.field {
+                Some((ref structty, ref name)) => {
+                    let member = structty.get_member(name).unwrap();
+                    (leaf.offset + member.offset, member.bits)
+                }
+                None => (leaf.offset, None),
+            };
+            leaf.val.populate_ty();
+            match constexpr::eval(&leaf.val) {
+                Some(ConstVal::Int(_)) => (),
+                // Addresses need a slot wide enough for a relocation
+                Some(ConstVal::Addr(..)) if leaf.ty.size() == 8 && bits.is_none() => (),
+                // Read-only memory is only ever written by the loader
+                _ if self.entry_inits.is_some() && !var.ty.is_readonly() => {
+                    let init = self.global_assign(var, leaf);
+                    self.entry_inits.as_mut().unwrap().push_back(init);
+                    continue;
+                }
+                _ => self.error("Initializer element is not constant"),
+            }
+            vals.push(InitVal {
+                offset,
+                ty: leaf.ty,
+                bits,
+                val: leaf.val,
+            });
         }
-
-        panic!("Not implemented type")
+        self.global_inits.push(GlobalInit {
+            name: var.name.clone(),
+            vals,
+        });
     }
 
-    fn generate_scalar_init(&self, offset: usize, ty: &Type, val: Node) -> Node {
-        let lvar = Node::new_lvar(offset, ty.clone());
-        Node::new_init(AssignMode::DEFAULT, lvar, val, false)
+    // Assigns the leaf of the initializer to the global var, which the REPL
+    // does for an element that is not constant
+    fn global_assign(&mut self, var: &Var, leaf: InitLeaf) -> Node {
+        let lhs = match leaf.field {
+            Some((structty, name)) => Node::new_member(global_at(var, leaf.offset, structty), name),
+            None => global_at(var, leaf.offset, leaf.ty),
+        };
+        let mut init = Node::new_init(AssignMode::DEFAULT, lhs, leaf.val, false);
+        init.populate_ty();
+        init
     }
 
-    fn generate_array_inits(
-        &self,
-        mut offset: usize,
-        ty: &Type,
-        mut vals: LinkedList<Node>,
-    ) -> LinkedList<Node> {
-        let mut inits: LinkedList<Node> = LinkedList::new();
-        let terminalty = ty.terminal_as_ref();
+    // initializer = assign | "{" initializer ("," initializer)* ","? "}"
+    // Every scalar an initializer of type ty sets, zeros included. The type
+    // is returned completed, as an array of unknown size takes its size
+    // from the initializer.
+    fn initializer_leaves(&mut self, ty: &Type) -> (Type, Vec<InitLeaf>) {
+        let mut leaves: Vec<InitLeaf> = Vec::new();
+        if self.at_string_initializer(ty) {
+            let ty = self.string_initializer(ty, 0, &mut leaves);
+            return (ty, leaves);
+        }
+        if !ty.is_scalar() && !self.iter.peek_is(0, "{") {
+            if ty.is_array() {
+                self.error("Expected '{' to initialize an array");
+            }
+            self.error("Initializing a struct from an expression is not supported yet");
+        }
+        if ty.is_array() && ty.is_incomplete() {
+            let ty = self.open_array_initializer(ty, &mut leaves);
+            return (ty, leaves);
+        }
+        self.object_initializer(ty, 0, &mut leaves);
+        (ty.clone(), leaves)
+    }
 
-        while let Some(val) = vals.pop_front() {
-            let mut init = self.generate_scalar_init(offset, terminalty, val);
-            init.populate_ty();
-            inits.push_back(init);
-            offset -= ty.terminal_size();
+    // Reads the braced list for an array of unknown size, which gets as
+    // many elements as the list has
+    fn open_array_initializer(&mut self, ty: &Type, leaves: &mut Vec<InitLeaf>) -> Type {
+        let elem = ty.clone_base();
+        let mut num_elems = 0;
+        self.iter.expect("{");
+        while !self.iter.peek_is(0, "}") {
+            self.object_initializer(&elem, num_elems * elem.total_size(), leaves);
+            num_elems += 1;
+            if !self.iter.consume(",") {
+                break;
+            }
+        }
+        self.iter.expect("}");
+        if num_elems == 0 {
+            self.error("Array of unknown size initialized with an empty list");
         }
+        Type::new_array(elem, num_elems)
+    }
 
-        inits
+    // Whether a string literal, optionally in braces, initializes ty
+    fn at_string_initializer(&self, ty: &Type) -> bool {
+        ty.is_array()
+            && ty.clone_base().is_integral()
+            && ty.clone_base().size() == 1
+            && (self.iter.peek_is_str(0) || self.iter.peek_is(0, "{") && self.iter.peek_is_str(1))
     }
 
-    fn scalar_initializer(&mut self, ty: &Type) -> Node {
-        if !ty.is_scalar() {
-            panic!("This is not a scalar type.")
-        }
-        if self.iter.consume("{") {
-            let node = self.scalar_initializer(ty);
-            while self.iter.consume(",") {
-                self.scalar_initializer_ignore(ty); // Ignored
-            }
+    // A char array takes the characters of the string one by one, and the
+    // terminating null character if there is room for it
+    fn string_initializer(&mut self, ty: &Type, offset: usize, leaves: &mut Vec<InitLeaf>) -> Type {
+        let braced = self.iter.consume("{");
+        let literal = self.iter.consume_str().unwrap();
+        if braced {
+            self.iter.consume(",");
             self.iter.expect("}");
-            node
+        }
+
+        let elem = ty.clone_base();
+        let mut bytes = unescape(&literal);
+        let ty = if ty.is_incomplete() {
+            Type::new_array(elem.clone(), bytes.len() + 1)
         } else {
-            self.assign()
+            ty.clone()
+        };
+        let num_elems = ty.total_size();
+        if bytes.len() > num_elems {
+            self.warn("Initializer-string for an array of chars is too long.");
         }
+        bytes.resize(num_elems, 0);
+        for (i, b) in bytes.into_iter().enumerate() {
+            leaves.push(InitLeaf {
+                offset: offset + i,
+                ty: elem.clone(),
+                field: None,
+                val: Node::new_int(b as i8 as i32),
+            });
+        }
+        ty
     }
 
-    // NOTE: Maybe this can be consolidated with the above method,
-    // but I dont like passing around Options just to communicate whether or not
-    // the values read are to be ignored.
-    fn scalar_initializer_ignore(&mut self, ty: &Type) {
-        if !ty.is_scalar() {
-            panic!("This is not a scalar type.")
-        }
-        if self.iter.consume("{") {
-            self.scalar_initializer_ignore(ty);
-            while self.iter.consume(",") {
-                self.scalar_initializer_ignore(ty);
-            }
+    // Reads the initializer of the object of type ty at offset. Without
+    // braces an aggregate takes as many initializers as it needs from the
+    // enclosing list, as per C89 6.5.7.
+    fn object_initializer(&mut self, ty: &Type, offset: usize, leaves: &mut Vec<InitLeaf>) {
+        if self.at_string_initializer(ty) {
+            self.string_initializer(ty, offset, leaves);
+        } else if ty.is_scalar() {
+            leaves.push(InitLeaf {
+                offset,
+                ty: ty.clone(),
+                field: None,
+                val: self.scalar_initializer(ty),
+            });
+        } else if self.iter.consume("{") {
+            self.aggregate_initializer(ty, offset, leaves, true);
             self.iter.expect("}");
         } else {
-            self.assign();
+            self.aggregate_initializer(ty, offset, leaves, false);
         }
     }
 
-    fn scalar_initialize_with_zero(&mut self, ty: &Type) -> Node {
-        // C89 6.5.7 semantics requires the excess elements to behave
-        // as if they were assigned 0 for arithmetic type and NULL for pointer type
-        Node::new_int(0)
-    }
-
-    fn array_initializer(&mut self, ty: &Type) -> LinkedList<Node> {
-        if !ty.is_array() {
-            panic!("Calling an array initializer on a non-array type.")
+    fn aggregate_initializer(
+        &mut self,
+        ty: &Type,
+        offset: usize,
+        leaves: &mut Vec<InitLeaf>,
+        braced: bool,
+    ) {
+        if ty.is_incomplete() {
+            self.error("Initializing an object of incomplete type");
         }
-
-        self.iter.expect("{");
-        let num_elems = ty.num_elems();
-        let basety = ty.base_as_ref();
-        let mut vals: LinkedList<Node> = LinkedList::new();
-        let mut pos = 0;
-        let mut warned = false;
-
-        loop {
-            if pos >= num_elems {
-                if !warned {
-                    self.warn("Excess elements in initializer for an array will be ignored.");
-                    warned = true;
-                }
-                // Ignored
-                if basety.is_array() {
-                    self.array_initializer_ignore(basety);
-                } else {
-                    self.scalar_initializer_ignore(basety);
-                }
-            } else {
-                if basety.is_array() {
-                    vals.append(&mut self.array_initializer(basety));
-                } else {
-                    vals.push_back(self.scalar_initializer(basety));
+        let subobjects = ty.subobjects();
+        let mut read = 0;
+        for sub in subobjects.iter() {
+            if read > 0 {
+                // A comma before the closing brace only ends the list
+                if !self.iter.peek_is(0, ",") || self.iter.peek_is(1, "}") {
+                    break;
                 }
+                self.iter.expect(",");
             }
-            pos += 1;
-            if !self.iter.consume(",") {
+            if self.iter.peek_is(0, "}") {
                 break;
             }
+            self.subobject_initializer(ty, offset, sub, leaves);
+            read += 1;
+        }
+        // C89 6.5.7 semantics requires the rest to be initialized as if
+        // they were static, i.e. with zeros
+        for sub in subobjects[read..].iter() {
+            self.subobject_zeros(ty, offset, sub, leaves);
         }
 
-        while pos < num_elems {
-            if basety.is_array() {
-                vals.append(&mut self.array_initialize_with_zeros(basety));
-            } else {
-                vals.push_back(self.scalar_initialize_with_zero(basety));
+        if braced {
+            let mut warned = false;
+            while self.iter.peek_is(0, ",") && !self.iter.peek_is(1, "}") {
+                self.iter.expect(",");
+                let lo = self.iter.peek_pos();
+                self.skip_initializer();
+                if !warned {
+                    let kind = if ty.is_array() {
+                        "an array"
+                    } else if ty.is_union() {
+                        "a union"
+                    } else {
+                        "a struct"
+                    };
+                    let msg = format!(
+                        "Excess elements in initializer for {} will be ignored.",
+                        kind
+                    );
+                    self.warn_at(self.iter.span_from(lo), &msg);
+                    warned = true;
+                }
             }
-            pos += 1
+            self.iter.consume(",");
         }
-        self.iter.expect("}");
-        vals
     }
 
-    // NOTE: See my comment for scalar_initializer_ignore
-    fn array_initializer_ignore(&mut self, ty: &Type) {
-        if !ty.is_array() {
-            panic!("This is not an array type.")
-        }
-        self.iter.expect("{");
-        if self.iter.consume("}") {
-            return;
+    // Bit-fields are set through the struct holding them
+    fn subobject_initializer(
+        &mut self,
+        ty: &Type,
+        offset: usize,
+        sub: &StructMember,
+        leaves: &mut Vec<InitLeaf>,
+    ) {
+        if sub.bits.is_some() {
+            leaves.push(InitLeaf {
+                offset,
+                ty: sub.ty.clone(),
+                field: Some((ty.clone(), sub.name.clone())),
+                val: self.scalar_initializer(&sub.ty),
+            });
+        } else {
+            self.object_initializer(&sub.ty, offset + sub.offset, leaves);
         }
+    }
 
-        let basety = ty.base_as_ref();
-        loop {
-            if basety.is_array() {
-                self.array_initializer_ignore(basety);
-            } else {
-                self.scalar_initializer_ignore(basety);
-            }
-            if !self.iter.consume(",") {
-                break;
+    fn subobject_zeros(
+        &mut self,
+        ty: &Type,
+        offset: usize,
+        sub: &StructMember,
+        leaves: &mut Vec<InitLeaf>,
+    ) {
+        if sub.bits.is_some() {
+            leaves.push(InitLeaf {
+                offset,
+                ty: sub.ty.clone(),
+                field: Some((ty.clone(), sub.name.clone())),
+                val: self.scalar_initialize_with_zero(&sub.ty),
+            });
+        } else if sub.ty.is_scalar() {
+            leaves.push(InitLeaf {
+                offset: offset + sub.offset,
+                ty: sub.ty.clone(),
+                field: None,
+                val: self.scalar_initialize_with_zero(&sub.ty),
+            });
+        } else {
+            for inner in sub.ty.subobjects().iter() {
+                self.subobject_zeros(&sub.ty, offset + sub.offset, inner, leaves);
             }
         }
-        self.iter.expect("}");
     }
 
-    fn array_initialize_with_zeros(&mut self, ty: &Type) -> LinkedList<Node> {
-        let num_elems = ty.num_elems();
-        let basety = ty.base_as_ref();
-        let mut vals: LinkedList<Node> = LinkedList::new();
+    fn scalar_initializer(&mut self, ty: &Type) -> Node {
+        if !ty.is_scalar() {
+            panic!("This is not a scalar type.")
+        }
+        if self.iter.consume("{") {
+            let node = self.scalar_initializer(ty);
+            let mut warned = false;
+            while self.iter.consume(",") {
+                if self.iter.peek_is(0, "}") {
+                    break;
+                }
+                let lo = self.iter.peek_pos();
+                self.skip_initializer(); // Ignored
+                if !warned {
+                    let msg = "Excess elements in a scalar initializer will be ignored.";
+                    self.warn_at(self.iter.span_from(lo), msg);
+                    warned = true;
+                }
+            }
+            self.iter.expect("}");
+            node
+        } else {
+            self.assign()
+        }
+    }
 
-        for _ in 0..num_elems {
-            if basety.is_array() {
-                vals.append(&mut self.array_initialize_with_zeros(basety))
-            } else {
-                vals.push_back(self.scalar_initialize_with_zero(basety))
+    // Reads an initializer whose values are thrown away
+    fn skip_initializer(&mut self) {
+        if self.iter.consume("{") {
+            loop {
+                self.skip_initializer();
+                if !self.iter.consume(",") || self.iter.peek_is(0, "}") {
+                    break;
+                }
             }
+            self.iter.expect("}");
+        } else {
+            self.assign();
         }
-        vals
+    }
+
+    fn scalar_initialize_with_zero(&mut self, _ty: &Type) -> Node {
+        // C89 6.5.7 semantics requires the excess elements to behave
+        // as if they were assigned 0 for arithmetic type and NULL for pointer type
+        Node::new_int(0)
     }
 
     // funcdef = decl_spec declarator "{" stmt* "}"
     // Assumes that everything up to the first "{" has already been read
     // NOTE: K&R style definition is not supported
-    fn funcdef(&mut self, ident_name: String, functy: Type) -> Option<Node> {
+    fn funcdef(&mut self, ident_name: String, functy: Type, span: Span) -> Option<Node> {
         let mut argvars: LinkedList<Var> = LinkedList::new();
         let mut stmts: LinkedList<Node> = LinkedList::new();
 
+        if self.funcdefs.contains(&ident_name) {
+            self.error(&format!("Redefinition of '{}'", ident_name));
+        }
+        self.funcdefs.push(ident_name.clone());
+        // "int f()" defines a function without parameters, which does not
+        // agree with a declaration that has some
+        let declared = self.env.find_prototype(&ident_name);
+        if functy.func_params().is_none()
+            && declared
+                .and_then(|ty| ty.func_params())
+                .is_some_and(|(params, _)| !params.is_empty())
+        {
+            self.error(&format!("Conflicting types for '{}'", ident_name));
+        }
+
         // Create new local scopes:
-        self.env.add_prototype(ident_name.clone(), functy.clone());
-        let mut arg_iter = functy.iter_func_args();
+        self.add_prototype(ident_name.clone(), functy.clone(), span);
+        let arg_iter = functy.iter_func_args();
 
         self.env.scopes.add_scope();
-        while let Some((name, ty)) = arg_iter.next() {
+        for (name, ty) in arg_iter {
             if ty.is_void() {
                 break;
             }
-            let var = self.env.scopes.add_var(name.clone(), ty.clone());
+            if ty.is_incomplete() {
+                self.error(&format!(
+                    "Parameter '{}' has the incomplete type '{}'",
+                    name, ty
+                ));
+            }
+            let var = self.add_var(name.clone(), ty.clone());
             argvars.push_back(var);
         }
 
@@ -696,18 +1416,20 @@ impl Parser {
     //      | jump
     //      | expr? ";"
     fn stmt(&mut self) -> Option<Node> {
-        let node = if let Some(decl) = self.local_declaration() {
-            Some(decl)
+        let lo = self.iter.peek_pos();
+
+        if let Some(decl) = self.local_declaration() {
+            Some(decl.span(self.iter.span_from(lo)))
         } else if let Some(labeled) = self.labeled() {
-            Some(labeled)
+            Some(labeled.span(self.iter.span_from(lo)))
         } else if let Some(compound) = self.compound() {
-            Some(compound)
+            Some(compound.span(self.iter.span_from(lo)))
         } else if let Some(select) = self.select() {
-            Some(select)
+            Some(select.span(self.iter.span_from(lo)))
         } else if let Some(iter) = self.iter() {
-            Some(iter)
+            Some(iter.span(self.iter.span_from(lo)))
         } else if let Some(jump) = self.jump() {
-            Some(jump)
+            Some(jump.span(self.iter.span_from(lo)))
         } else {
             if self.iter.consume(";") {
                 None
@@ -716,26 +1438,33 @@ impl Parser {
                 self.iter.expect(";");
                 Some(node)
             }
-        };
-        node
+        }
     }
 
-    // labeled = "case" num ":" stmt // TODO: constexpr
+    // labeled = "case" constexpr ":" stmt
     //         | "default" ":" stmt
     fn labeled(&mut self) -> Option<Node> {
-        let node = if self.iter.consume("case") {
-            let condval = self.iter.expect_number();
+        if self.iter.consume("case") {
+            let condval = self.constant_expr();
+            if condval != condval as i32 as i64 {
+                self.error("Case label is out of range of int");
+            }
             self.iter.expect(":");
             let stmt = self.stmt();
             Some(Node::new_case(condval as i32, stmt))
         } else if self.iter.consume("default") {
+            if let Some(seen) = self.defaults.last_mut() {
+                if *seen {
+                    self.error("Multiple default labels in one switch");
+                }
+                *seen = true;
+            }
             self.iter.expect(":");
             let stmt = self.stmt();
             Some(Node::new_default(stmt))
         } else {
             None
-        };
-        node
+        }
     }
 
     // compound = "{" stmt* "}"
@@ -778,10 +1507,19 @@ impl Parser {
             self.iter.expect("(");
             let ctrl = self.expr();
             self.iter.expect(")");
+            self.defaults.push(false);
             let stmt = self.stmt();
+            self.defaults.pop();
 
             node = Node::new_switch(ctrl, stmt);
             node.populate_switch();
+            if let NodeKind::NDSWITCH { ref cases, .. } = node.kind {
+                for (i, val) in cases.iter().enumerate() {
+                    if cases.iter().take(i).any(|prev| prev == val) {
+                        self.error(&format!("Duplicate case value {}", val));
+                    }
+                }
+            }
         } else {
             return None;
         }
@@ -862,40 +1600,52 @@ impl Parser {
 
     // assign = conditional (assign_op assign)?
     fn assign(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.conditional();
 
         if let Some(op_str) = self.iter.consume_assign_op() {
             let mode = AssignMode::from_str(&op_str);
-            node = Node::new_assign(mode, node, self.assign(), true);
+            node = Node::new_assign(mode, node, self.assign(), true).span(self.iter.span_from(lo));
             node.populate_ty();
         }
         node
     }
 
+    // constexpr = conditional
+    // Evaluated right away; anything not known at compile time is an error
+    fn constant_expr(&mut self) -> i64 {
+        let mut node = self.conditional();
+        node.populate_ty();
+        match constexpr::eval_int(&node) {
+            Some(val) => val,
+            None => self.error("Expected a constant expression"),
+        }
+    }
+
     // conditional = logical_or ("?" expr ":" conditional)?
     fn conditional(&mut self) -> Node {
-        let node = self.logical_or();
+        let lo = self.iter.peek_pos();
+        let mut node = self.logical_or();
 
         if self.iter.consume("?") {
-            let truenode = Some(self.expr());
+            let then = self.expr();
             self.iter.expect(":");
-            let falsenode = Some(self.conditional());
-            panic!("Not implemented!");
-            //node = Node::new(NDTERNARY, None, None)
-            //    .cond(Some(Box::new(node)))
-            //    .ifnode(truenode)
-            //    .elsenode(falsenode);
+            let els = self.conditional();
+            node = Node::new_cond(node, then, els).span(self.iter.span_from(lo));
+            node.populate_ty();
         }
         node
     }
 
     // logical_or = logical_and ("||" logical_and)*
     fn logical_or(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.logical_and();
 
         loop {
             if self.iter.consume("||") {
-                node = Node::new_binary("||", node, self.logical_and());
+                node =
+                    Node::new_binary("||", node, self.logical_and()).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -905,11 +1655,13 @@ impl Parser {
 
     // logical_and = bitwise_or ("&&" bitwise_or)*
     fn logical_and(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.bitwise_or();
 
         loop {
             if self.iter.consume("&&") {
-                node = Node::new_binary("&&", node, self.bitwise_or());
+                node =
+                    Node::new_binary("&&", node, self.bitwise_or()).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -919,11 +1671,13 @@ impl Parser {
 
     // bitwise_or = bitwise_xor ('|' bitwise_xor)*
     fn bitwise_or(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.bitwise_xor();
 
         loop {
             if self.iter.consume("|") {
-                node = Node::new_binary("|", node, self.bitwise_xor());
+                node =
+                    Node::new_binary("|", node, self.bitwise_xor()).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -933,11 +1687,13 @@ impl Parser {
 
     // bitwise_xor = bitwise_and ('^' bitwise_and)*
     fn bitwise_xor(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.bitwise_and();
 
         loop {
             if self.iter.consume("^") {
-                node = Node::new_binary("^", node, self.bitwise_and());
+                node =
+                    Node::new_binary("^", node, self.bitwise_and()).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -947,11 +1703,12 @@ impl Parser {
 
     // bitwise_and = equality ('&' equality)*
     fn bitwise_and(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.equality();
 
         loop {
             if self.iter.consume("&") {
-                node = Node::new_binary("&", node, self.equality());
+                node = Node::new_binary("&", node, self.equality()).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -961,13 +1718,16 @@ impl Parser {
 
     // equality = relational ("==" relational | "!=" relational)*
     fn equality(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.relational();
 
         loop {
             if self.iter.consume("==") {
-                node = Node::new_binary("==", node, self.relational());
+                node =
+                    Node::new_binary("==", node, self.relational()).span(self.iter.span_from(lo));
             } else if self.iter.consume("!=") {
-                node = Node::new_binary("!=", node, self.relational());
+                node =
+                    Node::new_binary("!=", node, self.relational()).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -977,18 +1737,19 @@ impl Parser {
 
     // relational = shift ("<" shift | "<=" shift | ">" shift | ">=" shift)*
     fn relational(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.shift();
 
         loop {
             if self.iter.consume("<") {
-                node = Node::new_binary("<", node, self.shift());
+                node = Node::new_binary("<", node, self.shift()).span(self.iter.span_from(lo));
             } else if self.iter.consume("<=") {
-                node = Node::new_binary("<=", node, self.shift());
+                node = Node::new_binary("<=", node, self.shift()).span(self.iter.span_from(lo));
             } else if self.iter.consume(">") {
                 // HACK: Simply flip lhs and rhs
-                node = Node::new_binary("<", self.shift(), node);
+                node = Node::new_binary("<", self.shift(), node).span(self.iter.span_from(lo));
             } else if self.iter.consume(">=") {
-                node = Node::new_binary("<=", self.shift(), node);
+                node = Node::new_binary("<=", self.shift(), node).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -998,16 +1759,17 @@ impl Parser {
 
     // shift = add ("<<" add | ">>" add)*
     fn shift(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.add();
 
         loop {
             if self.iter.consume("<<") {
-                node = Node::new_binary("<<", node, self.add());
+                node = Node::new_binary("<<", node, self.add()).span(self.iter.span_from(lo));
             } else if self.iter.consume(">>") {
                 // As per C89 6.3.7, simply performing logical right shift
                 // for both signed and unsigned should be deemed comformant
                 // with the standard... (Correct me if I'm wrong!)
-                node = Node::new_binary(">>", node, self.add());
+                node = Node::new_binary(">>", node, self.add()).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -1017,14 +1779,15 @@ impl Parser {
 
     // add = mul ("+" mul | "-" mul)*
     fn add(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.mul();
 
         loop {
             if self.iter.consume("+") {
-                node = Node::new_binary("+", node, self.mul());
+                node = Node::new_binary("+", node, self.mul()).span(self.iter.span_from(lo));
                 node.populate_ty();
             } else if self.iter.consume("-") {
-                node = Node::new_binary("-", node, self.mul());
+                node = Node::new_binary("-", node, self.mul()).span(self.iter.span_from(lo));
                 node.populate_ty();
             } else {
                 break;
@@ -1034,17 +1797,18 @@ impl Parser {
         node
     }
 
-    // mul = unary ("*" unary | "/" unary | "%" unary)*
+    // mul = cast ("*" cast | "/" cast | "%" cast)*
     fn mul(&mut self) -> Node {
-        let mut node = self.unary();
+        let lo = self.iter.peek_pos();
+        let mut node = self.cast();
 
         loop {
             if self.iter.consume("*") {
-                node = Node::new_binary("*", node, self.unary());
+                node = Node::new_binary("*", node, self.cast()).span(self.iter.span_from(lo));
             } else if self.iter.consume("/") {
-                node = Node::new_binary("/", node, self.unary());
+                node = Node::new_binary("/", node, self.cast()).span(self.iter.span_from(lo));
             } else if self.iter.consume("%") {
-                node = Node::new_binary("%", node, self.unary());
+                node = Node::new_binary("%", node, self.cast()).span(self.iter.span_from(lo));
             } else {
                 break;
             }
@@ -1052,17 +1816,44 @@ impl Parser {
         node
     }
 
+    // cast = "(" type_name ")" cast
+    //      | unary
+    fn cast(&mut self) -> Node {
+        if !self.at_paren_type_name() {
+            return self.unary();
+        }
+        let lo = self.iter.peek_pos();
+        self.iter.expect("(");
+        let ty = self.type_name();
+        self.iter.expect(")");
+        if !ty.is_scalar() && !ty.is_void() {
+            self.error("Cast to a non-scalar type");
+        }
+        let mut operand = self.cast();
+        operand.populate_ty();
+        Node::new_cast(operand, ty).span(self.iter.span_from(lo))
+    }
+
     // unary = "sizeof" unary
+    //       | "sizeof" "(" type_name ")"
     //       | "++" unary
     //       | "--" unary
-    //       | ("+" | "-" | "*" | "&" | "~") unary
+    //       | ("+" | "-" | "*" | "&" | "~") cast
     //       | postfix
     fn unary(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node;
         if self.iter.consume("sizeof") {
-            let mut lhs = self.unary();
-            lhs.populate_ty();
-            node = Node::new_int(lhs.ty.unwrap().total_size() as i32);
+            if self.at_paren_type_name() {
+                self.iter.expect("(");
+                let ty = self.type_name();
+                self.iter.expect(")");
+                node = Node::new_int(self.size_of(&ty) as i32);
+            } else {
+                let mut lhs = self.unary();
+                lhs.populate_ty();
+                node = Node::new_int(self.size_of(lhs.ty.as_ref().unwrap()) as i32);
+            }
         } else if self.iter.consume("++") {
             node = Node::new_assign(AssignMode::ADD, self.unary(), Node::new_int(1), true);
             node.populate_ty();
@@ -1070,21 +1861,28 @@ impl Parser {
             node = Node::new_assign(AssignMode::SUB, self.unary(), Node::new_int(1), true);
             node.populate_ty();
         } else if self.iter.consume("~") {
-            node = Node::new_unary("~", self.unary());
+            node = Node::new_unary("~", self.cast());
         } else if self.iter.consume("*") {
-            node = Node::new_unary("*", self.unary());
+            node = Node::new_unary("*", self.cast());
             node.populate_ty();
         } else if self.iter.consume("&") {
-            node = Node::new_unary("&", self.unary());
+            let operand = self.cast();
+            if let NodeKind::NDMEMBER { bits: Some(_), .. } = operand.kind {
+                self.error("Cannot take the address of a bit-field");
+            }
+            node = Node::new_unary("&", operand);
         } else if self.iter.consume("+") {
-            node = self.unary();
+            node = self.cast();
+            self.check_arith_operand(&mut node, "+", lo);
         } else if self.iter.consume("-") {
-            node = Node::new_binary("-", Node::new_int(0), self.unary());
+            let mut operand = self.cast();
+            self.check_arith_operand(&mut operand, "-", lo);
+            node = Node::new_binary("-", Node::new_int(0), operand);
             node.populate_ty();
         } else {
             node = self.postfix();
         }
-        node
+        node.span(self.iter.span_from(lo))
     }
 
     // postfix =
@@ -1095,43 +1893,68 @@ impl Parser {
     //          | "++"
     //          | "--")*
     fn postfix(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
         let mut node = self.primary();
 
         loop {
             if self.iter.consume("[") {
-                node = Node::new_binary("+", node, self.expr());
-                node = Node::new_unary("*", node);
-                node.populate_ty();
+                let index = self.expr();
                 self.iter.expect("]");
+                node = Node::new_binary("+", node, index).span(self.iter.span_from(lo));
+                node = Node::new_unary("*", node).span(self.iter.span_from(lo));
+                node.populate_ty();
             } else if self.iter.consume("(") {
-                // This is a function call
-                let mut args: LinkedList<Node> = LinkedList::new();
-                if self.iter.consume(")") {
-                    node = Node::new_call(node, args);
-                    continue;
+                // This is a function call, directly or through a pointer
+                node.populate_ty();
+                if node
+                    .ty
+                    .as_ref()
+                    .and_then(|ty| ty.callee_function())
+                    .is_none()
+                {
+                    self.error("Called object is not a function or a function pointer");
                 }
-                loop {
-                    args.push_back(self.expr());
-                    if !self.iter.consume(",") {
-                        break;
+                let mut args: LinkedList<Node> = LinkedList::new();
+                if !self.iter.consume(")") {
+                    loop {
+                        args.push_back(self.expr());
+                        if !self.iter.consume(",") {
+                            break;
+                        }
                     }
+                    self.iter.expect(")");
                 }
-                self.iter.expect(")");
-                node = Node::new_call(node, args)
+                node = Node::new_call(node, args).span(self.iter.span_from(lo));
+                node.populate_ty();
             } else if self.iter.consume(".") {
+                let dot = self.iter.last_span();
+                node.populate_ty();
+                self.accesses.push((dot, node.ty.clone().unwrap()));
                 let ident = self.iter.expect_ident();
-                node = Node::new_member(node, ident);
+                self.check_member(node.ty.as_ref().unwrap(), &ident);
+                node = Node::new_member(node, ident).span(self.iter.span_from(lo));
                 node.populate_ty();
             } else if self.iter.consume("->") {
+                let arrow = self.iter.last_span();
+                node.populate_ty();
+                let ty = node.ty.as_ref().unwrap();
+                if !ty.is_ptr_like() {
+                    self.error("Member reference through a non-pointer");
+                }
+                self.accesses.push((arrow, ty.clone_base()));
                 let ident = self.iter.expect_ident();
-                node = Node::new_member(Node::new_unary("*", node), ident);
+                let ty = node.ty.as_ref().unwrap();
+                self.check_member(&ty.clone_base(), &ident);
+                node = Node::new_member(Node::new_unary("*", node), ident)
+                    .span(self.iter.span_from(lo));
                 node.populate_ty();
             } else if self.iter.consume("++") {
-                node = Node::new_assign(AssignMode::ADD, node, Node::new_int(1), false);
+                node = Node::new_assign(AssignMode::ADD, node, Node::new_int(1), false)
+                    .span(self.iter.span_from(lo));
                 node.populate_ty();
-            // TODO: Check lvalue
             } else if self.iter.consume("--") {
-                node = Node::new_assign(AssignMode::SUB, node, Node::new_int(1), false);
+                node = Node::new_assign(AssignMode::SUB, node, Node::new_int(1), false)
+                    .span(self.iter.span_from(lo));
                 node.populate_ty();
             } else {
                 break;
@@ -1145,24 +1968,33 @@ impl Parser {
     //         | ident
     //         | "(" expr ")"
     fn primary(&mut self) -> Node {
+        let lo = self.iter.peek_pos();
+        let node = self.primary_inner();
+        node.span(self.iter.span_from(lo))
+    }
+
+    fn primary_inner(&mut self) -> Node {
         if self.iter.consume("(") {
             let node = self.expr();
             self.iter.expect(")");
             node
         } else if let Some(ident) = self.iter.consume_ident() {
-            if let Some(var) = self.env.scopes.find_var(&ident) {
+            let span = self.iter.last_span();
+            if let Some(var) = self.env.scopes.find_var(&ident).cloned() {
                 // Variable
-                let node = match var.offset {
-                    Some(offset) => Node::new_lvar(offset, var.ty.clone()),
-                    None => Node::new_gvar(ident, var.ty.clone()),
-                };
-                node
-            } else if let Some(ec) = self.env.scopes.find_const(&ident) {
+                self.use_ref(span, &var.ty, |r| r.decl == var.span);
+                match var.offset {
+                    Some(offset) => Node::new_lvar(ident, offset, var.ty),
+                    None => Node::new_gvar(ident, var.ty),
+                }
+            } else if let Some(ec) = self.env.scopes.find_const(&ident).cloned() {
                 // Enum const
+                self.use_ref(span, &Type::new_base("int"), |r| r.decl == ec.span);
                 Node::new_int(ec.member.val)
-            } else if self.env.is_prototype(&ident) {
+            } else if let Some(ty) = self.env.find_prototype(&ident).cloned() {
                 // Registered as a function prototype
-                Node::new_prototy(ident)
+                self.use_ref(span, &ty, |r| r.kind == RefKind::Func && r.name == ident);
+                Node::new_prototy(ident, ty)
             } else {
                 self.error("Found an undefined identifier.");
             }
@@ -1175,16 +2007,51 @@ impl Parser {
         }
     }
 
-    fn debug(&self, s: &str) {
-        let mut msg = "debug: ".to_string();
-        msg.push_str(s);
-        println!("{}", msg);
+    // Unary + and - take integers, and "-p" is not "0 - p" for a pointer p
+    fn check_arith_operand(&mut self, operand: &mut Node, op: &str, lo: Pos) {
+        operand.populate_ty();
+        if !operand.ty.as_ref().unwrap().is_integral() {
+            let span = self.iter.span_from(lo);
+            self.error_at(span, &format!("Invalid operand to unary {}", op));
+        }
     }
 
-    fn warn(&self, s: &str) {
-        let mut msg = "warning: ".to_string();
-        msg.push_str(s);
-        println!("{}", msg);
+    // The layout of a struct is known only once it is complete
+    fn check_member(&self, ty: &Type, name: &str) {
+        if !ty.is_struct() {
+            self.error(&format!("Member '{}' of a non-struct type '{}'", name, ty));
+        }
+        if ty.is_incomplete() {
+            self.error(&format!("Member access into the incomplete type '{}'", ty));
+        }
+        if ty.get_member(name).is_none() {
+            self.error(&format!("No member named '{}' in '{}'", name, ty));
+        }
+    }
+
+    fn size_of(&self, ty: &Type) -> usize {
+        if ty.is_incomplete() || ty.is_void() {
+            self.error(&format!("Applying sizeof to the incomplete type '{}'", ty));
+        }
+        if ty.is_function() {
+            self.error("Applying sizeof to a function type");
+        }
+        ty.total_size()
+    }
+
+    // Warnings point at the last token read unless told otherwise
+    fn warn(&mut self, s: &str) {
+        self.warn_at(self.iter.last_span(), s);
+    }
+
+    fn warn_at(&mut self, span: Span, s: &str) {
+        self.warnings.push((span, s.to_string()));
+    }
+
+    // Errors found after the fact point at what they are about
+    fn error_at(&mut self, span: Span, s: &str) -> ! {
+        self.error_span = Some(span);
+        self.error(s);
     }
 
     fn error(&self, s: &str) -> ! {
@@ -1193,3 +2060,15 @@ impl Parser {
         panic!("{}", msg);
     }
 }
+
+// The object of type ty at byte offset within the global var, as
+// *(ty *)((char *)&var + offset)
+fn global_at(var: &Var, offset: usize, ty: Type) -> Node {
+    if offset == 0 {
+        return Node::new_gvar(var.name.clone(), ty);
+    }
+    let addr = Node::new_unary("&", Node::new_gvar(var.name.clone(), var.ty.clone()));
+    let bytes = Node::new_cast(addr, Type::new_ptr(Type::new_base("char")));
+    let addr = Node::new_binary("+", bytes, Node::new_int(offset as i32));
+    Node::new_unary("*", Node::new_cast(addr, Type::new_ptr(ty)))
+}
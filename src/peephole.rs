@@ -0,0 +1,838 @@
+// Peephole optimization over the buffered instructions of one function.
+// Rules look at a few neighbouring lines at a time. Generated code never
+// keeps flags alive across a label, which the compare folding relies on.
+use crate::asm::{reg64, reg_named, reg_width, Line, Operand};
+use crate::ir::Width;
+use std::collections::HashSet;
+use std::ops::{Deref, DerefMut};
+
+/// Optimizes lines in place. Returns the index every surviving line had in
+/// the input, so that the caller can place its annotations again.
+pub fn optimize(lines: &mut Vec<Line>) -> Vec<usize> {
+    let mut tracked = Lines {
+        origins: (0..lines.len()).collect(),
+        lines: std::mem::take(lines),
+    };
+    loop {
+        let mut changed = forward_moves(&mut tracked);
+        changed |= fold_operands(&mut tracked);
+        changed |= remove_dead_moves(&mut tracked);
+        changed |= retarget_results(&mut tracked);
+        changed |= remove_unused_saves(&mut tracked);
+        changed |= fold_compares(&mut tracked);
+        changed |= thread_jumps(&mut tracked);
+        changed |= remove_dead_code(&mut tracked);
+        if !changed {
+            break;
+        }
+    }
+    *lines = tracked.lines;
+    tracked.origins
+}
+
+// Lines along with where each came from. Rules only ever replace or remove
+// lines, and removal goes through the methods below to keep both in step.
+struct Lines {
+    lines: Vec<Line>,
+    origins: Vec<usize>,
+}
+
+impl Lines {
+    fn remove(&mut self, i: usize) -> Line {
+        self.origins.remove(i);
+        self.lines.remove(i)
+    }
+
+    fn retain<F: FnMut(&Line) -> bool>(&mut self, mut keep: F) {
+        let lines = std::mem::take(&mut self.lines);
+        let origins = std::mem::take(&mut self.origins);
+        for (line, origin) in lines.into_iter().zip(origins) {
+            if keep(&line) {
+                self.lines.push(line);
+                self.origins.push(origin);
+            }
+        }
+    }
+}
+
+impl Deref for Lines {
+    type Target = Vec<Line>;
+
+    fn deref(&self) -> &Vec<Line> {
+        &self.lines
+    }
+}
+
+impl DerefMut for Lines {
+    fn deref_mut(&mut self) -> &mut Vec<Line> {
+        &mut self.lines
+    }
+}
+
+fn jump_target(line: &Line) -> Option<&str> {
+    match line {
+        Line::Inst(op, args) if op.starts_with('j') => match args.first() {
+            Some(Operand::Label(target)) => Some(target),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Every label a jump may go to, including the entries of a jump table
+fn jump_targets(line: &Line) -> Vec<&str> {
+    match line {
+        Line::Inst("jmp", args) => match args.first() {
+            Some(Operand::Table { targets, .. }) => targets.iter().map(String::as_str).collect(),
+            _ => jump_target(line).into_iter().collect(),
+        },
+        _ => jump_target(line).into_iter().collect(),
+    }
+}
+
+fn is_cond_jump(op: &str) -> bool {
+    op.starts_with('j') && op != "jmp"
+}
+
+// Conditional jump on cc, or on its negation
+fn jcc(cc: &str, negate: bool) -> Option<&'static str> {
+    let (jump, inverse) = match cc {
+        "e" => ("je", "jne"),
+        "ne" => ("jne", "je"),
+        "l" => ("jl", "jge"),
+        "ge" => ("jge", "jl"),
+        "le" => ("jle", "jg"),
+        "g" => ("jg", "jle"),
+        "b" => ("jb", "jae"),
+        "ae" => ("jae", "jb"),
+        "be" => ("jbe", "ja"),
+        "a" => ("ja", "jbe"),
+        _ => return None,
+    };
+    Some(if negate { inverse } else { jump })
+}
+
+fn is_reg64(opnd: &Operand) -> bool {
+    matches!(*opnd, Operand::Reg(name) if reg64(name) == name)
+}
+
+// 64-bit memory access that a move to or from a 64-bit register makes
+fn is_mem64(opnd: &Operand) -> bool {
+    matches!(
+        opnd,
+        Operand::Mem {
+            size: None | Some(Width::I64),
+            ..
+        }
+    )
+}
+
+// Whether writing dst may change the value or the address of opnd
+fn clobbers(dst: &Operand, opnd: &Operand) -> bool {
+    match (dst, opnd) {
+        (Operand::Reg(a), Operand::Reg(b)) => reg64(a) == reg64(b),
+        (Operand::Reg(a), Operand::Mem { base, .. }) => reg64(a) == *base,
+        (Operand::Mem { .. }, Operand::Mem { .. }) => true,
+        _ => false,
+    }
+}
+
+// Whether an instruction overwrites its first operand without reading it
+fn writes_only_first(op: &str) -> bool {
+    matches!(op, "mov" | "movsx" | "movsxd" | "movzb" | "lea")
+}
+
+// Whether the instruction mentions the register, as an operand or address
+fn mentions(args: &[Operand], reg: &str) -> bool {
+    args.iter().any(|arg| match *arg {
+        Operand::Reg(name) => reg64(name) == reg,
+        Operand::Mem { base, .. } => base == reg,
+        Operand::Table { base, index, .. } => index == reg || base == Some(reg),
+        _ => false,
+    })
+}
+
+// Registers tracked by the liveness analysis, one bit each
+static REGS: [&str; 16] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+
+fn bit(reg: &str) -> u32 {
+    REGS.iter().position(|r| *r == reg).map_or(0, |i| 1 << i)
+}
+
+fn bits(regs: &[&str]) -> u32 {
+    regs.iter().fold(0, |acc, r| acc | bit(r))
+}
+
+fn mention_bits(args: &[Operand]) -> u32 {
+    args.iter().fold(0, |acc, arg| match *arg {
+        Operand::Reg(name) => acc | bit(reg64(name)),
+        Operand::Mem { base, .. } => acc | bit(base),
+        Operand::Table { base, index, .. } => acc | bit(index) | base.map_or(0, bit),
+        _ => acc,
+    })
+}
+
+// Registers an instruction reads and registers it overwrites entirely
+fn uses_defs(op: &str, args: &[Operand]) -> (u32, u32) {
+    let caller_saved = bits(&["rax", "rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11"]);
+    match op {
+        // Arguments and the vector register count in al
+        "call" => {
+            let args_regs = bits(&["rdi", "rsi", "rdx", "rcx", "r8", "r9", "rax"]);
+            (mention_bits(args) | args_regs, caller_saved)
+        }
+        "ret" => (
+            bits(&["rax", "rbx", "rbp", "rsp", "r12", "r13", "r14", "r15"]),
+            0,
+        ),
+        "cqo" => (bit("rax"), bit("rdx")),
+        "idiv" => (
+            mention_bits(args) | bits(&["rax", "rdx"]),
+            bits(&["rax", "rdx"]),
+        ),
+        "push" | "pop" => (mention_bits(args) | bit("rsp"), 0),
+        _ if op.starts_with('j') => (mention_bits(args), 0),
+        _ => {
+            // 32-bit writes clear the upper half as well; narrower ones merge
+            let def = match args.first() {
+                Some(&Operand::Reg(name)) if matches!(reg_width(name), Width::I32 | Width::I64) => {
+                    bit(reg64(name))
+                }
+                _ => 0,
+            };
+            let uses = if writes_only_first(op) && def != 0 {
+                mention_bits(&args[1..])
+            } else {
+                mention_bits(args)
+            };
+            (uses, def)
+        }
+    }
+}
+
+/// Registers live after each line
+fn liveness(lines: &[Line]) -> Vec<u32> {
+    let labels: std::collections::HashMap<&str, usize> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| match line {
+            Line::Label(name) => Some((name.as_str(), i)),
+            _ => None,
+        })
+        .collect();
+    let succs: Vec<Vec<usize>> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let mut succs = Vec::new();
+            let falls_through = !matches!(line, Line::Inst("jmp", _) | Line::Inst("ret", _));
+            if falls_through && i + 1 < lines.len() {
+                succs.push(i + 1);
+            }
+            for target in jump_targets(line) {
+                // Jumps out of the function keep everything alive
+                succs.push(*labels.get(target).unwrap_or(&usize::MAX));
+            }
+            succs
+        })
+        .collect();
+
+    let mut live_in = vec![0u32; lines.len()];
+    let mut live_out = vec![0u32; lines.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..lines.len()).rev() {
+            let out = succs[i].iter().fold(0, |acc, &s| {
+                acc | live_in.get(s).copied().unwrap_or(u32::MAX)
+            });
+            let (uses, defs) = match lines[i] {
+                // setcc is only emitted to be zero-extended into rax, so
+                // the rest of rax is never read
+                Line::Inst(op, _) if op.starts_with("set") => (0, bit("rax")),
+                Line::Inst(op, ref args) => uses_defs(op, args),
+                Line::Label(_) => (0, 0),
+            };
+            let inn = uses | (out & !defs);
+            if out != live_out[i] || inn != live_in[i] {
+                live_out[i] = out;
+                live_in[i] = inn;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
+
+// Index of the next instruction after lines[at] that mentions reg, as
+// long as control stays in straight-line code until then
+fn next_use(lines: &[Line], at: usize, reg: &str) -> Option<usize> {
+    for (j, line) in lines.iter().enumerate().skip(at + 1) {
+        match line {
+            Line::Inst(op, _) if is_barrier(op) => return None,
+            Line::Inst(_, args) if mentions(args, reg) => return Some(j),
+            Line::Inst(..) => (),
+            Line::Label(_) => return None,
+        }
+    }
+    None
+}
+
+// Instructions with implicit operands or leaving the straight-line code
+fn is_barrier(op: &str) -> bool {
+    op.starts_with('j') || matches!(op, "call" | "ret" | "cqo" | "idiv" | "push" | "pop")
+}
+
+/// Folds an address or value computed into a scratch register straight into
+/// the one instruction that uses it, e.g. "lea r10, [rbp-4]" followed by
+/// "mov r11, [r10]" becomes "mov r11, [rbp-4]"
+fn fold_operands(lines: &mut Lines) -> bool {
+    let mut changed = false;
+    let mut live_out = liveness(lines);
+    let mut i = 0;
+    while i < lines.len() {
+        let (reg, src) = match lines[i] {
+            Line::Inst("lea", ref args) | Line::Inst("mov", ref args) => match args[0] {
+                Operand::Reg(reg) if reg64(reg) == reg => (reg, args[1].clone()),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            },
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let is_lea = matches!(lines[i], Line::Inst("lea", _));
+        let j = match next_use(lines, i, reg) {
+            Some(j) => j,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let src_bits = mention_bits(std::slice::from_ref(&src));
+        let folded = match (&lines[j], src) {
+            (
+                Line::Inst(op, args),
+                Operand::Mem {
+                    size: None,
+                    base,
+                    disp,
+                },
+                // rbp never changes, so neither does the address
+            ) if is_lea && base == "rbp" => fold_address(reg, base, disp, op, args),
+            // Constants stay valid however far the use is
+            (Line::Inst(op, args), Operand::Imm(val)) if !is_lea => fold_imm(reg, val, op, args)
+                .or_else(|| match *op {
+                    "mov" => fold_move(reg, &Operand::Imm(val), args),
+                    _ => None,
+                }),
+            // As does a register nothing in between mentions
+            (Line::Inst(op, args), Operand::Reg(src))
+                if !is_lea && !lines[i + 1..j].iter().any(|l| line_mentions(l, reg64(src))) =>
+            {
+                fold_copy(reg, src, op, args)
+            }
+            (Line::Inst("mov", args), src) if !is_lea && j == i + 1 => fold_move(reg, &src, args),
+            _ => None,
+        };
+        match folded {
+            Some(line) if live_out[j] & bit(reg) == 0 || overwrites(&line, reg) => {
+                lines[j] = line;
+                lines.remove(i);
+                // The registers src reads now stay live until the use. This
+                // only grows the sets, which keeps later folds safe without
+                // redoing the analysis.
+                live_out.remove(i);
+                for out in &mut live_out[i..j - 1] {
+                    *out |= src_bits;
+                }
+                changed = true;
+            }
+            _ => i += 1,
+        }
+    }
+    changed
+}
+
+fn line_mentions(line: &Line, reg: &str) -> bool {
+    match line {
+        Line::Inst(_, args) => mentions(args, reg),
+        Line::Label(_) => true,
+    }
+}
+
+// Reads reg's copy of src in place of reg
+fn fold_copy(
+    reg: &'static str,
+    src: &'static str,
+    op: &'static str,
+    args: &[Operand],
+) -> Option<Line> {
+    if reg64(src) != src || src == "rsp" || src == "rbp" {
+        return None;
+    }
+    let mut args = args.to_vec();
+    for (k, arg) in args.iter_mut().enumerate() {
+        match *arg {
+            Operand::Reg(name) if reg64(name) == reg => {
+                // A destination, or a shift count which must stay in cl
+                if k == 0 || matches!(op, "shl" | "shr" | "sar") {
+                    return None;
+                }
+                *arg = Operand::Reg(reg_named(src, reg_width(name)));
+            }
+            Operand::Mem { size, base, disp } if base == reg => {
+                *arg = Operand::mem(size, src, disp);
+            }
+            _ => (),
+        }
+    }
+    Some(Line::Inst(op, args))
+}
+
+/// Removes moves into registers that are never read afterwards. Only
+/// instructions that leave the flags alone qualify.
+fn remove_dead_moves(lines: &mut Lines) -> bool {
+    let live_out = liveness(lines);
+    let before = lines.len();
+    let mut i = 0;
+    lines.retain(|line| {
+        let dead = match line {
+            Line::Inst(op, args) if writes_only_first(op) || op.starts_with("set") => {
+                matches!(args[0], Operand::Reg(name) if live_out[i] & bit(reg64(name)) == 0)
+            }
+            _ => false,
+        };
+        i += 1;
+        !dead
+    });
+    lines.len() != before
+}
+
+/// Computes a result straight into the register it is then copied to,
+/// e.g. "movsxd r11, [rbp-4]; mov rbx, r11" becomes "movsxd rbx, [rbp-4]"
+fn retarget_results(lines: &mut [Line]) -> bool {
+    let mut changed = false;
+    let mut live_out = liveness(lines);
+    for i in 1..lines.len() {
+        let (dst, tmp) = match lines[i] {
+            Line::Inst("mov", ref args) if is_reg64(&args[0]) && is_reg64(&args[1]) => {
+                match (&args[0], &args[1]) {
+                    (&Operand::Reg(dst), &Operand::Reg(tmp)) => (dst, tmp),
+                    _ => unreachable!(),
+                }
+            }
+            _ => continue,
+        };
+        if dst == tmp || live_out[i] & bit(tmp) != 0 {
+            continue;
+        }
+        let retargeted = match lines[i - 1] {
+            Line::Inst(op, ref args)
+                if writes_only_first(op)
+                    && args[0] == Operand::Reg(tmp)
+                    && !mentions(&args[1..], dst) =>
+            {
+                let mut args = args.clone();
+                args[0] = Operand::Reg(dst);
+                Line::Inst(op, args)
+            }
+            _ => continue,
+        };
+        lines[i - 1] = retargeted;
+        lines[i] = Line::Inst("mov", vec![Operand::Reg(dst), Operand::Reg(dst)]);
+        live_out[i - 1] |= bit(dst);
+        changed = true;
+    }
+    changed
+}
+
+/// Drops the prologue save and epilogue restores of a callee-saved
+/// register the rest of the function no longer touches
+fn remove_unused_saves(lines: &mut Lines) -> bool {
+    let mut changed = false;
+    for reg in ["rbx", "r12", "r13", "r14", "r15"] {
+        // The prologue save is the first line to mention the register
+        let slot = lines.iter().find_map(|line| match line {
+            Line::Inst(_, args) if mentions(args, reg) => Some(args),
+            _ => None,
+        });
+        let slot = match slot.map(|args| (&args[0], &args[1])) {
+            Some((slot @ Operand::Mem { base: "rbp", .. }, &Operand::Reg(r))) if r == reg => {
+                slot.clone()
+            }
+            _ => continue,
+        };
+        // Spills of the register go through other slots and keep it
+        let only_saved = lines.iter().all(|line| match line {
+            Line::Inst("mov", args) if mentions(args, reg) => matches!(
+                (&args[0], &args[1]),
+                (mem, &Operand::Reg(r)) | (&Operand::Reg(r), mem) if r == reg && *mem == slot
+            ),
+            Line::Inst(_, args) => !mentions(args, reg),
+            Line::Label(_) => true,
+        });
+        let before = lines.len();
+        if only_saved {
+            lines.retain(|line| !line_mentions(line, reg) || matches!(line, Line::Label(_)));
+        }
+        changed |= lines.len() != before;
+    }
+    changed
+}
+
+// Whether line itself sets reg without depending on its old value
+fn overwrites(line: &Line, reg: &str) -> bool {
+    match line {
+        Line::Inst(op, args) if writes_only_first(op) => {
+            matches!(args[0], Operand::Reg(name) if name == reg) && !mentions(&args[1..], reg)
+        }
+        _ => false,
+    }
+}
+
+fn fold_address(
+    reg: &'static str,
+    base: &'static str,
+    disp: usize,
+    op: &'static str,
+    args: &[Operand],
+) -> Option<Line> {
+    if !matches!(op, "mov" | "movsx" | "movsxd") {
+        return None;
+    }
+    let mut uses = 0;
+    let mut args = args.to_vec();
+    for (i, arg) in args.iter_mut().enumerate() {
+        match *arg {
+            Operand::Mem {
+                size,
+                base: b,
+                disp: 0,
+            } if b == reg => {
+                *arg = Operand::mem(size, base, disp);
+                uses += 1;
+            }
+            Operand::Reg(name) if reg64(name) == reg && !(i == 0 && name == reg) => return None,
+            _ => (),
+        }
+    }
+    if uses == 0 {
+        return None;
+    }
+    Some(Line::Inst(op, args))
+}
+
+fn fold_imm(reg: &'static str, val: i64, op: &'static str, args: &[Operand]) -> Option<Line> {
+    // A store through a sub-register keeps the low bits
+    if let (
+        "mov",
+        Operand::Mem {
+            size: None,
+            base,
+            disp,
+        },
+        Operand::Reg(sub),
+    ) = (op, &args[0], &args[1])
+    {
+        if reg64(sub) != reg || *base == reg || *sub == reg {
+            return None;
+        }
+        let width = reg_width(sub);
+        let val = match width {
+            Width::I8 => val as i8 as i64,
+            Width::I16 => val as i16 as i64,
+            _ => val as i32 as i64,
+        };
+        let dst = Operand::mem(Some(width), base, *disp);
+        return Some(Line::Inst("mov", vec![dst, Operand::Imm(val)]));
+    }
+
+    let foldable = matches!(op, "add" | "sub" | "and" | "or" | "xor" | "cmp" | "imul");
+    // Instructions only take 32-bit immediates besides the destination
+    if !foldable || val != val as i32 as i64 || args[1] != Operand::Reg(reg) {
+        return None;
+    }
+    if mentions(&args[..1], reg) {
+        return None;
+    }
+    Some(Line::Inst(op, vec![args[0].clone(), Operand::Imm(val)]))
+}
+
+// "mov reg, src" followed by "mov dst, reg" as a single move
+fn fold_move(reg: &'static str, src: &Operand, args: &[Operand]) -> Option<Line> {
+    let dst = &args[0];
+    if args[1] != Operand::Reg(reg) || mentions(&args[..1], reg) {
+        return None;
+    }
+    let to_mem = is_mem64(dst);
+    if !to_mem && !is_reg64(dst) {
+        return None;
+    }
+    let ok = match *src {
+        Operand::Reg(_) => is_reg64(src),
+        Operand::Mem { .. } => is_mem64(src) && !to_mem,
+        Operand::Imm(val) => !to_mem || val == val as i32 as i64,
+        Operand::Offset(_) | Operand::Got(_) => !to_mem,
+        Operand::Label(_) | Operand::Rip(_) | Operand::Table { .. } => false,
+    };
+    if !ok {
+        return None;
+    }
+    let dst = match (dst, src) {
+        // Nothing else implies the size of the store any more
+        (&Operand::Mem { base, disp, .. }, Operand::Imm(_)) => {
+            Operand::mem(Some(Width::I64), base, disp)
+        }
+        _ => dst.clone(),
+    };
+    Some(Line::Inst("mov", vec![dst, src.clone()]))
+}
+
+/// Removes moves of a value into where it already is and reuses a register
+/// instead of reloading memory it was just stored to or loaded from
+fn forward_moves(lines: &mut Lines) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < lines.len() {
+        if let Line::Inst("mov", ref args) = lines[i] {
+            if args[0] == args[1] {
+                lines.remove(i);
+                changed = true;
+                continue;
+            }
+        }
+        if i == 0 {
+            i += 1;
+            continue;
+        }
+        let rewrite = match (&lines[i - 1], &lines[i]) {
+            (Line::Inst("mov", prev), Line::Inst("mov", cur)) => {
+                // The register and memory the previous move connected
+                let pair = if is_mem64(&prev[0]) && is_reg64(&prev[1]) {
+                    Some((&prev[1], &prev[0]))
+                } else if is_reg64(&prev[0]) && is_mem64(&prev[1]) && !clobbers(&prev[0], &prev[1])
+                {
+                    Some((&prev[0], &prev[1]))
+                } else {
+                    None
+                };
+                match pair {
+                    // Reload of the same memory
+                    Some((reg, mem)) if cur[1] == *mem && is_reg64(&cur[0]) => {
+                        Some(Line::Inst("mov", vec![cur[0].clone(), reg.clone()]))
+                    }
+                    // Storing back what was just moved between them
+                    Some((reg, mem)) if cur[0] == *mem && cur[1] == *reg => {
+                        Some(Line::Inst("mov", vec![reg.clone(), reg.clone()]))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some(line) = rewrite {
+            lines[i] = line;
+            changed = true;
+            continue;
+        }
+        i += 1;
+    }
+    changed
+}
+
+enum FlagSource {
+    Zero,              // ZF already tells whether the value is zero
+    Set(&'static str), // The value is the result of a setcc
+}
+
+// What set the value compared against 0 at lines[at], following moves back
+fn flag_source(lines: &[Line], at: usize, mut val: Operand) -> Option<FlagSource> {
+    for j in (0..at).rev() {
+        match lines[j] {
+            Line::Inst("mov", ref args) => {
+                if args[0] == val {
+                    match args[1] {
+                        Operand::Reg(_) => val = args[1].clone(),
+                        _ => return None,
+                    }
+                } else if clobbers(&args[0], &val) {
+                    return None;
+                }
+            }
+            Line::Inst("add", ref args)
+            | Line::Inst("sub", ref args)
+            | Line::Inst("and", ref args)
+            | Line::Inst("or", ref args)
+            | Line::Inst("xor", ref args)
+                if args[0] == val && is_reg64(&val) =>
+            {
+                return Some(FlagSource::Zero);
+            }
+            Line::Inst("movzb", ref args) if args[0] == val && j > 0 => {
+                return match lines[j - 1] {
+                    Line::Inst(set, ref set_args)
+                        if set.starts_with("set") && set_args[0] == Operand::Reg("al") =>
+                    {
+                        Some(FlagSource::Set(&set[3..]))
+                    }
+                    _ => None,
+                };
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Drops "cmp x, 0" before je/jne when the flags of an earlier instruction
+/// already tell whether x is zero
+fn fold_compares(lines: &mut Lines) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < lines.len() {
+        let (val, jump) = match (&lines[i], &lines[i + 1]) {
+            (Line::Inst("cmp", args), Line::Inst(jump, _))
+                if args[1] == Operand::Imm(0) && (*jump == "je" || *jump == "jne") =>
+            {
+                (args[0].clone(), *jump)
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let new_jump = match flag_source(lines, i, val) {
+            Some(FlagSource::Zero) => jump,
+            // Jumping on zero means the condition was false
+            Some(FlagSource::Set(cc)) => jcc(cc, jump == "je").unwrap(),
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        if let Line::Inst(ref mut op, _) = lines[i + 1] {
+            *op = new_jump;
+        }
+        lines.remove(i);
+        changed = true;
+    }
+    changed
+}
+
+// Labels placed right before lines[i], i.e. at the same address
+fn labels_at(lines: &[Line], i: usize) -> impl Iterator<Item = &String> {
+    lines[i..].iter().map_while(|line| match line {
+        Line::Label(name) => Some(name),
+        _ => None,
+    })
+}
+
+/// Removes jumps to the next instruction, retargets jumps to unconditional
+/// jumps and turns "jcc a; jmp b; a:" into "jncc b; a:"
+fn thread_jumps(lines: &mut Lines) -> bool {
+    let mut changed = false;
+
+    // First instruction after each label
+    let mut first_inst = std::collections::HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let Line::Label(name) = line {
+            let inst = lines[i..].iter().find(|l| matches!(l, Line::Inst(..)));
+            first_inst.insert(name.clone(), inst.cloned());
+        }
+    }
+    let resolve = |mut target: String| {
+        let mut seen = HashSet::new();
+        while let Some(Some(line @ Line::Inst("jmp", _))) = first_inst.get(&target) {
+            let next = match jump_target(line) {
+                Some(next) if seen.insert(target.clone()) => next,
+                _ => break,
+            };
+            target = next.to_string();
+        }
+        target
+    };
+
+    let mut i = 0;
+    while i < lines.len() {
+        let target = match jump_target(&lines[i]) {
+            Some(target) => target.to_string(),
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        if labels_at(lines, i + 1).any(|l| *l == target) {
+            lines.remove(i);
+            changed = true;
+            continue;
+        }
+        let final_target = resolve(target.clone());
+        if final_target != target {
+            if let Line::Inst(_, ref mut args) = lines[i] {
+                args[0] = Operand::Label(final_target);
+            }
+            changed = true;
+            continue;
+        }
+        if let (Line::Inst(op, _), Some(over)) = (&lines[i], lines.get(i + 1)) {
+            let over = match over {
+                Line::Inst("jmp", _) => jump_target(over).map(String::from),
+                _ => None,
+            };
+            if let Some(over) = over {
+                let negated = if is_cond_jump(op) {
+                    jcc(&op[1..], true)
+                } else {
+                    None
+                };
+                if let Some(negated) = negated {
+                    if labels_at(lines, i + 2).any(|l| *l == target) {
+                        lines[i] = Line::Inst(negated, vec![Operand::Label(over)]);
+                        lines.remove(i + 1);
+                        changed = true;
+                        continue;
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// Removes instructions after unconditional jumps and returns up to the
+/// next label, and local labels nothing jumps to
+fn remove_dead_code(lines: &mut Lines) -> bool {
+    let before = lines.len();
+    let mut reachable = true;
+    lines.retain(|line| match line {
+        Line::Label(_) => {
+            reachable = true;
+            true
+        }
+        Line::Inst(op, _) => {
+            let keep = reachable;
+            if *op == "jmp" || *op == "ret" {
+                reachable = false;
+            }
+            keep
+        }
+    });
+
+    let used: HashSet<String> = lines
+        .iter()
+        .flat_map(|line| jump_targets(line).into_iter().map(String::from))
+        .collect();
+    lines.retain(|line| match line {
+        Line::Label(name) => !name.starts_with(".L") || used.contains(name),
+        _ => true,
+    });
+    lines.len() != before
+}
@@ -0,0 +1,417 @@
+// C pretty-printer: regenerates source from a parsed program
+use crate::cenv::Var;
+use crate::ctype::{self, StructRef, Type};
+use crate::node::{AssignMode, Node, NodeKind};
+use crate::parser::Program;
+use std::collections::LinkedList;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// Prints prog as C source that parses back into the same AST.
+/// Prototypes and globals come first since their original order
+/// relative to function definitions is not kept. Prototypes lead as
+/// initializers of globals may take the address of a function.
+/// Struct tags lead them all, hoisted to file scope under unique names,
+/// after the enums at file scope that they may hold. Enums declared in a
+/// block are defined there instead, where their constants belong.
+pub fn print_program(prog: &Program) -> String {
+    let mut p = Printer {
+        out: String::new(),
+        prog,
+        indent: 0,
+        enums: Vec::new(),
+    };
+
+    for ty in prog.enums.iter() {
+        writeln!(p.out, "{};", ctype::define_enum(ty)).unwrap();
+    }
+
+    // Declaring every tag first lets the definitions refer to each other
+    let tagged: Vec<&StructRef> = prog
+        .structs
+        .iter()
+        .filter(|def| def.borrow().name.is_some())
+        .collect();
+    for def in tagged.iter() {
+        writeln!(p.out, "{};", def.borrow().spelling()).unwrap();
+    }
+    let mut defined: Vec<StructRef> = Vec::new();
+    for def in tagged.iter() {
+        p.define_struct(def, &mut defined);
+    }
+
+    let mut seen: Vec<&str> = Vec::new();
+    for (name, ty, _) in prog.prototypes.iter() {
+        if seen.contains(&name.as_str()) {
+            continue;
+        }
+        seen.push(name);
+        writeln!(p.out, "{};", ty.declare(name)).unwrap();
+    }
+    for gvar in prog.globals.iter() {
+        let mut decl = gvar.ty.declare(&gvar.name);
+        if gvar.is_extern {
+            decl = format!("extern {}", decl);
+        }
+        if let Some(init) = prog.global_inits.iter().find(|i| i.name == gvar.name) {
+            let mut vals = init.vals.iter().map(|init| &init.val);
+            write!(decl, " = {}", p.aggregate_initializer(&gvar.ty, &mut vals)).unwrap();
+        }
+        writeln!(p.out, "{};", decl).unwrap();
+    }
+    for node in prog.nodes.iter() {
+        p.out.push('\n');
+        p.stmt(node);
+    }
+    p.out
+}
+
+struct Printer<'a> {
+    out: String,
+    prog: &'a Program,
+    indent: usize,
+    enums: Vec<Vec<Type>>, // Tagged enums defined in each block being printed
+}
+
+impl<'a> Printer<'a> {
+    // A struct can only be defined after those it holds by value
+    fn define_struct(&mut self, def: &StructRef, defined: &mut Vec<StructRef>) {
+        if defined.iter().any(|d| Rc::ptr_eq(d, def)) {
+            return;
+        }
+        defined.push(def.clone());
+        for m in def.borrow().members().iter() {
+            let mut ty = m.ty.clone();
+            while ty.is_array() {
+                ty = ty.clone_base();
+            }
+            if let Some(inner) = ty.struct_def() {
+                self.define_struct(inner, defined);
+            }
+        }
+        let def = def.borrow();
+        if def.name.is_some() && def.is_complete() {
+            writeln!(self.out, "{};", ctype::define_struct(&def)).unwrap();
+        }
+    }
+
+    fn line(&mut self, s: &str) {
+        writeln!(self.out, "{}{}", "    ".repeat(self.indent), s).unwrap();
+    }
+
+    // Prints a statement that may have been omitted, e.g. "if (a) ;"
+    fn opt_stmt(&mut self, stmt: &Option<Box<Node>>) {
+        match stmt {
+            Some(stmt) => self.stmt(stmt),
+            None => self.line(";"),
+        }
+    }
+
+    // Prints a substatement one level deeper
+    fn body(&mut self, stmt: &Option<Box<Node>>) {
+        self.indent += 1;
+        self.opt_stmt(stmt);
+        self.indent -= 1;
+    }
+
+    fn block(&mut self, stmts: &LinkedList<Node>) {
+        self.line("{");
+        self.indent += 1;
+        self.enums.push(Vec::new());
+        for stmt in stmts.iter() {
+            self.stmt(stmt);
+        }
+        self.enums.pop();
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    // A tagged enum of a block is defined by the first declaration that
+    // uses it, and referred to by its tag from then on
+    fn define_enum(&mut self, specs: String, vars: &LinkedList<Var>) -> String {
+        let mut ty = vars.front().unwrap().ty.clone();
+        while ty.is_ptr_like() {
+            ty = ty.clone_base();
+        }
+        let tag = match ty.enum_tag() {
+            Some(tag) if !self.knows_enum(&ty) => format!("enum {}", tag),
+            _ => return specs,
+        };
+        let specs = specs.replacen(&tag, &ctype::define_enum(&ty), 1);
+        self.enums.last_mut().unwrap().push(ty);
+        specs
+    }
+
+    fn knows_enum(&self, ty: &Type) -> bool {
+        let members = |ty: &Type| -> Vec<(String, i32)> {
+            let members = ty.enum_members().iter();
+            members.map(|m| (m.name.clone(), m.val)).collect()
+        };
+        let same =
+            |known: &Type| known.enum_tag() == ty.enum_tag() && members(known) == members(ty);
+        let mut known = self.prog.enums.iter().chain(self.enums.iter().flatten());
+        known.any(same)
+    }
+
+    fn stmt(&mut self, node: &Node) {
+        use NodeKind::*;
+
+        match node.kind {
+            NDFUNCDEF {
+                ref name,
+                ref stmts,
+                ..
+            } => {
+                // The definition's own type is the prototype declared
+                // within it; later declarations may leave out the names
+                let span = node.span.unwrap();
+                let (_, ty, _) = self
+                    .prog
+                    .prototypes
+                    .iter()
+                    .find(|(proto, _, s)| proto == name && span.lo <= s.lo && s.hi <= span.hi)
+                    .unwrap();
+                self.line(&ty.declare(name));
+                self.block(stmts);
+            }
+            NDDECL {
+                ref vars,
+                ref inits,
+            } => {
+                if vars.is_empty() {
+                    // Only declared a tag; any tag keeps the node in place
+                    self.line("struct __incomplete;");
+                }
+                // All declarators share the specifiers of the first one
+                let mut specs = String::new();
+                let mut declarators: Vec<String> = Vec::new();
+                for var in vars.iter() {
+                    let (base, mut declarator) = var.ty.split_declare(&var.name);
+                    specs = base;
+                    if let Some(init) = self.initializer(var, inits) {
+                        declarator = format!("{} = {}", declarator, init);
+                    }
+                    declarators.push(declarator);
+                }
+                if vars.iter().any(|var| var.is_extern) {
+                    specs = format!("extern {}", specs);
+                }
+                if !vars.is_empty() {
+                    let specs = self.define_enum(specs, vars);
+                    self.line(&format!("{} {};", specs, declarators.join(", ")));
+                }
+            }
+            NDBLOCK { ref stmts } => self.block(stmts),
+            NDRETURN { ref node } => {
+                let s = format!("return {};", self.expr(node));
+                self.line(&s);
+            }
+            NDBREAK => self.line("break;"),
+            NDCONTINUE => self.line("continue;"),
+            NDIF {
+                ref cond,
+                ref ifnode,
+                ref elsenode,
+            } => {
+                let s = format!("if ({})", self.expr(cond));
+                self.line(&s);
+                self.body(ifnode);
+                if elsenode.is_some() {
+                    self.line("else");
+                    self.body(elsenode);
+                }
+            }
+            NDSWITCH {
+                ref ctrl, ref stmt, ..
+            } => {
+                let s = format!("switch ({})", self.expr(ctrl));
+                self.line(&s);
+                self.body(stmt);
+            }
+            NDCASE { ref stmt, val, .. } => {
+                self.line(&format!("case {}:", val));
+                self.body(stmt);
+            }
+            NDDEFAULT { ref stmt } => {
+                self.line("default:");
+                self.body(stmt);
+            }
+            NDWHILE {
+                ref cond,
+                ref repnode,
+            } => {
+                let s = format!("while ({})", self.expr(cond));
+                self.line(&s);
+                self.body(repnode);
+            }
+            NDDOWHILE {
+                ref cond,
+                ref repnode,
+            } => {
+                self.line("do");
+                self.body(repnode);
+                let s = format!("while ({});", self.expr(cond));
+                self.line(&s);
+            }
+            NDFOR {
+                ref init,
+                ref cond,
+                ref step,
+                ref repnode,
+            } => {
+                let init = init.as_ref().map_or(String::new(), |n| self.expr(n));
+                let cond = cond.as_ref().map_or(String::new(), |n| self.expr(n));
+                let step = step.as_ref().map_or(String::new(), |n| self.expr(n));
+                self.line(&format!("for ({}; {}; {})", init, cond, step));
+                self.body(repnode);
+            }
+            _ => {
+                let s = format!("{};", self.expr(node));
+                self.line(&s);
+            }
+        }
+    }
+
+    // Recovers the initializer of var from the element-wise assignments
+    // the parser lowered it into.
+    fn initializer(&self, var: &Var, inits: &LinkedList<Node>) -> Option<String> {
+        let hi = var.offset?;
+        let lo = hi - var.ty.total_size();
+        let mut vals = inits.iter().filter_map(|init| match init.kind {
+            NodeKind::NDASSIGN {
+                ref lhs, ref rhs, ..
+            } => {
+                // Bit-fields are set through the struct holding them
+                let lvar = match lhs.kind {
+                    NodeKind::NDMEMBER { ref node, .. } => node,
+                    _ => lhs,
+                };
+                match lvar.kind {
+                    NodeKind::NDLVAR { offset, .. } if lo < offset && offset <= hi => Some(rhs),
+                    _ => None,
+                }
+            }
+            _ => None,
+        });
+        let first = vals.next()?;
+        let mut vals = std::iter::once(first).chain(vals).map(|val| &**val);
+        Some(self.aggregate_initializer(&var.ty, &mut vals))
+    }
+
+    // Scalars print as the value itself, aggregates as nested braces
+    fn aggregate_initializer<'b, I>(&self, ty: &Type, vals: &mut I) -> String
+    where
+        I: Iterator<Item = &'b Node>,
+    {
+        if ty.is_scalar() {
+            return match vals.next() {
+                Some(val) => self.expr(val),
+                None => "0".to_string(),
+            };
+        }
+        let elems: Vec<String> = ty
+            .subobjects()
+            .iter()
+            .map(|sub| self.aggregate_initializer(&sub.ty, vals))
+            .collect();
+        format!("{{{}}}", elems.join(", "))
+    }
+
+    // Expressions are fully parenthesized so that no precedence is lost
+    fn expr(&self, node: &Node) -> String {
+        use NodeKind::*;
+
+        match node.kind {
+            NDINT { val } => val.to_string(),
+            NDSTR { pos } => format!("\"{}\"", self.prog.literals[pos]),
+            NDLVAR { ref name, .. } | NDGVAR { ref name } | NDPROTOTY { ref name } => name.clone(),
+            NDADD {
+                ref lhs, ref rhs, ..
+            } => self.binary("+", lhs, rhs),
+            NDSUB {
+                ref lhs, ref rhs, ..
+            } => self.binary("-", lhs, rhs),
+            NDMUL { ref lhs, ref rhs } => self.binary("*", lhs, rhs),
+            NDDIV { ref lhs, ref rhs } => self.binary("/", lhs, rhs),
+            NDMOD { ref lhs, ref rhs } => self.binary("%", lhs, rhs),
+            NDEQ { ref lhs, ref rhs } => self.binary("==", lhs, rhs),
+            NDNEQ { ref lhs, ref rhs } => self.binary("!=", lhs, rhs),
+            NDLEQ { ref lhs, ref rhs } => self.binary("<=", lhs, rhs),
+            NDLT { ref lhs, ref rhs } => self.binary("<", lhs, rhs),
+            NDBITAND { ref lhs, ref rhs } => self.binary("&", lhs, rhs),
+            NDBITXOR { ref lhs, ref rhs } => self.binary("^", lhs, rhs),
+            NDBITOR { ref lhs, ref rhs } => self.binary("|", lhs, rhs),
+            NDLOGAND { ref lhs, ref rhs } => self.binary("&&", lhs, rhs),
+            NDLOGOR { ref lhs, ref rhs } => self.binary("||", lhs, rhs),
+            NDSHL { ref lhs, ref rhs } => self.binary("<<", lhs, rhs),
+            NDSHR { ref lhs, ref rhs } => self.binary(">>", lhs, rhs),
+            NDASSIGN {
+                ref lhs,
+                ref rhs,
+                eval_pre,
+                assign_mode,
+                ..
+            } => {
+                if !eval_pre {
+                    let op = if assign_mode == AssignMode::ADD {
+                        "++"
+                    } else {
+                        "--"
+                    };
+                    format!("({}{})", self.expr(lhs), op)
+                } else {
+                    self.binary(assign_op(assign_mode), lhs, rhs)
+                }
+            }
+            NDBITNOT { ref node } => format!("(~{})", self.expr(node)),
+            NDADDR { ref node } => format!("(&{})", self.expr(node)),
+            NDDEREF { ref node } => format!("(*{})", self.expr(node)),
+            NDMEMBER {
+                ref node, ref name, ..
+            } => format!("({}.{})", self.expr(node), name),
+            NDCAST { node: ref operand } => {
+                let ty = node.ty.as_ref().unwrap();
+                format!("(({}) {})", ty.declare(""), self.expr(operand))
+            }
+            NDCOND {
+                ref cond,
+                ref then,
+                ref els,
+            } => format!(
+                "({} ? {} : {})",
+                self.expr(cond),
+                self.expr(then),
+                self.expr(els)
+            ),
+            NDCALL {
+                ref prototy,
+                ref args,
+            } => {
+                let args: Vec<String> = args.iter().map(|a| self.expr(a)).collect();
+                format!("{}({})", self.expr(prototy), args.join(", "))
+            }
+            _ => panic!("printer: {} is not an expression", node.kind.name()),
+        }
+    }
+
+    fn binary(&self, op: &str, lhs: &Node, rhs: &Node) -> String {
+        format!("({} {} {})", self.expr(lhs), op, self.expr(rhs))
+    }
+}
+
+fn assign_op(mode: AssignMode) -> &'static str {
+    use AssignMode::*;
+    match mode {
+        DEFAULT => "=",
+        ADD => "+=",
+        SUB => "-=",
+        MUL => "*=",
+        DIV => "/=",
+        MOD => "%=",
+        SHL => "<<=",
+        SHR => ">>=",
+        AND => "&=",
+        OR => "|=",
+        XOR => "^=",
+    }
+}
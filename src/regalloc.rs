@@ -0,0 +1,274 @@
+// Linear-scan register allocation over the IR, after Poletto and Sarkar.
+// Each virtual register gets a single live interval without holes over the
+// instructions numbered in block layout order.
+use crate::ir::*;
+use std::collections::HashSet;
+
+/// Machine registers handed out by the allocator. rax, rcx and rdx are kept
+/// as scratch for the code generator and rdi..r9 only carry arguments,
+/// so none of them appear here.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Reg {
+    R10,
+    R11,
+    RBX,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+// Caller-saved registers come first so that short intervals prefer them
+static ALLOCATABLE: [Reg; 7] = [
+    Reg::R10,
+    Reg::R11,
+    Reg::RBX,
+    Reg::R12,
+    Reg::R13,
+    Reg::R14,
+    Reg::R15,
+];
+
+impl Reg {
+    pub fn is_callee_saved(self) -> bool {
+        !matches!(self, Reg::R10 | Reg::R11)
+    }
+
+    /// Name of the register when accessed at width
+    pub fn name(self, width: Width) -> &'static str {
+        use Width::*;
+        match (self, width) {
+            (Reg::R10, I8) => "r10b",
+            (Reg::R10, I16) => "r10w",
+            (Reg::R10, I32) => "r10d",
+            (Reg::R10, I64) => "r10",
+            (Reg::R11, I8) => "r11b",
+            (Reg::R11, I16) => "r11w",
+            (Reg::R11, I32) => "r11d",
+            (Reg::R11, I64) => "r11",
+            (Reg::RBX, I8) => "bl",
+            (Reg::RBX, I16) => "bx",
+            (Reg::RBX, I32) => "ebx",
+            (Reg::RBX, I64) => "rbx",
+            (Reg::R12, I8) => "r12b",
+            (Reg::R12, I16) => "r12w",
+            (Reg::R12, I32) => "r12d",
+            (Reg::R12, I64) => "r12",
+            (Reg::R13, I8) => "r13b",
+            (Reg::R13, I16) => "r13w",
+            (Reg::R13, I32) => "r13d",
+            (Reg::R13, I64) => "r13",
+            (Reg::R14, I8) => "r14b",
+            (Reg::R14, I16) => "r14w",
+            (Reg::R14, I32) => "r14d",
+            (Reg::R14, I64) => "r14",
+            (Reg::R15, I8) => "r15b",
+            (Reg::R15, I16) => "r15w",
+            (Reg::R15, I32) => "r15d",
+            (Reg::R15, I64) => "r15",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Location {
+    Reg(Reg),
+    Spill(usize), // Index of an 8-byte spill slot
+}
+
+#[derive(Debug)]
+pub struct Allocation {
+    pub locs: Vec<Location>, // Indexed by virtual register
+    pub num_spills: usize,
+    pub callee_saved: Vec<Reg>, // Used callee-saved registers in ALLOCATABLE order
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Interval {
+    vreg: VReg,
+    start: usize,
+    end: usize,
+    crosses_call: bool,
+}
+
+pub fn allocate(func: &Function) -> Allocation {
+    let intervals = live_intervals(func);
+
+    let mut locs = vec![Location::Spill(0); func.num_vregs];
+    let mut num_spills = 0;
+    let mut used: HashSet<Reg> = HashSet::new();
+    let mut active: Vec<(Interval, Reg)> = Vec::new(); // Sorted by end
+    let mut free: Vec<Reg> = ALLOCATABLE.to_vec();
+
+    for cur in intervals.iter() {
+        // Expire intervals that ended before this one starts
+        active.retain(|(iv, reg)| {
+            if iv.end < cur.start {
+                free.push(*reg);
+                false
+            } else {
+                true
+            }
+        });
+
+        // A value live across a call must survive it in a callee-saved register
+        let usable = |r: &Reg| !cur.crosses_call || r.is_callee_saved();
+        let choice = ALLOCATABLE
+            .iter()
+            .find(|r| free.contains(r) && usable(r))
+            .cloned();
+
+        let reg = match choice {
+            Some(reg) => {
+                free.retain(|r| *r != reg);
+                Some(reg)
+            }
+            None => {
+                // Spill whichever interval ends last, the current one included
+                let victim = active
+                    .iter()
+                    .rposition(|(iv, reg)| usable(reg) && iv.end > cur.end);
+                match victim {
+                    Some(pos) => {
+                        let (iv, reg) = active.remove(pos);
+                        locs[iv.vreg.0] = Location::Spill(num_spills);
+                        num_spills += 1;
+                        Some(reg)
+                    }
+                    None => None,
+                }
+            }
+        };
+
+        match reg {
+            Some(reg) => {
+                used.insert(reg);
+                locs[cur.vreg.0] = Location::Reg(reg);
+                let pos = active
+                    .iter()
+                    .position(|(iv, _)| iv.end > cur.end)
+                    .unwrap_or(active.len());
+                active.insert(pos, (*cur, reg));
+            }
+            None => {
+                locs[cur.vreg.0] = Location::Spill(num_spills);
+                num_spills += 1;
+            }
+        }
+    }
+
+    let callee_saved = ALLOCATABLE
+        .iter()
+        .filter(|r| r.is_callee_saved() && used.contains(r))
+        .cloned()
+        .collect();
+    Allocation {
+        locs,
+        num_spills,
+        callee_saved,
+    }
+}
+
+// Intervals of every register that is ever written, sorted by start.
+// Instructions and terminators are numbered consecutively in layout order.
+fn live_intervals(func: &Function) -> Vec<Interval> {
+    let n = func.blocks.len();
+
+    // Position of the first instruction and of the terminator of each block
+    let mut starts = Vec::with_capacity(n);
+    let mut pos = 0;
+    for block in func.blocks.iter() {
+        starts.push(pos);
+        pos += block.insts.len() + 1;
+    }
+    let term_pos = |b: usize| starts[b] + func.blocks[b].insts.len();
+
+    // Per-block upward-exposed uses and definitions
+    let mut gen: Vec<HashSet<VReg>> = vec![HashSet::new(); n];
+    let mut kill: Vec<HashSet<VReg>> = vec![HashSet::new(); n];
+    for (b, block) in func.blocks.iter().enumerate() {
+        for inst in block.insts.iter() {
+            for u in inst.uses() {
+                if !kill[b].contains(&u) {
+                    gen[b].insert(u);
+                }
+            }
+            if let Some(d) = inst.def() {
+                kill[b].insert(d);
+            }
+        }
+        for u in block.term.uses() {
+            if !kill[b].contains(&u) {
+                gen[b].insert(u);
+            }
+        }
+    }
+
+    // Backward liveness to a fixed point
+    let mut live_in: Vec<HashSet<VReg>> = vec![HashSet::new(); n];
+    let mut live_out: Vec<HashSet<VReg>> = vec![HashSet::new(); n];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..n).rev() {
+            let mut out = HashSet::new();
+            for succ in func.blocks[b].term.successors() {
+                out.extend(live_in[succ.0].iter().cloned());
+            }
+            let mut inn: HashSet<VReg> = gen[b].clone();
+            inn.extend(out.difference(&kill[b]).cloned());
+            if inn != live_in[b] || out != live_out[b] {
+                live_in[b] = inn;
+                live_out[b] = out;
+                changed = true;
+            }
+        }
+    }
+
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; func.num_vregs];
+    let mut extend = |r: VReg, p: usize| {
+        ranges[r.0] = Some(match ranges[r.0] {
+            None => (p, p),
+            Some((lo, hi)) => (lo.min(p), hi.max(p)),
+        });
+    };
+    let mut calls = Vec::new();
+    for (b, block) in func.blocks.iter().enumerate() {
+        for r in live_in[b].iter() {
+            extend(*r, starts[b]);
+        }
+        for r in live_out[b].iter() {
+            extend(*r, term_pos(b));
+        }
+        for (i, inst) in block.insts.iter().enumerate() {
+            let p = starts[b] + i;
+            for u in inst.uses() {
+                extend(u, p);
+            }
+            if let Some(d) = inst.def() {
+                extend(d, p);
+            }
+            if let Inst::Call { .. } = inst {
+                calls.push(p);
+            }
+        }
+        for u in block.term.uses() {
+            extend(u, term_pos(b));
+        }
+    }
+
+    let mut intervals: Vec<Interval> = ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(v, range)| {
+            range.map(|(start, end)| Interval {
+                vreg: VReg(v),
+                start,
+                end,
+                crosses_call: calls.iter().any(|c| start < *c && *c < end),
+            })
+        })
+        .collect();
+    intervals.sort_by_key(|iv| (iv.start, iv.vreg));
+    intervals
+}
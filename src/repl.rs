@@ -0,0 +1,227 @@
+// Read-eval-print loop for --repl. Each input is parsed against what the
+// ones before it declared, checked, and handed to an interpreter session
+// whose globals carry over. Declarations and function definitions are
+// taken as at file scope; every other statement runs as soon as it is
+// entered, and the value of an expression is shown with its type:
+//
+//     > struct point { int x; int y; } p = {1, 2};
+//     > p.x + p.y
+//     (int) 3
+//
+// An input that fails to parse or check is forgotten, while one that fails
+// at run time keeps what it declared.
+use crate::constexpr;
+use crate::ctype::Type;
+use crate::interp::{Session, Stop};
+use crate::node::{Node, NodeKind};
+use crate::parser::{self, Parser, Program, ENTRY_HEAD};
+use crate::sema;
+use crate::tokenizer::{Span, Tokenizer};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+struct Repl {
+    parser: Parser,
+    session: Session,
+}
+
+/// Reads inputs from stdin until it ends or the program exits, giving the
+/// exit status
+pub fn run() -> i32 {
+    let stdin = io::stdin();
+    // Prompts would only get in the way of piped input
+    let prompt = stdin.is_terminal();
+    let mut repl = Repl {
+        parser: Parser::new(Tokenizer::new().tokenize(String::new()).unwrap()),
+        session: Session::new(),
+    };
+    let mut input = String::new();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if prompt {
+            print!("{}", if input.is_empty() { "> " } else { "... " });
+            let _ = io::stdout().flush();
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => return 0,
+        };
+        input.push_str(&line);
+        input.push('\n');
+        // A definition may go on over several lines
+        if !is_complete(&input) {
+            continue;
+        }
+        let text = std::mem::take(&mut input);
+        if text.trim().is_empty() {
+            continue;
+        }
+        if let Some(code) = repl.eval(text) {
+            return code;
+        }
+    }
+}
+
+impl Repl {
+    // Runs an input, giving the exit status if the program exited
+    fn eval(&mut self, mut text: String) -> Option<i32> {
+        // The last expression may leave out its semicolon
+        if !text.trim_end().ends_with([';', '}']) {
+            text.push(';');
+        }
+        let saved = self.parser.save();
+        let mut prog = match self.parse(text) {
+            Ok(prog) => prog,
+            Err(msg) => {
+                warn(&self.parser.take_warnings());
+                eprintln!("yarcc: {}", msg);
+                self.parser.restore(saved);
+                return None;
+            }
+        };
+        let checked = sema::analyze(&mut prog);
+        warn(&prog.warnings);
+        if let Err(errors) = checked {
+            for (span, msg) in errors.iter() {
+                eprintln!("yarcc: {}: {}", span.lo, msg);
+            }
+            self.parser.restore(saved);
+            return None;
+        }
+        constexpr::fold_program(&mut prog);
+        // Entries run in order once the session has the input
+        let entries: Vec<(String, Option<Type>)> = prog
+            .nodes
+            .iter_mut()
+            .filter_map(|node| {
+                let ty = address_aggregate(node);
+                match node.kind {
+                    NodeKind::NDFUNCDEF { ref name, .. } if name.starts_with(ENTRY_HEAD) => {
+                        Some((name.clone(), ty))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        self.session.load(prog);
+        for (name, ty) in entries {
+            match self.session.call(&name) {
+                Ok(val) => {
+                    if let Some(ty) = ty {
+                        println!("({}) {}", ty, self.session.show(val, &ty));
+                    }
+                }
+                Err(Stop::Exit(code)) => return Some(code),
+                Err(Stop::Error(msg)) => {
+                    eprintln!("yarcc: run: {}", msg);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    // The parser reports errors by panicking, which must not end the session
+    fn parse(&mut self, text: String) -> Result<Program, String> {
+        let tokens = Tokenizer::new()
+            .tokenize(text)
+            .map_err(|(span, msg)| format!("{}: {}", span.lo, msg))?;
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let parser = &mut self.parser;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| parser.parse_input(tokens)));
+        panic::set_hook(hook);
+        result.map_err(parser::error_message)
+    }
+}
+
+fn warn(warnings: &[(Span, String)]) {
+    for (span, msg) in warnings.iter() {
+        eprintln!("warning: {}: {}", span.lo, msg);
+    }
+}
+
+// Type of the value a statement of the REPL shows, if it is an expression.
+// The interpreter holds no arrays, structs or unions in a value, so an
+// expression of one is made to give its address instead, unless it is an
+// aggregate that fits no register and so gives its address already.
+fn address_aggregate(node: &mut Node) -> Option<Type> {
+    let stmts = match node.kind {
+        NodeKind::NDFUNCDEF {
+            ref name,
+            ref mut stmts,
+            ..
+        } if name.starts_with(ENTRY_HEAD) => stmts,
+        _ => return None,
+    };
+    let expr = stmts.front_mut().filter(|stmt| is_expr(stmt))?;
+    let ty = expr.ty.clone().unwrap();
+    if ty.is_void() {
+        return None;
+    }
+    if ty.is_struct() && !ty.in_memory() {
+        let inner = stmts.pop_front().unwrap();
+        let mut addr = Node::new_unary("&", inner);
+        addr.populate_ty();
+        stmts.push_front(addr);
+    }
+    Some(ty)
+}
+
+fn is_expr(node: &Node) -> bool {
+    use NodeKind::*;
+    !matches!(
+        node.kind,
+        NDRETURN { .. }
+            | NDBREAK
+            | NDCONTINUE
+            | NDIF { .. }
+            | NDSWITCH { .. }
+            | NDWHILE { .. }
+            | NDDOWHILE { .. }
+            | NDFOR { .. }
+            | NDBLOCK { .. }
+            | NDCASE { .. }
+            | NDDEFAULT { .. }
+            | NDFUNCDEF { .. }
+            | NDDECL { .. }
+    )
+}
+
+// Whether every bracket opened in the input has been closed, outside of
+// literals and comments
+fn is_complete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' | '\'' => {
+                while let Some(d) = chars.next() {
+                    if d == '\\' {
+                        chars.next();
+                    } else if d == c || d == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|&d| d == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut star = false;
+                loop {
+                    match chars.next() {
+                        Some('/') if star => break,
+                        Some(d) => star = d == '*',
+                        None => return false,
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    depth <= 0
+}
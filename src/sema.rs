@@ -0,0 +1,714 @@
+// Semantic analysis over the parsed program.
+// The parser types expressions as it builds them; this pass checks the
+// constraints that typing leaves alone and makes sure that every expression
+// carries its final type before lowering. All errors are collected rather
+// than stopping at the first one.
+use crate::cenv::Prototype;
+use crate::constexpr;
+use crate::ctype::Type;
+use crate::node::{AssignMode, Node, NodeKind};
+use crate::parser::Program;
+use crate::tokenizer::Span;
+use std::collections::LinkedList;
+
+/// Checks prog, giving every error found along with where it was found.
+/// Warnings are added to those of the program.
+pub fn analyze(prog: &mut Program) -> Result<(), Vec<(Span, String)>> {
+    let mut sema = Sema {
+        prototypes: prog.prototypes.clone(),
+        errors: Vec::new(),
+        warnings: Vec::new(),
+        span: None,
+        ret: None,
+        loops: 0,
+        breakables: 0,
+        switches: 0,
+    };
+
+    for node in prog.nodes.iter_mut() {
+        sema.stmt(node);
+    }
+    for init in prog.global_inits.iter_mut() {
+        for init in init.vals.iter_mut() {
+            sema.expr(&mut init.val);
+            sema.check_assign(&init.ty, &init.val);
+        }
+    }
+
+    prog.warnings.append(&mut sema.warnings);
+    if sema.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(sema.errors)
+    }
+}
+
+struct Sema {
+    prototypes: Vec<Prototype>,
+    errors: Vec<(Span, String)>,
+    warnings: Vec<(Span, String)>,
+    span: Option<Span>, // Innermost span seen, for nodes the parser made up
+    ret: Option<Type>,  // Return type of the current function
+    loops: usize,       // Loops around the current statement
+    breakables: usize,  // Loops and switches around the current statement
+    switches: usize,    // Switches around the current statement
+}
+
+impl Sema {
+    fn error(&mut self, msg: &str) {
+        let span = self.span.unwrap_or_default();
+        self.errors.push((span, msg.to_string()));
+    }
+
+    fn warn(&mut self, msg: &str) {
+        let span = self.span.unwrap_or_default();
+        self.warnings.push((span, msg.to_string()));
+    }
+
+    fn opt_stmt(&mut self, node: &mut Option<Box<Node>>) {
+        if let Some(node) = node {
+            self.stmt(node);
+        }
+    }
+
+    fn stmt(&mut self, node: &mut Node) {
+        use NodeKind::*;
+
+        let outer = self.span;
+        self.span = node.span.or(outer);
+        match node.kind {
+            NDFUNCDEF {
+                ref name,
+                ref argvars,
+                ref mut stmts,
+                ..
+            } => {
+                self.ret = self
+                    .prototypes
+                    .iter()
+                    .rev()
+                    .find(|(proto, _, _)| proto == name)
+                    .map(|(_, ty, _)| ty.func_ret().clone());
+                if let Some(ret) = self.ret.clone() {
+                    self.by_value("Returning", &ret);
+                }
+                for var in argvars.iter() {
+                    self.span = Some(var.span);
+                    self.by_value("Passing", &var.ty);
+                }
+                self.span = node.span.or(outer);
+                for stmt in stmts.iter_mut() {
+                    self.stmt(stmt);
+                }
+            }
+            NDDECL { ref mut inits, .. } => {
+                for init in inits.iter_mut() {
+                    self.expr(init);
+                }
+            }
+            NDBLOCK { ref mut stmts } => {
+                for stmt in stmts.iter_mut() {
+                    self.stmt(stmt);
+                }
+            }
+            NDRETURN { node: ref mut val } => {
+                self.expr(val);
+                match self.ret.clone() {
+                    Some(ret) if ret.is_void() => {
+                        self.error("Returning a value from a void function")
+                    }
+                    Some(ret) => self.check_assign(&ret, val),
+                    None => (),
+                }
+            }
+            NDIF {
+                ref mut cond,
+                ref mut ifnode,
+                ref mut elsenode,
+            } => {
+                self.condition(cond);
+                self.opt_stmt(ifnode);
+                self.opt_stmt(elsenode);
+            }
+            NDSWITCH {
+                ref mut ctrl,
+                ref mut stmt,
+                ..
+            } => {
+                self.expr(ctrl);
+                if !ctrl.ty.as_ref().unwrap().is_integral() {
+                    self.error("Switch quantity is not an integer");
+                }
+                self.switches += 1;
+                self.breakables += 1;
+                if let Some(body) = stmt {
+                    match body.kind {
+                        NDBLOCK { ref mut stmts } => {
+                            for stmt in stmts.iter_mut() {
+                                self.switch_stmt(stmt);
+                            }
+                        }
+                        _ => self.switch_stmt(body),
+                    }
+                }
+                self.breakables -= 1;
+                self.switches -= 1;
+            }
+            NDWHILE {
+                ref mut cond,
+                ref mut repnode,
+            }
+            | NDDOWHILE {
+                ref mut cond,
+                ref mut repnode,
+            } => {
+                self.condition(cond);
+                self.loop_body(repnode);
+            }
+            NDFOR {
+                ref mut init,
+                ref mut cond,
+                ref mut step,
+                ref mut repnode,
+            } => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                if let Some(cond) = cond {
+                    self.condition(cond);
+                }
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+                self.loop_body(repnode);
+            }
+            NDCASE { .. } | NDDEFAULT { .. } => {
+                if self.switches == 0 {
+                    self.error("Case label outside of a switch");
+                } else {
+                    // Lowering only finds the labels populate_switch numbered
+                    self.error("Case label nested in another statement of the switch");
+                }
+            }
+            NDBREAK => {
+                if self.breakables == 0 {
+                    self.error("Break statement outside of a loop or a switch");
+                }
+            }
+            NDCONTINUE => {
+                if self.loops == 0 {
+                    self.error("Continue statement outside of a loop");
+                }
+            }
+            _ => self.expr(node),
+        }
+        self.span = outer;
+    }
+
+    // Statement directly in the body of a switch, where labels may appear
+    fn switch_stmt(&mut self, node: &mut Node) {
+        match node.kind {
+            NodeKind::NDCASE { ref mut stmt, .. } | NodeKind::NDDEFAULT { ref mut stmt } => {
+                if let Some(stmt) = stmt {
+                    self.switch_stmt(stmt);
+                }
+            }
+            _ => self.stmt(node),
+        }
+    }
+
+    fn loop_body(&mut self, body: &mut Option<Box<Node>>) {
+        self.loops += 1;
+        self.breakables += 1;
+        self.opt_stmt(body);
+        self.breakables -= 1;
+        self.loops -= 1;
+    }
+
+    fn condition(&mut self, cond: &mut Node) {
+        self.expr(cond);
+        if !is_scalar(cond.ty.as_ref().unwrap()) {
+            self.error("Condition is not a scalar");
+        }
+    }
+
+    // Checks node after its operands and fills in its type
+    fn expr(&mut self, node: &mut Node) {
+        use NodeKind::*;
+
+        let outer = self.span;
+        self.span = node.span.or(outer);
+        let name = node.kind.name();
+        let equality = matches!(node.kind, NDEQ { .. } | NDNEQ { .. });
+        match node.kind {
+            NDSTR { .. } => {
+                if node.ty.is_none() {
+                    node.ty = Some(Type::new_ptr(Type::new_base("char")));
+                }
+            }
+            NDADD {
+                ref mut lhs,
+                ref mut rhs,
+                ..
+            } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                let (l, r) = (lhs.ty.as_ref().unwrap(), rhs.ty.as_ref().unwrap());
+                let ok = match (l.is_ptr_like(), r.is_ptr_like()) {
+                    (false, false) => l.is_integral() && r.is_integral(),
+                    (true, false) => r.is_integral(),
+                    (false, true) => l.is_integral(),
+                    (true, true) => false,
+                };
+                if !ok {
+                    self.error("Invalid operands to binary +");
+                } else if l.is_ptr_like() {
+                    self.pointer_arith(l);
+                } else {
+                    self.pointer_arith(r);
+                }
+            }
+            NDSUB {
+                ref mut lhs,
+                ref mut rhs,
+                ..
+            } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                let (l, r) = (lhs.ty.as_ref().unwrap(), rhs.ty.as_ref().unwrap());
+                match (l.is_ptr_like(), r.is_ptr_like()) {
+                    (true, true) => {
+                        if !l.clone_base().is_compatible(&r.clone_base()) {
+                            let msg = format!(
+                                "Subtracting pointers to incompatible types {} and {}",
+                                l, r
+                            );
+                            self.error(&msg);
+                        }
+                        self.pointer_arith(l);
+                    }
+                    (true, false) if r.is_integral() => self.pointer_arith(l),
+                    (false, false) if l.is_integral() && r.is_integral() => (),
+                    _ => self.error("Invalid operands to binary -"),
+                }
+            }
+            NDMUL {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDDIV {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDMOD {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDBITAND {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDBITXOR {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDBITOR {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDSHL {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDSHR {
+                ref mut lhs,
+                ref mut rhs,
+            } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                if !lhs.ty.as_ref().unwrap().is_integral()
+                    || !rhs.ty.as_ref().unwrap().is_integral()
+                {
+                    let msg = format!("Invalid operands to binary {}", operator(&node.kind));
+                    self.error(&msg);
+                }
+            }
+            NDEQ {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDNEQ {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDLT {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDLEQ {
+                ref mut lhs,
+                ref mut rhs,
+            } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.comparison(lhs, rhs, equality);
+            }
+            NDLOGAND {
+                ref mut lhs,
+                ref mut rhs,
+            }
+            | NDLOGOR {
+                ref mut lhs,
+                ref mut rhs,
+            } => {
+                self.condition(lhs);
+                self.condition(rhs);
+            }
+            NDBITNOT {
+                node: ref mut operand,
+            } => {
+                self.expr(operand);
+                if !operand.ty.as_ref().unwrap().is_integral() {
+                    self.error("Invalid operand to unary ~");
+                }
+            }
+            NDADDR {
+                node: ref mut operand,
+            } => {
+                self.expr(operand);
+                if !is_lvalue(operand) && !matches!(operand.kind, NDPROTOTY { .. }) {
+                    self.error("Cannot take the address of an rvalue");
+                }
+            }
+            NDDEREF {
+                node: ref mut operand,
+            } => {
+                self.expr(operand);
+                let ty = operand.ty.as_ref().unwrap();
+                if !ty.is_ptr_like() && !ty.is_function() {
+                    self.error("Dereferencing a non-pointer");
+                } else if ty.is_void_ptr() {
+                    self.error("Dereferencing a void pointer");
+                }
+            }
+            NDMEMBER {
+                node: ref mut operand,
+                ..
+            } => self.expr(operand),
+            NDCAST {
+                node: ref mut operand,
+            } => {
+                self.expr(operand);
+                if !is_scalar(operand.ty.as_ref().unwrap()) {
+                    self.error("Cast of a non-scalar value");
+                }
+            }
+            NDCOND {
+                ref mut cond,
+                ref mut then,
+                ref mut els,
+            } => {
+                self.condition(cond);
+                self.expr(then);
+                self.expr(els);
+                let (t, e) = (then.ty.as_ref().unwrap(), els.ty.as_ref().unwrap());
+                let ok = if t.is_integral() && e.is_integral() {
+                    true
+                } else if is_pointer(t) && is_pointer(e) {
+                    if !pointers_compatible(t, e) {
+                        self.warn("Pointer type mismatch in conditional expression");
+                    }
+                    true
+                } else if is_pointer(t) {
+                    is_null(els)
+                } else if is_pointer(e) {
+                    is_null(then)
+                } else {
+                    t.is_compatible(e)
+                };
+                if !ok {
+                    self.error("Type mismatch in conditional expression");
+                }
+            }
+            NDCALL {
+                ref mut prototy,
+                ref mut args,
+            } => {
+                self.expr(prototy);
+                for arg in args.iter_mut() {
+                    self.expr(arg);
+                }
+                self.call(prototy, args);
+                if let Some(ty) = node.ty.as_ref() {
+                    self.by_value("Returning", ty);
+                }
+            }
+            NDASSIGN {
+                ref mut lhs,
+                ref mut rhs,
+                assign_mode,
+                is_init,
+                ..
+            } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.assignment(lhs, rhs, assign_mode, is_init);
+            }
+            NDINT { .. } | NDLVAR { .. } | NDGVAR { .. } | NDPROTOTY { .. } => (),
+            _ => {
+                let msg = format!("{} is not an expression", name);
+                self.error(&msg);
+            }
+        }
+        node.populate_ty();
+        self.span = outer;
+    }
+
+    fn comparison(&mut self, lhs: &Node, rhs: &Node, equality: bool) {
+        let (l, r) = (lhs.ty.as_ref().unwrap(), rhs.ty.as_ref().unwrap());
+        let ok = if l.is_integral() && r.is_integral() {
+            true
+        } else if is_pointer(l) && is_pointer(r) {
+            if !pointers_compatible(l, r) {
+                self.warn("Comparison of distinct pointer types");
+            }
+            true
+        } else if (is_pointer(l) && is_null(rhs)) || (is_pointer(r) && is_null(lhs)) {
+            if !equality {
+                self.warn("Ordered comparison of a pointer with a null pointer constant");
+            }
+            true
+        } else {
+            false
+        };
+        if !ok {
+            if is_pointer(l) || is_pointer(r) {
+                self.error("Comparison between a pointer and an integer");
+            } else {
+                self.error("Invalid operands to a comparison");
+            }
+        }
+    }
+
+    fn assignment(&mut self, lhs: &Node, rhs: &Node, mode: AssignMode, is_init: bool) {
+        use AssignMode::*;
+
+        let l = lhs.ty.as_ref().unwrap();
+        // A string literal is an array, though typed as a pointer to char
+        let literal = matches!(lhs.kind, NodeKind::NDSTR { .. });
+        if !is_lvalue(lhs) || literal || l.is_array() || l.is_function() {
+            self.error("Expression is not assignable");
+            return;
+        }
+        if l.is_const && !is_init {
+            self.error("Assignment to a const-qualified object");
+        }
+        let r = rhs.ty.as_ref().unwrap();
+        match mode {
+            DEFAULT => self.check_assign(l, rhs),
+            ADD | SUB if is_pointer(l) => {
+                if !r.is_integral() {
+                    self.error("Invalid operands to a compound assignment");
+                }
+                self.pointer_arith(l);
+            }
+            _ => {
+                if !l.is_integral() || !r.is_integral() {
+                    self.error("Invalid operands to a compound assignment");
+                }
+            }
+        }
+    }
+
+    // Stepping a pointer takes the size of what it points to
+    fn pointer_arith(&mut self, ty: &Type) {
+        if !ty.is_ptr_like() {
+            return;
+        }
+        let base = ty.clone_base();
+        if base.is_void() {
+            self.warn("Arithmetic on a void pointer is a GNU extension");
+        } else if base.is_function() {
+            let msg = format!("Arithmetic on a pointer to the function type '{}'", base);
+            self.error(&msg);
+        } else if base.is_incomplete() {
+            let msg = format!("Arithmetic on a pointer to the incomplete type '{}'", base);
+            self.error(&msg);
+        }
+    }
+
+    // Whether the value of rhs may be stored into an object of type ty,
+    // as in assignments, initializers and returns
+    fn check_assign(&mut self, ty: &Type, rhs: &Node) {
+        if let Some(msg) = self.assign_error(ty, rhs) {
+            self.error(&msg);
+        }
+    }
+
+    fn assign_error(&mut self, ty: &Type, rhs: &Node) -> Option<String> {
+        let r = rhs.ty.as_ref().unwrap();
+        let ok = if ty.is_integral() {
+            r.is_integral()
+        } else if is_pointer(ty) {
+            if is_pointer(r) {
+                if !pointers_compatible(ty, r) {
+                    self.warn(&format!(
+                        "Storing {} into {} with incompatible pointer types",
+                        r, ty
+                    ));
+                } else if r.decay().clone_base().is_const && !ty.clone_base().is_const {
+                    self.warn(&format!("Storing {} into {} discards const", r, ty));
+                }
+                true
+            } else {
+                is_null(rhs)
+            }
+        } else {
+            ty.is_compatible(r)
+        };
+        if ok {
+            None
+        } else if is_pointer(ty) && r.is_integral() {
+            Some(format!("Storing an integer into {} without a cast", ty))
+        } else if ty.is_integral() && is_pointer(r) {
+            Some(format!("Storing {} into an integer without a cast", r))
+        } else {
+            Some(format!("Incompatible types: storing {} into {}", r, ty))
+        }
+    }
+
+    // Aggregates that fit no register are only ever copied in memory, which
+    // calls and returns do not do yet
+    fn by_value(&mut self, what: &str, ty: &Type) {
+        if ty.in_memory() {
+            let kind = if ty.is_union() { "union" } else { "struct" };
+            let msg = format!(
+                "{} a {} of {} bytes by value is not supported yet",
+                what,
+                kind,
+                ty.size()
+            );
+            self.error(&msg);
+        }
+    }
+
+    // Checks the arguments against the parameters of the callee and
+    // converts each to the type the callee expects
+    fn call(&mut self, callee: &Node, args: &mut LinkedList<Node>) {
+        let functy = match callee.ty.as_ref().unwrap().callee_function() {
+            Some(functy) => functy.clone(),
+            None => return,
+        };
+        let (name, declared) = match callee.kind {
+            NodeKind::NDPROTOTY { ref name } => {
+                let declared = self
+                    .prototypes
+                    .iter()
+                    .rev()
+                    .find(|(proto, ty, _)| proto == name && ty.func_params().is_some())
+                    .map(|(_, _, span)| format!(" (declared at {}:{})", span.lo.line, span.lo.col));
+                (name.clone(), declared.unwrap_or_default())
+            }
+            _ => ("a function pointer".to_string(), String::new()),
+        };
+
+        // Without a prototype only the default promotions apply
+        let (params, variadic) = functy.func_params().unwrap_or((&[], true));
+        if args.len() < params.len() || args.len() > params.len() && !variadic {
+            let msg = format!(
+                "Too {} arguments to {}, expected {}{} but got {}{}",
+                if args.len() < params.len() {
+                    "few"
+                } else {
+                    "many"
+                },
+                name,
+                if variadic { "at least " } else { "" },
+                params.len(),
+                args.len(),
+                declared
+            );
+            self.error(&msg);
+        }
+        for (i, arg) in args.iter_mut().enumerate() {
+            let outer = self.span;
+            self.span = arg.span.or(outer);
+            self.by_value("Passing", arg.ty.as_ref().unwrap());
+            match params.get(i) {
+                Some((_, ty)) => match self.assign_error(ty, arg) {
+                    Some(msg) => {
+                        let msg = format!("Argument {} to {}: {}{}", i + 1, name, msg, declared);
+                        self.error(&msg);
+                    }
+                    None => convert(arg, ty),
+                },
+                None => {
+                    let argty = arg.ty.as_ref().unwrap();
+                    if argty.is_integral() && argty.size() < 4 {
+                        convert(arg, &Type::new_base("int"));
+                    }
+                }
+            }
+            self.span = outer;
+        }
+    }
+}
+
+// Spelling of an integer operator, for diagnostics
+fn operator(kind: &NodeKind) -> &'static str {
+    use NodeKind::*;
+    match kind {
+        NDMUL { .. } => "*",
+        NDDIV { .. } => "/",
+        NDMOD { .. } => "%",
+        NDBITAND { .. } => "&",
+        NDBITXOR { .. } => "^",
+        NDBITOR { .. } => "|",
+        NDSHL { .. } => "<<",
+        NDSHR { .. } => ">>",
+        _ => unreachable!(),
+    }
+}
+
+// Pointer after decay, i.e. a pointer, an array or a function designator
+fn is_pointer(ty: &Type) -> bool {
+    ty.is_ptr_like() || ty.is_function()
+}
+
+fn is_scalar(ty: &Type) -> bool {
+    ty.is_scalar() || is_pointer(ty)
+}
+
+// Either points to void or both point to compatible types
+fn pointers_compatible(a: &Type, b: &Type) -> bool {
+    let (a, b) = (a.decay(), b.decay());
+    a.is_void_ptr() || b.is_void_ptr() || a.is_compatible(&b)
+}
+
+// Wraps an integer in a conversion to the integer type ty
+fn convert(node: &mut Node, ty: &Type) {
+    let from = node.ty.as_ref().unwrap();
+    if !ty.is_integral() || !from.is_integral() || from.is_compatible(ty) {
+        return;
+    }
+    let span = node.span;
+    let operand = std::mem::replace(node, Node::new_int(0));
+    let mut ty = ty.clone();
+    ty.set_type_qual(false, false);
+    *node = Node::new_cast(operand, ty);
+    node.span = span;
+}
+
+// Null pointer constant, i.e. an integer constant expression of value 0
+fn is_null(node: &Node) -> bool {
+    node.ty.as_ref().unwrap().is_integral() && constexpr::eval_int(node) == Some(0)
+}
+
+fn is_lvalue(node: &Node) -> bool {
+    use NodeKind::*;
+    match node.kind {
+        NDLVAR { .. } | NDGVAR { .. } | NDDEREF { .. } | NDSTR { .. } => true,
+        NDMEMBER { ref node, .. } => is_lvalue(node),
+        _ => false,
+    }
+}
@@ -1,42 +1,94 @@
 use std::collections::LinkedList;
+use std::fmt;
 
 static ASSIGN_OPS: [&str; 11] = [
     "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "|=", "^=",
 ];
 static STORAGE_CLASSES: [&str; 5] = ["typedef", "extern", "static", "auto", "register"];
-static TYPES: [&str; 7] = ["void", "char", "short", "int", "long", "struct", "enum"];
+static TYPES: [&str; 8] = [
+    "void", "char", "short", "int", "long", "struct", "union", "enum",
+];
 static TYPE_QUALS: [&str; 2] = ["const", "volatile"];
 static KEYWORDS: [&str; 12] = [
     "return", "if", "else", "while", "for", "sizeof", "break", "continue", "do", "switch", "case",
     "default",
 ];
 
+#[allow(dead_code)] // For storage class support
 fn is_storage_class(s: &str) -> bool {
-    match s {
-        _s if STORAGE_CLASSES.contains(&_s) => true,
-        _ => false,
+    STORAGE_CLASSES.contains(&s)
+}
+
+/// Decodes the escape sequences of a string literal, which is otherwise
+/// kept as written so that the assembler can read it back
+pub fn unescape(s: &str) -> Vec<u8> {
+    decode(s).0
+}
+
+// The bytes of a string literal, and whether the value of every escape
+// fits in a byte. Values that do not are truncated.
+fn decode(s: &str) -> (Vec<u8>, bool) {
+    let mut bytes = s.bytes().peekable();
+    let mut out = Vec::new();
+    let mut in_range = true;
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        let c = match bytes.next() {
+            Some(c) => c,
+            None => break,
+        };
+        let decoded = match c {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'v' => 0x0b,
+            b'0'..=b'7' => {
+                let mut v = (c - b'0') as u32;
+                for _ in 0..2 {
+                    match bytes.peek() {
+                        Some(&d @ b'0'..=b'7') => {
+                            v = v * 8 + (d - b'0') as u32;
+                            bytes.next();
+                        }
+                        _ => break,
+                    }
+                }
+                in_range &= v <= 0xff;
+                v as u8
+            }
+            b'x' => {
+                let mut v = 0u32;
+                while let Some(d) = bytes.peek().and_then(|&d| (d as char).to_digit(16)) {
+                    v = (v << 4 | d) & 0xfff;
+                    in_range &= v <= 0xff;
+                    bytes.next();
+                }
+                v as u8
+            }
+            // \\, \', \" and \? stand for themselves
+            c => c,
+        };
+        out.push(decoded);
     }
+    (out, in_range)
 }
 
 fn is_type(s: &str) -> bool {
-    match s {
-        _s if TYPES.contains(&_s) => true,
-        _ => false,
-    }
+    TYPES.contains(&s)
 }
 
 fn is_type_qual(s: &str) -> bool {
-    match s {
-        _s if TYPE_QUALS.contains(&_s) => true,
-        _ => false,
-    }
+    TYPE_QUALS.contains(&s)
 }
 
 fn is_assign_op(s: &str) -> bool {
-    match s {
-        _s if ASSIGN_OPS.contains(&_s) => true,
-        _ => false,
-    }
+    ASSIGN_OPS.contains(&s)
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -48,11 +100,32 @@ pub enum TokenKind {
     TKEOF,
 }
 
+/// Line and column in the source, both starting at 1
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Default)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// Source range covered by a token or a node; hi is exclusive
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Span {
+    pub lo: Pos,
+    pub hi: Pos,
+}
+
 #[derive(Debug)]
 pub struct Token {
     pub kind: TokenKind,
     pub string: Option<String>,
-    val: i32,
+    pub val: i32,
+    pub span: Span,
 }
 
 pub struct Tokenizer {
@@ -62,15 +135,17 @@ pub struct Tokenizer {
 pub struct TokenIter {
     buf: LinkedList<Token>,
     tokens: LinkedList<Token>,
+    last: Span, // Of the most recently consumed token
 }
 
 impl Token {
     fn new(kind: TokenKind) -> Self {
         // TODO: Can we have some default behavior?
         Token {
-            kind: kind,
+            kind,
             string: None,
             val: 0,
+            span: Span::default(),
         }
     }
 
@@ -93,24 +168,29 @@ impl Tokenizer {
         }
     }
 
-    // Tokenizes the passed str
-    pub fn tokenize(mut self, in_str: String) -> TokenIter {
+    // Tokenizes the passed str, or gives where and why it cannot
+    pub fn tokenize(mut self, in_str: String) -> Result<TokenIter, (Span, String)> {
         use TokenKind::*;
 
         let len = in_str.len();
         let mut cur = 0;
+        let line_starts = line_starts(&in_str);
+        let span = |lo, hi| Span {
+            lo: locate(&line_starts, lo),
+            hi: locate(&line_starts, hi),
+        };
 
         while cur != len {
             let c = in_str.chars().nth(cur).unwrap();
+            let start = cur;
+            let num_tokens = self.tokens.len();
             match c {
                 c if c.is_whitespace() => {
                     cur += 1;
-                    continue;
                 }
 
                 '\n' => {
                     cur += 1;
-                    continue;
                 }
 
                 '"' => {
@@ -124,9 +204,12 @@ impl Tokenizer {
                         }
                         str_literal.push(_c);
                     }
+                    if !decode(&str_literal).1 {
+                        let msg = "Escape sequence out of range.".to_string();
+                        return Err((span(start, cur), msg));
+                    }
                     self.tokens
                         .push_back(Token::new(TKSTR).string(&str_literal));
-                    continue;
                 }
 
                 '<' => {
@@ -147,7 +230,6 @@ impl Tokenizer {
                         c.to_string()
                     };
                     self.tokens.push_back(Token::new(TKRESERVED).string(&tkstr));
-                    continue;
                 }
 
                 '>' => {
@@ -168,7 +250,6 @@ impl Tokenizer {
                         c.to_string()
                     };
                     self.tokens.push_back(Token::new(TKRESERVED).string(&tkstr));
-                    continue;
                 }
 
                 '+' => {
@@ -183,7 +264,6 @@ impl Tokenizer {
                         c.to_string()
                     };
                     self.tokens.push_back(Token::new(TKRESERVED).string(&tkstr));
-                    continue;
                 }
 
                 '-' => {
@@ -201,7 +281,6 @@ impl Tokenizer {
                         c.to_string()
                     };
                     self.tokens.push_back(Token::new(TKRESERVED).string(&tkstr));
-                    continue;
                 }
 
                 '%' | '*' | '!' | '=' | '^' => {
@@ -213,7 +292,6 @@ impl Tokenizer {
                         c.to_string()
                     };
                     self.tokens.push_back(Token::new(TKRESERVED).string(&tkstr));
-                    continue;
                 }
 
                 '/' => {
@@ -240,7 +318,6 @@ impl Tokenizer {
                     if let Some(tk) = tkstr {
                         self.tokens.push_back(Token::new(TKRESERVED).string(&tk));
                     }
-                    continue;
                 }
 
                 '&' => {
@@ -255,7 +332,6 @@ impl Tokenizer {
                         c.to_string()
                     };
                     self.tokens.push_back(Token::new(TKRESERVED).string(&tkstr));
-                    continue;
                 }
 
                 '|' => {
@@ -270,14 +346,17 @@ impl Tokenizer {
                         c.to_string()
                     };
                     self.tokens.push_back(Token::new(TKRESERVED).string(&tkstr));
-                    continue;
+                }
+
+                '.' if in_str[cur..].starts_with("...") => {
+                    self.tokens.push_back(Token::new(TKRESERVED).string("..."));
+                    cur += 3;
                 }
 
                 '(' | ')' | ':' | ';' | '{' | '}' | '.' | ',' | '[' | ']' | '~' | '?' => {
                     self.tokens
                         .push_back(Token::new(TKRESERVED).string(&c.to_string()));
                     cur += 1;
-                    continue;
                 }
 
                 c if c.is_ascii_alphabetic() || c == '_' => {
@@ -300,14 +379,13 @@ impl Tokenizer {
                                 || TYPE_QUALS.contains(&ident_name) =>
                         {
                             self.tokens
-                                .push_back(Token::new(TKRESERVED).string(&ident_name));
+                                .push_back(Token::new(TKRESERVED).string(ident_name));
                         }
                         _ => {
                             self.tokens
                                 .push_back(Token::new(TKIDENT).string(&ident_name));
                         }
                     }
-                    continue;
                 }
 
                 c if c.is_numeric() => {
@@ -322,17 +400,51 @@ impl Tokenizer {
                         cur += 1;
                     }
                     self.tokens.push_back(Token::new(TKNUM).val(val));
-                    continue;
                 }
 
-                unmatched => panic!("Unexpected char: {}.", unmatched),
+                unmatched => {
+                    let msg = format!("Unexpected char: {}.", unmatched);
+                    return Err((span(start, start + 1), msg));
+                }
+            }
+
+            if self.tokens.len() > num_tokens {
+                self.tokens.back_mut().unwrap().span = span(start, cur);
             }
         }
 
         // Finally add tof
-        self.tokens.push_back(Token::new(TKEOF));
+        let eof_pos = locate(&line_starts, len);
+        let mut eof = Token::new(TKEOF);
+        eof.span = Span {
+            lo: eof_pos,
+            hi: eof_pos,
+        };
+        self.tokens.push_back(eof);
+
+        Ok(TokenIter::new(self.tokens))
+    }
+}
+
+// Indices at which each line of in_str begins
+fn line_starts(in_str: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    for (i, c) in in_str.chars().enumerate() {
+        if c == '\n' {
+            starts.push(i + 1);
+        }
+    }
+    starts
+}
 
-        TokenIter::new(self.tokens)
+fn locate(line_starts: &[usize], idx: usize) -> Pos {
+    let line = match line_starts.binary_search(&idx) {
+        Ok(line) => line,
+        Err(next) => next - 1,
+    };
+    Pos {
+        line: line + 1,
+        col: idx - line_starts[line] + 1,
     }
 }
 
@@ -340,40 +452,82 @@ impl TokenIter {
     pub fn new(tokens: LinkedList<Token>) -> Self {
         TokenIter {
             buf: LinkedList::new(),
-            tokens: tokens,
+            tokens,
+            last: Span::default(),
         }
     }
 
-    pub fn expect(&mut self, s: &str) {
-        let t = self.next();
-        if t.kind != TokenKind::TKRESERVED {
-            panic!("TokenIter: Expected reserved token {}", s);
+    /// Iterates over the remaining tokens without consuming them
+    pub fn iter(&self) -> std::collections::linked_list::Iter<'_, Token> {
+        self.tokens.iter()
+    }
+
+    /// Whether the n-th upcoming token is s, without consuming anything
+    pub fn peek_is(&self, n: usize, s: &str) -> bool {
+        match self.tokens.iter().nth(n) {
+            Some(t) => t.kind == TokenKind::TKRESERVED && t.string.as_deref() == Some(s),
+            None => false,
         }
-        if let Some(ref tkstr) = t.string {
-            if tkstr != s {
-                panic!(
-                    "TokenIter: Wrong token string! Expected {} but got {}",
-                    s, tkstr
-                );
+    }
+
+    /// Whether the n-th upcoming token is an identifier
+    pub fn peek_is_ident(&self, n: usize) -> bool {
+        matches!(self.tokens.iter().nth(n), Some(t) if t.kind == TokenKind::TKIDENT)
+    }
+
+    pub fn peek_is_str(&self, n: usize) -> bool {
+        matches!(self.tokens.iter().nth(n), Some(t) if t.kind == TokenKind::TKSTR)
+    }
+
+    /// Whether the n-th upcoming token can begin a type name
+    pub fn starts_type_name(&self, n: usize) -> bool {
+        match self.tokens.iter().nth(n) {
+            Some(t) if t.kind == TokenKind::TKRESERVED => {
+                let s = t.string.as_ref().unwrap();
+                is_type(s) || is_type_qual(s)
             }
-        } else {
-            panic!("TokenIter: Expected that string exists.")
+            _ => false,
         }
     }
 
-    pub fn expect_type(&mut self) -> String {
+    /// Whether the nth token starts the specifiers of a declaration
+    pub fn starts_declaration(&self, n: usize) -> bool {
+        self.starts_type_name(n)
+            || matches!(self.tokens.iter().nth(n), Some(t) if t.kind == TokenKind::TKRESERVED
+                && is_storage_class(t.string.as_ref().unwrap()))
+    }
+
+    /// Start of the next token
+    pub fn peek_pos(&self) -> Pos {
+        self.tokens.front().unwrap().span.lo
+    }
+
+    /// Span of the last consumed token
+    pub fn last_span(&self) -> Span {
+        self.last
+    }
+
+    /// Span from lo up to the end of the last consumed token
+    pub fn span_from(&self, lo: Pos) -> Span {
+        Span {
+            lo,
+            hi: self.last.hi,
+        }
+    }
+
+    pub fn expect(&mut self, s: &str) {
         let t = self.next();
-        if is_type(t.string.as_ref().unwrap().as_str()) {
-            t.string.as_ref().unwrap().to_string()
-        } else {
-            panic!("TokenIter: Expected type specifier.");
+        match t.string {
+            Some(ref tkstr) if t.kind == TokenKind::TKRESERVED && tkstr == s => (),
+            Some(ref tkstr) => panic!("error: Expected '{}' but got '{}'", s, tkstr),
+            None => panic!("error: Expected '{}'", s),
         }
     }
 
     pub fn expect_number(&mut self) -> i32 {
         let t = self.next();
         if t.kind != TokenKind::TKNUM {
-            panic!("TokenIter: Expected number.")
+            panic!("error: Expected number")
         }
 
         t.val
@@ -382,7 +536,7 @@ impl TokenIter {
     pub fn expect_ident(&mut self) -> String {
         let t = self.next();
         if t.kind != TokenKind::TKIDENT {
-            panic!("TokenIter: Expected ident.")
+            panic!("error: Expected identifier")
         }
 
         t.string.clone().unwrap()
@@ -390,15 +544,23 @@ impl TokenIter {
 
     pub fn delay(&mut self) -> (TokenKind, Option<String>) {
         let t = self.next();
-        let kind = t.kind.clone();
+        let kind = t.kind;
         let maybe_str = t.string.clone();
         self.buf.push_back(t);
         (kind, maybe_str)
     }
 
-    pub fn commit_delay(&mut self) {
+    /// Marks where the tokens delayed from now on begin, so that delays
+    /// can nest
+    pub fn delay_mark(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Puts the tokens delayed since mark back in front of the rest
+    pub fn commit_delay(&mut self, mark: usize) {
         // TODO: Use prepend when stable
-        while let Some(t) = self.buf.pop_back() {
+        while self.buf.len() > mark {
+            let t = self.buf.pop_back().unwrap();
             self.tokens.push_front(t);
         }
     }
@@ -492,28 +654,6 @@ impl TokenIter {
         ret
     }
 
-    pub fn is_func(&self) -> bool {
-        let mut iter = self.tokens.iter();
-
-        let t = iter.next().unwrap();
-        if !is_type(t.string.as_ref().unwrap().as_str()) {
-            panic!("Illegal!")
-        }
-
-        loop {
-            if iter.next().unwrap().string.as_ref().unwrap().as_str() != "*" {
-                break;
-            }
-        }
-
-        if let Some(tk) = iter.next().unwrap().string.as_ref() {
-            if tk.as_str() == "(" {
-                return true;
-            }
-        }
-        return false;
-    }
-
     pub fn at_eof(&mut self) -> bool {
         self.peek().kind == TokenKind::TKEOF
     }
@@ -525,6 +665,8 @@ impl TokenIter {
 
     // Wrapper to hide option unwrapping
     fn next(&mut self) -> Token {
-        self.tokens.pop_front().unwrap()
+        let t = self.tokens.pop_front().unwrap();
+        self.last = t.span;
+        t
     }
 }
@@ -0,0 +1,44 @@
+// Buffered x86 output and the peephole pass over it
+#[macro_use]
+mod macros;
+
+test_succeed! {
+    no_peephole: (["--no-peephole", "tests/ctests/regalloc.c"], 0),
+    peephole_spills: (["-O2", "tests/ctests/regalloc.c"], 0),
+    peephole_char_store: (["int main() { char c[2]; c[1] = 300; return c[1]; }"], 44),
+    sections: (["tests/ctests/sections.c"], 0),
+    peephole_big_const: (["int main() { long a = 65536; a = a * 65536; long b = a; return (b >> 32) + 1; }"], 2),
+}
+
+test_pattern! {
+    fold_address: (["--dump-asm", "int main() { int a = 3; return a; }"],
+                   "  mov dword ptr [rbp-4], 3\n  movsxd {r}, dword ptr [rbp-4]\n"),
+    fold_compare: (["--dump-asm", "int main() { int a = 3; if (a < 2) return 1; return 0; }"],
+                   "  cmp {r}, 2\n  jge .LBB0_2\n"),
+    fold_compare_zero: (["--dump-asm", "int main() { int a = 1; int b = 2; if (a - b) return 1; return 0; }"],
+                        "  sub {a}, {b}\n  je .LBB0_2\n  mov rax, 1\n"),
+    no_jump_to_next: (["--dump-asm", "int main() { int a = 0; while (a) a = a - 1; return a; }"], "  cmp {r}, 0\n  je .LBB0_3\n  movsxd"),
+    unused_save: (["--dump-asm", "int main() { int a = 3; if (a < 2) return 1; return 0; }"],
+                  "main:\n  push rbp\n  mov rbp, rsp\n  sub rsp, 16\n  mov dword ptr"),
+    switch_table: (["--dump-asm", "-fno-pic", "int main() { int a = 2; switch (a) { case 1: case 2: case 4: return 1; case 3: return 2; } return 0; }"],
+                   "  cmp {i}, 3\n  ja .LBB0_5\n  jmp qword ptr [.LJT0_0+{i}*8]\n"),
+    switch_table_data: (["--dump-asm", "-fno-pic", "int main() { int a = 2; switch (a) { case 1: case 2: case 4: return 1; case 3: return 2; } return 0; }"],
+                        ".LJT0_0:\n  .quad .LBB0_1\n  .quad .LBB0_2\n  .quad .LBB0_4\n  .quad .LBB0_3\n"),
+    switch_search: (["--dump-asm", "int main() { int a = 2; switch (a) { case 1: case 20: case 400: case 8000: return 1; } return 0; }"],
+                    "  cmp {r}, 400\n  je .LBB0_3\n  jg .LSW0_0\n"),
+    common_symbol: (["--dump-asm", "int a; int a; int b[3] = {1}; int main() { return a; }"],
+                    ".comm a, 4, 4\n\n.data\n.global b\n"),
+    object_symbol: (["--dump-asm", "int b[3] = {1}; int main() { return b[0]; }"],
+                    ".global b\n.type b, @object\n.size b, 12\n.p2align 2\nb:\n  .long 1\n  .long 0\n"),
+    function_symbol: (["--dump-asm", "int main() { return 0; }"], ".global main\n.type main, @function\nmain:\n"),
+    function_size: (["--dump-asm", "int main() { return 0; }"], "  ret\n.size main, .-main\n"),
+    rodata_const: (["--dump-asm", "const int c[2] = {1, 2}; int main() { return c[1]; }"], ".section .rodata\n.global c\n"),
+    object_align: (["--dump-asm", "char s[3] = \"ab\"; const long r = 7; int main() { return r; }"],
+                   ".size s, 3\n.p2align 0\ns:\n  .byte 97\n  .byte 98\n  .byte 0\n\n.section .rodata\n.global r\n.type r, @object\n.size r, 8\n.p2align 3\nr:\n"),
+    bss_zero: (["--dump-asm", "int z[2] = {0}; long y = 0; int main() { return z[1]; }"],
+               ".bss\n.global z\n.type z, @object\n.size z, 8\n.p2align 3\nz:\n  .zero 8\n.global y\n"),
+    merged_literals: (["--dump-asm", "int main() { char *a = \"hi\"; char *b = \"hi\"; return a == b; }"],
+                      ".section .rodata.str1.1,\"aMS\",@progbits,1\n.Lstr0:\n.Lstr1:\n  .string \"hi\"\n"),
+    literal_with_nul: (["--dump-asm", "int main() { char *a = \"a\\0b\"; return a[2]; }"], ".section .rodata\n.Lstr0:\n"),
+    raw_asm: (["--dump-asm", "--no-peephole", "int main() { int a = 3; return a; }"], "  lea {a}, [rbp-4]\n  mov {v}, 3\n  mov [{a}], "),
+}
@@ -102,6 +102,8 @@ test_succeed! {
     //sizeof6: ("int main() { int x; return sizeof (x + 1); }", 4),
     sizeof7: ("int main() { int array[10]; return sizeof array; } ", 40),
     sizeof8: ("int main() { int *arr[13]; return sizeof arr; }", 104),
+    sizeof9: ("int main() { int a[4]; return sizeof(a + 0) == 8 && sizeof(1 + a) == 8 && sizeof(a - 0) == 8; }", 1),
+    sizeof10: ("int main() { int a[4]; int *p; return sizeof(1 ? a : p) + sizeof(0 ? p : a); }", 16),
     //ternary0: ("int main() { int a = 4; return a > 4 ? 1 : 5; }", 5),
     func0: ("int foo() { return 123; } int main() { return foo(); }", 123),
     func1: ("int bar () { 24; }
@@ -13,8 +13,22 @@ test_succeed! {
     gvar3: ("int a[4]; int main() { a[0] = 3; a[1] = a[0]+2; a[2] = a[1]*a[0]; return a[2]; }", 15),
     inits: ("tests/ctests/init.c", 0),
     literals: ("tests/ctests/literal.c", 1),
+    redeclare: ("tests/ctests/redeclare.c", 0),
 }
 
 test_fail! {
     no_prototype: ("int main() { return hoge(); }"),
+    conflicting_prototypes: ("int f(int); int f(char *); int main() { return 0; }"),
+    unprototyped_char_param: ("int f(); int f(char c); int main() { return 0; }"),
+    func_redefinition: ("int f(int a) { return a; } int f(int b) { return b; } int main() { return 0; }"),
+    def_without_params: ("int f(int a); int f() { return 0; } int main() { return 0; }"),
+    var_then_func: ("int x; int x(); int main() { return 0; }"),
+    func_then_var: ("int x(); int x; int main() { return 0; }"),
+    global_conflict: ("int x; char x; int main() { return 0; }"),
+    local_redefinition: ("int main() { int a; int a; return 0; }"),
+    param_redefinition: ("int f(int a, int a) { return a; } int main() { return 0; }"),
+    param_local_redefinition: ("int f(int a) { int a; return a; } int main() { return 0; }"),
+    local_then_extern: ("int main() { int a; extern int a; return 0; }"),
+    block_extern_conflict: ("int g; int main() { extern char g; return 0; }"),
+    enum_const_redefinition: ("int main() { enum { A }; int A; return 0; }"),
 }
@@ -0,0 +1,124 @@
+// Programs interpreted with --check, which stops at undefined behaviour
+#[macro_use]
+mod macros;
+
+use assert_cmd::prelude::*;
+use std::process::Command;
+
+// array.c is checked for its error below
+mod fixtures {
+    fixtures!(test_run, ["--check"], defined);
+}
+
+test_run! {
+    check_one_past_end: (["--check", "int main() { int a[3]; a[2] = 5; int *p = a + 3; return p[-1]; }"], 5),
+    check_char_promotion: (["--check", "int main() { char c = 127; c = c + 1; return c < 0; }"], 1),
+    check_unsigned_wrap: (["--check", "int main() { long a = 2147483647; return a + 1 > 0; }"], 1),
+    check_calloc: (["--check", "void *calloc(); int main() { int *p = calloc(2, 4); return p[1]; }"], 0),
+    check_block_lifetime: (["--check", "int main() { int i; int s = 0; for (i = 0; i < 3; i++) { int x = i; s = s + x; } return s; }"], 3),
+    check_partial_struct_copy: (["--check", "struct S { int x; int y; }; int main() { struct S a; struct S b; a.x = 1; b = a; return b.x; }"], 1),
+    check_partial_struct_pass: (["--check", "struct S { int x; int y; }; int f(struct S s) { return s.x; } int main() { struct S a; a.x = 2; return f(a); }"], 2),
+}
+
+fn check(src: &str) -> String {
+    let out = Command::cargo_bin("rcc")
+        .unwrap()
+        .args(["--check", src])
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), out.status.code(), "{}", src);
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+#[test]
+fn check_errors() {
+    for (src, msg) in [
+        (
+            "int main() { int a; return a + 1; }",
+            "1:28: Read of the uninitialized local a",
+        ),
+        (
+            "struct S { int x; int y; }; int main() { struct S a; struct S b; a.x = 1; b = a; return b.y; }",
+            "Read of the uninitialized local b",
+        ),
+        (
+            "int main() { int a = 2147483647; int b = 1; return a + b; }",
+            "1:52: Signed overflow: 2147483647 + 1 does not fit in int",
+        ),
+        (
+            "int main() { long a = 1073741824; a = a * a * 4; return a * 2 > 0; }",
+            "Signed overflow: 4611686018427387904 * 2 does not fit in long",
+        ),
+        (
+            "int main() { int a = 2147483647; a += 1; return 0; }",
+            "1:34: Signed overflow: 2147483647 + 1 does not fit in int",
+        ),
+        (
+            "int main() { int a = -2147483647 - 1; int b = -1; return a / b; }",
+            "Signed overflow: -2147483648 / -1 does not fit in int",
+        ),
+        (
+            "int main() { int a = 7; int b = 0; return a % b; }",
+            "1:43: Division by zero",
+        ),
+        (
+            "int main() { int a = 1; int b = 32; return a << b; }",
+            "1:44: Shift by 32 bits of a 32-bit int",
+        ),
+        (
+            "int main() { int a = -1; return a << 1; }",
+            "Left shift of the negative value -1",
+        ),
+        (
+            "int main() { int a[4]; int *p = a; p = p + 5; return 0; }",
+            "1:40: Pointer arithmetic moves from offset 0 to 20, outside an object of 16 bytes",
+        ),
+        (
+            "int main() { int a[4]; int i = 4; a[i] = 1; return 0; }",
+            "1:35: Access of 4 bytes at offset 16 into an object of 16 bytes",
+        ),
+        (
+            "int main() { int a; int b; return &a - &b; }",
+            "Subtraction of pointers into different objects",
+        ),
+        (
+            "void *malloc(); int main() { int *p = malloc(8); return p[1]; }",
+            "Read of uninitialized memory",
+        ),
+        (
+            "struct S { int a; int b; }; int main() { struct S s; s.a = 1; return s.b; }",
+            "Read of the uninitialized local s",
+        ),
+    ] {
+        let stderr = check(src);
+        assert!(stderr.contains(msg), "{}: {}", src, stderr);
+    }
+}
+
+#[test]
+fn check_trace() {
+    let stderr = check(
+        "int *f(int v) { int x = v; return &x; }\n\
+         int g(int *p) { return *p + 1; }\n\
+         int main() { int *p = f(3); return g(p); }",
+    );
+    let expected = "yarcc: run: 2:24: Access to x, a local of f, after it returned\n  \
+                    in `*p`\n    \
+                    2:25: p = offset 0 into x of f, which returned\n  \
+                    in g called at 3:36\n  \
+                    in main\n";
+    assert_eq!(expected, stderr);
+
+    let stderr = check("int main() { int a[3]; int i = 3; a[0] = 1; return a[i] + a[0]; }");
+    for line in ["in `a[i]`", "&a[i] = offset 12 into a of main", "i = 3"] {
+        assert!(stderr.contains(line), "{}", stderr);
+    }
+}
+
+#[test]
+fn check_ctests() {
+    // Runs to completion natively, but only by luck
+    let stderr = check("tests/ctests/array.c");
+    let msg = "Pointer arithmetic moves from offset 96 to 112, outside an object of 96 bytes";
+    assert!(stderr.contains(msg), "{}", stderr);
+}
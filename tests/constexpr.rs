@@ -0,0 +1,31 @@
+// Constant expressions, casts, ?:, bit-fields and global initializers
+#[macro_use]
+mod macros;
+
+test_succeed! {
+    constexpr: ("tests/ctests/constexpr.c", 0),
+    ternary0: ("int main() { int a = 0; return a ? 3 : a + 4; }", 4),
+    ternary1: ("int main() { int a = 2; int b = 1; return a ? b ? 5 : 6 : 7; }", 5),
+    cast0: ("int main() { int a = 511; return (char) a + 1; }", 0),
+    global_array: ("int g[4] = {1, 2, 3}; int main() { return g[0] + g[2] + g[3]; }", 4),
+}
+
+test_fail! {
+    nonconst_case: ("int main() { int a = 1; switch (a) { case a: return 1; } return 0; }"),
+    nonconst_array: ("int main() { int n = 2; int a[n]; return 0; }"),
+    nonconst_global: ("int x; int y = x; int main() { return 0; }"),
+    narrow_addr_global: ("int x; int y = &x; int main() { return 0; }"),
+    bit_field_addr: ("int main() { struct { int a : 3; } s; int *p = &s.a; return 0; }"),
+    bit_field_wide: ("struct { char c : 9; } s; int main() { return 0; }"),
+}
+
+test_pattern! {
+    fold0: (["--dump-ir", "int main() { return (1 << 4) + 2 * 3 - (char) 257; }"], "  {c} = const 21\n  ret {c}\n"),
+    global_data0: (["--dump-ir", "int g[3] = {1, 2}; int *p = g + 1; int main() { return 0; }"],
+                   "global g [12 bytes]\n  +0 i32 1\n  +4 i32 2\n  +8 i32 0\nglobal p [8 bytes]\n  +0 i64 g+4\n"),
+    ptr_diff0: (["--dump-ir", "int g[4]; long n = &g[3] - g; int main() { return 0; }"], "global n [8 bytes]\n  +0 i64 3\n"),
+    cond_ast: (["--dump-ast", "int main() { int a; return a ? 1 : 2; }"], "cond: NDLVAR a -4 <int>"),
+    bit_field_ast: (["--dump-ast", "int main() { struct { int a : 3; int b : 5; } s; return s.b; }"], "NDMEMBER b +0 bits 3:5 <int>"),
+    cast_c: (["--dump-c", "int main() { int a; return (char *) a; }"], "return ((char *) a);"),
+    roundtrip_constexpr: (["--round-trip", "tests/ctests/constexpr.c"], ""),
+}
@@ -0,0 +1,42 @@
+// Arguments are converted to the parameter types of the prototype
+
+int printf(char *fmt, ...);
+
+long widen(long x) { return x; }
+int narrow(int x) { return x; }
+char to_char(char c) { return c; }
+short to_short(short s) { return s; }
+
+/* Declared before the definition, which is the one called */
+long sum3(long a, int b, char c);
+
+/* Parameters are only known from the definition below */
+int later();
+
+int main() {
+    int big = 65536;
+    long wide = 65536;
+
+    /* An int widens to a long with its sign */
+    if (widen(big) != (long)big || widen(-big) != -65536)
+        return 1;
+    wide = wide * wide + 5;
+    if (narrow(wide) != 5)
+        return 2;
+    if (to_char(300) != 44 || to_char(-1) != -1)
+        return 3;
+    if (to_short(65537) != 1)
+        return 4;
+    if (sum3(wide, 2, 257) != wide + 3)
+        return 5;
+    if (later(3, 4) != 7)
+        return 6;
+
+    /* The variadic part only gets promoted */
+    printf("%d %ld %s\n", to_char(65), wide, "ok");
+    return 0;
+}
+
+long sum3(long a, int b, char c) { return a + b + c; }
+
+int later(int a, int b) { return a + b; }
@@ -0,0 +1,100 @@
+
+enum { FOO = 3, BAR = FOO * 2, BAZ };
+enum { N = 4 };
+
+int g = 5;
+int garr[N * 2] = {1, 2, BAR};
+char gc = 300;
+int *gp = &g;
+int *gq = garr + 2;
+char *gstr = "hi";
+long gl = sizeof(int) << 3;
+
+int test_case(int x) {
+    switch (x) {
+        case FOO + 1:
+            return 1;
+        case BAR - 1:
+            return 2;
+        case BAZ == 7 ? 20 : 10:
+            return 3;
+    }
+    return 0;
+}
+
+int test_array_bound() {
+    int buf[N * 2];
+    char c[sizeof(int) + 1];
+    return sizeof(buf) + sizeof(c); // Expect: 37
+}
+
+int test_enum() {
+    return FOO + BAR + BAZ; // Expect: 16
+}
+
+int test_cast() {
+    int a = 300;
+    char c = (char) a;
+    short s = (short) 65537;
+    long l = (long) a * 2;
+    return c + s + l; // Expect: 44 + 1 + 600
+}
+
+int test_sizeof_type() {
+    return sizeof(int) + sizeof(char *) + sizeof(int[3]) + sizeof(struct { int x; char y; });
+}
+
+int test_ternary(int x) {
+    int y = x ? 7 : 9;
+    int *p = x ? &y : 0;
+    return y + (p ? *p : 100); // 14 when x, 109 otherwise
+}
+
+int test_bit_fields() {
+    struct {
+        int a : 3;
+        int b : 5;
+        int : 0;
+        int c : 4;
+        char d;
+    } s;
+    s.a = 3;
+    s.b = -2;
+    s.c = 7;
+    s.d = 1;
+    s.a += 1; // Overflows into -4
+    if (sizeof(s) != 9) return 1;
+    if (s.a != -4) return 2;
+    if (s.b != -2) return 3;
+    if (s.c != 7) return 4;
+    if (s.d != 1) return 5;
+    return 0;
+}
+
+int test_globals() {
+    if (g != 5) return 1;
+    if (garr[0] + garr[1] + garr[2] + garr[7] != 9) return 2;
+    if (gc != 44) return 3;
+    if (*gp != 5) return 4;
+    if (*gq != 6) return 5;
+    if (gstr[1] != 105) return 6;
+    if (gl != 32) return 7;
+    return 0;
+}
+
+int main() {
+    if (test_case(4) != 1) return 1;
+    if (test_case(5) != 2) return 2;
+    if (test_case(20) != 3) return 3;
+    if (test_array_bound() != 37) return 4;
+    if (test_enum() != 16) return 5;
+    if (test_cast() != 645) return 6;
+    if (test_sizeof_type() != 29) return 7;
+    if (test_ternary(1) != 14) return 8;
+    if (test_ternary(0) != 109) return 9;
+    if (test_bit_fields() != 0) return 10;
+    if (test_globals() != 0) return 11;
+
+    // Successful
+    return 0;
+}
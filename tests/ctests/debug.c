@@ -0,0 +1,49 @@
+// Something of every kind for the debug information to describe
+
+struct point {
+    int x;
+    int y;
+};
+
+struct shape {
+    char name[8];
+    struct point corners[4];
+    struct shape *next;
+    int filled : 1;
+};
+
+enum kind { CIRCLE, SQUARE = 4 } last_kind;
+
+int counter = 7;
+const char greeting[] = "hi";
+struct shape origin;
+
+int area(struct point *a, struct point *b) {
+    int w = b->x - a->x;
+    int h = b->y - a->y;
+    return w * h;
+}
+
+int count(struct shape *s) {
+    int n = 0;
+    for (; s; s = s->next)
+        n++;
+    return n;
+}
+
+int main() {
+    struct shape sq;
+    enum kind k = SQUARE;
+    last_kind = k;
+    sq.corners[0].x = 1;
+    sq.corners[0].y = 1;
+    sq.corners[2].x = 3;
+    sq.corners[2].y = 4;
+    sq.next = &origin;
+    sq.filled = 0;
+    if (area(&sq.corners[0], &sq.corners[2]) != 6)
+        return 1;
+    if (count(&sq) != 2 || k != 4 || counter != 7)
+        return 2;
+    return greeting[1] - 105;
+}
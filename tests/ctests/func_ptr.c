@@ -0,0 +1,83 @@
+// Function pointers and declarators with parentheses
+
+int count;
+
+int add1(int x) { return x + 1; }
+int dbl(int x) { return x * 2; }
+int three(void) { return 3; }
+void bump(void) { count = count + 1; }
+void bump2(void) { count = count + 2; }
+
+/* Unnamed parameters */
+int sum(int, long);
+int sum(int a, long b) { return a + b; }
+
+/* A parameter of function type is a pointer */
+int apply(int (*f)(int), int v) { return f(v); }
+int apply2(int f(int), int v) { return (*f)(v); }
+
+/* Returns a pointer to a function */
+int (*pick(int which))(int) {
+    if (which)
+        return dbl;
+    return add1;
+}
+
+char *name(int i) {
+    char *s = "xy";
+    return s + i;
+}
+
+int (*table[2])(int) = {add1, dbl};
+void (*handlers[4])(void) = {bump, bump2, 0, bump};
+
+int main() {
+    int i;
+    int (*fp)(int);
+    int (*h[4])(void);
+    int (*fp2)(int, long) = sum;
+    char *(*np)(int) = name;
+    int a[3];
+    int (*p)[3];
+    long addr;
+
+    fp = add1;
+    if (fp(2) != 3)
+        return 1;
+    fp = &dbl;
+    if ((*fp)(5) != 10)
+        return 2;
+    h[2] = three;
+    if (h[2]() != 3)
+        return 3;
+    if (apply(dbl, 4) != 8 || apply2(add1, 4) != 5)
+        return 4;
+    if (pick(1)(7) != 14 || pick(0)(7) != 8)
+        return 5;
+    if (table[1](6) != 12 || (**table)(6) != 7)
+        return 6;
+
+    for (i = 0; i < 4; i++) {
+        if (handlers[i])
+            handlers[i]();
+    }
+    if (count != 4)
+        return 7;
+    if (fp2(3, 4) != 7 || *np(1) != 121)
+        return 8;
+
+    /* Abstract declarators in casts and sizeof */
+    addr = (long)(int (*)(int, long))sum;
+    fp2 = (int (*)(int, long))addr;
+    if (fp2(1, 1) != 2)
+        return 9;
+    if (sizeof(int (*)(int)) != 8 || sizeof(int *[3]) != 24 || sizeof(int (*)[3]) != 8)
+        return 10;
+
+    p = &a;
+    (*p)[1] = 4;
+    if (a[1] != 4)
+        return 11;
+
+    return 0;
+}
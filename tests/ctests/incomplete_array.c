@@ -0,0 +1,75 @@
+// Arrays of unknown size, completed by initializers and later declarations
+
+struct pair {
+    char tag[4];
+    int val;
+};
+
+extern int primes[];
+extern char greeting[];
+int *prime_list = primes;
+
+int first_primes() {
+    return primes[0] + (*&primes)[4]; // Expect: 2 + 11 = 13
+}
+
+int primes[] = {2, 3, 5, 7, 11, 13};
+char greeting[] = "hey\tyou\n";
+char padded[6] = "ab";
+int rows[][3] = {{1, 2, 3}, {4}, 5, 6};
+struct pair pairs[] = {{"ab", 1}, "cd", 2, {{120}, 3}};
+int single[];
+
+// Parameters of array type are pointers to the first element
+int sum(int a[], int n) {
+    int s = 0;
+    int i;
+    for (i = 0; i < n; i++)
+        s = s + a[i];
+    return s;
+}
+
+int count(char s[10]) {
+    int n = 0;
+    while (s[n])
+        n++;
+    return n + sizeof(s); // Expect: strlen + 8
+}
+
+int local_arrays() {
+    int a[] = {4, 5, 6, 7};
+    char s[] = "hello";
+    char t[] = {"xyz"};
+    char u[3] = "abc";
+    return sizeof(a) * 100 + sizeof(s) * 10 + sizeof(t) + u[2] - 99; // Expect: 1664
+}
+
+int block_extern() {
+    extern int primes[6];
+    return sizeof(primes) + primes[5]; // Expect: 24 + 13 = 37
+}
+
+int main() {
+    int a[] = {1, 2, 3};
+    if (first_primes() != 13 || prime_list[1] != 3)
+        return 1;
+    if (sizeof(primes) != 24 || sizeof(greeting) != 9 || greeting[3] != 9 || greeting[7] != 10)
+        return 2;
+    if (sizeof(padded) != 6 || padded[1] != 98 || padded[2] || padded[5])
+        return 3;
+    if (sizeof(rows) != 36 || rows[1][0] != 4 || rows[1][2] || rows[2][1] != 6 || rows[2][2])
+        return 4;
+    if (sizeof(pairs) != 24 || pairs[1].tag[1] != 100 || pairs[1].val != 2 || pairs[2].tag[0] != 120)
+        return 5;
+    if (single[0])
+        return 6;
+    if (sum(a, 3) != 6 || sum(primes, 6) != 41)
+        return 7;
+    if (count(greeting) != 16)
+        return 8;
+    if (local_arrays() != 1664)
+        return 9;
+    if (block_extern() != 37)
+        return 10;
+    return 0;
+}
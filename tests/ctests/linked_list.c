@@ -0,0 +1,124 @@
+// Self-referential structs and tags completed after their first use
+
+struct node {
+    int val;
+    struct node *next;
+};
+
+// Only a pointer to the tree is needed until it is completed below
+struct tree *root;
+struct tree *make_leaf(int val);
+
+struct pair {
+    struct left *l; // Completed later
+    struct right *r;
+};
+
+struct left {
+    int val;
+    struct right *other;
+};
+
+struct right {
+    struct left *other;
+    int val;
+};
+
+struct tree {
+    struct tree *kids[2];
+    int val;
+};
+
+struct node nodes[4];
+struct tree pool[7];
+int used;
+
+int sum_list(struct node *head) {
+    int sum = 0;
+    while (head) {
+        sum = sum + head->val;
+        head = head->next;
+    }
+    return sum;
+}
+
+int list() {
+    int i;
+    for (i = 0; i < 4; i++) {
+        nodes[i].val = i + 1;
+        nodes[i].next = i < 3 ? &nodes[i + 1] : 0;
+    }
+    return sum_list(&nodes[0]) * 10 + nodes[0].next->next->val; // Expect: 103
+}
+
+struct tree *make_leaf(int val) {
+    struct tree *t = &pool[used++];
+    t->kids[0] = t->kids[1] = 0;
+    t->val = val;
+    return t;
+}
+
+struct tree *insert(struct tree *t, int val) {
+    if (t == 0)
+        return make_leaf(val);
+    if (val < t->val)
+        t->kids[0] = insert(t->kids[0], val);
+    else
+        t->kids[1] = insert(t->kids[1], val);
+    return t;
+}
+
+int depth(struct tree *t) {
+    int l;
+    int r;
+    if (t == 0)
+        return 0;
+    l = depth(t->kids[0]);
+    r = depth(t->kids[1]);
+    return 1 + (l > r ? l : r);
+}
+
+int tree() {
+    root = insert(root, 4);
+    insert(root, 2);
+    insert(root, 6);
+    insert(root, 1);
+    insert(root, 3);
+    insert(root, 5);
+    return depth(root) * 100 + root->kids[0]->kids[1]->val * 10 + root->kids[1]->kids[0]->val; // Expect: 335
+}
+
+int mutual() {
+    struct left a;
+    struct right b;
+    struct pair p;
+    a.val = 1;
+    b.val = 2;
+    a.other = &b;
+    b.other = &a;
+    p.l = &a;
+    p.r = &b;
+    return p.l->other->other->val * 10 + p.r->other->other->val; // Expect: 12
+}
+
+int shadowed() {
+    struct node { char c; struct node *up; } inner;
+    struct node outer;
+    inner.c = 7;
+    inner.up = &inner;
+    outer.c = 5;
+    outer.up = &inner;
+    return inner.up->up->c * 10 + outer.up->c + outer.c; // Expect: 70 + 7 + 5 = 82
+}
+
+int main() {
+    if (list() != 103)
+        return 1;
+    if (tree() != 335)
+        return 2;
+    if (mutual() != 12)
+        return 3;
+    if (shadowed() != 82)
+        return 4;
+    return 0;
+}
@@ -0,0 +1,88 @@
+// Pointer differences, pointer comparisons and void pointer arithmetic
+
+struct point {
+    int x;
+    int y;
+};
+
+int nums[8] = {3, 1, 4, 1, 5, 9, 2, 6};
+int *nums_end = nums + 8;
+long nums_len = &nums[8] - &nums[0];
+
+int length(char *s) {
+    char *p = s;
+    while (*p)
+        p++;
+    return p - s;
+}
+
+int count_until(int *p, int *end, int val) {
+    int *q;
+    for (q = p; q < end; q++)
+        if (*q == val)
+            return q - p;
+    return -1;
+}
+
+int max_index(int *p, int *end) {
+    int *best = p;
+    int *q;
+    for (q = p; q <= end - 1; q++)
+        if (*q > *best)
+            best = q;
+    return best - p;
+}
+
+int point_distance() {
+    struct point pts[5];
+    struct point *a = &pts[1];
+    struct point *b = pts + 4;
+    return (b - a) * 10 + (a - b); // Expect: 27
+}
+
+// Addresses above 2^63 still order after those below
+int unsigned_order() {
+    char *lo = (char *)1;
+    char *hi = (char *)-1;
+    if (hi < lo || hi <= lo || (lo < hi) == 0)
+        return 1;
+    return 0;
+}
+
+int null_compare(int *p) {
+    if (p == 0)
+        return 1;
+    if (0 != p && p > 0)
+        return 2;
+    return 3;
+}
+
+int void_step() {
+    int a[2] = {7, 8};
+    void *v = a;
+    void *w = v + sizeof(int);
+    v++;
+    v += 3;
+    return *(int *)w * 10 + (v == w) + (w - (void *)a); // Expect: 85
+}
+
+int main() {
+    int a;
+    if (length("hello") != 5 || length("") != 0)
+        return 1;
+    if (count_until(nums, nums_end, 5) != 4 || count_until(nums, nums_end, 7) != -1)
+        return 2;
+    if (max_index(nums, nums_end) != 5)
+        return 3;
+    if (nums_len != 8 || sizeof(nums_end - nums) != 8 || nums_end - nums != 8)
+        return 4;
+    if (point_distance() != 27)
+        return 5;
+    if (unsigned_order())
+        return 6;
+    if (null_compare(0) != 1 || null_compare(&a) != 2)
+        return 7;
+    if (void_step() != 85)
+        return 8;
+    return 0;
+}
@@ -0,0 +1,53 @@
+// Redeclarations as headers make them, and tentative definitions
+
+int printf();
+int printf();
+
+int add(int, int);
+int add();
+int scale(int x);
+
+int counter;
+int counter;
+int table[];
+int table[4];
+int total = 5;
+extern int total;
+int total;
+
+enum { ONE = 1, TWO };
+
+int add(int a, int b) {
+    return a + b;
+}
+
+int add(int, int);
+
+int scale(int factor) {
+    extern int counter;
+    return counter * factor;
+}
+
+// A block may declare the same object again, and shadow what is outside
+int shadow(int TWO) {
+    extern int total;
+    extern int total;
+    int counter = TWO;
+    {
+        int TWO = 3;
+        counter = counter + TWO;
+    }
+    return counter + total;
+}
+
+int main() {
+    counter = add(ONE, TWO);
+    table[3] = 4;
+    if (counter != 3 || table[3] != 4 || total != 5)
+        return 1;
+    if (scale(TWO) != 6)
+        return 2;
+    if (shadow(10) != 18)
+        return 3;
+    return 0;
+}
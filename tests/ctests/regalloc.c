@@ -0,0 +1,58 @@
+// Register pressure, spills and values live across calls
+
+int id(int x) {
+    return x;
+}
+
+int fib(int n) {
+    if (n <= 1)
+        return n;
+    return fib(n - 1) + fib(n - 2);
+}
+
+/* Six arguments, each used after a call */
+int mix(int a, int b, int c, int d, int e, int f) {
+    return a + id(b) * c - id(d) + e * id(f);
+}
+
+/* More temporaries than there are registers */
+int deep(int x) {
+    return x + (x * 2 + (x * 3 + (x * 4 + (x * 5 + (x * 6 + (x * 7 + (x * 8 + (x * 9 + x * 10))))))));
+}
+
+int deep_calls(int x) {
+    return id(x) + (id(x + 1) + (id(x + 2) + (id(x + 3) + (id(x + 4) + (id(x + 5) + (id(x + 6) + (id(x + 7) + id(x + 8))))))));
+}
+
+int main() {
+    int i;
+    int sum;
+    char c;
+    short s;
+
+    if (fib(15) != 610)
+        return 1;
+    if (mix(1, 2, 3, 4, 5, 6) != 33)
+        return 2;
+    if (deep(2) != 110)
+        return 3;
+    if (deep_calls(1) != 45)
+        return 4;
+
+    sum = 0;
+    for (i = 0; i < 100; i++) {
+        sum += id(i) + i;
+    }
+    if (sum != 9900)
+        return 5;
+
+    /* Narrow stores from every allocatable register */
+    c = id(300);
+    s = id(70000);
+    if (c != 44)
+        return 6;
+    if (s != 4464)
+        return 7;
+
+    return 0;
+}
@@ -0,0 +1,29 @@
+// Globals in .data, .rodata, .bss and common symbols, and shared literals
+
+int tentative;
+int zeros[4] = {0};
+long zero = 0;
+int data[3] = {1, 2, 3};
+const int limits[2] = {10, 20};
+const char name[] = "abc";
+char *greeting = "hello";
+char *again = "hello";
+char *nul = "a\0b";
+
+int main() {
+    char *local = "hello";
+    tentative = 4;
+    zeros[3] = 5;
+    if (tentative + zeros[3] + zero + zeros[0] != 9)
+        return 1;
+    data[1] = 7;
+    if (data[0] + data[1] + data[2] != 11)
+        return 2;
+    if (limits[1] - limits[0] != 10 || name[2] != 99 || name[3])
+        return 3;
+    if (greeting != again || local != greeting || greeting[4] != 111)
+        return 4;
+    if (nul[0] != 97 || nul[1] || nul[2] != 98)
+        return 5;
+    return 0;
+}
@@ -0,0 +1,29 @@
+// Built into a shared library by tests/pic.rs
+
+int calls;
+int base = 40;
+int *base_ptr = &base;
+const char *const names[3] = {"zero", "one", "two"};
+
+int count() {
+    return ++calls;
+}
+
+int lookup(int i) {
+    switch (i) {
+    case 0:
+        return names[0][1];
+    case 1:
+        return names[1][1];
+    case 2:
+        return names[2][1];
+    case 3:
+        return *base_ptr;
+    }
+    return -1;
+}
+
+int apply(int (*f)(int), int x) {
+    count();
+    return f(x);
+}
@@ -0,0 +1,25 @@
+// Linked against the library built from lib.c
+
+extern int calls;
+extern int base;
+int count();
+int lookup(int i);
+int apply(int (*f)(int), int x);
+
+int twice(int x) {
+    return x * 2;
+}
+
+int main() {
+    count();
+    if (calls != 1)
+        return 1;
+    if (lookup(0) != 101 || lookup(1) != 110 || lookup(2) != 119 || lookup(4) != -1)
+        return 2;
+    base = 7;
+    if (lookup(3) != 7)
+        return 3;
+    if (apply(twice, 21) != 42 || calls != 2)
+        return 4;
+    return 0;
+}
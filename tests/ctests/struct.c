@@ -71,7 +71,6 @@ int test_array() {
     return *l.c[1]; // Expect: 4
 }
 
-/* TODO:
 int test_ptr_to_self() {
     struct a {
         int val;
@@ -83,7 +82,21 @@ int test_ptr_to_self() {
     foo.ptr = &bar;
     
     return foo.ptr->val; // Expect: 123456
-} */
+}
+
+int test_copy() {
+    struct three { int a; int b; int c; } x, y, z; // 12 bytes
+    union odd { char c[3]; } p, q;
+    x.a = 1; x.b = 2; x.c = 3;
+    y = x;
+    z = y = x;
+    z.c = 4;
+    y = 1 ? z : x;
+    p.c[0] = 5; p.c[2] = 7;
+    q = p;
+
+    return y.a + y.b + y.c + z.c + q.c[0] + q.c[2]; // Expect: 23
+}
 
 int main() {
     if (test_simple() != 6) return 1;
@@ -93,7 +106,8 @@ int main() {
     if (test_arrow() != 6) return 5;
     if (test_comma_sep() != 8) return 6;
     if (test_array() != 4) return 7;
-    // TODO: if (test_ptr_to_self() != 123456) return 6;
+    if (test_ptr_to_self() != 123456) return 8;
+    if (test_copy() != 23) return 9;
 
     // Successful
     return 0;
@@ -0,0 +1,117 @@
+// Brace initializers for structs, unions and nested aggregates
+
+struct point {
+    int x;
+    int y;
+};
+
+struct entry {
+    char *name;
+    int code;
+    struct point at;
+    short flags[2];
+};
+
+struct bits {
+    int lo : 4;
+    int : 0;
+    int hi : 12;
+    short mid;
+    long wide : 40;
+};
+
+union num {
+    long l;
+    int i;
+    char c;
+};
+
+/* Configuration table with braces elided in the last entry */
+struct entry table[3] = {
+    {"one", 1, {2, 3}, {4, 5}},
+    {"two", 2, {6}},
+    "six", 6, 7, 8, 9, 10,
+};
+
+struct point origin;
+struct point corners[2][2] = {{{1, 2}, {3, 4}}, {{5, 6}}};
+struct bits gbits = {-3, 1000, 7, 1099511627};
+union num gnum = {-2};
+int count = sizeof(table) / sizeof(table[0]);
+
+int local_struct() {
+    struct point p = {7, 8};
+    struct point q = {9};
+    return p.x * p.y + q.x + q.y; // Expect: 65
+}
+
+int local_nested() {
+    struct entry e = {"x", 11, 12, 13, {14}};
+    return e.code + e.at.x + e.at.y + e.flags[0] + e.flags[1] + (e.name[0] == 120); // Expect: 51
+}
+
+int local_array_of_structs() {
+    struct point ps[3] = {{1, 2}, 3, 4, {5},};
+    int sum = 0;
+    int i;
+    for (i = 0; i < 3; i++)
+        sum = sum * 10 + ps[i].x + ps[i].y;
+    return sum; // Expect: 3 * 100 + 7 * 10 + 5 = 375
+}
+
+int local_bits() {
+    struct bits b = {5, -7, 1, 3};
+    return b.lo * 100 + b.hi * 10 + b.mid + b.wide; // Expect: 500 - 70 + 1 + 3 = 434
+}
+
+int local_union() {
+    union num n = {258};
+    n.c = 1;
+    return n.l; // Expect: 257
+}
+
+int local_zeros() {
+    struct entry e = {0};
+    int a[3][2] = {{1}, 2, 3};
+    if (e.name != 0 || e.code || e.at.x || e.at.y || e.flags[1])
+        return 0;
+    return a[0][0] + a[0][1] * 10 + a[1][0] * 100 + a[1][1] * 1000 + a[2][0] + a[2][1]; // Expect: 3201
+}
+
+int local_excess() {
+    struct point p = {1, 2, 3, 4};
+    return p.x + p.y; // Expect: 3
+}
+
+int main() {
+    if (local_struct() != 65)
+        return 1;
+    if (local_nested() != 51)
+        return 2;
+    if (local_array_of_structs() != 375)
+        return 3;
+    if (local_bits() != 434)
+        return 4;
+    if (local_union() != 257)
+        return 5;
+    if (local_zeros() != 3201)
+        return 6;
+    if (local_excess() != 3)
+        return 7;
+
+    if (count != 3 || table[1].name[1] != 119 || table[2].name[0] != 115)
+        return 8;
+    if (table[0].at.y != 3 || table[0].flags[1] != 5 || table[1].at.x != 6 || table[1].at.y)
+        return 9;
+    if (table[2].code != 6 || table[2].at.x != 7 || table[2].at.y != 8 || table[2].flags[1] != 10)
+        return 10;
+    if (origin.x || origin.y)
+        return 11;
+    if (corners[0][1].y != 4 || corners[1][0].x != 5 || corners[1][1].x || corners[1][1].y)
+        return 12;
+    if (gbits.lo != -3 || gbits.hi != 1000 || gbits.mid != 7 || gbits.wide != 1099511627)
+        return 13;
+    if (gnum.l != -2 || gnum.i != -2)
+        return 14;
+    return 0;
+}
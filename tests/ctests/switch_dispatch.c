@@ -0,0 +1,85 @@
+// Switches large enough for a jump table or a binary search
+
+/* Dense with a hole, so it goes through a table */
+int dense(int op) {
+    int acc = 0;
+    switch (op) {
+        case 10:
+            acc = 1;
+            break;
+        case 11:
+            acc = 2;
+            break;
+        case 12:
+            acc = 3;
+        case 13:
+            acc += 4;
+            break;
+        case 15:
+            acc = 5;
+            break;
+        case 16:
+            return 6;
+        default:
+            acc = 100;
+    }
+    return acc;
+}
+
+/* Starting below zero, without a default */
+int negative(int op) {
+    int acc = 7;
+    switch (op) {
+        case -3: acc = 1; break;
+        case -2: acc = 2; break;
+        case -1: acc = 3; break;
+        case 0: acc = 4; break;
+        case 1: acc = 5; break;
+    }
+    return acc;
+}
+
+/* Too sparse for a table */
+int sparse(int op) {
+    switch (op) {
+        case -1000: return 1;
+        case 3: return 2;
+        case 64: return 3;
+        case 900: return 4;
+        case 4096: return 5;
+        case 70000: return 6;
+        case 123456: return 7;
+        case 2000000000: return 8;
+        default: return 9;
+    }
+}
+
+int main() {
+    int i;
+    int sum;
+
+    if (dense(10) != 1 || dense(11) != 2 || dense(12) != 7 || dense(13) != 4)
+        return 1;
+    if (dense(14) != 100 || dense(15) != 5 || dense(16) != 6)
+        return 2;
+    if (dense(9) != 100 || dense(17) != 100 || dense(-10) != 100)
+        return 3;
+
+    if (negative(-3) != 1 || negative(0) != 4 || negative(1) != 5)
+        return 4;
+    if (negative(-4) != 7 || negative(2) != 7)
+        return 5;
+
+    sum = 0;
+    for (i = -2000; i < 2000; i++) {
+        sum += sparse(i);
+    }
+    if (sum != 3996 * 9 + 1 + 2 + 3 + 4)
+        return 6;
+    if (sparse(4096) != 5 || sparse(70000) != 6 || sparse(123456) != 7)
+        return 7;
+    if (sparse(2000000000) != 8 || sparse(-2000000000) != 9 || sparse(123457) != 9)
+        return 8;
+
+    return 0;
+}
@@ -2,6 +2,9 @@
 #[macro_use]
 mod macros;
 
+use assert_cmd::prelude::*;
+use std::process::Command;
+
 test_succeed! {
     char0: ("int main() { char a; a = 1; return a; }", 1),
     char1: ("int y; int main() { char a[10]; a[0] = -4; a[5] = 19; y = a[0] + a[5]; return y; }", 15),
@@ -14,12 +17,87 @@ test_succeed! {
     pointer4: ("int foo(int **x) { **x = 32; } int main() {int a; int *b; b = &a; foo(&b); return a; } ", 32),
     array: ("tests/ctests/array.c", 0),
     cstruct: ("tests/ctests/struct.c", 0),
+    struct_init: ("tests/ctests/struct_init.c", 0),
+    linked_list: ("tests/ctests/linked_list.c", 0),
+    tentative_struct: ("struct S s; struct S { int a; }; int main() { s.a = 3; return s.a; }", 3),
+    cunion: ("union u { long l; char c[2]; }; int main() { union u a; a.l = 0; a.c[1] = 1; return a.l + sizeof(a); }", 8),
+    incomplete_array: ("tests/ctests/incomplete_array.c", 0),
+    char_array_str: ("int main() { char s[] = \"a\\tb\"; return sizeof(s) * 10 + s[1]; }", 49),
+    char_array_escapes: ("int main() { char s[] = \"\\x00ff\\377\\x7\"; return (s[0] == s[1]) + s[2]; }", 8),
     cenum: ("tests/ctests/enum.c", 0),
     typequal: ("tests/ctests/type_qual.c", 0),
     func: ("tests/ctests/func.c", 0),
+    func_ptr: ("tests/ctests/func_ptr.c", 0),
+    func_ptr_ret: ("char f(int a) { return a; } int main() { char (*p)(int) = f; return p(300); }", 44),
 }
 
 test_fail! {
     const_assign: ("int main() { const int i = 0; i = 2; }"),
     ptr_const_assing: ("int main() { int a, b, * const i=&a; i = &b; }"),
+    call_non_func: ("int main() { int a; return a(); }"),
+    func_ret_array: ("int f()[3]; int main() { return 0; }"),
+    abstract_named: ("int main() { return sizeof(int *p); }"),
+    array_init_expr: ("int main() { int a[2] = 3; return 0; }"),
+    struct_init_expr: ("struct S { int a; }; int main() { struct S s = {1}; struct S t = s; return 0; }"),
+    struct_arg_in_memory: ("struct S { int a[3]; }; int f(struct S s) { return 0; } int main() { return 0; }"),
+    struct_ret_in_memory: ("struct S { int a[3]; } s; struct S f(); int main() { f(); return 0; }"),
+    global_struct_init_nonconst: ("struct S { int a; }; int x; struct S s = {x}; int main() { return 0; }"),
+    union_tag_mismatch: ("struct S { int a; }; int main() { union S s; return 0; }"),
+    incomplete_local: ("struct S; int main() { struct S s; return 0; }"),
+    incomplete_global: ("struct S s; int main() { return 0; }"),
+    void_global: ("void v; int main() { return 0; }"),
+    void_global_init: ("void v = 1; int main() { return 0; }"),
+    void_array: ("void v[2]; int main() { return 0; }"),
+    incomplete_param: ("struct S; int f(struct S s) { return 0; } int main() { return 0; }"),
+    incomplete_member_access: ("struct S *p; int main() { return p->a; }"),
+    struct_self_by_value: ("struct S { int a; struct S s; }; int main() { return 0; }"),
+    struct_redefinition: ("struct S { int a; }; struct S { int b; }; int main() { return 0; }"),
+    no_such_member: ("struct S { int a; }; int main() { struct S s; return s.b; }"),
+    array_size_missing: ("int main() { int a[]; return 0; }"),
+    array_size_conflict: ("int a[3]; int a[4]; int main() { return 0; }"),
+    array_empty_init: ("int main() { int a[] = {}; return 0; }"),
+    sizeof_incomplete_array: ("extern int a[]; int main() { return sizeof(a); }"),
+    extern_with_init: ("int main() { extern int a = 1; return a; }"),
+}
+
+#[test]
+fn excess_initializer_warnings() {
+    let src = "int x = {1, 2};
+union u { int a; char b; } v = {1, 2};
+int main() { int a[1] = {1, 2}; struct { int a; } s = {1, {2}}; return x; }";
+    let out = Command::cargo_bin("rcc")
+        .unwrap()
+        .args(["--run", src])
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), out.status.code());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    for warning in [
+        "warning: 1:13: Excess elements in a scalar initializer will be ignored.",
+        "warning: 2:36: Excess elements in initializer for a union will be ignored.",
+        "warning: 3:29: Excess elements in initializer for an array will be ignored.",
+        "warning: 3:59: Excess elements in initializer for a struct will be ignored.",
+    ] {
+        assert!(stderr.contains(warning), "{}", stderr);
+    }
+}
+
+#[test]
+fn escape_out_of_range() {
+    for literal in ["\\x123456789", "\\x100", "\\400"] {
+        let src = format!("char s[] = \"{}\"; int main() {{ return 0; }}", literal);
+        let out = Command::cargo_bin("rcc")
+            .unwrap()
+            .args(["--run", &src])
+            .output()
+            .unwrap();
+        assert_eq!(Some(1), out.status.code());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(
+            stderr.contains("yarcc: 1:12: Escape sequence out of range."),
+            "{}: {}",
+            literal,
+            stderr
+        );
+    }
 }
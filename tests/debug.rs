@@ -0,0 +1,114 @@
+// Debug information under -g
+#[macro_use]
+mod macros;
+
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+
+test_succeed! {
+    debug_run: (["-g", "tests/ctests/debug.c"], 0),
+    debug_opt: (["-g", "-O2", "tests/ctests/linked_list.c"], 0),
+    debug_switch: (["-g", "-O2", "tests/ctests/switch_dispatch.c"], 0),
+}
+
+test_stdout! {
+    debug_file: (["--dump-asm", "-g", "int main() { return 0; }"], ".file 1 \"<command line>\"\n"),
+    debug_loc: (["--dump-asm", "-g", "int main() {\n  int a = 1;\n  return a;\n}"],
+                "  .loc 1 2 3\n"),
+    debug_loop_cond: (["--dump-asm", "-g", "int main() {\n  int i = 0;\n  while (i < 3)\n    i++;\n  return i;\n}"],
+                      "  .loc 1 3 10\n"),
+    debug_cfi: (["--dump-asm", "-g", "int main() { return 0; }"],
+                "  .cfi_startproc\n  .loc 1 1 0\n  push rbp\n  .cfi_def_cfa_offset 16\n  .cfi_offset rbp, -16\n  mov rbp, rsp\n  .cfi_def_cfa_register rbp\n"),
+    debug_epilogue: (["--dump-asm", "-g", "int main() { return 0; }"],
+                     "  .cfi_remember_state\n  mov rsp, rbp\n  pop rbp\n  .cfi_def_cfa rsp, 8\n  ret\n  .cfi_restore_state\n  .cfi_endproc\n"),
+    debug_sections: (["--dump-asm", "-g", "int main() { return 0; }"], ".section .debug_abbrev,\"\",@progbits\n"),
+}
+
+fn dump_asm(args: &[&str]) -> String {
+    let output = Command::cargo_bin("rcc")
+        .unwrap()
+        .arg("--dump-asm")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn readelf(obj: &std::path::Path, what: &str) -> String {
+    let output = Command::new("readelf")
+        .arg(format!("--debug-dump={}", what))
+        .arg(obj)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// The debugger should see the same code that runs without -g
+#[test]
+fn debug_keeps_code() {
+    for level in ["-O0", "-O2"] {
+        let plain = dump_asm(&[level, "tests/ctests/debug.c"]);
+        let debug = dump_asm(&["-g", level, "tests/ctests/debug.c"]);
+        let insts = |asm: &str| -> Vec<String> {
+            asm.lines()
+                .filter(|line| line.starts_with("  ") && !line.starts_with("  ."))
+                .map(String::from)
+                .collect()
+        };
+        assert_eq!(insts(&plain), insts(&debug));
+    }
+}
+
+#[test]
+fn debug_info() {
+    let dir = std::env::temp_dir().join(format!("yarcc-debug-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let asm = dir.join("debug.s");
+    let obj = dir.join("debug.o");
+    fs::write(&asm, dump_asm(&["-g", "tests/ctests/debug.c"])).unwrap();
+    let status = Command::new("cc")
+        .args(["-c", "-o"])
+        .arg(&obj)
+        .arg(&asm)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let info = readelf(&obj, "info");
+    let lines = readelf(&obj, "decodedline");
+    fs::remove_dir_all(&dir).unwrap();
+
+    for expected in [
+        "DW_AT_producer    : yarcc",
+        "DW_TAG_subprogram",
+        "DW_AT_name        : area",
+        "DW_TAG_formal_parameter",
+        "DW_OP_breg6 (rbp): -8",
+        "DW_AT_name        : sq",
+        "DW_AT_name        : counter",
+        "DW_OP_addr",
+        "DW_AT_name        : shape",
+        "DW_TAG_structure_type",
+        "DW_AT_data_member_location: 4",
+        "DW_AT_bit_size    : 1",
+        "DW_TAG_pointer_type",
+        "DW_TAG_array_type",
+        "DW_AT_upper_bound : 7",
+        "DW_TAG_enumeration_type",
+        "DW_AT_name        : kind",
+        "DW_AT_name        : SQUARE",
+        "DW_TAG_const_type",
+    ] {
+        assert!(info.contains(expected), "missing {}", expected);
+    }
+    // Every statement of area() starts a row
+    for line in ["21", "22", "23", "24"] {
+        let found = lines.lines().any(|row| {
+            let cols: Vec<&str> = row.split_whitespace().collect();
+            cols.len() > 2 && cols[0] == "debug.c" && cols[1] == line
+        });
+        assert!(found, "missing line {}", line);
+    }
+}
@@ -0,0 +1,38 @@
+// --dump-tokens, --dump-ast, --dump-c and --round-trip
+#[macro_use]
+mod macros;
+
+test_stdout! {
+    tokens0: (["--dump-tokens", "int x;"], "1:5-1:6      TKIDENT    x"),
+    tokens1: (["--dump-tokens", "int main() {\n  return 42; }"], "2:10-2:12    TKNUM      42"),
+    ast0: (["--dump-ast", "int main() { return 3 * 4; }"], "  NDRETURN @1:14-1:27\n    NDMUL <int> @1:21-1:26\n      NDINT 3 <int> @1:21-1:22\n"),
+    ast1: (["--dump-ast", "int main() { int a[2]; return a[1]; }"], "var a: int[2] -8"),
+    ast2: (["--dump-ast", "int main() { struct { int x; } s; return s.x; }"], "NDMEMBER x +0 <int>"),
+    ast3: (["--dump-ast", "int *g; int main() { return 0; }"], "global g: int *"),
+    ast4: (["--dump-ast", "void (*h[4])(char *); int main() { return 0; }"], "global h: void (*[4])(char *)"),
+    printc0: (["--dump-c", "int main() { int a = 1, *b = &a; return *b + 2 * a; }"],
+              "    int a = 1, *b = (&a);\n    return ((*b) + (2 * a));\n"),
+    printc1: (["--dump-c", "int main() { int a[2][2] = {{1, 2}}; return a[1][0]; }"], "int a[2][2] = {{1, 2}, {0, 0}};"),
+    printc2: (["--dump-c", "int main() { int *(*p)[3]; return 0; }"], "int *(*p)[3];"),
+    printc3: (["--dump-c", "extern int t[]; int f(int a[2]) { return t[1]; }"], "extern int t[];\n\nint f(int *a)\n"),
+    printc4: (["--dump-c", "int main() { struct n { struct n *next; } a; return 0; }"], "struct n;\nstruct n { struct n *next; };\n"),
+    printc5: (["--dump-c", "enum color { R, G }; enum color a; enum color b;"], "enum color { R = 0, G = 1 };\nenum color a;\nenum color b;\n"),
+    printc6: (["--dump-c", "int main() { enum c { R } a; { enum c b; } enum c d; return 0; }"],
+              "    enum c { R = 0 } a;\n    {\n        enum c b;\n    }\n    enum c d;\n"),
+}
+
+// Every fixture prints back to the same AST, whatever it exits with
+macro_rules! round_trip {
+    ($($name:ident: ([$($arg:expr),*], $status:tt),)*) => {
+        test_stdout! { $($name: ([$($arg),*], ""),)* }
+    };
+}
+
+mod round_trip {
+    fixtures!(round_trip, ["--round-trip"]);
+}
+
+test_stdout! {
+    roundtrip_singlenum: (["--round-trip", "examples/singlenum.c"], ""),
+    roundtrip_enum_tags: (["--round-trip", "enum color { R, G }; enum color a; enum color b;\nint f() { enum color { X } x; enum color y; return x + y; }"], ""),
+}
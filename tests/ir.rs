@@ -0,0 +1,23 @@
+// --dump-ir and the IR-based backend
+#[macro_use]
+mod macros;
+
+test_pattern! {
+    ir0: (["--dump-ir", "int main() { return 3 * 4; }"],
+          "func main(0 params, frame 0) {\nbb0:\n  {c} = const 12\n  ret {c}\n}\n"),
+    ir1: (["--dump-ir", "int f(char c) { return c; }"], "  {p} = param 0\n  {a} = frameaddr 1\n  store i8 {a}, {p}\n"),
+    ir2: (["--dump-ir", "int main() { int a; if (a) a = 1; return a; }"], "  {v} = load i32 {a}\n  br {v}, bb1, bb2\n"),
+    ir3: (["--dump-ir", "int main() { switch (2) { case 1: return 4; case 2: return 5; default: ; } return 0; }"],
+          "switch {v} [1 => bb1, 2 => bb2] default bb3"),
+    ir4: (["--dump-ir", "int g[3]; int main() { char *s = \"ab\"; return s[0]; }"], "global g [12 bytes]\nliteral .Lstr0 \"ab\"\n"),
+    ir5: (["--dump-ir", "int f(); int main() { return f(1, 2); }"], "{r} = call f({a}, {b})"),
+    ir6: (["--dump-ir", "int f(int *p) { return p <= p + 1; }"], "cmp ule"),
+    ir7: (["--dump-ir", "-g", "int main() {\n  return 2;\n}"], "bb0:\n  loc 2:3\n  {c} = const 2\n"),
+}
+
+test_succeed! {
+    regalloc: ("tests/ctests/regalloc.c", 0),
+    continue_in_switch: ("int main() { int i; int n = 0; for (i = 0; i < 5; i++) { switch (i) { case 2: continue; default: n++; } } return n; }", 4),
+    char_param: ("int f(char c, short s) { return c + s; } int main() { return f(3, 4); }", 7),
+    logical_value: ("int main() { int a = 2; int b = a && 3; int c = 0 || a; int d = a && 0; return b + c + d; }", 2),
+}
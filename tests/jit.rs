@@ -0,0 +1,70 @@
+// Programs compiled into memory and run with --jit
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[macro_use]
+mod macros;
+
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+
+mod fixtures {
+    fixtures!(test_jit, []);
+}
+
+test_jit! {
+    jit_pic: (["-fPIC", "tests/ctests/sections.c"], 0),
+    jit_no_pic: (["-fno-pic", "tests/ctests/sections.c"], 0),
+    jit_debug: (["-g", "tests/ctests/func_ptr.c"], 0),
+    jit_optimized: (["-O2", "tests/ctests/regalloc.c"], 0),
+    jit_common: (["int count; int main() { count = 5; return count; }"], 5),
+    jit_libc: (["void *malloc(); int strcpy(); int strlen(); int main() { char *p = malloc(8); strcpy(p, \"hello\"); return strlen(p); }"], 5),
+    jit_libc_pointer: (["int abs(); int (*f)() = abs; int main() { int (*g)() = abs; return f(-3) + g(-4); }"], 7),
+    jit_exit: (["void exit(); int main() { exit(9); return 0; }"], 9),
+    jit_argc: (["int main(int argc) { return argc; }", "--", "a", "b"], 3),
+}
+
+test_stdout! {
+    jit_printf: (["--jit", "int printf(); int main() { printf(\"%d-%s\\n\", 42, \"ok\"); return 0; }"], "42-ok\n"),
+    jit_argv: (["--jit", "int puts(); int main(int argc, char **argv) { puts(argv[2]); return 0; }", "--", "a", "b c"], "b c\n"),
+}
+
+#[test]
+fn jit_errors() {
+    for (args, msg) in [
+        (
+            &["--jit", "int g(); int main() { return g(); }"][..],
+            "yarcc: jit: Undefined symbol g",
+        ),
+        (
+            &["--jit", "int f() { return 1; }"][..],
+            "yarcc: jit: No definition of main",
+        ),
+        (
+            &["int main() { return 0; }", "--", "a"][..],
+            "Program arguments are only passed on with --jit",
+        ),
+    ] {
+        let out = Command::cargo_bin("rcc")
+            .unwrap()
+            .args(args)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert_eq!(Some(1), out.status.code(), "{:?}", args);
+        assert!(stderr.contains(msg), "{:?}: {}", args, stderr);
+    }
+}
+
+#[test]
+fn jit_writes_nothing() {
+    let dir = std::env::temp_dir().join(format!("yarcc-jit-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let status = Command::cargo_bin("rcc")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["--jit", "int main() { return 3; }"])
+        .status()
+        .unwrap();
+    assert_eq!(Some(3), status.code());
+    assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+}
@@ -0,0 +1,432 @@
+// Scripted JSON-RPC sessions with rcc-lsp
+use assert_cmd::prelude::*;
+use rcc::json::Json;
+use std::process::Command;
+
+const URI: &str = "file:///test.c";
+
+const SOURCE: &str = "struct point { int x; int y; };
+enum color { RED, GREEN };
+int total;
+int add(int a, int b);
+int add(int a, int b) {
+    return a + b;
+}
+int main() {
+    struct point p;
+    struct point *q = &p;
+    p.x = 1;
+    total = add(p.x, GREEN);
+    { int total = 2; p.y = total; }
+    return q->y * 2;
+}
+";
+
+fn request(id: usize, method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("id", id.into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+fn open(text: &str) -> Json {
+    let doc = Json::object([
+        ("uri", URI.into()),
+        ("languageId", "c".into()),
+        ("version", 1.into()),
+        ("text", text.into()),
+    ]);
+    notification(
+        "textDocument/didOpen",
+        Json::object([("textDocument", doc)]),
+    )
+}
+
+fn change(text: &str) -> Json {
+    let doc = Json::object([("uri", URI.into()), ("version", 2.into())]);
+    let changes = vec![Json::object([("text", text.into())])];
+    notification(
+        "textDocument/didChange",
+        Json::object([("textDocument", doc), ("contentChanges", changes.into())]),
+    )
+}
+
+// A request about the position in the document
+fn at(id: usize, method: &str, line: usize, character: usize) -> Json {
+    let params = Json::object([
+        ("textDocument", Json::object([("uri", URI.into())])),
+        (
+            "position",
+            Json::object([("line", line.into()), ("character", character.into())]),
+        ),
+    ]);
+    request(id, method, params)
+}
+
+// Runs rcc-lsp on the messages, giving what it sent back and its exit status
+fn session(msgs: &[Json], shutdown: bool) -> (Vec<Json>, Option<i32>) {
+    let mut input = String::new();
+    let mut msgs = msgs.to_vec();
+    if shutdown {
+        msgs.push(request(1000, "shutdown", Json::Null));
+        msgs.push(notification("exit", Json::Null));
+    }
+    for msg in msgs.iter() {
+        let body = msg.to_string();
+        input.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    }
+    let out = Command::cargo_bin("rcc-lsp")
+        .unwrap()
+        .with_stdin()
+        .buffer(input)
+        .output()
+        .unwrap();
+
+    let mut stdout = String::from_utf8(out.stdout).unwrap();
+    let mut replies = Vec::new();
+    while !stdout.is_empty() {
+        let (header, rest) = stdout.split_once("\r\n\r\n").unwrap();
+        let len: usize = header["Content-Length: ".len()..].parse().unwrap();
+        replies.push(Json::parse(&rest[..len]).unwrap());
+        stdout = rest[len..].to_string();
+    }
+    (replies, out.status.code())
+}
+
+fn result(replies: &[Json], id: usize) -> &Json {
+    let reply = replies
+        .iter()
+        .find(|r| r.get("id").as_u64() == Some(id as u64))
+        .unwrap_or_else(|| panic!("No reply to {}", id));
+    assert!(reply.get("error").is_null(), "{}", reply);
+    reply.get("result")
+}
+
+fn diagnostics(replies: &[Json]) -> Vec<&Json> {
+    replies
+        .iter()
+        .filter(|r| r.get("method").as_str() == Some("textDocument/publishDiagnostics"))
+        .map(|r| r.get("params").get("diagnostics"))
+        .collect()
+}
+
+fn range(line: usize, start: usize, end: usize) -> String {
+    format!(
+        "{{\"start\":{{\"line\":{},\"character\":{}}},\"end\":{{\"line\":{},\"character\":{}}}}}",
+        line, start, line, end
+    )
+}
+
+#[test]
+fn lsp_lifecycle() {
+    let (replies, code) = session(&[request(1, "initialize", Json::object([]))], true);
+    assert_eq!(Some(0), code);
+    let capabilities = result(&replies, 1).get("capabilities");
+    for capability in [
+        "hoverProvider",
+        "definitionProvider",
+        "documentSymbolProvider",
+    ] {
+        assert_eq!(&Json::Bool(true), capabilities.get(capability));
+    }
+    let triggers = capabilities
+        .get("completionProvider")
+        .get("triggerCharacters");
+    assert_eq!(r#"[".",">"]"#, triggers.to_string());
+    assert!(result(&replies, 1000).is_null());
+
+    // Exiting without a shutdown is an error
+    let (_, code) = session(&[notification("exit", Json::Null)], false);
+    assert_eq!(Some(1), code);
+}
+
+#[test]
+fn lsp_diagnostics() {
+    let (replies, _) = session(
+        &[
+            open("int main() {\n    return undeclared;\n}\n"),
+            change("void f() {\n    return 1;\n}\nint main() { break; }\n"),
+            change("int main() {\n    return 1 @ 2;\n}\n"),
+            change("void v;\nint main() {\n    return 0;\n}\n"),
+            change(SOURCE),
+        ],
+        true,
+    );
+    let published = diagnostics(&replies);
+    assert_eq!(5, published.len());
+
+    let parse = published[0].elems();
+    assert_eq!(1, parse.len());
+    assert_eq!(
+        "Found an undefined identifier.",
+        parse[0].get("message").as_str().unwrap()
+    );
+    assert_eq!(range(1, 11, 21), parse[0].get("range").to_string());
+    assert_eq!(Some(1), parse[0].get("severity").as_u64());
+
+    let sema = published[1].elems();
+    let messages: Vec<&str> = sema
+        .iter()
+        .map(|d| d.get("message").as_str().unwrap())
+        .collect();
+    assert_eq!(
+        vec![
+            "Returning a value from a void function",
+            "Break statement outside of a loop or a switch"
+        ],
+        messages
+    );
+    assert_eq!(range(1, 4, 13), sema[0].get("range").to_string());
+    assert_eq!(range(3, 13, 19), sema[1].get("range").to_string());
+
+    let tokens = published[2].elems();
+    assert_eq!(1, tokens.len());
+    assert_eq!(
+        "Unexpected char: @.",
+        tokens[0].get("message").as_str().unwrap()
+    );
+    assert_eq!(range(1, 13, 14), tokens[0].get("range").to_string());
+
+    // Found at the end of the file, but about the declaration
+    let late = published[3].elems();
+    assert_eq!(1, late.len());
+    assert_eq!(range(0, 5, 6), late[0].get("range").to_string());
+
+    assert!(published[4].elems().is_empty());
+}
+
+#[test]
+fn lsp_hover() {
+    let hover = "textDocument/hover";
+    let (replies, _) = session(
+        &[
+            open(SOURCE),
+            at(1, hover, 11, 5),  // total
+            at(2, hover, 9, 18),  // q
+            at(3, hover, 8, 12),  // point
+            at(4, hover, 11, 22), // GREEN
+            at(5, hover, 11, 13), // add
+            at(6, hover, 13, 14), // q->y
+            at(7, hover, 5, 13),  // a + b
+            at(8, hover, 12, 29), // the inner total
+            at(9, hover, 1, 5),   // color
+            at(10, hover, 0, 0),  // struct, where nothing has a type
+        ],
+        true,
+    );
+    let shown = |id| {
+        let value = result(&replies, id).get("contents").get("value");
+        let value = value.as_str().unwrap();
+        value["```c\n".len()..value.len() - "\n```".len()].to_string()
+    };
+    assert_eq!("int total", shown(1));
+    assert_eq!("struct point *q", shown(2));
+    assert_eq!("struct point { int x; int y; }", shown(3));
+    assert_eq!("GREEN = 1", shown(4));
+    assert_eq!("int add(int a, int b)", shown(5));
+    assert_eq!("int", shown(6));
+    assert_eq!("int", shown(7));
+    assert_eq!("int total", shown(8));
+    assert_eq!("enum color { RED = 0, GREEN = 1 }", shown(9));
+    assert!(result(&replies, 10).is_null());
+
+    assert_eq!(
+        range(11, 4, 9),
+        result(&replies, 1).get("range").to_string()
+    );
+    assert_eq!(
+        range(13, 11, 15),
+        result(&replies, 6).get("range").to_string()
+    );
+    assert_eq!(
+        range(5, 11, 16),
+        result(&replies, 7).get("range").to_string()
+    );
+}
+
+#[test]
+fn lsp_definition() {
+    let definition = "textDocument/definition";
+    let (replies, _) = session(
+        &[
+            open(SOURCE),
+            at(1, definition, 11, 13), // add, to its definition
+            at(2, definition, 11, 23), // GREEN
+            at(3, definition, 8, 12),  // point
+            at(4, definition, 11, 5),  // total at file scope
+            at(5, definition, 12, 29), // total in the block
+            at(6, definition, 5, 11),  // a
+            at(7, definition, 13, 12), // q
+            at(8, definition, 10, 6),  // a member, which is not resolved
+        ],
+        true,
+    );
+    let location = |id| {
+        let loc = result(&replies, id);
+        assert_eq!(Some(URI), loc.get("uri").as_str());
+        loc.get("range").to_string()
+    };
+    assert_eq!(range(4, 4, 7), location(1));
+    assert_eq!(range(1, 18, 23), location(2));
+    assert_eq!(range(0, 7, 12), location(3));
+    assert_eq!(range(2, 4, 9), location(4));
+    assert_eq!(range(12, 10, 15), location(5));
+    assert_eq!(range(4, 12, 13), location(6));
+    assert_eq!(range(9, 18, 19), location(7));
+    assert!(result(&replies, 8).is_null());
+}
+
+#[test]
+fn lsp_document_symbols() {
+    let doc = Json::object([("uri", URI.into())]);
+    let (replies, _) = session(
+        &[
+            open(SOURCE),
+            request(
+                1,
+                "textDocument/documentSymbol",
+                Json::object([("textDocument", doc)]),
+            ),
+        ],
+        true,
+    );
+    let symbols = result(&replies, 1).elems();
+    let listed: Vec<(&str, u64)> = symbols
+        .iter()
+        .map(|s| {
+            (
+                s.get("name").as_str().unwrap(),
+                s.get("kind").as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            ("point", 23),
+            ("color", 10),
+            ("RED", 22),
+            ("GREEN", 22),
+            ("total", 13),
+            ("add", 12),
+            ("main", 12),
+        ],
+        listed
+    );
+    // A function covers its body, and is named where it is defined
+    let add = &symbols[5];
+    assert_eq!("int (int a, int b)", add.get("detail").as_str().unwrap());
+    assert_eq!(range(4, 4, 7), add.get("selectionRange").to_string());
+    assert_eq!(
+        r#"{"start":{"line":4,"character":0},"end":{"line":6,"character":1}}"#,
+        add.get("range").to_string()
+    );
+}
+
+#[test]
+fn lsp_completion() {
+    let completion = "textDocument/completion";
+    let typing = SOURCE.replace("return q->y * 2;", "q->");
+    let (replies, _) = session(
+        &[
+            open(SOURCE),
+            at(1, completion, 10, 6),  // p.|x
+            at(2, completion, 10, 7),  // p.x|
+            at(3, completion, 11, 10), // not after a member access
+            change(&typing),
+            at(4, completion, 13, 7), // q->|, which does not parse yet
+        ],
+        true,
+    );
+    let labels = |id| -> Vec<String> {
+        result(&replies, id)
+            .elems()
+            .iter()
+            .map(|item| {
+                assert_eq!(Some(5), item.get("kind").as_u64());
+                assert_eq!(Some("int"), item.get("detail").as_str());
+                item.get("label").as_str().unwrap().to_string()
+            })
+            .collect()
+    };
+    assert_eq!(vec!["x", "y"], labels(1));
+    assert_eq!(vec!["x", "y"], labels(2));
+    assert!(labels(3).is_empty());
+    assert_eq!(vec!["x", "y"], labels(4));
+    assert_eq!(1, diagnostics(&replies)[1].elems().len());
+}
+
+#[test]
+fn lsp_errors() {
+    let (replies, code) = session(
+        &[
+            request(1, "textDocument/formatting", Json::object([])),
+            at(2, "textDocument/hover", 0, 0),
+        ],
+        true,
+    );
+    assert_eq!(Some(0), code);
+    let error = |id: u64| {
+        let reply = replies.iter().find(|r| r.get("id").as_u64() == Some(id));
+        reply.unwrap().get("error").get("code").to_string()
+    };
+    assert_eq!("-32601", error(1));
+    assert_eq!("-32602", error(2));
+
+    // Bodies that are not JSON are answered, and the session goes on
+    let input = "Content-Length: 5\r\n\r\n{oops";
+    let out = Command::cargo_bin("rcc-lsp")
+        .unwrap()
+        .with_stdin()
+        .buffer(input)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains(r#""code":-32700"#), "{}", stdout);
+}
+
+#[test]
+fn lsp_warnings() {
+    let (replies, _) = session(
+        &[
+            open("int;\nint main() {\n    int *p = 0;\n    long *q = p;\n    return 0;\n}\n"),
+            change("int;\nint main() {\n    return 0\n}\n"),
+        ],
+        true,
+    );
+    let published = diagnostics(&replies);
+    assert_eq!(2, published.len());
+    let severity = |d: &Json| d.get("severity").as_u64().unwrap();
+
+    // Of the parser, then of sema
+    let checked = published[0].elems();
+    assert_eq!(2, checked.len());
+    assert_eq!(
+        "This is a useless empty declaration.",
+        checked[0].get("message").as_str().unwrap()
+    );
+    assert_eq!(range(0, 3, 4), checked[0].get("range").to_string());
+    assert_eq!(
+        "Storing int * into long * with incompatible pointer types",
+        checked[1].get("message").as_str().unwrap()
+    );
+    assert_eq!(range(3, 4, 16), checked[1].get("range").to_string());
+    assert!(checked.iter().all(|d| severity(d) == 2));
+
+    // Warnings found before a parse error are kept
+    let failed = published[1].elems();
+    assert_eq!(vec![2, 1], failed.iter().map(severity).collect::<Vec<_>>());
+    assert_eq!(
+        "Expected ';' but got '}'",
+        failed[1].get("message").as_str().unwrap()
+    );
+}
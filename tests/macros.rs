@@ -1,41 +1,58 @@
-/// Expects that the outcome of the produced binary matches $expect
+/// Expects that the outcome of the produced binary matches $expect.
+/// The input may be a list of arguments to pass options along with it.
+/// Each test builds in a directory of its own, as the tests of a binary
+/// run in parallel and would otherwise share tmp.s and tmp.
 #[allow(unused_macros)]
 macro_rules! test_succeed {
-    ($($name:ident: ($input:tt, $expected:tt),)*) => {
+    ($($name:ident: ([$($arg:expr),*], $expected:tt),)*) => {
         $(
             #[test]
             fn $name() {
                 use assert_cmd::prelude::*;
                 use std::process::Command;
 
-                let _rcc = Command::cargo_bin("rcc")
+                let dir = std::env::temp_dir().join(format!(
+                    "yarcc-{}-{}-{}",
+                    module_path!().replace("::", "-"),
+                    stringify!($name),
+                    std::process::id()
+                ));
+                std::fs::create_dir_all(&dir).unwrap();
+
+                let out = Command::cargo_bin("rcc")
                                 .unwrap()
-                                .arg($input)
-                                .assert()
-                                .success();
+                                .arg("--dump-asm")
+                                .args(&[$($arg),*])
+                                .output()
+                                .unwrap();
+                assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+                std::fs::write(dir.join("tmp.s"), out.stdout).unwrap();
 
-                let obj = Command::new("cc")
-                                 .args(&["-no-pie", "-o", "tmp", "tmp.s"])
-                                 .output();
-                match obj {
-                    Ok(_) => (),
-                    Err(_) => panic!("Failed to assemble/link the generated file.")
-                }
+                let status = Command::new("cc")
+                                 .arg("-o")
+                                 .arg(dir.join("tmp"))
+                                 .arg(dir.join("tmp.s"))
+                                 .status()
+                                 .expect("cc is needed to assemble and link the generated file");
+                assert!(status.success(), "Failed to assemble/link the generated file.");
 
-                let status = Command::new("./tmp")
+                let status = Command::new(dir.join("tmp"))
                                        .status()
                                        .unwrap();
-                // println!("{}", status.code().unwrap());
+                std::fs::remove_dir_all(&dir).unwrap();
                 assert_eq!($expected, status.code().unwrap());
             }
         )*
-    }
+    };
+    ($($name:ident: ($input:tt, $expected:tt),)*) => {
+        test_succeed! { $($name: ([$input], $expected),)* }
+    };
 }
 
 /// Expects that the compilation fails
 #[allow(unused_macros)]
 macro_rules! test_fail {
-    ($($name:ident: ($input:tt),)*) => {
+    ($($name:ident: ([$($arg:expr),*]),)*) => {
         $(
             #[test]
             fn $name() {
@@ -44,10 +61,205 @@ macro_rules! test_fail {
 
                 let _rcc = Command::cargo_bin("rcc")
                                 .unwrap()
-                                .arg($input)
+                                .args(&[$($arg),*])
                                 .assert()
                                 .failure();
             }
         )*
+    };
+    ($($name:ident: ($input:tt),)*) => {
+        test_fail! { $($name: ([$input]),)* }
+    };
+}
+
+/// Expects that rcc run with $args succeeds and prints $expected somewhere on stdout
+#[allow(unused_macros)]
+macro_rules! test_stdout {
+    ($($name:ident: ([$($arg:expr),*], $expected:tt),)*) => {
+        $(
+            #[test]
+            fn $name() {
+                use assert_cmd::prelude::*;
+                use std::process::Command;
+
+                let out = Command::cargo_bin("rcc")
+                                .unwrap()
+                                .args(&[$($arg),*])
+                                .output()
+                                .unwrap();
+                assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+                let stdout = String::from_utf8(out.stdout).unwrap();
+                assert!(stdout.contains($expected), "Expected {:?} in:\n{}", $expected, stdout);
+            }
+        )*
+    }
+}
+
+/// Like test_stdout, but $pattern may name registers instead of spelling
+/// them out, as contains_pattern describes
+#[allow(unused_macros)]
+macro_rules! test_pattern {
+    ($($name:ident: ([$($arg:expr),*], $pattern:tt),)*) => {
+        $(
+            #[test]
+            fn $name() {
+                use assert_cmd::prelude::*;
+                use std::process::Command;
+
+                let out = Command::cargo_bin("rcc")
+                                .unwrap()
+                                .args(&[$($arg),*])
+                                .output()
+                                .unwrap();
+                assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+                let stdout = String::from_utf8(out.stdout).unwrap();
+                assert!($crate::macros::contains_pattern(&stdout, $pattern),
+                        "Expected {:?} in:\n{}", $pattern, stdout);
+            }
+        )*
+    }
+}
+
+/// Whether text contains pattern, in which {name} stands for a register
+/// or virtual register: the same one wherever the name appears, and a
+/// different one for each name. "..." skips over any text.
+#[allow(dead_code)]
+pub fn contains_pattern(text: &str, pattern: &str) -> bool {
+    (0..=text.len()).any(|at| match_at(text, at, pattern, &mut Vec::new()))
+}
+
+#[allow(dead_code)]
+fn match_at(text: &str, at: usize, pattern: &str, bound: &mut Vec<(String, String)>) -> bool {
+    if pattern.is_empty() {
+        return true;
+    }
+    if let Some(rest) = pattern.strip_prefix("...") {
+        return (at..=text.len()).any(|at| match_at(text, at, rest, &mut bound.clone()));
+    }
+    if let Some(name) = placeholder(pattern) {
+        let len = text[at..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(text.len() - at);
+        let reg = &text[at..at + len];
+        let fits = match bound.iter().find(|(n, _)| n == name) {
+            Some((_, r)) => r == reg,
+            None => len > 0 && !bound.iter().any(|(_, r)| r == reg),
+        };
+        if !fits {
+            return false;
+        }
+        bound.push((name.to_string(), reg.to_string()));
+        return match_at(text, at + len, &pattern[name.len() + 2..], bound);
     }
+    let c = pattern.chars().next().unwrap();
+    text[at..].starts_with(c) && match_at(text, at + c.len_utf8(), &pattern[c.len_utf8()..], bound)
+}
+
+// The name in a {name} at the start of pattern
+#[allow(dead_code)]
+fn placeholder(pattern: &str) -> Option<&str> {
+    let rest = pattern.strip_prefix('{')?;
+    let end = rest.find('}')?;
+    let name = &rest[..end];
+    if !name.is_empty() && name.bytes().all(|b| b.is_ascii_lowercase()) {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Expects that interpreting the program with --run exits with $expected
+#[allow(unused_macros)]
+macro_rules! test_run {
+    ($($name:ident: ([$($arg:expr),*], $expected:tt),)*) => {
+        $(
+            #[test]
+            fn $name() {
+                use assert_cmd::prelude::*;
+                use std::process::Command;
+
+                let out = Command::cargo_bin("rcc")
+                                .unwrap()
+                                .arg("--run")
+                                .args(&[$($arg),*])
+                                .output()
+                                .unwrap();
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                assert!(!stderr.contains("yarcc: run:"), "{}", stderr);
+                assert_eq!($expected, out.status.code().unwrap());
+            }
+        )*
+    };
+    ($($name:ident: ($input:tt, $expected:tt),)*) => {
+        test_run! { $($name: ([$input], $expected),)* }
+    };
+}
+
+/// Expects that running the program with --jit exits with $expected
+#[allow(unused_macros)]
+macro_rules! test_jit {
+    ($($name:ident: ([$($arg:expr),*], $expected:tt),)*) => {
+        $(
+            #[test]
+            fn $name() {
+                use assert_cmd::prelude::*;
+                use std::process::Command;
+
+                let out = Command::cargo_bin("rcc")
+                                .unwrap()
+                                .arg("--jit")
+                                .args(&[$($arg),*])
+                                .output()
+                                .unwrap();
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                assert!(!stderr.contains("yarcc: jit:"), "{}", stderr);
+                assert_eq!($expected, out.status.code().unwrap());
+            }
+        )*
+    };
+    ($($name:ident: ($input:tt, $expected:tt),)*) => {
+        test_jit! { $($name: ([$input], $expected),)* }
+    };
+}
+
+/// Hands every program in tests/ctests to $test along with the status it
+/// exits with, each test named after its file, so that every mode runs
+/// them all. $arg come before the path. With "defined", the programs that
+/// deliberately have undefined behaviour somewhere are left out.
+#[allow(unused_macros)]
+macro_rules! fixtures {
+    ($test:ident, [$($arg:expr),*], defined) => {
+        $test! {
+            break_c: ([$($arg,)* "tests/ctests/break.c"], 0),
+            call_args_c: ([$($arg,)* "tests/ctests/call_args.c"], 0),
+            comment_c: ([$($arg,)* "tests/ctests/comment.c"], 4),
+            constexpr_c: ([$($arg,)* "tests/ctests/constexpr.c"], 0),
+            continue_c: ([$($arg,)* "tests/ctests/continue.c"], 0),
+            debug_c: ([$($arg,)* "tests/ctests/debug.c"], 0),
+            enum_c: ([$($arg,)* "tests/ctests/enum.c"], 0),
+            func_c: ([$($arg,)* "tests/ctests/func.c"], 0),
+            func_ptr_c: ([$($arg,)* "tests/ctests/func_ptr.c"], 0),
+            incomplete_array_c: ([$($arg,)* "tests/ctests/incomplete_array.c"], 0),
+            init_c: ([$($arg,)* "tests/ctests/init.c"], 0),
+            linked_list_c: ([$($arg,)* "tests/ctests/linked_list.c"], 0),
+            literal_c: ([$($arg,)* "tests/ctests/literal.c"], 1),
+            pointer_arith_c: ([$($arg,)* "tests/ctests/pointer_arith.c"], 0),
+            redeclare_c: ([$($arg,)* "tests/ctests/redeclare.c"], 0),
+            regalloc_c: ([$($arg,)* "tests/ctests/regalloc.c"], 0),
+            sections_c: ([$($arg,)* "tests/ctests/sections.c"], 0),
+            struct_c: ([$($arg,)* "tests/ctests/struct.c"], 0),
+            struct_init_c: ([$($arg,)* "tests/ctests/struct_init.c"], 0),
+            switch_cases_c: ([$($arg,)* "tests/ctests/switch_cases.c"], 1),
+            switch_dispatch_c: ([$($arg,)* "tests/ctests/switch_dispatch.c"], 0),
+            type_qual_c: ([$($arg,)* "tests/ctests/type_qual.c"], 0),
+        }
+    };
+    ($test:ident, [$($arg:expr),*]) => {
+        fixtures!($test, [$($arg),*], defined);
+        $test! {
+            array_c: ([$($arg,)* "tests/ctests/array.c"], 0),
+        }
+    };
 }
@@ -0,0 +1,172 @@
+// Objects written by the built-in assembler under -c
+use assert_cmd::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Directory of a test's own, as -c writes into the current one
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("yarcc-obj-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn rcc(dir: &Path, args: &[&str]) {
+    let output = Command::cargo_bin("rcc")
+        .unwrap()
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn source(file: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/ctests")
+        .join(file)
+        .display()
+        .to_string()
+}
+
+fn link_and_run(dir: &Path, objects: &[&str], extra: &[&str]) -> i32 {
+    let exe = dir.join("a.out");
+    let status = Command::new("cc")
+        .current_dir(dir)
+        .arg("-o")
+        .arg(&exe)
+        .args(objects)
+        .args(extra)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    Command::new(&exe).status().unwrap().code().unwrap()
+}
+
+// Bytes of a section, as objcopy sees them
+fn section(obj: &Path, name: &str) -> Vec<u8> {
+    let out = obj.with_extension(format!("{}.bin", name.trim_start_matches('.')));
+    let status = Command::new("objcopy")
+        .args(["-O", "binary", "--only-section", name])
+        .arg(obj)
+        .arg(&out)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::read(out).unwrap()
+}
+
+#[test]
+fn object_runs() {
+    let dir = work_dir("runs");
+    for (file, args) in [
+        ("array.c", vec![]),
+        ("struct_init.c", vec![]),
+        ("linked_list.c", vec!["-O2"]),
+        ("func_ptr.c", vec![]),
+        ("init.c", vec!["-fno-pic"]),
+        ("sections.c", vec!["-fPIC"]),
+        ("switch_dispatch.c", vec!["-O2"]),
+        ("regalloc.c", vec!["--no-peephole"]),
+    ] {
+        let src = source(file);
+        let mut full = vec!["-c"];
+        full.extend(args.iter());
+        full.push(&src);
+        rcc(&dir, &full);
+        let obj = file.replace(".c", ".o");
+        let extra = if args.contains(&"-fno-pic") {
+            vec!["-no-pie"]
+        } else {
+            vec![]
+        };
+        assert_eq!(0, link_and_run(&dir, &[&obj], &extra), "{}", file);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn object_raw_source() {
+    let dir = work_dir("raw");
+    rcc(&dir, &["-c", "int main() { return 42; }"]);
+    assert_eq!(42, link_and_run(&dir, &["tmp.o"], &[]));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// -S, the default, still leaves the assembly in tmp.s
+#[test]
+fn object_then_asm() {
+    let dir = work_dir("asm");
+    rcc(&dir, &["-c", "-S", "int main() { return 3; }"]);
+    assert!(dir.join("tmp.s").exists());
+    assert!(!dir.join("tmp.o").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// The code is the same as the system assembler makes of the text
+#[test]
+fn object_matches_text() {
+    let dir = work_dir("match");
+    for (file, level) in [("switch_dispatch.c", "-O2"), ("regalloc.c", "-O0")] {
+        let src = source(file);
+        rcc(&dir, &[level, &src]);
+        let status = Command::new("cc")
+            .current_dir(&dir)
+            .args(["-c", "-o", "text.o", "tmp.s"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        rcc(&dir, &["-c", level, &src]);
+        let obj = dir.join(file.replace(".c", ".o"));
+        assert_eq!(
+            section(&dir.join("text.o"), ".text"),
+            section(&obj, ".text"),
+            "{}",
+            file
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn object_debug() {
+    let dir = work_dir("debug");
+    rcc(&dir, &["-c", "-g", &source("debug.c")]);
+    let output = Command::new("readelf")
+        .args(["--debug-dump=decodedline,frames"])
+        .arg(dir.join("debug.o"))
+        .output()
+        .unwrap();
+    let dump = String::from_utf8(output.stdout).unwrap();
+    assert!(dump.contains("debug.c"), "{}", dump);
+    assert!(
+        dump.contains("DW_CFA_def_cfa_register: r6 (rbp)"),
+        "{}",
+        dump
+    );
+    assert_eq!(0, link_and_run(&dir, &["debug.o"], &[]));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn object_shared_library() {
+    let dir = work_dir("shared");
+    rcc(&dir, &["-c", "-fPIC", &source("shared/lib.c")]);
+    rcc(&dir, &["-c", &source("shared/main.c")]);
+    let status = Command::new("cc")
+        .current_dir(&dir)
+        .args(["-shared", "-o", "libshared.so", "lib.o"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let rpath = format!("-Wl,-rpath,{}", dir.display());
+    assert_eq!(
+        0,
+        link_and_run(&dir, &["main.o", "libshared.so"], &[&rpath])
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
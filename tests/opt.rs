@@ -0,0 +1,35 @@
+// Optimization passes, one at a time through --passes and as -O pipelines
+#[macro_use]
+mod macros;
+
+mod fixtures {
+    fixtures!(test_succeed, ["-O2"]);
+}
+
+test_succeed! {
+    o1_loop: (["-O1", "int main() { int s = 0; int i; for (i = 0; i < 10; i = i + 1) s = s + i; return s; }"], 45),
+    o2_div_zero: (["-O2", "int main() { int a = 0; if (a) return 1 / 0; return 3; }"], 3),
+}
+
+test_fail! {
+    unknown_pass: (["--passes=unroll", "int main() { return 0; }"]),
+}
+
+test_pattern! {
+    constprop0: (["--dump-ir", "--passes=constprop", "int main() { int a; a = 2; if (1 < 2) return a; return 0; }"],
+                 "  {c} = const 1\n  jmp bb1\nbb1:\n"),
+    constprop_identity: (["--dump-ir", "--passes=constprop", "int main() { int a[2]; return a[0]; }"],
+                         "  {a} = frameaddr 8\n...  {p} = copy {a}\n  {v} = load i32 {p}\n"),
+    cse0: (["--dump-ir", "--passes=cse", "int main() { int a[2]; a[0] = 3; return a[0] + a[0]; }"],
+           "  store i32 {a}, {c}\n...  {b} = copy {a}\n  {v} = load i32 {b}\n"),
+    copyprop0: (["--dump-ir", "--passes=cse,copyprop", "int main() { int a[2]; a[0] = 3; return a[0] + a[0]; }"],
+                "  store i32 {a}, {c}\n...  {v} = load i32 {a}\n...  {w} = load i32 {a}\n  {s} = add {v}, {w}\n"),
+    strength0: (["--dump-ir", "--passes=strength", "int main() { int a[2]; int i = 1; return a[i]; }"],
+                "  {k} = const 2\n  {s} = shl {i}, {k}\n"),
+    dce0: (["--dump-ir", "--passes=dce", "int main() { 1 + 2; return 0; }"],
+           "bb0:\n  {z} = const 0\n  ret {z}\n}"),
+    jumpthread0: (["--dump-ir", "--passes=jumpthread", "int main() { int a = 1; if (a) { if (a) a = 2; } return a; }"],
+                  "  br {c}, bb3, bb2\nbb2:\n  {a} = frameaddr 4\n"),
+    o1_fold: (["--dump-ir", "-O1", "int main() { int a = 0; if (a) return 1; return 2 * 3; }"],
+              "  {s} = const 6\n  ret {s}\n"),
+}
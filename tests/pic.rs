@@ -0,0 +1,79 @@
+// Position-independent code, the default, and shared libraries
+#[macro_use]
+mod macros;
+
+use assert_cmd::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+test_succeed! {
+    pic_sections: (["-fPIC", "tests/ctests/sections.c"], 0),
+    pic_func_ptr: (["-fPIC", "tests/ctests/func_ptr.c"], 0),
+    pic_switch: (["-fPIC", "-O2", "tests/ctests/switch_dispatch.c"], 0),
+    pie_init: (["-fPIE", "tests/ctests/init.c"], 0),
+}
+
+test_pattern! {
+    pie_local: (["--dump-asm", "int g; int main() { return g; }"], "  lea {r}, [rip+g]\n"),
+    pie_extern: (["--dump-asm", "extern int g; int main() { return g; }"], "  mov {r}, qword ptr [rip+g@GOTPCREL]\n"),
+    pie_literal: (["--dump-asm", "int main() { char *s = \"a\"; return s[0]; }"], "[rip+.Lstr0]"),
+    pie_call: (["--dump-asm", "int f() { return 1; } int main() { return f(); }"], "  call f\n"),
+    pie_call_extern: (["--dump-asm", "int f(); int main() { return f(); }"], "  call f@PLT\n"),
+    pic_global: (["--dump-asm", "-fPIC", "int g; int main() { return g; }"], "  mov {r}, qword ptr [rip+g@GOTPCREL]\n"),
+    pic_call: (["--dump-asm", "-fPIC", "int f() { return 1; } int main() { return f(); }"], "  call f@PLT\n"),
+    pic_relro: (["--dump-asm", "-fPIC", "int g; int *const p = &g; int main() { return *p; }"],
+                ".section .data.rel.ro,\"aw\"\n.global p\n"),
+    pie_switch_table: (["--dump-asm", "int main() { int a = 2; switch (a) { case 1: case 2: case 4: return 1; case 3: return 2; } return 0; }"],
+                       "  lea {t}, [rip+.LJT0_0]\n  jmp qword ptr [{t}+{i}*8]\n"),
+    no_pic: (["--dump-asm", "-fno-pic", "int g; int f(); int main() { return g + f(); }"], "offset g\n"),
+}
+
+// Compiles src into an assembly file of its own, as the tests of a binary
+// run in parallel and tmp.s would be shared
+fn compile(args: &[&str], src: &str, out: &Path) {
+    let output = Command::cargo_bin("rcc")
+        .unwrap()
+        .arg("--dump-asm")
+        .args(args)
+        .arg(src)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    fs::write(out, output.stdout).unwrap();
+}
+
+#[test]
+fn shared_library() {
+    let dir = std::env::temp_dir().join(format!("yarcc-pic-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    compile(&["-fPIC"], "tests/ctests/shared/lib.c", &dir.join("lib.s"));
+    compile(&[], "tests/ctests/shared/main.c", &dir.join("main.s"));
+
+    let lib = dir.join("libshared.so");
+    let status = Command::new("cc")
+        .args(["-shared", "-o"])
+        .arg(&lib)
+        .arg(dir.join("lib.s"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let exe = dir.join("main");
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&exe)
+        .arg(dir.join("main.s"))
+        .arg(&lib)
+        .arg(format!("-Wl,-rpath,{}", dir.display()))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let status = Command::new(&exe).status().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(Some(0), status.code());
+}
@@ -0,0 +1,112 @@
+// Sessions of --repl fed through stdin
+use assert_cmd::prelude::*;
+use std::process::{Command, Output};
+
+fn repl(input: &str) -> Output {
+    Command::cargo_bin("rcc")
+        .unwrap()
+        .arg("--repl")
+        .with_stdin()
+        .buffer(input)
+        .output()
+        .unwrap()
+}
+
+fn session(input: &str, expected: &str) {
+    let out = repl(input);
+    assert_eq!(Some(0), out.status.code(), "{}", input);
+    assert_eq!(expected, String::from_utf8_lossy(&out.stdout), "{}", input);
+}
+
+#[test]
+fn repl_values() {
+    session("1 + 2\n", "(int) 3\n");
+    session("3;\n", "(int) 3\n");
+    session("\"hi\"\n", "(char *) \"hi\"\n");
+    session("char c = 65; c\n", "(char) 65 'A'\n");
+    session("long l = 1; l << 40\n", "(long) 1099511627776\n");
+    session("int a[3] = {1, 2, 3};\na\n", "(int[3]) {1, 2, 3}\n");
+    session(
+        "struct point { int x; int y; } p = {1, 2};\np\np.x + p.y\n",
+        "(struct point) {x = 1, y = 2}\n(int) 3\n",
+    );
+    session(
+        "enum color { RED, GREEN } c = GREEN;\nc\n",
+        "(enum color) GREEN\n",
+    );
+    session("void f() {}\nf()\n", "");
+    session(
+        "int f() { return 1; }\nf\nint (*fp)() = f;\nfp\nfp = 0;\n",
+        "(int ()) f\n(int (*)()) f\n(int (*)()) null\n",
+    );
+    session("int x;\nvoid *v = &x;\nv\n", "(void *) offset 0 into x\n");
+}
+
+#[test]
+fn repl_definitions() {
+    session(
+        "int count;\nvoid bump() { count++; }\nbump(); bump();\ncount\n",
+        "(int) 2\n",
+    );
+    session(
+        "int sq(int a) {\n  /* { */\n  return a * a;\n}\nsq(\n  7)\n",
+        "(int) 49\n",
+    );
+    session("int x = 5;\nx = x + 1;\nx\n", "(int) 6\n(int) 6\n");
+    session(
+        "int x = 1;\nif (x) x = 2; else x = 3;\nwhile (x < 10) x = x * 2;\nx\n",
+        "(int) 16\n",
+    );
+}
+
+#[test]
+fn repl_initializers() {
+    // Initializers may use what earlier inputs computed, once, as they are entered
+    session(
+        "int x = 3;\nint y = x * 2;\nx = 5;\ny\n",
+        "(int) 5\n(int) 6\n",
+    );
+    session(
+        "int x = 3;\nint a[3] = {1, x, x + 1};\na\n",
+        "(int[3]) {1, 3, 4}\n",
+    );
+    session(
+        "int x = 3;\nstruct s { int f : 3; int g; } s = {x, x * x};\ns\n",
+        "(struct s) {f = 3, g = 9}\n",
+    );
+
+    // Read-only globals still need a constant
+    let out = repl("int x = 3;\nconst int c = x;\n");
+    assert_eq!("", String::from_utf8_lossy(&out.stdout));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Initializer element is not constant"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn repl_errors() {
+    // A rejected input is forgotten, one that fails while running is not
+    let out = repl("int x = 1;\nint y = ;\nundeclared + 1\nx = 2;\n1 / 0\nx\n");
+    assert_eq!(Some(0), out.status.code());
+    assert_eq!("(int) 2\n(int) 2\n", String::from_utf8_lossy(&out.stdout));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    for msg in [
+        "Expected number",
+        "Found an undefined identifier",
+        "yarcc: run: 1:1: Division by zero",
+    ] {
+        assert!(stderr.contains(msg), "{}", stderr);
+    }
+
+    session("int y = ;\nint y = 2;\ny\n", "(int) 2\n");
+}
+
+#[test]
+fn repl_exit() {
+    let out = repl("void exit(int);\n1\nexit(7);\n2\n");
+    assert_eq!(Some(7), out.status.code());
+    assert_eq!("(int) 1\n", String::from_utf8_lossy(&out.stdout));
+}
@@ -0,0 +1,85 @@
+// Programs interpreted with --run
+#[macro_use]
+mod macros;
+
+use assert_cmd::prelude::*;
+use std::process::Command;
+
+mod fixtures {
+    fixtures!(test_run, []);
+}
+
+// The modes only run the programs listed in the table of fixtures!
+#[test]
+fn fixtures_listed() {
+    let table = include_str!("macros.rs");
+    for entry in std::fs::read_dir("tests/ctests").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "c") {
+            let quoted = format!("\"{}\"", path.display());
+            assert!(table.contains(&quoted), "{} is not in fixtures!", quoted);
+        }
+    }
+}
+
+test_run! {
+    run_exit_status: ("int main() { return 300; }", 44),
+    run_fall_off: ("int main() { 7; }", 7),
+    run_recursion: ("int d(int n) { if (n == 0) return 0; return 1 + d(n - 1); } int main() { return d(5000) & 255; }", 136),
+    run_fallthrough: ("int main() { int c = 0; switch (2) { case 1: c = c + 1; case 2: c = c + 2; case 3: c = c + 4; break; default: c = 100; } return c; }", 6),
+    run_switch_continue: ("int main() { int c = 0; int i; for (i = 0; i < 5; i++) { switch (i) { case 1: continue; default: c++; } c = c + 10; } return c; }", 44),
+    run_malloc: ("void *malloc(); void free(); int main() { int *p = malloc(16); int r; p[3] = 9; r = p[3]; free(p); return r; }", 9),
+    run_exit: ("void exit(); int f() { exit(5); return 1; } int main() { f(); return 2; }", 5),
+}
+
+test_stdout! {
+    run_putchar: (["--run", "int putchar(); int main() { putchar(104); putchar(105); putchar(10); return 0; }"], "hi\n"),
+    run_printf: (["--run", "int printf(); int main() { printf(\"[%5d|%-4d|%05d|%x|%#X|%c|%.2s|%3s|%%]\", 42, 7, -42, 255, 255, 65, \"hello\", \"a\"); return 0; }"],
+                 "[   42|7   |-0042|ff|0XFF|A|he|  a|%]"),
+    run_printf_long: (["--run", "tests/ctests/call_args.c"], "65 4294967301 ok\n"),
+    run_output_before_exit: (["--run", "int puts(); void exit(); int main() { puts(\"bye\"); exit(0); }"], "bye\n"),
+}
+
+// Faults stop the program with the place they happened at
+#[test]
+fn run_errors() {
+    for (src, msg) in [
+        (
+            "int main() { int a = 0; return 3 / a; }",
+            "1:32: Division by zero",
+        ),
+        (
+            "int main() { int *p = 0; return *p; }",
+            "1:33: Null pointer dereference",
+        ),
+        (
+            "int main() { int a[2]; return a[5]; }",
+            "into an object of 8 bytes",
+        ),
+        (
+            "int *f() { int x = 1; return &x; } int main() { return *f(); }",
+            "local of f after it returned",
+        ),
+        (
+            "int g(); int main() { return g(); }",
+            "Call to undefined function g",
+        ),
+        (
+            "int main() { char *s = \"abc\"; s[0] = 1; return 0; }",
+            "Write to read-only memory",
+        ),
+        (
+            "int d(int n) { return d(n + 1); } int main() { return d(0); }",
+            "Stack overflow calling d",
+        ),
+    ] {
+        let out = Command::cargo_bin("rcc")
+            .unwrap()
+            .args(["--run", src])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert_eq!(Some(1), out.status.code(), "{}", src);
+        assert!(stderr.contains(msg), "{}: {}", src, stderr);
+    }
+}
@@ -16,4 +16,14 @@ test_succeed! {
     switch3: ("int main() { int i = 3, b = 3; switch(i) case 2: b = 4; return b;  }", 3),
     switch4: ("int main() { int i = 3, b = 3; switch(i) default: b=4; return b; }", 4),
     switch5: ("tests/ctests/switch_cases.c", 1),
+    switch6: ("tests/ctests/switch_dispatch.c", 0),
+    switch7: ("int main() { int i = 2; switch (i) { case 1: case 2: default: case 3: return 5; } return 0; }", 5),
+    switch8: ("int main() { switch (5) { default: switch (1) { default: return 3; } } return 0; }", 3),
+}
+
+test_fail! {
+    duplicate_case: ("int main() { switch (1) { case 1: case 2: case 1: ; } return 0; }"),
+    duplicate_case_expr: ("int main() { switch (1) { case 4: case 2 * 2: ; } return 0; }"),
+    duplicate_default: ("int main() { switch (5) { default: return 1; case 2: ; default: return 2; } return 0; }"),
+    duplicate_default_nested: ("int main() { switch (5) { default: if (1) { default: ; } } return 0; }"),
 }
@@ -0,0 +1,109 @@
+// semantic analysis
+#[macro_use]
+mod macros;
+
+use assert_cmd::prelude::*;
+use std::process::Command;
+
+test_succeed! {
+    null_ptr: ("int main() { int *p = 0; p = 0; return p == 0; }", 1),
+    void_ptr: ("int main() { int a = 3; void *v = &a; int *p = v; return *p; }", 3),
+    str_ptr: ("int main() { char *s = \"ab\"; return s[1] - s[0]; }", 1),
+    str_arith: ("int main() { char *s = \"abc\" + 1; return *s - 98; }", 0),
+    unary_arith: ("int main() { int a = 3; return -(-a) + +a; }", 6),
+    switch_labels: ("int main() { int a = 0; switch (2) { case 1: case 2: a = 5; break; default: a = 1; } return a; }", 5),
+    call_args: ("tests/ctests/call_args.c", 0),
+    pointer_arith: ("tests/ctests/pointer_arith.c", 0),
+    break_in_switch: ("int main() { int i; for (i = 0; i < 9; i++) { switch (i) { case 3: continue; } if (i == 4) break; } return i; }", 4),
+}
+
+test_fail! {
+    assign_rvalue: ("int main() { int a; a + 1 = 2; return a; }"),
+    assign_str: ("int main() { \"abc\" = 0; return 0; }"),
+    neg_ptr: ("int main() { int a; int *p = &a; return -p; }"),
+    plus_ptr: ("int main() { int a; int *p = &a; +p; return 0; }"),
+    assign_array: ("int main() { int a[2], b[2]; a = b; return 0; }"),
+    addr_rvalue: ("int main() { int a; int *p = &(a + 1); return 0; }"),
+    inc_rvalue: ("int main() { int a = 0; (a + 1)++; return a; }"),
+    dec_const: ("int main() { const int a = 1; --a; return a; }"),
+    int_to_ptr: ("int main() { int a = 1; int *p = a; return 0; }"),
+    ptr_to_int: ("int main() { int a; int b = &a; return b; }"),
+    return_ptr: ("int f(int *p) { return p; } int main() { return 0; }"),
+    return_void: ("void f() { return 1; } int main() { return 0; }"),
+    ptr_add_ptr: ("int main() { int a; int *p = &a; p + p; return 0; }"),
+    int_sub_ptr: ("int main() { int a; int *p = &a; 1 - p; return 0; }"),
+    ptr_mul: ("int main() { int a; int *p = &a; p * 2; return 0; }"),
+    ptr_lt_int: ("int main() { int a; int *p = &a; return p < 1; }"),
+    sub_incompatible_ptrs: ("int main() { int a; char c; return &a - &c; }"),
+    sub_void_int_ptrs: ("int main() { int a; void *v = &a; return v - &a; }"),
+    func_ptr_arith: ("int f(); int main() { int (*fp)() = f; fp++; return 0; }"),
+    deref_int: ("int main() { int a = 0; return *a; }"),
+    cast_struct: ("struct S { int a; }; int main() { struct S s; return (int)s; }"),
+    cond_struct: ("struct S { int a; }; int main() { struct S s; if (s) return 1; return 0; }"),
+    break_outside: ("int main() { break; return 0; }"),
+    continue_in_switch: ("int main() { switch (1) { case 1: continue; } return 0; }"),
+    case_outside: ("int main() { case 1: return 0; }"),
+    default_outside: ("int main() { default: return 0; }"),
+    too_few_args: ("int f(int a, int b); int main() { return f(1); }"),
+    too_many_args: ("int f(int a) { return a; } int main() { return f(1, 2); }"),
+    void_params: ("int f(void); int main() { return f(1); }"),
+    variadic_too_few: ("int f(int a, ...); int main() { return f(); }"),
+    fp_args: ("int main() { int (*fp)(int); return fp(1, 2); }"),
+    int_to_ptr_arg: ("int f(int *p) { return 0; } int main() { return f(3); }"),
+    struct_arg: ("struct S { int a; }; int f(int a); int main() { struct S s; return f(s); }"),
+    incomplete_ptr_arith: ("struct S *p; int main() { p++; return 0; }"),
+    incomplete_ptr_index: ("struct S; int f(struct S *p) { return &p[1] == 0; } int main() { return 0; }"),
+}
+
+#[test]
+fn operand_messages() {
+    let out = Command::cargo_bin("rcc")
+        .unwrap()
+        .arg("int main() { int *p; return p * 2 + (p << 1); }")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("1:29: Invalid operands to binary *\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("1:37: Invalid operands to binary <<\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn operand_messages_unary() {
+    // Interpreted, so that nothing is written to the working directory
+    let stderr = |src: &str| {
+        let out = Command::cargo_bin("rcc")
+            .unwrap()
+            .args(["--run", src])
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&out.stderr).to_string()
+    };
+
+    let literal = stderr("int main() { \"abc\" = 0; return 0; }");
+    assert!(
+        literal.contains("1:14: Expression is not assignable\n"),
+        "{}",
+        literal
+    );
+
+    // Only the operand is wrong, not what -p is used for
+    let neg = stderr("int main() { int a; int *p = &a; return -p; }");
+    assert!(neg.contains("error: Invalid operand to unary -"), "{}", neg);
+    assert!(!neg.contains("Storing"), "{}", neg);
+
+    let discard =
+        stderr("int main() { const int a = 1; const int *cp = &a; int *p = cp; return 0; }");
+    assert!(
+        discard.contains("warning: 1:51: Storing const int * into int * discards const\n"),
+        "{}",
+        discard
+    );
+}
@@ -0,0 +1,85 @@
+// Intel and AT&T syntax output
+#[macro_use]
+mod macros;
+
+use assert_cmd::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+test_succeed! {
+    att_run: (["-masm=att", "tests/ctests/linked_list.c"], 0),
+    att_switch: (["-masm=att", "-O2", "tests/ctests/switch_dispatch.c"], 0),
+}
+
+test_fail! {
+    unknown_syntax: (["-masm=motorola", "int main() { return 0; }"]),
+}
+
+test_pattern! {
+    att_no_directive: (["--dump-asm", "-masm=att", "int main() { return 0; }"], ".text\n"),
+    att_prologue: (["--dump-asm", "-masm=att", "int main() { int a = 3; return a; }"],
+                   "main:\n  push %rbp\n  mov %rsp, %rbp\n  sub $16, %rsp\n  movl $3, -4(%rbp)\n  movslq -4(%rbp), %{r}\n"),
+    att_extend: (["--dump-asm", "-masm=att", "int main() { int a = 3; return a < 4; }"], "  movzbq %al, %rax\n"),
+    att_divide: (["--dump-asm", "-masm=att", "int main() { long a = 7; long b = 2; return a / b; }"], "  cqto\n  idiv %"),
+    att_rip: (["--dump-asm", "-masm=att", "int g; int main() { return g; }"], "  lea g(%rip), %{r}\n"),
+    att_got: (["--dump-asm", "-masm=att", "extern int g; int main() { return g; }"], "  mov g@GOTPCREL(%rip), %{r}\n"),
+    att_offset: (["--dump-asm", "-masm=att", "-fno-pic", "int g; int main() { return g; }"], "  mov $g, %{r}\n"),
+    att_table: (["--dump-asm", "-masm=att", "-fno-pic", "int main() { int a = 2; switch (a) { case 1: case 2: case 4: return 1; case 3: return 2; } return 0; }"],
+                "  jmp *.LJT0_0(,%{i},8)\n"),
+    att_indirect_call: (["--dump-asm", "-masm=att", "int f() { return 1; } int main() { int (*p)() = f; return p(); }"], "  call *%"),
+    att_cfi: (["--dump-asm", "-masm=att", "-g", "int main() { return 0; }"], "  .cfi_offset %rbp, -16\n"),
+}
+
+fn assemble(args: &[&str], dir: &Path, name: &str) -> Vec<u8> {
+    let output = Command::cargo_bin("rcc")
+        .unwrap()
+        .arg("--dump-asm")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let asm = dir.join(format!("{}.s", name));
+    let obj = dir.join(format!("{}.o", name));
+    let text = dir.join(format!("{}.bin", name));
+    fs::write(&asm, output.stdout).unwrap();
+    let status = Command::new("cc")
+        .args(["-c", "-o"])
+        .arg(&obj)
+        .arg(&asm)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new("objcopy")
+        .args(["-O", "binary", "--only-section", ".text"])
+        .arg(&obj)
+        .arg(&text)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::read(text).unwrap()
+}
+
+// Both dialects are the same machine code
+#[test]
+fn same_code() {
+    let dir = std::env::temp_dir().join(format!("yarcc-syntax-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (src, flags) in [
+        ("tests/ctests/regalloc.c", vec!["--no-peephole"]),
+        ("tests/ctests/regalloc.c", vec!["-O2"]),
+        ("tests/ctests/switch_dispatch.c", vec!["-fno-pic"]),
+        ("tests/ctests/struct_init.c", vec!["-fPIC"]),
+        ("tests/ctests/pointer_arith.c", vec![]),
+        ("tests/ctests/debug.c", vec!["-g"]),
+    ] {
+        let mut args = flags.clone();
+        args.push(src);
+        let intel = assemble(&args, &dir, "intel");
+        args.insert(0, "-masm=att");
+        let att = assemble(&args, &dir, "att");
+        assert!(!intel.is_empty());
+        assert_eq!(intel, att, "{} {:?}", src, flags);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}